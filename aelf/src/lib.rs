@@ -70,6 +70,9 @@ pub enum Error {
     /// Failed to memory map
     MapFailed,
 
+    /// Failed to change memory protection
+    ProtectFailed,
+
     /// I/O error: {0}
     IoError(IoError),
 }
@@ -84,6 +87,19 @@ pub trait VirtualMapper {
         size: usize,
         protection: PageProtection,
     ) -> ContiguousMapping;
+
+    /// Changes the protection of an already mapped region.
+    ///
+    /// Called on each loadable segment after relocations have been
+    /// applied. The default implementation leaves the mapping as is.
+    unsafe fn protect(
+        &mut self,
+        _base: VAddr,
+        _size: usize,
+        _protection: PageProtection,
+    ) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 pub struct ContiguousMapping {
@@ -363,6 +379,21 @@ where
             dynamic.fixup();
         }
 
+        for ph in self.program_headers.iter() {
+            if ph.p_type != PT_LOAD || ph.p_memsz == 0 {
+                continue;
+            }
+
+            let vaddr = ph.p_vaddr as usize;
+            let vend = vaddr + ph.p_memsz as usize;
+            let addr = self.page_start(load_bias + vaddr);
+            let size = self.page_align(vend) - self.page_start(vaddr);
+
+            unsafe {
+                memory.protect(VAddr::from_usize(addr), size, ph.prot_flags())?;
+            }
+        }
+
         log::debug!(
             "GDB: add-symbol-file /path/to/elf -o 0x{:x}",
             load_bias
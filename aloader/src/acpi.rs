@@ -6,6 +6,17 @@ use core::mem;
 use acpi::{AcpiTables, AcpiTable, PhysicalMapping};
 use acpi::handler::AcpiHandler;
 use acpi::sdt::{Signature, SdtHeader};
+use astd::boot::{
+    EcamRegion, IommuScope, IommuScopeKind, IommuUnit, MAX_ECAM_REGIONS, MAX_IOMMU_UNITS,
+};
+use astd::heapless::Vec as ArrayVec;
+use x86::io::{inl, outl};
+
+const PCI_CONFIG_ADDRESS: u16 = 0xcf8;
+const PCI_CONFIG_DATA: u16 = 0xcfc;
+
+const PCI_SECONDARY_BUS: u8 = 0x19;
+const PCI_SUBORDINATE_BUS: u8 = 0x1a;
 
 #[derive(Clone, Debug)]
 struct NullAcpiHandler {}
@@ -46,6 +57,41 @@ struct Drhd {
     register_base_address: u64,
 }
 
+/// Device Scope
+///
+/// Section 8.3.1
+#[derive(Debug)]
+#[repr(C, packed)]
+struct DeviceScope {
+    scope_type: u8,
+    length: u8,
+    flags: u8,
+    _reserved: u8,
+    enumeration_id: u8,
+    start_bus_number: u8,
+}
+
+/// PCI Express Memory-mapped Configuration Space
+///
+/// PCI Firmware Specification, Section 4.1.2
+#[derive(Debug)]
+#[repr(C, packed)]
+struct Mcfg {
+    header: SdtHeader,
+    _reserved: [u8; 8],
+}
+
+/// Configuration space base address allocation structure of the MCFG table.
+#[derive(Debug, Clone, Copy)]
+#[repr(C, packed)]
+struct McfgEntry {
+    base_address: u64,
+    segment_group: u16,
+    start_bus_number: u8,
+    end_bus_number: u8,
+    _reserved: u32,
+}
+
 impl NullAcpiHandler {
     fn new() -> Self {
         Self {}
@@ -85,6 +131,27 @@ impl Dmar {
     }
 }
 
+impl Mcfg {
+    fn entries(&self) -> &[McfgEntry] {
+        // a truncated table has no entries
+        let length = (self.header.length as usize)
+            .checked_sub(mem::size_of::<Self>())
+            .unwrap_or(0);
+        unsafe {
+            let start = (self as *const Self).add(1) as *const McfgEntry;
+            core::slice::from_raw_parts(start, length / mem::size_of::<McfgEntry>())
+        }
+    }
+}
+
+unsafe impl AcpiTable for Mcfg {
+    const SIGNATURE: Signature = Signature::MCFG;
+
+    fn header(&self) -> &SdtHeader {
+        &self.header
+    }
+}
+
 unsafe impl AcpiTable for Dmar {
     const SIGNATURE: Signature = Signature::DMAR;
 
@@ -114,6 +181,111 @@ impl Iterator for RemappingPtrIter {
     }
 }
 
+impl Drhd {
+    const FLAG_INCLUDE_PCI_ALL: u8 = 1 << 0;
+
+    fn iter_device_scopes(&self) -> impl Iterator<Item = &DeviceScope> {
+        let start = unsafe { (self as *const Self).add(1) as *const u8 };
+        let limit = unsafe { (self as *const Self as *const u8).add(self.header.length as usize) };
+        let mut cur = start;
+
+        core::iter::from_fn(move || {
+            // a scope header must fit before the end of the DRHD
+            if (limit as usize).saturating_sub(cur as usize) < mem::size_of::<DeviceScope>() {
+                return None;
+            }
+
+            let scope = unsafe { &*(cur as *const DeviceScope) };
+            let length = scope.length as usize;
+            // a malformed table would loop forever or run past the DRHD
+            if length < mem::size_of::<DeviceScope>() || length > limit as usize - cur as usize {
+                return None;
+            }
+            cur = unsafe { cur.add(length) };
+            Some(scope)
+        })
+    }
+
+    fn to_unit(&self) -> IommuUnit {
+        let mut unit = IommuUnit {
+            base: self.register_base_address,
+            segment: self.segment_number,
+            include_pci_all: self.flags & Self::FLAG_INCLUDE_PCI_ALL != 0,
+            scopes: ArrayVec::new(),
+        };
+
+        for scope in self.iter_device_scopes() {
+            match scope.resolve() {
+                Some(scope) => {
+                    if unit.scopes.push(scope).is_err() {
+                        log::warn!("DRHD @ {:#x}: too many device scopes", unit.base);
+                    }
+                }
+                None => log::warn!("DRHD @ {:#x}: unsupported device scope", unit.base),
+            }
+        }
+
+        unit
+    }
+}
+
+impl DeviceScope {
+    /// Follows the PCI path of the scope down to the device it names.
+    fn resolve(&self) -> Option<IommuScope> {
+        let kind = match self.scope_type {
+            1 => IommuScopeKind::Endpoint,
+            2 => IommuScopeKind::Bridge,
+            3 => IommuScopeKind::IoApic,
+            4 => IommuScopeKind::Hpet,
+            5 => IommuScopeKind::Namespace,
+            _ => return None,
+        };
+
+        let path_len = (self.length as usize).checked_sub(mem::size_of::<Self>())? / 2;
+        if path_len == 0 {
+            return None;
+        }
+        let path = unsafe { (self as *const Self).add(1) as *const [u8; 2] };
+
+        let mut bus = self.start_bus_number;
+        for i in 0..path_len - 1 {
+            let [device, function] = unsafe { *path.add(i) };
+            bus = unsafe { pci_read_config_u8(bus, device, function, PCI_SECONDARY_BUS) };
+        }
+        let [device, function] = unsafe { *path.add(path_len - 1) };
+
+        let (secondary_bus, subordinate_bus) = if kind == IommuScopeKind::Bridge {
+            unsafe {
+                (
+                    pci_read_config_u8(bus, device, function, PCI_SECONDARY_BUS),
+                    pci_read_config_u8(bus, device, function, PCI_SUBORDINATE_BUS),
+                )
+            }
+        } else {
+            (0, 0)
+        };
+
+        Some(IommuScope {
+            kind,
+            bus,
+            device,
+            function,
+            secondary_bus,
+            subordinate_bus,
+        })
+    }
+}
+
+unsafe fn pci_read_config_u8(bus: u8, device: u8, function: u8, offset: u8) -> u8 {
+    let address = 0x8000_0000
+        | (bus as u32) << 16
+        | (device as u32 & 0x1f) << 11
+        | (function as u32 & 0x7) << 8
+        | (offset as u32 & 0xfc);
+    outl(PCI_CONFIG_ADDRESS, address);
+    (inl(PCI_CONFIG_DATA) >> ((offset & 3) * 8)) as u8
+}
+
 impl RemappingHeader {
     const TYPE_DRHD: u16 = 0;
 
@@ -128,11 +300,17 @@ impl RemappingHeader {
     }
 }
 
-pub fn probe_iommu() -> Option<u64> {
+fn find_acpi_tables() -> AcpiTables<NullAcpiHandler> {
     let handler = NullAcpiHandler::new();
-    let acpi = unsafe {
+    unsafe {
         AcpiTables::search_for_rsdp_bios(handler).expect("failed to find RSDP")
-    };
+    }
+}
+
+pub fn probe_iommu() -> ArrayVec<IommuUnit, MAX_IOMMU_UNITS> {
+    let mut units = ArrayVec::new();
+
+    let acpi = find_acpi_tables();
 
     log::info!("ACPI: {:?}", acpi);
 
@@ -140,14 +318,54 @@ pub fn probe_iommu() -> Option<u64> {
         dmar
     } else {
         log::warn!("IOMMU not available - No DMAR table");
-        return None;
+        return units;
     };
 
     log::info!("DMAR: {:#?}", *dmar);
 
-    let drhde = dmar.iter_remapping_structs().find_map(|header| {
-        header.as_drhd()
-    }).expect("failed to find DRHD entry");
+    for drhd in dmar.iter_remapping_structs().filter_map(|header| header.as_drhd()) {
+        let unit = drhd.to_unit();
+        log::info!("DRHD: {:x?}", unit);
+
+        if units.push(unit).is_err() {
+            log::warn!("Too many DRHD entries, ignoring the rest");
+            break;
+        }
+    }
+
+    if units.is_empty() {
+        log::warn!("IOMMU not available - No DRHD entry");
+    }
+
+    units
+}
+
+pub fn probe_ecam() -> ArrayVec<EcamRegion, MAX_ECAM_REGIONS> {
+    let mut regions = ArrayVec::new();
+
+    let acpi = find_acpi_tables();
+
+    let mcfg = if let Ok(mcfg) = acpi.find_table::<Mcfg>() {
+        mcfg
+    } else {
+        log::warn!("ECAM not available - No MCFG table");
+        return regions;
+    };
+
+    for entry in mcfg.entries() {
+        let region = EcamRegion {
+            base: entry.base_address,
+            segment: entry.segment_group,
+            start_bus: entry.start_bus_number,
+            end_bus: entry.end_bus_number,
+        };
+        log::info!("MCFG: {:x?}", region);
+
+        if regions.push(region).is_err() {
+            log::warn!("Too many MCFG entries, ignoring the rest");
+            break;
+        }
+    }
 
-    Some(drhde.register_base_address)
+    regions
 }
@@ -42,6 +42,9 @@ use memory::{
 const KERNEL_RESERVATION: usize = 1024 * 1024 * 1024; // 1 GiB
 const DOM0_RESERVATION: usize = 256 * 1024 * 1024; // 256 MiB
 
+/// Local and I/O APIC registers, used by the kernel itself.
+const APIC_WINDOW: core::ops::Range<u64> = 0xFEC0_0000..0xFF00_0000;
+
 /// Loader entry point.
 #[cfg_attr(not(test), start, no_mangle)]
 fn main(_argc: isize, _argv: *const *const u8) -> ! {
@@ -109,7 +112,8 @@ fn main(_argc: isize, _argv: *const *const u8) -> ! {
     }
 
     boot_info.pml4 = address_space.pml4();
-    boot_info.iommu_base = acpi::probe_iommu();
+    boot_info.iommu_units = acpi::probe_iommu();
+    boot_info.ecam_regions = acpi::probe_ecam();
 
     let (jumbo_file, jumbo_size) = {
         let range = boot::get_kernel_image_range().expect("No kernel image was passed");
@@ -199,7 +203,7 @@ fn main(_argc: isize, _argv: *const *const u8) -> ! {
 
             boot_info
                 .pages
-                .push((cur, PhysicalMemoryType::Reserved))
+                .push((cur, hole_page_type(cur)))
                 .expect("Too many pages");
             cur += PAGE_SIZE as u64;
         }
@@ -225,11 +229,24 @@ fn main(_argc: isize, _argv: *const *const u8) -> ! {
 
         boot_info
             .pages
-            .push((cur, PhysicalMemoryType::Reserved))
+            .push((cur, hole_page_type(cur)))
             .expect("Too many pages");
         cur += PAGE_SIZE as u64;
     }
 
+    // The firmware reserves the ECAM windows in the memory map. Hand them
+    // to the kernel as device memory so dom0 can map configuration space.
+    for region in boot_info.ecam_regions.iter() {
+        let mut cur = region.base;
+        while cur < region.base + region.size() {
+            match boot_info.pages.get_mut((cur / PAGE_SIZE as u64) as usize) {
+                Some(page) => page.1 = PhysicalMemoryType::Mmio,
+                None => break,
+            }
+            cur += PAGE_SIZE as u64;
+        }
+    }
+
     log::info!("ALoader: Num of free pages --> {:?}", free_page_count);
 
     debugger::on_ready();
@@ -427,6 +444,18 @@ fn cpu_has_pcid() -> bool {
     (cpuid_1.ecx & (1 << 17)) != 0
 }
 
+/// Type of a page that is not covered by the memory map.
+///
+/// Holes above 1 MiB are where PCI BARs live, so they are handed to the
+/// kernel as device memory that dom0 may map or pass on to drivers.
+fn hole_page_type(paddr: u64) -> PhysicalMemoryType {
+    if paddr < 0x10_0000 || APIC_WINDOW.contains(&paddr) {
+        PhysicalMemoryType::Reserved
+    } else {
+        PhysicalMemoryType::Mmio
+    }
+}
+
 /// The kernel panic handler.
 #[cfg(not(test))]
 #[panic_handler]
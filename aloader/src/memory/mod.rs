@@ -67,6 +67,7 @@ impl From<BootMemoryType> for PhysicalMemoryType {
             BMT::Other(AcpiMemoryType::Memory) => PMT::Available,
 
             BMT::Bios => PMT::Reserved,
+            BMT::Pci => PMT::Mmio,
             _ => PMT::Reserved,
         }
     }
@@ -6,6 +6,15 @@ use core::ptr;
 use crate::heapless::Vec as ArrayVec;
 use crate::string::ArrayString;
 
+/// Maximum number of IOMMU remapping units passed to the kernel.
+pub const MAX_IOMMU_UNITS: usize = 8;
+
+/// Maximum number of device scopes of one IOMMU remapping unit.
+pub const MAX_IOMMU_SCOPES: usize = 32;
+
+/// Maximum number of ECAM regions passed to the kernel.
+pub const MAX_ECAM_REGIONS: usize = 4;
+
 /// The type of physical memory.
 ///
 /// This is a simplified version of `BootMemoryType` in aloader.
@@ -26,6 +35,9 @@ pub enum PhysicalMemoryType {
 
     /// Reserved for other use.
     Reserved,
+
+    /// Memory-mapped device registers.
+    Mmio,
 }
 
 /// Boot manager handoff information.
@@ -50,8 +62,111 @@ pub struct BootInfo {
     /// Whether Tagged TLB is enabled.
     pub pcide: bool,
 
-    /// The base of the IOMMU remapping hardware registers.
-    pub iommu_base: Option<u64>,
+    /// The IOMMU remapping hardware units from the DMAR table.
+    pub iommu_units: ArrayVec<IommuUnit, MAX_IOMMU_UNITS>,
+
+    /// The PCIe enhanced configuration regions from the MCFG table.
+    pub ecam_regions: ArrayVec<EcamRegion, MAX_ECAM_REGIONS>,
+}
+
+/// A memory-mapped PCIe configuration space region (ECAM).
+///
+/// The 4 KiB configuration space of `bus:device.function` is at
+/// `base + ((bus - start_bus) << 20 | device << 15 | function << 12)`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct EcamRegion {
+    /// The physical base of the region.
+    pub base: u64,
+
+    /// The PCI segment the region belongs to.
+    pub segment: u16,
+
+    /// The buses decoded by the region.
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl EcamRegion {
+    /// Returns the size of the region in bytes.
+    pub fn size(&self) -> u64 {
+        (self.end_bus as u64 - self.start_bus as u64 + 1) << 20
+    }
+}
+
+/// A DMA remapping hardware unit.
+#[repr(C)]
+#[derive(Debug, Clone)]
+pub struct IommuUnit {
+    /// The base of the remapping hardware registers.
+    pub base: u64,
+
+    /// The PCI segment the unit belongs to.
+    pub segment: u16,
+
+    /// Whether the unit also covers all devices of the segment that no
+    /// other unit lists in its scopes.
+    pub include_pci_all: bool,
+
+    /// The devices covered by the unit.
+    pub scopes: ArrayVec<IommuScope, MAX_IOMMU_SCOPES>,
+}
+
+/// A device scope of a remapping unit, with its path already resolved
+/// to a bus number.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct IommuScope {
+    /// The type of the scope.
+    pub kind: IommuScopeKind,
+
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+
+    /// The buses behind the device, for `IommuScopeKind::Bridge`.
+    pub secondary_bus: u8,
+    pub subordinate_bus: u8,
+}
+
+/// The type of a DMAR device scope.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(u8)]
+pub enum IommuScopeKind {
+    /// A PCI endpoint device.
+    Endpoint = 1,
+
+    /// A PCI-PCI bridge and all devices behind it.
+    Bridge = 2,
+
+    /// An I/O APIC.
+    IoApic = 3,
+
+    /// An HPET timer block.
+    Hpet = 4,
+
+    /// An ACPI namespace device.
+    Namespace = 5,
+}
+
+impl IommuScope {
+    /// Returns whether requests from `bus:device.function` belong to this scope.
+    pub fn covers(&self, bus: u8, device: u8, function: u8) -> bool {
+        match self.kind {
+            IommuScopeKind::Bridge => {
+                (self.bus, self.device, self.function) == (bus, device, function)
+                    || (self.secondary_bus..=self.subordinate_bus).contains(&bus)
+            }
+            _ => (self.bus, self.device, self.function) == (bus, device, function),
+        }
+    }
+}
+
+impl IommuUnit {
+    /// Returns whether one of the scopes lists `bus:device.function`.
+    pub fn covers(&self, bus: u8, device: u8, function: u8) -> bool {
+        self.scopes.iter().any(|scope| scope.covers(bus, device, function))
+    }
 }
 
 /// A loaded domain.
@@ -100,7 +215,8 @@ impl BootInfo {
             pml4: ptr::null(),
             pages: ArrayVec::new(),
             pcide: false,
-            iommu_base: None,
+            iommu_units: ArrayVec::new(),
+            ecam_regions: ArrayVec::new(),
         }
     }
 }
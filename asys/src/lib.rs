@@ -28,6 +28,139 @@ pub const __NR_MRESOLVE_IO: usize = 17;
 pub const __NR_SET_DEVICE_IOMMU: usize = 18;
 pub const __NR_INVALIDATE_IOTLB: usize = 19;
 pub const __NR_SEND_EMPTY_TRY_SCH: usize = 20;
+pub const __NR_MPROTECT: usize = 21;
+pub const __NR_MEM_USAGE: usize = 22;
+pub const __NR_DONATE_QUOTA: usize = 23;
+pub const __NR_RECLAIM_QUOTA: usize = 24;
+pub const __NR_MMAP_MMIO: usize = 25;
+pub const __NR_DUMP_ADDRESS_SPACE: usize = 26;
+pub const __NR_SEND_PCI: usize = 27;
+pub const __NR_RECEIVE_PCI: usize = 28;
+pub const __NR_IO_MUNMAP: usize = 29;
+pub const __NR_TAKE_IOMMU_FAULT: usize = 30;
+pub const __NR_SET_DEVICE_MSI: usize = 31;
+pub const __NR_CLEAR_DEVICE_IRQ: usize = 32;
+pub const __NR_SET_IOAPIC_IRQ: usize = 33;
+pub const __NR_BIND_IRQ: usize = 34;
+pub const __NR_WAIT_IRQ: usize = 35;
+pub const __NR_ACK_IRQ: usize = 36;
+pub const __NR_PCI_ECAM: usize = 37;
+pub const __NR_BIND_IOMMU_FAULT: usize = 38;
+pub const __NR_WAIT_IOMMU_FAULT: usize = 39;
+
+/// Memory type of a mapping, passed in the permission bits of `sys_mmap` and
+/// as the last argument of `sys_mmap_mmio`. These are the PWT and PCD bits of
+/// a page table entry, which the kernel's PAT maps to WB, WC and UC.
+pub const MEM_TYPE_WB: usize = 0;
+pub const MEM_TYPE_WC: usize = 1 << 3;
+pub const MEM_TYPE_UC: usize = (1 << 3) | (1 << 4);
+pub const MEM_TYPE_MASK: usize = (1 << 3) | (1 << 4);
+
+/// Memory usage as reported by `sys_mem_usage`.
+///
+/// The quota fields are what is left in the caller's container.
+/// `mapped_4k` is the number of 4k pages mapped by the calling process and
+/// `procs` the number of `ProcMemUsage` entries written.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MemUsage {
+    pub mem_4k: usize,
+    pub mem_2m: usize,
+    pub mem_1g: usize,
+    pub pcid: usize,
+    pub ioid: usize,
+    pub mapped_4k: usize,
+    pub procs: usize,
+}
+
+/// Pages mapped by one process of the caller's container.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ProcMemUsage {
+    pub pcid: usize,
+    pub mapped_4k: usize,
+}
+
+/// Bits of `MappingRange::perm`, laid out like the permission bits of
+/// `sys_mmap`. The memory type is reported with the `MEM_TYPE_*` bits.
+pub const MAP_WRITE: usize = 1 << 1;
+pub const MAP_NO_EXECUTE: usize = 1 << 63;
+
+/// Flags of `sys_send_pages`.
+///
+/// With `SEND_PAGES_READ_ONLY` the receiver gets a read-only mapping of the
+/// pages. `sys_mprotect` refuses to make it writable while the pages are
+/// shared.
+///
+/// With `SEND_PAGES_COPY_ON_WRITE` both the sender and the receiver keep
+/// read-only mappings, and whoever writes first gets a private copy of the
+/// page. Pages a device can reach cannot be sent copy-on-write, and
+/// copy-on-write pages cannot be mapped for a device. It cannot be combined
+/// with the other flags.
+///
+/// With `SEND_PAGES_MOVE` the pages are unmapped from the sender once the
+/// receiver has them. Only pages the sender maps exactly once, with no
+/// device mappings, can be moved.
+pub const SEND_PAGES_READ_ONLY: usize = 1 << 0;
+pub const SEND_PAGES_COPY_ON_WRITE: usize = 1 << 1;
+pub const SEND_PAGES_MOVE: usize = 1 << 2;
+
+/// A run of mapped pages as reported by `sys_dump_address_space`.
+///
+/// Consecutive pages are merged into one range when both their virtual and
+/// physical addresses are contiguous and their permissions match.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MappingRange {
+    pub va: usize,
+    pub pa: usize,
+    /// Number of pages in the range.
+    pub len: usize,
+    pub page_size: usize,
+    pub perm: usize,
+}
+
+/// A DMA fault raised by a device owned by the caller or by one of its
+/// children, as reported by `sys_take_iommu_fault`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct IommuFault {
+    /// The faulting device, encoded like `pci_bdf`.
+    pub bdf: usize,
+    /// Page-aligned DMA address the device tried to access.
+    pub address: u64,
+    /// VT-d fault reason code.
+    pub reason: u8,
+    pub write: bool,
+    /// Number of identical faults merged into this report.
+    pub count: usize,
+}
+
+/// An MSI routed through the interrupt remapping table, as returned by
+/// `sys_set_device_msi`. The driver writes `address` and `data` into the
+/// device's MSI or MSI-X capability.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MsiMessage {
+    /// Handle of the interrupt, see `sys_bind_irq`.
+    pub handle: usize,
+    pub address: u64,
+    pub data: u32,
+    /// Vector the interrupt is delivered on.
+    pub vector: u8,
+}
+
+/// A memory-mapped PCIe configuration space region, as returned by
+/// `sys_pci_ecam`. The configuration space of `bus:device.function` is at
+/// `base + ((bus - start_bus) << 20 | device << 15 | function << 12)`.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PciEcam {
+    pub base: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
 
 macro_rules! syscall {
     ($nr:expr, $a:expr, $b:expr, $c:expr) => {{
@@ -92,6 +225,10 @@ pub unsafe fn sys_mmap(va:usize, perm_bits:usize, range:usize) -> usize {
     return syscall!(__NR_MMAP,va,perm_bits,range) as usize;
 }
 
+pub unsafe fn sys_mprotect(va:usize, perm_bits:usize, range:usize) -> usize {
+    return syscall!(__NR_MPROTECT,va,perm_bits,range) as usize;
+}
+
 pub unsafe fn sys_mresolve(va:usize) -> (usize,usize) {
     let va_masked = va & 0xFFFFFFFFFFFFF000u64 as usize;
     let _low_bits = va & 0xFFFu64 as usize;
@@ -116,9 +253,9 @@ pub unsafe fn sys_new_proc(endpoint_index:usize, ip:usize, sp:usize, va: usize,
     return syscall!(__NR_NEW_PROC,endpoint_index,ip, sp, va, range) as usize;
 }
 
-// pub unsafe fn sys_new_proc_with_iommu(endpoint_index:usize, ip:usize) -> usize{
-//     return syscall!(__NR_NEW_PROC_W_IO,endpoint_index,ip,0) as usize;
-// }
+pub unsafe fn sys_new_proc_with_iommu(endpoint_index:usize, ip:usize, sp:usize) -> usize{
+    return syscall!(__NR_NEW_PROC_W_IO,endpoint_index,ip,sp) as usize;
+}
 
 pub unsafe fn sys_new_thread(endpoint_index:usize, ip:usize, sp:usize) -> usize{
     return syscall!(__NR_NEW_THREAD,endpoint_index,ip,sp) as usize;
@@ -136,32 +273,47 @@ pub unsafe fn sys_receive_empty(endpoint_index:usize) -> usize{
     return syscall!(__NR_RECEIVE_EMPTY,endpoint_index,0,0) as usize;
 }
 
-// pub unsafe fn sys_new_proc_with_iommu_pass_mem(endpoint_index:usize, ip: usize, sp: usize, va: usize, range:usize) -> usize{
-//     return syscall!(__NR_NEW_PROC_W_IO_MEM,endpoint_index,ip,sp,va,range) as usize;
-// }
+pub unsafe fn sys_new_proc_with_iommu_pass_mem(endpoint_index:usize, ip: usize, sp: usize, va: usize, range:usize) -> usize{
+    return syscall!(__NR_NEW_PROC_W_IO_MEM,endpoint_index,ip,sp,va,range) as usize;
+}
 
 // pub unsafe fn sys_send_pages_no_wait(endpoint_index:usize, va: usize, range:usize) -> usize{
 //     return syscall!(__NR_SEND_PAGE_NW,endpoint_index,va,range) as usize;
 // }
 
-// pub unsafe fn sys_send_pages(endpoint_index:usize, va: usize, range:usize) -> usize{
-//     return syscall!(__NR_SEND_PAGE,endpoint_index,va,range) as usize;
-// }
+/// Share `range` pages at `va` with the thread receiving on the endpoint,
+/// blocking until it does. `flags` is a combination of `SEND_PAGES_*`.
+pub unsafe fn sys_send_pages(endpoint_index:usize, va: usize, range:usize, flags: usize) -> usize{
+    return syscall!(__NR_SEND_PAGE,endpoint_index,va,range,flags) as usize;
+}
 
-// pub unsafe fn sys_receive_pages(endpoint_index:usize, va: usize, range:usize) -> usize{
-//     return syscall!(__NR_RECEIVE_PAGE,endpoint_index,va,range) as usize;
-// }
+/// Receive `range` pages at `va` over the endpoint, blocking until they are
+/// sent.
+pub unsafe fn sys_receive_pages(endpoint_index:usize, va: usize, range:usize) -> usize{
+    return syscall!(__NR_RECEIVE_PAGE,endpoint_index,va,range) as usize;
+}
 
 pub unsafe fn sys_io_mmap(va:usize, perm_bits:usize, range:usize) -> usize {
     return syscall!(__NR_IO_MMAP,va,perm_bits,range) as usize;
 }
 
+/// Unmap `range` pages at `va` from the caller's IOMMU table. The IOTLB of the
+/// caller's IOMMU domain is flushed before the call returns.
+///
+/// Returns 1 if the range is not mapped. It also returns 1 if the flush could
+/// not be issued: the range is unmapped anyway, but the caller's devices are
+/// detached from its IOMMU table and must be bound again.
+pub unsafe fn sys_io_munmap(va:usize, range:usize) -> usize {
+    return syscall!(__NR_IO_MUNMAP,va,range,0) as usize;
+}
+
 pub unsafe fn sys_rd_io_cr3() -> usize {
     return syscall!(__NR_RD_IO_CR3,0,0,0) as usize;
 }
 
-pub unsafe fn sys_set_device_iommu(bus: usize, device: usize, function: usize, pml4: u64) -> isize {
-    syscall!(__NR_SET_DEVICE_IOMMU, bus, device, function, pml4)
+/// Bind a PCI device owned by the caller to the caller's IOMMU table.
+pub unsafe fn sys_set_device_iommu(bus: usize, device: usize, function: usize) -> isize {
+    syscall!(__NR_SET_DEVICE_IOMMU, bus, device, function)
 }
 
 pub unsafe fn sys_invalidate_iotlb(bus: usize, device: usize, function: usize, page: u64) -> isize {
@@ -169,4 +321,138 @@ pub unsafe fn sys_invalidate_iotlb(bus: usize, device: usize, function: usize, p
 }
 pub unsafe fn sys_send_empty_try_schedule(endpoint_index:usize) -> usize{
     return syscall!(__NR_SEND_EMPTY_TRY_SCH, endpoint_index, 0, 0) as usize;
-}
\ No newline at end of file
+}
+
+/// Fill `usage` and report per-process usage for up to `procs.len()`
+/// processes of the caller's container.
+pub unsafe fn sys_mem_usage(usage: &mut MemUsage, procs: &mut [ProcMemUsage]) -> usize {
+    return syscall!(
+        __NR_MEM_USAGE,
+        usage as *mut MemUsage,
+        procs.as_mut_ptr(),
+        procs.len()
+    ) as usize;
+}
+
+pub unsafe fn sys_donate_quota(child: usize, mem_4k: usize) -> usize {
+    return syscall!(__NR_DONATE_QUOTA, child, mem_4k, 0) as usize;
+}
+
+pub unsafe fn sys_reclaim_quota(child: usize, mem_4k: usize) -> usize {
+    return syscall!(__NR_RECLAIM_QUOTA, child, mem_4k, 0) as usize;
+}
+
+/// Map `len` pages of device memory starting at physical address `pa` to
+/// `va`. The pages must be reserved for the caller's container: dom0 starts
+/// out with all device memory, and the memory BARs of a PCI device move with
+/// it on `sys_send_pci`.
+/// `mem_type` is `MEM_TYPE_UC` or `MEM_TYPE_WC`.
+pub unsafe fn sys_mmap_mmio(va: usize, pa: usize, len: usize, mem_type: usize) -> usize {
+    return syscall!(__NR_MMAP_MMIO, va, pa, len, mem_type) as usize;
+}
+
+/// Encode a PCI address the way `sys_send_pci` and `sys_receive_pci` take it.
+pub const fn pci_bdf(bus: usize, device: usize, function: usize) -> usize {
+    (bus << 8) | (device << 3) | function
+}
+
+/// Hand a PCI device owned by the caller to the thread receiving it on
+/// `endpoint_index`. Blocks until a receiver waits for the same device. No
+/// process may map the device's BARs, otherwise the transfer fails.
+pub unsafe fn sys_send_pci(endpoint_index: usize, bus: usize, device: usize, function: usize) -> usize {
+    return syscall!(__NR_SEND_PCI, endpoint_index, pci_bdf(bus, device, function), 0) as usize;
+}
+
+/// Take over a PCI device sent on `endpoint_index`. The caller must have an
+/// IOMMU table and needs `sys_set_device_iommu` before the device can DMA.
+/// The device's memory BARs can then be mapped with `sys_mmap_mmio`.
+pub unsafe fn sys_receive_pci(endpoint_index: usize, bus: usize, device: usize, function: usize) -> usize {
+    return syscall!(__NR_RECEIVE_PCI, endpoint_index, pci_bdf(bus, device, function), 0) as usize;
+}
+
+/// Fill `buf` with up to `max` mapped ranges of the caller's address space,
+/// starting at `start_va`. Upper-half addresses are reported sign-extended.
+/// Returns the number of ranges written, or `usize::MAX` if `buf` is not
+/// writable for `max` entries. If it returns `max`, call again from the end
+/// of the last range to continue.
+pub unsafe fn sys_dump_address_space(start_va: usize, buf: *mut MappingRange, max: usize) -> usize {
+    return syscall!(__NR_DUMP_ADDRESS_SPACE, start_va, buf, max) as usize;
+}
+
+/// Take the oldest pending DMA fault raised by a device of the caller or of
+/// one of its children. With `quarantine` set, the device is also detached
+/// from its IOMMU table. Returns 0 when a fault was written to `fault`.
+///
+/// Faults of devices nobody owns are dropped. Once 64 faults are pending, a
+/// new one pushes out a fault whose device lost its owner, or else the oldest.
+pub unsafe fn sys_take_iommu_fault(fault: &mut IommuFault, quarantine: bool) -> usize {
+    return syscall!(__NR_TAKE_IOMMU_FAULT, fault as *mut IommuFault, quarantine as usize, 0) as usize;
+}
+
+/// Notify the caller's endpoint `endpoint_index` whenever a device of the
+/// caller or of one of its children raises a DMA fault. Binding again
+/// replaces the endpoint, and the binding lapses once the calling thread no
+/// longer holds it.
+pub unsafe fn sys_bind_iommu_fault(endpoint_index: usize) -> usize {
+    return syscall!(__NR_BIND_IOMMU_FAULT, endpoint_index, 0, 0) as usize;
+}
+
+/// Wait until a DMA fault can be taken with `sys_take_iommu_fault`. Needs an
+/// endpoint bound with `sys_bind_iommu_fault`.
+pub unsafe fn sys_wait_iommu_fault() -> usize {
+    return syscall!(__NR_WAIT_IOMMU_FAULT, 0, 0, 0) as usize;
+}
+
+/// Route MSIs of a device owned by the caller to `cpu`, which must belong to
+/// the caller's container. Returns 0 when `msg` was filled in.
+///
+/// The interrupt is revoked when the device is sent to another process with
+/// `sys_send_pci`, and when its owner is gone.
+pub unsafe fn sys_set_device_msi(bus: usize, device: usize, function: usize, cpu: usize, msg: &mut MsiMessage) -> usize {
+    return syscall!(__NR_SET_DEVICE_MSI, pci_bdf(bus, device, function), cpu, msg as *mut MsiMessage) as usize;
+}
+
+/// Block the device interrupt set up with `handle`.
+pub unsafe fn sys_clear_device_irq(handle: usize) -> usize {
+    return syscall!(__NR_CLEAR_DEVICE_IRQ, handle, 0, 0) as usize;
+}
+
+/// Route the legacy IOAPIC `pin` to `cpu`, which must belong to the
+/// caller's container. Returns 0 when the interrupt handle was written to
+/// `handle`. The pin stays masked until it is bound with `sys_bind_irq`.
+///
+/// `device` is a `(bus, device, function)` owned by the caller whose INTx
+/// line the firmware routed to `pin`. Only dom0 may pass `None`.
+pub unsafe fn sys_set_ioapic_irq(pin: usize, cpu: usize, device: Option<(usize, usize, usize)>, handle: &mut usize) -> usize {
+    let bdf = match device {
+        Some((bus, device, function)) => pci_bdf(bus, device, function),
+        None => usize::MAX,
+    };
+    return syscall!(__NR_SET_IOAPIC_IRQ, pin, cpu, handle as *mut usize, bdf) as usize;
+}
+
+/// Notify the caller's endpoint `endpoint_index` whenever the device
+/// interrupt `handle` fires.
+/// The binding lapses once the calling thread no longer holds the endpoint.
+pub unsafe fn sys_bind_irq(handle: usize, endpoint_index: usize) -> usize {
+    return syscall!(__NR_BIND_IRQ, handle, endpoint_index, 0) as usize;
+}
+
+/// Wait for the device interrupt `handle`. Interrupts that fire before the
+/// previous one is acknowledged are merged into a single notification.
+pub unsafe fn sys_wait_irq(handle: usize) -> usize {
+    return syscall!(__NR_WAIT_IRQ, handle, 0, 0) as usize;
+}
+
+/// Acknowledge the device interrupt `handle` once the device is serviced.
+/// A legacy pin stays masked until then.
+pub unsafe fn sys_ack_irq(handle: usize) -> usize {
+    return syscall!(__NR_ACK_IRQ, handle, 0, 0) as usize;
+}
+
+/// Fill `ecam` with the `index`-th PCIe ECAM region found by the loader.
+/// Returns 0 if the region exists. The region can then be mapped with
+/// `sys_mmap_mmio`.
+pub unsafe fn sys_pci_ecam(index: usize, ecam: &mut PciEcam) -> usize {
+    return syscall!(__NR_PCI_ECAM, index, ecam as *mut PciEcam, 0) as usize;
+}
@@ -58,7 +58,7 @@ pub struct Opts {
     #[clap(long)]
     nvme_img: Option<String>,
 
-    /// Passthru this host pci device
+    /// Passthru this host pci device, or emulate `virtio-net` or `virtio-blk:<image>`
     #[clap(long)]
     pci_dev: Vec<String>,
 
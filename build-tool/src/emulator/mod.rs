@@ -89,7 +89,8 @@ pub struct RunConfiguration {
     /// Image file for emulating nvme device
     nvme_img: Option<String>,
 
-    /// Host pci address to passthru to the guest kernel
+    /// Host pci address to passthru to the guest kernel, or `virtio-net` or
+    /// `virtio-blk:<image>` to emulate
     pci_dev: Vec<String>,
 }
 
@@ -164,7 +165,7 @@ impl RunConfiguration {
         self
     }
 
-    /// Set whether to use Qemu's Nvme emulation.
+    /// Set the pci devices to passthru or emulate.
     pub fn pci_dev(&mut self, pci_dev: Vec<String>) -> &mut Self {
         self.pci_dev = pci_dev;
         self
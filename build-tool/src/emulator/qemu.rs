@@ -134,7 +134,29 @@ impl Emulator for Qemu {
         }
 
         if !config.pci_dev.is_empty() {
-            for dev in &config.pci_dev {
+            for (i, dev) in config.pci_dev.iter().enumerate() {
+                if dev == "virtio-net" {
+                    // Emulated modern-only virtio-net behind QEMU's user
+                    // network stack, translated by the IOMMU like the
+                    // passthru devices
+                    command.args(&["-netdev", &format!("user,id=vnet{}", i)]);
+                    command.args(&[
+                        "-device",
+                        &format!("virtio-net-pci,netdev=vnet{},disable-legacy=on,iommu_platform=on", i),
+                    ]);
+                    continue;
+                }
+
+                if let Some(img_path) = dev.strip_prefix("virtio-blk:") {
+                    // Emulated modern-only virtio-blk backed by an image file
+                    command.args(&["-drive", &format!("file={},if=none,format=raw,id=vblk{}", img_path, i)]);
+                    command.args(&[
+                        "-device",
+                        &format!("virtio-blk-pci,drive=vblk{},disable-legacy=on,iommu_platform=on", i),
+                    ]);
+                    continue;
+                }
+
                 //command.args(&["-object", "iommufd,id=iommufd0"]);
                 command.args(&["-device", &format!("vfio-pci,host={}", dev)]);
                 //command.args(&["-device", &format!("vfio-pci,host={},iommufd=iommufd0", dev)]);
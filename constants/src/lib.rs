@@ -18,6 +18,17 @@ pub const USERSPACE_BASE: u64 = 0x80_0000_0000;
 
 pub const IOMMU_TEST_ADDR: u64 = 0xF0_0000_0000;
 
+// dom0 maps the PCIe configuration space (ECAM) here
+pub const PCI_ECAM_ADDR: u64 = 0xE0_0000_0000;
+// each ECAM region gets a window large enough for 256 buses
+pub const PCI_ECAM_REGION_SIZE: u64 = 256 << 20;
+
+// dom0 maps the BARs of the virtio devices it drives here, 256MiB apart
+pub const VIRTIO_MMIO_ADDR: u64 = 0xD0_0000_0000;
+
+// dom0 maps BAR 0 of the ixgbe virtual function it drives here
+pub const IXGBE_VF_MMIO_ADDR: u64 = 0xC0_0000_0000;
+
 const COM1: usize = 0x3f8;
 // d430 baremetal needs COM2
 const COM2: usize = 0x2f8;
@@ -1,6 +1,6 @@
 use x86::current::paging::{VAddr, PAddr};
 
-use aelf::{VirtualMapper, ContiguousMapping};
+use aelf::{VirtualMapper, ContiguousMapping, Error};
 use astd::memory::PageProtection;
 
 pub struct Mapper {
@@ -33,7 +33,7 @@ impl VirtualMapper for Mapper {
             panic!("Size {} is not aligned", size);
         }
 
-        match asys::sys_mmap(self.base, 0x0000_0000_0000_0002u64 as usize, size / 4096) {
+        match asys::sys_mmap(self.base, asys::MAP_WRITE, size / 4096) {
             0 => {
                 ContiguousMapping {
                     vaddr: VAddr(self.base as u64),
@@ -45,4 +45,22 @@ impl VirtualMapper for Mapper {
             }
         }
     }
+
+    unsafe fn protect(&mut self, base: VAddr, size: usize, protection: PageProtection) -> Result<(), Error> {
+        let mut perm_bits = 0;
+        if protection.write {
+            perm_bits |= asys::MAP_WRITE;
+        }
+        if !protection.execute {
+            perm_bits |= asys::MAP_NO_EXECUTE;
+        }
+
+        match asys::sys_mprotect(base.0 as usize, perm_bits, size / 4096) {
+            0 => Ok(()),
+            error_code => {
+                log::error!("sys_mprotect failure error_code {} base {:x} size {}", error_code, base.0, size);
+                Err(Error::ProtectFailed)
+            }
+        }
+    }
 }
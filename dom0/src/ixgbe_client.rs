@@ -1,12 +1,56 @@
 use crate::USERSPACE_BASE;
+use asys::MappingRange;
 use ixgbe_driver::device::IxgbeDevice;
+use ixgbe_driver::vf::IxgbeVfDevice;
 use libtime::rdtsc;
 use libtime::sys_ns_loopsleep;
 use pcid::utils::PciBarAddr;
 
 use ring_buffer::*;
+use crate::pci::PCI_TREE;
 use crate::*;
+use alloc::collections::VecDeque;
+use alloc::vec;
 use core::ptr;
+use spin::Mutex;
+
+const IXGBE_VENDOR_ID: u16 = 0x8086;
+const IXGBE_82599_SFP_ID: u16 = 0x10fb;
+
+/// Locally administered MAC addresses handed to the VFs, one per VF.
+const IXGBE_VF_MACS: [[u8; 6]; 2] = [
+    [0x02, 0x00, 0x00, 0x00, 0x00, 0x01],
+    [0x02, 0x00, 0x00, 0x00, 0x00, 0x02],
+];
+
+const VF_ENDPOINT: usize = 2;
+const VF_STACK_SIZE: usize = 4 * 1024 * 1024;
+const VF_RX_BUFFER_SIZE: usize = 2048;
+const VF_TEST_PACKETS: usize = 32;
+const VF_MAX_HEAP_RUNS: usize = 16;
+const ONE_MS_IN_NS: u64 = 1_000_000;
+
+/// What the driver process of a VF starts from, prepared by dom0 before it spawns the process.
+///
+/// The driver process shares dom0's image but has an IOMMU table of its own. It does not
+/// allocate: dom0 shares the heap pages listed in `heap`, which hold the device state and
+/// packet buffers built here, and the driver maps them for the VF.
+struct VfDriver {
+    bus: u8,
+    dev: u8,
+    func: u8,
+    /// Physical BAR 0 of the VF. The driver maps it at `IXGBE_VF_MMIO_ADDR`.
+    bar: PciBarAddr,
+    heap: [MappingRange; VF_MAX_HEAP_RUNS],
+    heap_runs: usize,
+    vf_dev: IxgbeVfDevice,
+    rx_buffers: VecDeque<Vec<u8>>,
+    tx_queue: VecDeque<Vec<u8>>,
+    done: VecDeque<Vec<u8>>,
+    received: VecDeque<Vec<u8>>,
+}
+
+static VF_DRIVER: Mutex<Option<VfDriver>> = Mutex::new(None);
 
 // use crate::maglev::*;
 
@@ -21,6 +65,248 @@ pub fn test_ixgbe_driver() {
     ixgbe_driver::ixgbe_test::run_fwd_udptest(&mut ixgbe_dev, 64, false);
 }
 
+/// Bring up the 82599 PF in SR-IOV mode with one VF per entry of `IXGBE_VF_MACS`, and hand
+/// VF 0 to a driver process with its own IOMMU table while this thread serves the VF mailbox.
+/// Never returns once the driver process is running.
+pub fn test_ixgbe_sriov() {
+    let (pf_addr, sriov) = {
+        let tree = PCI_TREE.lock();
+        let pf = match tree
+            .find_by_id(IXGBE_VENDOR_ID, Some(IXGBE_82599_SFP_ID))
+            .next()
+        {
+            Some(pf) => pf,
+            None => {
+                log::info!("No 82599 NIC found");
+                return;
+            }
+        };
+        match pf.sriov() {
+            Some(sriov) => (pf.pci_addr, sriov),
+            None => {
+                log::info!("The 82599 NIC has no SR-IOV capability");
+                return;
+            }
+        }
+    };
+
+    let mut ixgbe_dev =
+        unsafe { IxgbeDevice::new(PciBarAddr::new(USERSPACE_BASE + 0xFE000000, 0x4000)) };
+
+    log::info!("Initializing Ixgbe driver with SR-IOV...");
+
+    ixgbe_dev.init();
+    ixgbe_dev.enable_sriov(&IXGBE_VF_MACS);
+    if let Err(e) = sriov.enable_vfs(&pf_addr, IXGBE_VF_MACS.len() as u16) {
+        log::info!("enable_vfs failed: {}", e);
+        return;
+    }
+    // VFs may ignore configuration requests for 100ms after being enabled
+    sys_ns_loopsleep(ONE_MS_IN_NS * 100);
+
+    let vf_addr = match sriov.vf_address(&pf_addr, 0) {
+        Ok(vf_addr) => vf_addr,
+        Err(e) => {
+            log::info!("No address for VF 0: {}", e);
+            return;
+        }
+    };
+    let vf_bar = match sriov.vf_bar(0, 0) {
+        Some(vf_bar) => vf_bar,
+        None => {
+            log::info!("VF BAR 0 is not assigned");
+            return;
+        }
+    };
+    let (bus, dev, func): (u8, u8, u8) = vf_addr.into();
+
+    // everything the driver process touches is allocated before its heap is handed over
+    let mut driver = VfDriver {
+        bus,
+        dev,
+        func,
+        bar: vf_bar,
+        heap: [MappingRange::default(); VF_MAX_HEAP_RUNS],
+        heap_runs: 0,
+        vf_dev: IxgbeVfDevice::new(unsafe { PciBarAddr::new(IXGBE_VF_MMIO_ADDR, vf_bar.size()) }),
+        rx_buffers: (0..VF_TEST_PACKETS)
+            .map(|_| Vec::with_capacity(VF_RX_BUFFER_SIZE))
+            .collect(),
+        tx_queue: (0..VF_TEST_PACKETS)
+            .map(|_| {
+                let mut frame = vec![0u8; 60];
+                frame[0..6].copy_from_slice(&[0xff; 6]);
+                // local experimental ethertype
+                frame[12..14].copy_from_slice(&[0x88, 0xb5]);
+                frame
+            })
+            .collect(),
+        done: VecDeque::with_capacity(VF_TEST_PACKETS),
+        received: VecDeque::with_capacity(VF_TEST_PACKETS),
+    };
+    let (heap_start, heap_end) = crate::slab_alloc::heap_range();
+    let count = unsafe {
+        asys::sys_dump_address_space(heap_start, driver.heap.as_mut_ptr(), VF_MAX_HEAP_RUNS)
+    };
+    if count > VF_MAX_HEAP_RUNS {
+        log::info!("sys_dump_address_space failed {:?}", count);
+        return;
+    }
+    driver.heap_runs = driver.heap[..count]
+        .iter()
+        .take_while(|run| run.va < heap_end)
+        .count();
+    if driver.heap_runs == VF_MAX_HEAP_RUNS {
+        log::info!("The heap is split into too many ranges to hand over");
+        return;
+    }
+    let heap = driver.heap;
+    let heap_runs = driver.heap_runs;
+    *VF_DRIVER.lock() = Some(driver);
+
+    unsafe {
+        let error_code = asys::sys_new_endpoint(VF_ENDPOINT);
+        if error_code != 0 {
+            log::info!("sys_new_endpoint failed {:?}", error_code);
+            return;
+        }
+        let mut range = 0;
+        loop {
+            let (_pa, perm) = asys::sys_mresolve(0x8000000000usize + range * 4096);
+            if perm == 34 {
+                break;
+            }
+            range = range + 1;
+        }
+        let new_stack = 0x8000000000usize + range * 4096;
+        let error_code = asys::sys_mmap(new_stack, 0x2, VF_STACK_SIZE / 4096);
+        if error_code != 0 {
+            log::info!("sys_mmap for the VF driver stack failed {:?}", error_code);
+            return;
+        }
+        let rsp = new_stack + VF_STACK_SIZE;
+        let error_code = asys::sys_new_proc_with_iommu_pass_mem(
+            VF_ENDPOINT,
+            ixgbe_vf_main as *const () as usize,
+            rsp,
+            0x8000000000usize,
+            range + VF_STACK_SIZE / 4096,
+        );
+        if error_code != 0 {
+            log::info!("sys_new_proc_with_iommu_pass_mem failed {:?}", error_code);
+            return;
+        }
+
+        for run in &heap[..heap_runs] {
+            let error_code =
+                asys::sys_send_pages(VF_ENDPOINT, run.va, run.len * run.page_size / 4096, 0);
+            if error_code != 0 {
+                log::info!(
+                    "sys_send_pages for the VF driver heap failed {:?}",
+                    error_code
+                );
+                return;
+            }
+        }
+        let error_code = asys::sys_send_pci(VF_ENDPOINT, bus as usize, dev as usize, func as usize);
+        if error_code != 0 {
+            log::info!("sys_send_pci for the VF failed {:?}", error_code);
+            return;
+        }
+    }
+
+    // the VF driver cannot reset its function or learn its MAC without the PF
+    loop {
+        ixgbe_dev.handle_vf_mailbox();
+        sys_ns_loopsleep(ONE_MS_IN_NS);
+    }
+}
+
+/// Entry of the VF driver process: takes over its heap and VF 0 from dom0, binds the VF to its
+/// own IOMMU table and sends a burst of broadcast frames through it.
+fn ixgbe_vf_main() {
+    let mut driver = match VF_DRIVER.lock().take() {
+        Some(driver) => driver,
+        None => loop {},
+    };
+
+    unsafe {
+        for run in &driver.heap[..driver.heap_runs] {
+            let pages = run.len * run.page_size / 4096;
+            let error_code = asys::sys_receive_pages(0, run.va, pages);
+            if error_code != 0 {
+                log::info!(
+                    "sys_receive_pages for the VF driver heap failed {:?}",
+                    error_code
+                );
+                loop {}
+            }
+            let error_code = asys::sys_io_mmap(run.va, 0x2, pages);
+            if error_code != 0 {
+                log::info!("sys_io_mmap for the VF driver heap failed {:?}", error_code);
+                loop {}
+            }
+        }
+        let (bus, dev, func) = (
+            driver.bus as usize,
+            driver.dev as usize,
+            driver.func as usize,
+        );
+        let error_code = asys::sys_receive_pci(0, bus, dev, func);
+        if error_code != 0 {
+            log::info!("sys_receive_pci for the VF failed {:?}", error_code);
+            loop {}
+        }
+        let error_code = asys::sys_set_device_iommu(bus, dev, func);
+        if error_code != 0 {
+            log::info!("sys_set_device_iommu for the VF failed {:?}", error_code);
+            loop {}
+        }
+        let pages = (driver.bar.size() + 4095) / 4096;
+        let error_code = asys::sys_mmap_mmio(
+            IXGBE_VF_MMIO_ADDR as usize,
+            driver.bar.base() as usize,
+            pages,
+            asys::MEM_TYPE_UC,
+        );
+        if error_code != 0 {
+            log::info!("sys_mmap_mmio for the VF BAR failed {:?}", error_code);
+            loop {}
+        }
+    }
+
+    let vf_dev = &mut driver.vf_dev;
+    if let Err(e) = vf_dev.init() {
+        log::info!("VF init failed: {}", e);
+        loop {}
+    }
+    while !vf_dev.link_up() {
+        sys_ns_loopsleep(ONE_MS_IN_NS * 10);
+    }
+
+    vf_dev.post_rx_buffers(&mut driver.rx_buffers);
+
+    let mac = vf_dev.get_mac_addr();
+    for frame in driver.tx_queue.iter_mut() {
+        frame[6..12].copy_from_slice(&mac);
+    }
+    while driver.done.len() < VF_TEST_PACKETS {
+        vf_dev.submit(&mut driver.tx_queue);
+        vf_dev.reap_tx(&mut driver.done);
+        vf_dev.poll_rx(&mut driver.received);
+    }
+
+    let (rx, tx) = vf_dev.get_stats();
+    log::info!(
+        "VF sent {} frames, received {}, counters rx {} tx {}",
+        driver.done.len(),
+        driver.received.len(),
+        rx,
+        tx
+    );
+    loop {}
+}
+
 // #[no_mangle]
 // pub fn test_ixgbe_with_ring_buffer_ap()-> ! {
 //     log::info!("hello from test_ixgbe_with_ring_buffer_ap");
@@ -13,6 +13,8 @@ mod ixgbe_client;
 mod nvme_client;
 mod pci;
 mod slab_alloc;
+mod virtio_blk_client;
+mod virtio_net_client;
 // mod elf;
 // mod dom1;
 // mod maglev;
@@ -29,6 +31,8 @@ use libtime::sys_ns_loopsleep;
 pub use log::info as println;
 use nvme_client::test_nvme_driver;
 use pci::scan_pci_devs;
+use virtio_blk_client::test_virtio_blk_driver;
+use virtio_net_client::test_virtio_net_driver;
 
 fn test_sleep() {
     log::trace!("Sleeping for 100 ns");
@@ -55,6 +59,7 @@ extern "C" fn main(payload_base: *mut u8, payload_size: usize) -> isize {
     unsafe {
         asys::sys_print("meow".as_ptr(), 4);
     }
+
     // test_mmap();
     // test_pingpong();
     // test_proc_pingpong();
@@ -106,6 +111,9 @@ extern "C" fn main(payload_base: *mut u8, payload_size: usize) -> isize {
     // // test_ixgbe_with_ring_buffer_tx();
 
     // test_ixgbe_driver(); // Commenting out ixgbe driver for now.
+    // test_ixgbe_sriov();
+    // test_virtio_net_driver();
+    // test_virtio_blk_driver();
     test_e810_driver();
     // test_nvme_driver();
 
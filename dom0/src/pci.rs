@@ -1,21 +1,24 @@
-use pcid::pci::{Pci, PciClass};
-use pcid::utils::PciDevice;
+use pcid::pci::PciClass;
+use pcid::utils::{PciBarAddr, PciDevice};
+use pcid::PciTree;
 
 use alloc::format;
-use alloc::vec::Vec;
+use constants::{PCI_ECAM_ADDR, PCI_ECAM_REGION_SIZE};
 use lazy_static::lazy_static;
 use spin::Mutex;
 
 lazy_static! {
-    pub static ref PCI_DEVICES: Mutex<Vec<PciDevice>> = Mutex::new(Vec::new());
+    pub static ref PCI_TREE: Mutex<PciTree> = Mutex::new(PciTree::default());
 }
 
-fn handle_parsed_header(pci_dev: &PciDevice) {
+fn handle_parsed_header(pci_dev: &PciDevice, depth: usize) {
     let header = &pci_dev.pci_hdr.hdr;
     let raw_class: u8 = header.class().into();
     let (bus_num, dev_num, func_num) = &pci_dev.pci_addr.into();
-    let mut string = format!(
-        "PCI {:>02X}/{:>02X}/{:>02X} {:>04X}:{:>04X} {:>02X}.{:>02X}.{:>02X}.{:>02X} {:?}",
+    let mut string = " ".repeat(2 * depth);
+    string.push_str(&format!(
+        "PCI {:>04X}/{:>02X}/{:>02X}/{:>02X} {:>04X}:{:>04X} {:>02X}.{:>02X}.{:>02X}.{:>02X} {:?}",
+        pci_dev.pci_addr.segment(),
         bus_num,
         dev_num,
         func_num,
@@ -26,7 +29,7 @@ fn handle_parsed_header(pci_dev: &PciDevice) {
         header.interface(),
         header.revision(),
         header.class()
-    );
+    ));
 
     match header.class() {
         PciClass::Storage => match header.subclass() {
@@ -68,23 +71,70 @@ fn handle_parsed_header(pci_dev: &PciDevice) {
     log::info!("{}", string);
 }
 
+/// Map every ECAM region so pcid can reach the extended configuration space and the
+/// segments beyond the first.
+fn map_ecam() {
+    let mut ecam = asys::PciEcam::default();
+    let mut index = 0;
+    while unsafe { asys::sys_pci_ecam(index, &mut ecam) } == 0 {
+        let virt_base = PCI_ECAM_ADDR as usize + index * PCI_ECAM_REGION_SIZE as usize;
+        let pages = (ecam.end_bus as usize - ecam.start_bus as usize + 1) << 8;
+        let error_code =
+            unsafe { asys::sys_mmap_mmio(virt_base, ecam.base as usize, pages, asys::MEM_TYPE_UC) };
+        if error_code != 0 {
+            log::info!("sys_mmap_mmio for ECAM failed {:?}", error_code);
+        } else if let Err(e) =
+            unsafe { pcid::ecam::set_ecam(virt_base, ecam.segment, ecam.start_bus, ecam.end_bus) }
+        {
+            log::info!("Ignoring ECAM region {}: {}", index, e);
+        }
+        index += 1;
+    }
+
+    if index == 0 {
+        log::info!("No ECAM region, extended capabilities unavailable");
+    }
+}
+
 pub fn scan_pci_devs() {
-    //print!("PCI BS/DV/FN VEND:DEVI CL.SC.IN.RV\n");
+    //print!("PCI SEGM/BS/DV/FN VEND:DEVI CL.SC.IN.RV\n");
 
-    let pci = Pci::new();
-    let mut pci_devices = PCI_DEVICES.lock();
-    for bus in pci.buses() {
-        for dev in bus.devs() {
-            for func in dev.funcs() {
-                let func_num = func.num;
-                match pcid::utils::get_config(bus.num, dev.num, func_num) {
-                    Ok(pci_dev) => {
-                        handle_parsed_header(&pci_dev);
-                        pci_devices.push(pci_dev);
-                    }
-                    Err(_) => {}
-                }
+    map_ecam();
+
+    let tree = PciTree::enumerate();
+    for node in tree.nodes() {
+        handle_parsed_header(&node.device, node.depth);
+    }
+    *PCI_TREE.lock() = tree;
+}
+
+/// Map `bars` of `pci_dev` uncached, one after the other starting at `va`.
+///
+/// Returns the virtual mappings indexed by BAR number, the way drivers take them.
+pub fn map_bars<I: Iterator<Item = u8>>(
+    pci_dev: &PciDevice,
+    bars: I,
+    va: usize,
+) -> Option<[Option<PciBarAddr>; 6]> {
+    let mut mapped = [None; 6];
+    let mut va = va;
+    for bar in bars {
+        let phys = match pci_dev.pci_hdr.get_bar(bar as usize) {
+            Some(phys) if phys.size() > 0 => *phys,
+            _ => {
+                log::info!("BAR {} of {:?} is not a memory BAR", bar, pci_dev.pci_addr);
+                return None;
             }
+        };
+        let pages = (phys.size() + 4095) / 4096;
+        let error_code =
+            unsafe { asys::sys_mmap_mmio(va, phys.base() as usize, pages, asys::MEM_TYPE_UC) };
+        if error_code != 0 {
+            log::info!("sys_mmap_mmio for BAR {} failed {:?}", bar, error_code);
+            return None;
         }
+        mapped[bar as usize] = Some(unsafe { PciBarAddr::new(va as u64, phys.size()) });
+        va += pages * 4096;
     }
+    Some(mapped)
 }
@@ -15,7 +15,15 @@ static SLAB_ALLOC: SafeZoneAllocator = SafeZoneAllocator(Mutex::new(ZoneAllocato
 
 struct Pager;
 
-static BASE_ADDR: Mutex<usize> = Mutex::new(0xA0_0001_0000);
+const HEAP_START: usize = 0xA0_0001_0000;
+
+static BASE_ADDR: Mutex<usize> = Mutex::new(HEAP_START);
+
+/// Returns the virtual address range the heap has grown into so far. Pages
+/// skipped to align large pages are left unmapped.
+pub fn heap_range() -> (usize, usize) {
+    (HEAP_START, *BASE_ADDR.lock())
+}
 
 impl Pager {
     const BASE_PAGE_SIZE: usize = 4096;
@@ -0,0 +1,152 @@
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+use constants::VIRTIO_MMIO_ADDR;
+use interface::block::{BlockDevice, BlockOp, BlockReq, BlockResp};
+use libtime::sys_ns_loopsleep;
+use libvirtio::{modern_device_id, VirtioPciCaps, VIRTIO_ID_BLOCK, VIRTIO_PCI_VENDOR_ID};
+use virtio_blk_driver::device::VirtioBlkDevice;
+
+use crate::pci::{map_bars, PCI_TREE};
+
+/// Where the BARs of the virtio-blk device are mapped, after the virtio-net window.
+const VIRTIO_BLK_MMIO_ADDR: u64 = VIRTIO_MMIO_ADDR + 0x1000_0000;
+
+const BATCH_SIZE: usize = 32;
+const BLOCKS_PER_REQ: u16 = 8;
+const ONE_MS_IN_NS: u64 = 1_000_000;
+
+/// Bring up the virtio-blk device QEMU emulates with `--pci-dev virtio-blk:<image>`, then
+/// write a batch of blocks and read them back.
+///
+/// QEMU needs `--iommu` as well, so the device uses the IOMMU table dom0 binds it to.
+pub fn test_virtio_blk_driver() {
+    let tree = PCI_TREE.lock();
+    let pci_dev = match tree
+        .find_by_id(
+            VIRTIO_PCI_VENDOR_ID,
+            Some(modern_device_id(VIRTIO_ID_BLOCK)),
+        )
+        .next()
+    {
+        Some(pci_dev) => pci_dev,
+        None => {
+            log::info!("No virtio-blk device found");
+            return;
+        }
+    };
+
+    let caps = match VirtioPciCaps::read(pci_dev) {
+        Ok(caps) => caps,
+        Err(e) => {
+            log::info!("Not a modern virtio device: {:?}", e);
+            return;
+        }
+    };
+    let bars = match map_bars(pci_dev, caps.bars(), VIRTIO_BLK_MMIO_ADDR as usize) {
+        Some(bars) => bars,
+        None => return,
+    };
+
+    let (bus, dev, func): (u8, u8, u8) = pci_dev.pci_addr.into();
+    unsafe {
+        asys::sys_set_device_iommu(bus as usize, dev as usize, func as usize);
+    }
+
+    log::info!("Initializing virtio-blk driver...");
+
+    let mut blk_dev = match unsafe { VirtioBlkDevice::new(&bars, &caps) } {
+        Ok(blk_dev) => blk_dev,
+        Err(e) => {
+            log::info!("VirtioBlkDevice::new failed: {:?}", e);
+            return;
+        }
+    };
+    if let Err(e) = blk_dev.init() {
+        log::info!("virtio-blk init failed: {:?}", e);
+        return;
+    }
+
+    if blk_dev.read_only() {
+        log::info!("virtio-blk disk is read-only, skipping the read/write test");
+        return;
+    }
+
+    log::info!("Running virtio-blk read/write tests!");
+    run_blocktest_verify(&mut blk_dev);
+    log::info!("virtio-blk {}", blk_dev.stats);
+}
+
+/// Run `op` on consecutive blocks from the start of the disk, one request per buffer.
+///
+/// Returns whether every request completed successfully.
+fn run_batch(blk_dev: &mut dyn BlockDevice, buffers: &mut [Vec<u8>], op: BlockOp) -> bool {
+    let mut submit: VecDeque<BlockReq> = buffers
+        .iter_mut()
+        .enumerate()
+        .map(|(i, buf)| {
+            BlockReq::new(
+                (i * BLOCKS_PER_REQ as usize) as u64,
+                BLOCKS_PER_REQ,
+                buf.as_mut_ptr() as usize,
+                op,
+            )
+        })
+        .collect();
+    let mut collect: VecDeque<BlockResp> = VecDeque::new();
+
+    for _ in 0..1000 {
+        blk_dev.submit_and_poll_breq(&mut submit, &mut collect);
+        if submit.is_empty() && collect.len() == buffers.len() {
+            let failed = collect.iter().filter(|resp| resp.res != 0).count();
+            if failed != 0 {
+                log::info!(
+                    "{:?} batch: {} of {} requests failed",
+                    op,
+                    failed,
+                    buffers.len()
+                );
+                return false;
+            }
+            return true;
+        }
+        sys_ns_loopsleep(ONE_MS_IN_NS);
+    }
+
+    log::info!(
+        "{:?} batch timed out, {} of {} requests completed",
+        op,
+        collect.len(),
+        buffers.len()
+    );
+    false
+}
+
+/// Write a batch of blocks from the start of `blk_dev`, read them back and compare.
+pub fn run_blocktest_verify(blk_dev: &mut dyn BlockDevice) {
+    if blk_dev.capacity() < BATCH_SIZE as u64 * BLOCKS_PER_REQ as u64 {
+        log::info!("Block device too small, skipping the read/write test");
+        return;
+    }
+
+    let buf_size = BLOCKS_PER_REQ as usize * blk_dev.block_size();
+    let mut written: Vec<Vec<u8>> = (0..BATCH_SIZE)
+        .map(|i| (0..buf_size).map(|j| (i + j) as u8).collect())
+        .collect();
+    let mut read: Vec<Vec<u8>> = (0..BATCH_SIZE).map(|_| vec![0u8; buf_size]).collect();
+
+    if !run_batch(blk_dev, &mut written, BlockOp::Write)
+        || !run_batch(blk_dev, &mut read, BlockOp::Read)
+    {
+        return;
+    }
+
+    let mismatches = written.iter().zip(&read).filter(|(w, r)| w != r).count();
+    log::info!(
+        "Wrote and read back {} requests of {} bytes, {} mismatches ({})",
+        BATCH_SIZE,
+        buf_size,
+        mismatches,
+        blk_dev.get_stats()
+    );
+}
@@ -0,0 +1,129 @@
+use alloc::collections::VecDeque;
+use alloc::vec;
+use alloc::vec::Vec;
+use constants::VIRTIO_MMIO_ADDR;
+use libtime::sys_ns_loopsleep;
+use libvirtio::{modern_device_id, VirtioPciCaps, VIRTIO_ID_NET, VIRTIO_PCI_VENDOR_ID};
+use virtio_net_driver::device::VirtioNetDevice;
+
+use crate::pci::{map_bars, PCI_TREE};
+
+/// Where the BARs of the virtio-net device are mapped.
+const VIRTIO_NET_MMIO_ADDR: u64 = VIRTIO_MMIO_ADDR;
+
+const VIRTIO_NET_QUEUE_PAIRS: u16 = 2;
+
+const BATCH_SIZE: usize = 32;
+const RX_BUFFER_SIZE: usize = 2048;
+const ONE_MS_IN_NS: u64 = 1_000_000;
+
+// The QEMU user network stack answers ARP for its gateway
+const GUEST_IP: [u8; 4] = [10, 0, 2, 15];
+const GATEWAY_IP: [u8; 4] = [10, 0, 2, 2];
+
+/// Bring up the virtio-net device QEMU emulates with `--pci-dev virtio-net`, and exchange
+/// ARP packets with the user network stack on every queue pair.
+///
+/// QEMU needs `--iommu` as well, so the device uses the IOMMU table dom0 binds it to.
+pub fn test_virtio_net_driver() {
+    let tree = PCI_TREE.lock();
+    let pci_dev = match tree
+        .find_by_id(VIRTIO_PCI_VENDOR_ID, Some(modern_device_id(VIRTIO_ID_NET)))
+        .next()
+    {
+        Some(pci_dev) => pci_dev,
+        None => {
+            log::info!("No virtio-net device found");
+            return;
+        }
+    };
+
+    let caps = match VirtioPciCaps::read(pci_dev) {
+        Ok(caps) => caps,
+        Err(e) => {
+            log::info!("Not a modern virtio device: {:?}", e);
+            return;
+        }
+    };
+    let bars = match map_bars(pci_dev, caps.bars(), VIRTIO_NET_MMIO_ADDR as usize) {
+        Some(bars) => bars,
+        None => return,
+    };
+
+    let (bus, dev, func): (u8, u8, u8) = pci_dev.pci_addr.into();
+    unsafe {
+        asys::sys_set_device_iommu(bus as usize, dev as usize, func as usize);
+    }
+
+    log::info!("Initializing virtio-net driver...");
+
+    let mut net_dev = match unsafe { VirtioNetDevice::new(&bars, &caps) } {
+        Ok(net_dev) => net_dev,
+        Err(e) => {
+            log::info!("VirtioNetDevice::new failed: {:?}", e);
+            return;
+        }
+    };
+    if let Err(e) = net_dev.init(VIRTIO_NET_QUEUE_PAIRS) {
+        log::info!("virtio-net init failed: {:?}", e);
+        return;
+    }
+
+    for qid in 0..net_dev.num_queue_pairs() {
+        run_arp_test(&mut net_dev, qid);
+    }
+    log::info!("{}", net_dev.get_stats());
+}
+
+fn arp_request(mac: [u8; 6]) -> Vec<u8> {
+    let mut frame = vec![0u8; 60];
+    frame[0..6].copy_from_slice(&[0xff; 6]);
+    frame[6..12].copy_from_slice(&mac);
+    frame[12..14].copy_from_slice(&[0x08, 0x06]);
+    // Ethernet, IPv4, request
+    frame[14..22].copy_from_slice(&[0x00, 0x01, 0x08, 0x00, 6, 4, 0x00, 0x01]);
+    frame[22..28].copy_from_slice(&mac);
+    frame[28..32].copy_from_slice(&GUEST_IP);
+    frame[38..42].copy_from_slice(&GATEWAY_IP);
+    frame
+}
+
+fn run_arp_test(net_dev: &mut VirtioNetDevice, qid: usize) {
+    let mac = net_dev.get_mac_addr();
+
+    let mut rx_buffers: VecDeque<Vec<u8>> = (0..BATCH_SIZE)
+        .map(|_| Vec::with_capacity(RX_BUFFER_SIZE))
+        .collect();
+    let mut received = VecDeque::new();
+    net_dev.submit_and_poll_queue(qid, &mut rx_buffers, &mut received, false, false);
+
+    let mut packets: VecDeque<Vec<u8>> = (0..BATCH_SIZE).map(|_| arp_request(mac)).collect();
+    let mut sent = VecDeque::new();
+    let submitted = net_dev.submit_and_poll_queue(qid, &mut packets, &mut sent, true, false);
+
+    let mut replies = 0;
+    for _ in 0..100 {
+        sys_ns_loopsleep(ONE_MS_IN_NS);
+        net_dev.poll_queue(qid, &mut sent, true);
+        net_dev.poll_queue(qid, &mut received, false);
+
+        while let Some(packet) = received.pop_front() {
+            // ARP reply
+            if packet.len() >= 42 && packet[12..14] == [0x08, 0x06] && packet[21] == 2 {
+                replies += 1;
+            }
+        }
+        if sent.len() == submitted && replies > 0 {
+            break;
+        }
+    }
+
+    log::info!(
+        "virtio-net queue {}: sent {} of {} ARP requests, {} transmitted, {} replies",
+        qid,
+        submitted,
+        BATCH_SIZE,
+        sent.len(),
+        replies
+    );
+}
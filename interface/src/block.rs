@@ -0,0 +1,85 @@
+use alloc::collections::VecDeque;
+use core::fmt;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum BlockOp {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BlockReq {
+    pub block: u64,
+    pub num_blocks: u16,
+    pub data: usize,
+    pub op: BlockOp,
+}
+
+/// A completed request. `res` is 0 if it succeeded, a driver specific error code otherwise.
+#[derive(Debug, Clone, Copy)]
+pub struct BlockResp {
+    pub req: BlockReq,
+    pub res: i32,
+}
+
+impl BlockReq {
+    pub fn new(block: u64, num_blocks: u16, data: usize, op: BlockOp) -> BlockReq {
+        BlockReq {
+            block,
+            num_blocks,
+            data,
+            op,
+        }
+    }
+}
+
+impl BlockResp {
+    pub fn new(req: BlockReq, res: i32) -> Self {
+        BlockResp { req, res }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BlockStats {
+    pub submitted: u64,
+    pub completed: u64,
+}
+
+impl fmt::Display for BlockStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "submitted {} completed {}",
+            self.submitted, self.completed
+        )
+    }
+}
+
+/// A block device polled from a single queue.
+///
+/// `block` and `num_blocks` of a `BlockReq` count blocks of `block_size` bytes, and `data`
+/// is the address of a buffer large enough for all of them. The buffer belongs to the device
+/// until the request comes back as a `BlockResp` through `submit_and_poll_breq` or
+/// `poll_breq`.
+pub trait BlockDevice {
+    /// Submit the requests in `submit`, then move completed requests to `collect`.
+    ///
+    /// Requests the device has no room for are left in `submit`, requests it cannot serve at
+    /// all may complete right away with an error. Returns the number of submitted requests.
+    fn submit_and_poll_breq(
+        &mut self,
+        submit: &mut VecDeque<BlockReq>,
+        collect: &mut VecDeque<BlockResp>,
+    ) -> usize;
+
+    /// Move completed requests to `collect`, returning how many there were.
+    fn poll_breq(&mut self, collect: &mut VecDeque<BlockResp>) -> usize;
+
+    fn get_stats(&self) -> BlockStats;
+
+    /// Size in bytes of the blocks requests are made of.
+    fn block_size(&self) -> usize;
+
+    /// Size of the device in blocks.
+    fn capacity(&self) -> u64;
+}
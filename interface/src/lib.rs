@@ -0,0 +1,14 @@
+//! Interfaces shared by the device drivers.
+//!
+//! Every network driver implements `NetDevice` and every storage driver `BlockDevice`, so
+//! applications can be written once and run on whichever device the machine has.
+
+#![no_std]
+
+extern crate alloc;
+
+pub mod block;
+pub mod net;
+
+pub use block::BlockDevice;
+pub use net::NetDevice;
@@ -0,0 +1,49 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// Packet counters every network driver can report. Counters a device does not keep stay 0.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct NetStats {
+    pub tx_count: u64,
+    pub rx_count: u64,
+    /// Packets dropped because no receive buffer was available.
+    pub rx_missed: u64,
+}
+
+impl fmt::Display for NetStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "=> Tx stats: Count: {}\n=> Rx stats: Count: {} missed: {}",
+            self.tx_count, self.rx_count, self.rx_missed
+        )
+    }
+}
+
+/// A network device polled from a single queue.
+///
+/// Packets are plain `Vec<u8>`s. The driver owns the buffers it is given until they come back
+/// through `submit_and_poll` or `poll`, either transmitted or filled with a received packet.
+pub trait NetDevice {
+    /// Submit `packets` for transmission, or as empty receive buffers if `tx` is false, then
+    /// move the transmitted or received packets to `collect`.
+    ///
+    /// Packets the device has no room for are left in `packets`. Returns the number of
+    /// packets submitted when transmitting and the number received otherwise.
+    fn submit_and_poll(
+        &mut self,
+        packets: &mut VecDeque<Vec<u8>>,
+        collect: &mut VecDeque<Vec<u8>>,
+        tx: bool,
+    ) -> usize;
+
+    /// Move the transmitted packets, or the received ones if `tx` is false, to `collect`.
+    fn poll(&mut self, collect: &mut VecDeque<Vec<u8>>, tx: bool) -> usize;
+
+    fn get_stats(&self) -> NetStats;
+
+    fn mac_addr(&self) -> [u8; 6];
+
+    fn link_up(&self) -> bool;
+}
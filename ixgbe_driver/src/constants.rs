@@ -65,3 +65,40 @@ pub const IXGBE_GPIE_OCD: u64 = 0x00000020; /* Other Clear Disable */
 pub const IXGBE_GPIE_EIMEN: u64 = 0x00000040; /* Immediate Interrupt Enable */
 pub const IXGBE_GPIE_EIAME: u64 = 0x40000000;
 pub const IXGBE_GPIE_PBA_SUPPORT: u64 = 0x80000000;
+
+/* SR-IOV, physical function side */
+pub const IXGBE_GCR_EXT_VT_MODE_64: u64 = 0x00000003;
+pub const IXGBE_GPIE_VTMODE_MASK: u64 = 0x0000C000;
+pub const IXGBE_GPIE_VTMODE_64: u64 = 0x0000C000;
+pub const IXGBE_VT_CTL_VT_ENABLE: u64 = 0x00000001; /* Enable VT Mode */
+pub const IXGBE_VT_CTL_POOL_SHIFT: u64 = 7;
+pub const IXGBE_VT_CTL_REPLEN: u64 = 0x40000000; /* replication enabled */
+pub const IXGBE_MRQC_VMDQEN: u64 = 0x00000008;
+pub const IXGBE_MTQC_VT_ENA: u64 = 0x00000002;
+pub const IXGBE_MTQC_64VF: u64 = 0x00000008;
+pub const IXGBE_PFDTXGSWC_VT_LBEN: u64 = 0x00000001; /* Local L2 VT switch enable */
+pub const IXGBE_VMOLR_AUPE: u64 = 0x01000000; /* accept untagged packets */
+pub const IXGBE_VMOLR_BAM: u64 = 0x08000000; /* accept broadcast packets */
+pub const IXGBE_RAH_AV: u64 = 0x80000000; /* Address Valid */
+
+pub const IXGBE_PFMAILBOX_STS: u64 = 0x00000001; /* Initiate message send to VF */
+pub const IXGBE_PFMAILBOX_ACK: u64 = 0x00000002; /* Ack message recv'd from VF */
+pub const IXGBE_PFMAILBOX_PFU: u64 = 0x00000008; /* PF owns the mailbox buffer */
+
+/* SR-IOV, virtual function side */
+pub const IXGBE_VFMAILBOX_REQ: u32 = 0x00000001; /* Request for PF Ready bit */
+pub const IXGBE_VFMAILBOX_ACK: u32 = 0x00000002; /* Ack PF message received */
+pub const IXGBE_VFMAILBOX_VFU: u32 = 0x00000004; /* VF owns the mailbox buffer */
+pub const IXGBE_VFMAILBOX_PFSTS: u32 = 0x00000010; /* PF wrote a message in the MB */
+pub const IXGBE_VFMAILBOX_PFACK: u32 = 0x00000020; /* PF ack the previous VF msg */
+pub const IXGBE_VFMAILBOX_RSTI: u32 = 0x00000040; /* PF has reset indication */
+pub const IXGBE_VFMAILBOX_RSTD: u32 = 0x00000080; /* PF has indicated reset done */
+pub const IXGBE_VFMAILBOX_R2C_BITS: u32 = 0x000000B0; /* All read to clear bits */
+
+/* Mailbox messages between PF and VF, see ixgbe_mbx.h */
+pub const IXGBE_VF_MBX_SIZE: usize = 16; /* dwords */
+pub const IXGBE_VF_RESET: u32 = 0x01; /* VF requests reset */
+pub const IXGBE_VT_MSGTYPE_ACK: u32 = 0x80000000;
+pub const IXGBE_VT_MSGTYPE_NACK: u32 = 0x40000000;
+pub const IXGBE_VT_MSGTYPE_CTS: u32 = 0x20000000;
+pub const IXGBE_VT_MSG_MASK: u32 = 0x0000FFFF;
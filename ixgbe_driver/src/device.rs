@@ -9,6 +9,7 @@ use alloc::format;
 use alloc::vec::Vec;
 use core::cell::UnsafeCell;
 use core::{fmt, mem, ptr};
+use interface::net::{NetDevice, NetStats};
 use libdma::ixgbe::{allocate_dma, ixgbe_adv_rx_desc, ixgbe_adv_tx_desc, ixgbe_adv_tx_desc_read};
 use libdma::Dma;
 use libtime::sys_ns_loopsleep;
@@ -121,6 +122,10 @@ pub struct IxgbeDevice {
     pub nd_regs: IxgbeNonDmaRegs,
     dump: bool,
     rx_dump: bool,
+    /// MAC addresses of the SR-IOV virtual functions, empty unless `enable_sriov` was called.
+    vf_macs: Vec<[u8; 6]>,
+    /// Index of the rx/tx queue pair the PF uses, 0 unless `enable_sriov` moved it.
+    queue: u64,
 }
 
 fn wrap_ring(index: usize, ring_size: usize) -> usize {
@@ -153,6 +158,8 @@ impl IxgbeDevice {
             nd_regs: unsafe { IxgbeNonDmaRegs::new(bar) },
             dump: false,
             rx_dump: false,
+            vf_macs: Vec::new(),
+            queue: 0,
         }
     }
 
@@ -290,6 +297,171 @@ impl IxgbeDevice {
         self.write_reg_idx(IxgbeNoDmaArrayRegs::Rah, 0, high as u64);
     }
 
+    // section 4.6.10.1 - SR-IOV initialization
+    /// Switches the device to 64-pool virtualization mode for `vf_macs.len()` virtual
+    /// functions. VF `i` owns pool `i` and receives frames sent to `vf_macs[i]`. The PF takes
+    /// the pool after the last VF and moves its rings to the first queue of that pool, as queue
+    /// 0 now belongs to VF 0.
+    ///
+    /// Call this right after `init`, before the PF has sent or received anything, and before
+    /// enabling the VFs in the SR-IOV capability. Keep calling `handle_vf_mailbox` afterwards
+    /// so VF drivers can reset their functions.
+    pub fn enable_sriov(&mut self, vf_macs: &[[u8; 6]]) {
+        let num_vfs = vf_macs.len();
+        // the PF queue has to stay within the first 64 queues, see `IxgbeDmaRegs`
+        assert!(num_vfs > 0 && num_vfs < 32, "supports 1 to 31 VFs");
+        assert!(
+            self.transmit_index == 0 && self.receive_index == 0,
+            "enable SR-IOV before using the PF rings"
+        );
+        let pf_pool = num_vfs as u64;
+
+        // queue 0 belongs to VF 0 from now on
+        self.clear_qflag_idx(IxgbeDmaArrayRegs::Rxdctl, self.queue, IXGBE_RXDCTL_ENABLE);
+        self.clear_qflag_idx(IxgbeDmaArrayRegs::Txdctl, self.queue, IXGBE_TXDCTL_ENABLE);
+        self.clear_flag(IxgbeRegs::RXCTRL, IXGBE_RXCTRL_RXEN);
+
+        self.write_reg(IxgbeRegs::GCR_EXT, IXGBE_GCR_EXT_VT_MODE_64);
+        self.write_reg(
+            IxgbeRegs::GPIE,
+            (self.read_reg(IxgbeRegs::GPIE) & !IXGBE_GPIE_VTMODE_MASK) | IXGBE_GPIE_VTMODE_64,
+        );
+
+        // section 7.10.3.3 - 64 pools of 2 queues, no RSS
+        self.write_reg(IxgbeRegs::MRQC, IXGBE_MRQC_VMDQEN);
+        self.write_flag(IxgbeRegs::RTTDCS, IXGBE_RTTDCS_ARBDIS);
+        self.write_reg(IxgbeRegs::MTQC, IXGBE_MTQC_VT_ENA | IXGBE_MTQC_64VF);
+        self.clear_flag(IxgbeRegs::RTTDCS, IXGBE_RTTDCS_ARBDIS);
+
+        self.write_reg(
+            IxgbeRegs::PFVTCTL,
+            IXGBE_VT_CTL_VT_ENABLE | (pf_pool << IXGBE_VT_CTL_POOL_SHIFT) | IXGBE_VT_CTL_REPLEN,
+        );
+        self.set_pool_enabled(pf_pool, true);
+
+        // pool n owns queues 2n and 2n + 1
+        self.queue = 2 * pf_pool;
+        self.init_rx_inner();
+        self.init_tx_inner();
+        self.start_rx_queue(self.queue as u16);
+        self.start_tx_queue(self.queue as u16);
+
+        // let VFs talk to each other and to the PF without leaving the NIC
+        self.write_reg(IxgbeRegs::PFDTXGSWC, IXGBE_PFDTXGSWC_VT_LBEN);
+
+        // receive address 0 is the PF's own MAC
+        self.set_rar_pool(0, pf_pool);
+        for (vf, mac) in vf_macs.iter().enumerate() {
+            let rar = vf as u64 + 1;
+            let low = u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]);
+            let high = u32::from(mac[4]) | (u32::from(mac[5]) << 8);
+            self.write_reg_idx(IxgbeNoDmaArrayRegs::Ral, rar, low as u64);
+            self.write_reg_idx(IxgbeNoDmaArrayRegs::Rah, rar, high as u64 | IXGBE_RAH_AV);
+            self.set_rar_pool(rar, vf as u64);
+            self.write_reg_idx(
+                IxgbeNoDmaArrayRegs::Vmolr,
+                vf as u64,
+                IXGBE_VMOLR_AUPE | IXGBE_VMOLR_BAM,
+            );
+        }
+        self.vf_macs = vf_macs.to_vec();
+
+        self.write_flag(IxgbeRegs::RXCTRL, IXGBE_RXCTRL_RXEN);
+        println!(
+            "SR-IOV mode with {} VFs, PF pool {} queue {}",
+            num_vfs, pf_pool, self.queue
+        );
+    }
+
+    /// Routes frames matching receive address `rar` to `pool` only.
+    fn set_rar_pool(&self, rar: u64, pool: u64) {
+        let (low, high) = if pool < 32 {
+            (1 << pool, 0)
+        } else {
+            (0, 1 << (pool - 32))
+        };
+        self.write_reg_idx(IxgbeNoDmaArrayRegs::MpsarLo, rar, low);
+        self.write_reg_idx(IxgbeNoDmaArrayRegs::MpsarHi, rar, high);
+    }
+
+    /// Enables or disables rx and tx for all queues of `pool`.
+    fn set_pool_enabled(&self, pool: u64, enabled: bool) {
+        for reg in [IxgbeNoDmaArrayRegs::Pfvfre, IxgbeNoDmaArrayRegs::Pfvfte] {
+            let value = self.read_reg_idx(reg, pool / 32);
+            let bit = 1 << (pool % 32);
+            let value = if enabled { value | bit } else { value & !bit };
+            self.write_reg_idx(reg, pool / 32, value);
+        }
+    }
+
+    /// Serves pending mailbox requests of the virtual functions. Returns the number of
+    /// messages handled.
+    pub fn handle_vf_mailbox(&self) -> usize {
+        let mut handled = 0;
+
+        for vf in 0..self.vf_macs.len() as u64 {
+            let icr = self.read_reg_idx(IxgbeNoDmaArrayRegs::Pfmbicr, vf / 16);
+            let request = 1 << (vf % 16);
+            if icr & request == 0 {
+                continue;
+            }
+            // write 1 to clear
+            self.write_reg_idx(IxgbeNoDmaArrayRegs::Pfmbicr, vf / 16, request);
+
+            let msg = self.read_vf_message(vf);
+            match msg[0] & IXGBE_VT_MSG_MASK {
+                IXGBE_VF_RESET => {
+                    // the VF starts from scratch, enable its queues and tell it its MAC
+                    self.set_pool_enabled(vf, true);
+                    let mac = self.vf_macs[vf as usize];
+                    let reply = [
+                        IXGBE_VF_RESET | IXGBE_VT_MSGTYPE_ACK,
+                        u32::from_le_bytes([mac[0], mac[1], mac[2], mac[3]]),
+                        u32::from(mac[4]) | (u32::from(mac[5]) << 8),
+                        // multicast filter type, unused
+                        0,
+                    ];
+                    self.write_vf_message(vf, &reply);
+                    println!("VF {} reset", vf);
+                }
+                _ => {
+                    self.write_vf_message(
+                        vf,
+                        &[msg[0] | IXGBE_VT_MSGTYPE_NACK | IXGBE_VT_MSGTYPE_CTS],
+                    );
+                }
+            }
+            handled += 1;
+        }
+        handled
+    }
+
+    fn read_vf_message(&self, vf: u64) -> [u32; IXGBE_VF_MBX_SIZE] {
+        let mut msg = [0u32; IXGBE_VF_MBX_SIZE];
+        self.write_reg_idx(IxgbeNoDmaArrayRegs::Pfmailbox, vf, IXGBE_PFMAILBOX_PFU);
+        for (i, word) in msg.iter_mut().enumerate() {
+            let idx = vf * IXGBE_VF_MBX_SIZE as u64 + i as u64;
+            *word = self.read_reg_idx(IxgbeNoDmaArrayRegs::Pfmbmem, idx) as u32;
+        }
+        // releases the buffer and tells the VF we have read it
+        self.write_reg_idx(IxgbeNoDmaArrayRegs::Pfmailbox, vf, IXGBE_PFMAILBOX_ACK);
+        msg
+    }
+
+    fn write_vf_message(&self, vf: u64, msg: &[u32]) {
+        self.write_reg_idx(IxgbeNoDmaArrayRegs::Pfmailbox, vf, IXGBE_PFMAILBOX_PFU);
+        if self.read_reg_idx(IxgbeNoDmaArrayRegs::Pfmailbox, vf) & IXGBE_PFMAILBOX_PFU == 0 {
+            println!("VF {} mailbox busy, dropping reply", vf);
+            return;
+        }
+        for (i, word) in msg.iter().enumerate() {
+            let idx = vf * IXGBE_VF_MBX_SIZE as u64 + i as u64;
+            self.write_reg_idx(IxgbeNoDmaArrayRegs::Pfmbmem, idx, *word as u64);
+        }
+        // releases the buffer and interrupts the VF
+        self.write_reg_idx(IxgbeNoDmaArrayRegs::Pfmailbox, vf, IXGBE_PFMAILBOX_STS);
+    }
+
     // see section 4.6.4
     /// Initializes the link of this device.
     fn init_link(&self) {
@@ -643,7 +815,7 @@ impl IxgbeDevice {
     }
 
     pub fn init_rx_inner(&self) {
-        let i: u64 = 0;
+        let i = self.queue;
 
         // probably a broken feature, this flag is initialized with 1 but has to be set to 0
         self.clear_qflag_idx(IxgbeDmaArrayRegs::DcaRxctrl, i, 1 << 12);
@@ -695,7 +867,7 @@ impl IxgbeDevice {
     }
 
     pub fn init_tx_inner(&self) {
-        let i: u64 = 0;
+        let i = self.queue;
 
         // section 4.6.11.3.4 - set default buffer size allocations
         self.write_qreg_idx(IxgbeDmaArrayRegs::Txpbsize, 0, IXGBE_TXPBSIZE_40KB);
@@ -855,7 +1027,11 @@ impl IxgbeDevice {
 
         if sent > 0 {
             //self.bar.write_reg_tdt(0, self.transmit_index as u64);
-            self.write_qreg_idx(IxgbeDmaArrayRegs::Tdt, 0, self.transmit_index as u64);
+            self.write_qreg_idx(
+                IxgbeDmaArrayRegs::Tdt,
+                self.queue,
+                self.transmit_index as u64,
+            );
         }
 
         sent
@@ -957,8 +1133,8 @@ impl IxgbeDevice {
             }
         }
         println!("Found {} sent DDs", count);
-        let head = self.read_qreg_idx(IxgbeDmaArrayRegs::Tdh, 0);
-        let tail = self.read_qreg_idx(IxgbeDmaArrayRegs::Tdt, 0);
+        let head = self.read_qreg_idx(IxgbeDmaArrayRegs::Tdh, self.queue);
+        let tail = self.read_qreg_idx(IxgbeDmaArrayRegs::Tdt, self.queue);
 
         println!(
             "Tx ring {:16x} len {} HEAD {} TAIL {}\n",
@@ -1019,8 +1195,8 @@ impl IxgbeDevice {
         }
         println!("Found {} sent DDs", count);
 
-        let head = self.read_qreg_idx(IxgbeDmaArrayRegs::Rdh, 0);
-        let tail = self.read_qreg_idx(IxgbeDmaArrayRegs::Rdt, 0);
+        let head = self.read_qreg_idx(IxgbeDmaArrayRegs::Rdh, self.queue);
+        let tail = self.read_qreg_idx(IxgbeDmaArrayRegs::Rdt, self.queue);
 
         println!(
             "rx_index {} rx_clean_index {}\n",
@@ -1068,8 +1244,8 @@ impl IxgbeDevice {
             }
         }
         println!("Found {} sent DDs", count);
-        let head = self.read_qreg_idx(IxgbeDmaArrayRegs::Tdh, 0);
-        let tail = self.read_qreg_idx(IxgbeDmaArrayRegs::Tdt, 0);
+        let head = self.read_qreg_idx(IxgbeDmaArrayRegs::Tdh, self.queue);
+        let tail = self.read_qreg_idx(IxgbeDmaArrayRegs::Tdt, self.queue);
 
         println!(
             "Tx ring {:16x} len {} HEAD {} TAIL {}\n",
@@ -1130,8 +1306,8 @@ impl IxgbeDevice {
         }
         println!("Found {} rx DDs", count);
 
-        let head = self.read_qreg_idx(IxgbeDmaArrayRegs::Rdh, 0);
-        let tail = self.read_qreg_idx(IxgbeDmaArrayRegs::Rdt, 0);
+        let head = self.read_qreg_idx(IxgbeDmaArrayRegs::Rdh, self.queue);
+        let tail = self.read_qreg_idx(IxgbeDmaArrayRegs::Rdt, self.queue);
 
         println!(
             "rx_index {} rx_clean_index {}\n",
@@ -1157,8 +1333,8 @@ impl IxgbeDevice {
         println!("Rx descriptors\n");
         println!("=====================\n");
 
-        let head = self.read_qreg_idx(IxgbeDmaArrayRegs::Rdh, 0);
-        let tail = self.read_qreg_idx(IxgbeDmaArrayRegs::Rdt, 0);
+        let head = self.read_qreg_idx(IxgbeDmaArrayRegs::Rdh, self.queue);
+        let tail = self.read_qreg_idx(IxgbeDmaArrayRegs::Rdt, self.queue);
 
         println!(
             "Rx ring {:16x} len {} HEAD {} TAIL {}\n",
@@ -1206,8 +1382,8 @@ impl IxgbeDevice {
         println!("Tx descriptors\n");
         println!("=====================\n");
 
-        let head = self.read_qreg_idx(IxgbeDmaArrayRegs::Tdh, 0);
-        let tail = self.read_qreg_idx(IxgbeDmaArrayRegs::Tdt, 0);
+        let head = self.read_qreg_idx(IxgbeDmaArrayRegs::Tdh, self.queue);
+        let tail = self.read_qreg_idx(IxgbeDmaArrayRegs::Tdt, self.queue);
 
         println!(
             "Tx ring {:16x} len {} HEAD {} TAIL {}\n",
@@ -1409,12 +1585,12 @@ impl IxgbeDevice {
             if debug {
                 println!(
                     "Update tdt from {} to {}",
-                    self.read_qreg_idx(IxgbeDmaArrayRegs::Tdt, 0),
+                    self.read_qreg_idx(IxgbeDmaArrayRegs::Tdt, self.queue),
                     tx_index
                 );
             }
             //self.bar.write_reg_tdt(0, tx_index as u64);
-            self.write_qreg_idx(IxgbeDmaArrayRegs::Tdt, 0, tx_index as u64);
+            self.write_qreg_idx(IxgbeDmaArrayRegs::Tdt, self.queue, tx_index as u64);
             self.transmit_index = tx_index;
             self.tx_clean_index = tx_clean_index;
         }
@@ -1606,12 +1782,12 @@ impl IxgbeDevice {
             if debug {
                 println!(
                     "Update tdt from {} to {}",
-                    self.read_qreg_idx(IxgbeDmaArrayRegs::Tdt, 0),
+                    self.read_qreg_idx(IxgbeDmaArrayRegs::Tdt, self.queue),
                     tx_index
                 );
             }
             //self.bar.write_reg_tdt(0, tx_index as u64);
-            self.write_qreg_idx(IxgbeDmaArrayRegs::Tdt, 0, tx_index as u64);
+            self.write_qreg_idx(IxgbeDmaArrayRegs::Tdt, self.queue, tx_index as u64);
             self.transmit_index = tx_index;
             self.tx_clean_index = tx_clean_index;
         }
@@ -1789,12 +1965,12 @@ impl IxgbeDevice {
             if debug {
                 println!(
                     "Update rdt from {} to {}",
-                    self.read_qreg_idx(IxgbeDmaArrayRegs::Rdt, 0),
+                    self.read_qreg_idx(IxgbeDmaArrayRegs::Rdt, self.queue),
                     last_rx_index
                 );
                 println!("rx_index {} clean_index {}", rx_index, self.rx_clean_index);
             }
-            self.write_qreg_idx(IxgbeDmaArrayRegs::Rdt, 0, last_rx_index as u64);
+            self.write_qreg_idx(IxgbeDmaArrayRegs::Rdt, self.queue, last_rx_index as u64);
             self.receive_index = rx_index;
             self.rx_clean_index = rx_clean_index;
         }
@@ -1891,12 +2067,12 @@ impl IxgbeDevice {
             if debug {
                 println!(
                     "Update rdt from {} to {}",
-                    self.read_qreg_idx(IxgbeDmaArrayRegs::Rdt, 0),
+                    self.read_qreg_idx(IxgbeDmaArrayRegs::Rdt, self.queue),
                     last_rx_index
                 );
                 println!("rx_index {} clean_index {}", rx_index, self.rx_clean_index);
             }
-            self.write_qreg_idx(IxgbeDmaArrayRegs::Rdt, 0, last_rx_index as u64);
+            self.write_qreg_idx(IxgbeDmaArrayRegs::Rdt, self.queue, last_rx_index as u64);
             self.receive_index = rx_index;
             self.rx_clean_index = rx_clean_index;
         }
@@ -2065,10 +2241,10 @@ impl IxgbeDevice {
 
         if tx_index != last_tx_index {
             if debug {
-                // println!("Update tdt from {} to {}", self.read_qreg_idx(IxgbeDmaArrayRegs::Tdt, 0), tx_index);
+                // println!("Update tdt from {} to {}", self.read_qreg_idx(IxgbeDmaArrayRegs::Tdt, self.queue), tx_index);
             }
             //self.bar.write_reg_tdt(0, tx_index as u64);
-            self.write_qreg_idx(IxgbeDmaArrayRegs::Tdt, 0, tx_index as u64);
+            self.write_qreg_idx(IxgbeDmaArrayRegs::Tdt, self.queue, tx_index as u64);
             self.transmit_index = tx_index;
             self.tx_clean_index = tx_clean_index;
         }
@@ -2247,12 +2423,12 @@ impl IxgbeDevice {
             if debug {
                 println!(
                     "Update rdt from {} to {}",
-                    self.read_qreg_idx(IxgbeDmaArrayRegs::Rdt, 0),
+                    self.read_qreg_idx(IxgbeDmaArrayRegs::Rdt, self.queue),
                     last_rx_index
                 );
                 println!("rx_index {} clean_index {}", rx_index, self.rx_clean_index);
             }
-            self.write_qreg_idx(IxgbeDmaArrayRegs::Rdt, 0, last_rx_index as u64);
+            self.write_qreg_idx(IxgbeDmaArrayRegs::Rdt, self.queue, last_rx_index as u64);
             self.receive_index = rx_index;
             self.rx_clean_index = rx_clean_index;
         }
@@ -2271,24 +2447,24 @@ impl IxgbeDevice {
         string.push_str(&format!("Receive regs:\n\tRXPBSIZE(0): {:08X} SRRCTL(0) {:08X}\n\tRDBAL(0) {:08X} RDBAH(0) {:08X} \
                                  \n\tRDLEN(0) {:08X} RDH(0) {:08X} RDT(0) {:08X}\n",
                                  self.read_qreg_idx(IxgbeDmaArrayRegs::Rxpbsize, 0) as u32,
-                                 self.read_qreg_idx(IxgbeDmaArrayRegs::Srrctl, 0) as u32,
-                                 self.read_qreg_idx(IxgbeDmaArrayRegs::Rdbal, 0) as u32,
-                                 self.read_qreg_idx(IxgbeDmaArrayRegs::Rdbah, 0) as u32,
-                                 self.read_qreg_idx(IxgbeDmaArrayRegs::Rdlen, 0) as u32,
-                                 self.read_qreg_idx(IxgbeDmaArrayRegs::Rdh, 0) as u32,
-                                 self.read_qreg_idx(IxgbeDmaArrayRegs::Rdt, 0) as u32));
+                                 self.read_qreg_idx(IxgbeDmaArrayRegs::Srrctl, self.queue) as u32,
+                                 self.read_qreg_idx(IxgbeDmaArrayRegs::Rdbal, self.queue) as u32,
+                                 self.read_qreg_idx(IxgbeDmaArrayRegs::Rdbah, self.queue) as u32,
+                                 self.read_qreg_idx(IxgbeDmaArrayRegs::Rdlen, self.queue) as u32,
+                                 self.read_qreg_idx(IxgbeDmaArrayRegs::Rdh, self.queue) as u32,
+                                 self.read_qreg_idx(IxgbeDmaArrayRegs::Rdt, self.queue) as u32));
 
         string.push_str(&format!(
             "Transmit regs:\n\tTXDCTL(0) {:08X} TXPBSIZE(0): {:08X}\n\t \
                                  TDBAL(0) {:08X} TDBAH(0) {:08X}\n\t \
                                  TDLEN(0) {:08X} TDH(0) {:08X} TDT(0) {:08X}\n",
-            self.read_qreg_idx(IxgbeDmaArrayRegs::Txdctl, 0) as u32,
+            self.read_qreg_idx(IxgbeDmaArrayRegs::Txdctl, self.queue) as u32,
             self.read_qreg_idx(IxgbeDmaArrayRegs::Txpbsize, 0) as u32,
-            self.read_qreg_idx(IxgbeDmaArrayRegs::Tdbal, 0) as u32,
-            self.read_qreg_idx(IxgbeDmaArrayRegs::Tdbah, 0) as u32,
-            self.read_qreg_idx(IxgbeDmaArrayRegs::Tdlen, 0) as u32,
-            self.read_qreg_idx(IxgbeDmaArrayRegs::Tdh, 0) as u32,
-            self.read_qreg_idx(IxgbeDmaArrayRegs::Tdt, 0) as u32
+            self.read_qreg_idx(IxgbeDmaArrayRegs::Tdbal, self.queue) as u32,
+            self.read_qreg_idx(IxgbeDmaArrayRegs::Tdbah, self.queue) as u32,
+            self.read_qreg_idx(IxgbeDmaArrayRegs::Tdlen, self.queue) as u32,
+            self.read_qreg_idx(IxgbeDmaArrayRegs::Tdh, self.queue) as u32,
+            self.read_qreg_idx(IxgbeDmaArrayRegs::Tdt, self.queue) as u32
         ));
 
         println!("{}", string);
@@ -2466,12 +2642,12 @@ impl IxgbeDevice {
             if debug {
                 println!(
                     "Update tdt from {} to {}",
-                    self.read_qreg_idx(IxgbeDmaArrayRegs::Tdt, 0),
+                    self.read_qreg_idx(IxgbeDmaArrayRegs::Tdt, self.queue),
                     tx_index
                 );
             }
             //self.bar.write_reg_tdt(0, tx_index as u64);
-            self.write_qreg_idx(IxgbeDmaArrayRegs::Tdt, 0, tx_index as u64);
+            self.write_qreg_idx(IxgbeDmaArrayRegs::Tdt, self.queue, tx_index as u64);
             self.transmit_index = tx_index;
             self.tx_clean_index = tx_clean_index;
         }
@@ -2705,12 +2881,12 @@ impl IxgbeDevice {
             if debug {
                 println!(
                     "Update rdt from {} to {}",
-                    self.read_qreg_idx(IxgbeDmaArrayRegs::Rdt, 0),
+                    self.read_qreg_idx(IxgbeDmaArrayRegs::Rdt, self.queue),
                     last_rx_index
                 );
                 println!("rx_index {} clean_index {}", rx_index, self.rx_clean_index);
             }
-            self.write_qreg_idx(IxgbeDmaArrayRegs::Rdt, 0, last_rx_index as u64);
+            self.write_qreg_idx(IxgbeDmaArrayRegs::Rdt, self.queue, last_rx_index as u64);
             self.receive_index = rx_index;
             self.rx_clean_index = rx_clean_index;
         }
@@ -2833,12 +3009,12 @@ impl IxgbeDevice {
             if debug {
                 println!(
                     "Update tdt from {} to {}",
-                    self.read_qreg_idx(IxgbeDmaArrayRegs::Tdt, 0),
+                    self.read_qreg_idx(IxgbeDmaArrayRegs::Tdt, self.queue),
                     tx_index
                 );
             }
             //self.bar.write_reg_tdt(0, tx_index as u64);
-            self.write_qreg_idx(IxgbeDmaArrayRegs::Tdt, 0, tx_index as u64);
+            self.write_qreg_idx(IxgbeDmaArrayRegs::Tdt, self.queue, tx_index as u64);
             self.transmit_index = tx_index;
         }
 
@@ -2985,11 +3161,11 @@ impl IxgbeDevice {
         if rx_index != self.receive_index {
             // println!(
             //     "Update rdt from {} to {}",
-            //     self.read_qreg_idx(IxgbeDmaArrayRegs::Rdt, 0),
+            //     self.read_qreg_idx(IxgbeDmaArrayRegs::Rdt, self.queue),
             //     rx_index
             // );
             // println!("rx_index {} clean_index {}", rx_index, self.rx_clean_index);
-            self.write_qreg_idx(IxgbeDmaArrayRegs::Rdt, 0, rx_index as u64);
+            self.write_qreg_idx(IxgbeDmaArrayRegs::Rdt, self.queue, rx_index as u64);
             self.receive_index = rx_index;
         }
 
@@ -3063,3 +3239,35 @@ impl IxgbeDevice {
         self.rx_clean_index = rx_clean_index;
     }
 }
+
+impl NetDevice for IxgbeDevice {
+    fn submit_and_poll(
+        &mut self,
+        packets: &mut VecDeque<Vec<u8>>,
+        collect: &mut VecDeque<Vec<u8>>,
+        tx: bool,
+    ) -> usize {
+        IxgbeDevice::submit_and_poll(self, packets, collect, tx, false)
+    }
+
+    fn poll(&mut self, collect: &mut VecDeque<Vec<u8>>, tx: bool) -> usize {
+        IxgbeDevice::poll(self, collect, tx)
+    }
+
+    fn get_stats(&self) -> NetStats {
+        let stats = IxgbeDevice::get_stats(self);
+        NetStats {
+            tx_count: stats.tx_count,
+            rx_count: stats.rx_count,
+            rx_missed: stats.rx_missed,
+        }
+    }
+
+    fn mac_addr(&self) -> [u8; 6] {
+        self.get_mac_addr()
+    }
+
+    fn link_up(&self) -> bool {
+        self.get_link_speed() != 0
+    }
+}
@@ -5,6 +5,8 @@ extern crate alloc;
 mod constants;
 pub mod device;
 pub mod ixgbe_test;
+pub mod nullnet;
 mod packettool;
 mod regs;
+pub mod vf;
 pub use log::info as println;
@@ -1,11 +1,10 @@
-use crate::NetworkStats;
-use alloc::boxed::Box;
 use alloc::collections::VecDeque;
 use alloc::vec::Vec;
-use interface::rref::RRefDeque;
-use interface::error::Result;
-use interface::rpc::RpcResult;
+use interface::net::{NetDevice, NetStats};
 
+/// A network device that completes every packet immediately, for measuring the cost of the
+/// layers above a driver.
+#[derive(Default)]
 pub struct NullNet {}
 
 impl NullNet {
@@ -14,55 +13,33 @@ impl NullNet {
     }
 }
 
-impl interface::net::Net for NullNet {
-    fn clone_net(&self) -> RpcResult<Box<dyn interface::net::Net>> {
-        Ok(box Self::new())
-    }
-
+impl NetDevice for NullNet {
     fn submit_and_poll(
-        &self,
+        &mut self,
         packets: &mut VecDeque<Vec<u8>>,
         collect: &mut VecDeque<Vec<u8>>,
         _tx: bool,
-    ) -> RpcResult<Result<usize>> {
+    ) -> usize {
         let ret = packets.len();
         while let Some(pkt) = packets.pop_front() {
             collect.push_back(pkt);
         }
-        Ok(Ok(ret))
-    }
-
-    fn submit_and_poll_rref(
-        &self,
-        mut packets: RRefDeque<[u8; 1514], 32>,
-        mut collect: RRefDeque<[u8; 1514], 32>,
-        _tx: bool,
-        _pkt_len: usize,
-    ) -> RpcResult<Result<(usize, RRefDeque<[u8; 1514], 32>, RRefDeque<[u8; 1514], 32>)>> {
-        while let Some(pkt) = packets.pop_front() {
-            collect.push_back(pkt);
-        }
-
-        Ok(Ok((collect.len(), packets, collect)))
+        ret
     }
 
-    fn poll(&self, _collect: &mut VecDeque<Vec<u8>>, _tx: bool) -> RpcResult<Result<usize>> {
-        Ok(Ok(0))
+    fn poll(&mut self, _collect: &mut VecDeque<Vec<u8>>, _tx: bool) -> usize {
+        0
     }
 
-    fn poll_rref(
-        &self,
-        collect: RRefDeque<[u8; 1514], 512>,
-        _tx: bool,
-    ) -> RpcResult<Result<(usize, RRefDeque<[u8; 1514], 512>)>> {
-        Ok(Ok((0, collect)))
+    fn get_stats(&self) -> NetStats {
+        NetStats::default()
     }
 
-    fn get_stats(&self) -> RpcResult<Result<NetworkStats>> {
-        Ok(Ok(NetworkStats::new()))
+    fn mac_addr(&self) -> [u8; 6] {
+        [0; 6]
     }
 
-    fn test_domain_crossing(&self) -> RpcResult<()> {
-        Ok(())
+    fn link_up(&self) -> bool {
+        true
     }
 }
@@ -76,6 +76,39 @@ pub enum IxgbeRegs {
     RFC = 0x040A8,
     ROC = 0x040AC,
     RJC = 0x040B0,
+    GCR_EXT = 0x11050,
+    PFVTCTL = 0x051B0,
+    MRQC = 0x05818,
+    MTQC = 0x08120,
+    PFDTXGSWC = 0x08220,
+}
+
+/// Registers of an 82599 virtual function. Only the first queue pair is listed.
+#[derive(Copy, Clone, Debug)]
+pub enum IxgbeVfRegs {
+    VFCTRL = 0x00000,
+    VFSTATUS = 0x00008,
+    VFLINKS = 0x00010,
+    VTEICR = 0x00100,
+    VTEIMS = 0x00108,
+    VTEIMC = 0x0010C,
+    VFMBMEM = 0x00200,
+    VFMAILBOX = 0x002FC,
+    VFRDBAL = 0x01000,
+    VFRDBAH = 0x01004,
+    VFRDLEN = 0x01008,
+    VFRDH = 0x01010,
+    VFSRRCTL = 0x01014,
+    VFRDT = 0x01018,
+    VFGPRC = 0x0101C,
+    VFRXDCTL = 0x01028,
+    VFTDBAL = 0x02000,
+    VFTDBAH = 0x02004,
+    VFTDLEN = 0x02008,
+    VFTDH = 0x02010,
+    VFTDT = 0x02018,
+    VFGPTC = 0x0201C,
+    VFTXDCTL = 0x02028,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -134,7 +167,8 @@ impl IxgbeDmaRegs {
     const RDLEN: u64 = 0x01008;
     const RDH: u64 = 0x01010;
     const RDT: u64 = 0x01018;
-    const SRRCTL: u64 = 0x02100;
+    // the alias at 0x01014 covers all 64 queues, the one at 0x02100 only the first 16
+    const SRRCTL: u64 = 0x01014;
     const RXPBSIZE: u64 = 0x03C00;
     const DCA_RXCTRL: u64 = 0x0100C;
     const RXDCTL: u64 = 0x01028;
@@ -157,10 +191,10 @@ impl IxgbeDmaRegs {
             bar,
             rdbal: ixgbe_dmareg_mult!(RDBAL, 64, 0x40),
             rdbah: ixgbe_dmareg_mult!(RDBAH, 64, 0x40),
-            rdlen: ixgbe_dmareg_mult!(RDLEN, 64, 0x60),
+            rdlen: ixgbe_dmareg_mult!(RDLEN, 64, 0x40),
             rdh: ixgbe_dmareg_mult!(RDH, 64, 0x40),
             rdt: ixgbe_dmareg_mult!(RDT, 64, 0x40),
-            srrctl: ixgbe_dmareg_mult!(SRRCTL, 16, 0x4),
+            srrctl: ixgbe_dmareg_mult!(SRRCTL, 64, 0x40),
             dca_rxctrl: ixgbe_dmareg_mult!(DCA_RXCTRL, 64, 0x40),
             rxpbsize: ixgbe_dmareg_mult!(RXPBSIZE, 8, 0x4),
             rxdctl: ixgbe_dmareg_mult!(RXDCTL, 64, 0x40),
@@ -237,11 +271,20 @@ impl IxgbeDmaRegs {
     }
 }
 
+#[derive(Copy, Clone, Debug)]
 pub enum IxgbeNoDmaArrayRegs {
     Qptc,
     Rxmpc,
     Ral,
     Rah,
+    MpsarLo,
+    MpsarHi,
+    Vmolr,
+    Pfvfre,
+    Pfvfte,
+    Pfmailbox,
+    Pfmbicr,
+    Pfmbmem,
 }
 
 pub struct IxgbeNonDmaRegs {
@@ -250,6 +293,14 @@ pub struct IxgbeNonDmaRegs {
     rxmpc: ArrayRegister,
     ral: ArrayRegister,
     rah: ArrayRegister,
+    mpsar_lo: ArrayRegister,
+    mpsar_hi: ArrayRegister,
+    vmolr: ArrayRegister,
+    pfvfre: ArrayRegister,
+    pfvfte: ArrayRegister,
+    pfmailbox: ArrayRegister,
+    pfmbicr: ArrayRegister,
+    pfmbmem: ArrayRegister,
 }
 
 impl IxgbeNonDmaRegs {
@@ -258,6 +309,17 @@ impl IxgbeNonDmaRegs {
 
     const RAL: u64 = 0x0A200;
     const RAH: u64 = 0x0A204;
+    const MPSAR_LO: u64 = 0x0A600;
+    const MPSAR_HI: u64 = 0x0A604;
+
+    const VMOLR: u64 = 0x0F000;
+    const PFVFRE: u64 = 0x051E0;
+    const PFVFTE: u64 = 0x08110;
+
+    const PFMAILBOX: u64 = 0x04B00;
+    const PFMBICR: u64 = 0x00710;
+    // 16 dwords per VF, index with vf * IXGBE_VF_MBX_SIZE + word
+    const PFMBMEM: u64 = 0x13000;
 
     pub unsafe fn new(bar: PciBarAddr) -> Self {
         IxgbeNonDmaRegs {
@@ -266,6 +328,14 @@ impl IxgbeNonDmaRegs {
             rxmpc: ixgbe_nodma_reg_mult!(RXMPC, 8, 0x4),
             ral: ixgbe_nodma_reg_mult!(RAL, 128, 0x8),
             rah: ixgbe_nodma_reg_mult!(RAH, 128, 0x8),
+            mpsar_lo: ixgbe_nodma_reg_mult!(MPSAR_LO, 128, 0x8),
+            mpsar_hi: ixgbe_nodma_reg_mult!(MPSAR_HI, 128, 0x8),
+            vmolr: ixgbe_nodma_reg_mult!(VMOLR, 64, 0x4),
+            pfvfre: ixgbe_nodma_reg_mult!(PFVFRE, 2, 0x4),
+            pfvfte: ixgbe_nodma_reg_mult!(PFVFTE, 2, 0x4),
+            pfmailbox: ixgbe_nodma_reg_mult!(PFMAILBOX, 64, 0x4),
+            pfmbicr: ixgbe_nodma_reg_mult!(PFMBICR, 4, 0x4),
+            pfmbmem: ixgbe_nodma_reg_mult!(PFMBMEM, 64 * 16, 0x4),
         }
     }
 
@@ -276,6 +346,14 @@ impl IxgbeNonDmaRegs {
             IxgbeNoDmaArrayRegs::Rxmpc => self.rxmpc,
             IxgbeNoDmaArrayRegs::Ral => self.ral,
             IxgbeNoDmaArrayRegs::Rah => self.rah,
+            IxgbeNoDmaArrayRegs::MpsarLo => self.mpsar_lo,
+            IxgbeNoDmaArrayRegs::MpsarHi => self.mpsar_hi,
+            IxgbeNoDmaArrayRegs::Vmolr => self.vmolr,
+            IxgbeNoDmaArrayRegs::Pfvfre => self.pfvfre,
+            IxgbeNoDmaArrayRegs::Pfvfte => self.pfvfte,
+            IxgbeNoDmaArrayRegs::Pfmailbox => self.pfmailbox,
+            IxgbeNoDmaArrayRegs::Pfmbicr => self.pfmbicr,
+            IxgbeNoDmaArrayRegs::Pfmbmem => self.pfmbmem,
         }
     }
     #[inline(always)]
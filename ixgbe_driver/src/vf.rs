@@ -0,0 +1,383 @@
+//! Driver for an 82599 SR-IOV virtual function.
+//!
+//! A VF owns one queue pair of the physical NIC. The PF driver in dom0 assigns its MAC address
+//! and enables its queues, see `IxgbeDevice::enable_sriov`. Everything else, including the
+//! descriptor rings, is set up here so the VF can be driven from its own container and IOMMU
+//! domain.
+
+use crate::constants::*;
+use crate::println;
+use crate::regs::IxgbeVfRegs;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::{mem, ptr};
+use libdma::ixgbe::{allocate_dma, ixgbe_adv_rx_desc, ixgbe_adv_tx_desc};
+use libdma::Dma;
+use libtime::sys_ns_loopsleep;
+use pcid::utils::PciBarAddr;
+
+const NUM_TX_DESCS: usize = 256;
+const NUM_RX_DESCS: usize = 256;
+
+const ONE_MS_IN_NS: u64 = 1_000_000;
+const MAILBOX_TIMEOUT_MS: u64 = 500;
+
+const IXGBE_SRRCTL_BSIZEPKT_2K: u32 = 2; /* in 1 KB units */
+const IXGBE_SRRCTL_DESCTYPE_ADV_ONEBUF: u32 = 0x02000000;
+const IXGBE_SRRCTL_DROP_EN: u32 = 0x10000000;
+const IXGBE_XDCTL_ENABLE: u32 = 0x02000000;
+const IXGBE_VFLINKS_UP: u32 = 0x40000000;
+
+const IXGBE_RXDADV_STAT_DD: u32 = 0x01;
+const IXGBE_RXDADV_STAT_EOP: u32 = 0x02;
+const IXGBE_ADVTXD_STAT_DD: u32 = 0x01;
+const IXGBE_ADVTXD_PAYLEN_SHIFT: u32 = 14;
+const IXGBE_ADVTXD_DCMD_EOP: u32 = 0x01000000;
+const IXGBE_ADVTXD_DCMD_IFCS: u32 = 0x02000000;
+const IXGBE_ADVTXD_DCMD_RS: u32 = 0x08000000;
+const IXGBE_ADVTXD_DCMD_DEXT: u32 = 0x20000000;
+const IXGBE_ADVTXD_DTYP_DATA: u32 = 0x00300000;
+
+pub struct IxgbeVfDevice {
+    bar: PciBarAddr,
+    mac: [u8; 6],
+    /// Read-to-clear bits of VFMAILBOX seen but not consumed yet.
+    mailbox_bits: u32,
+    transmit_buffers: [Option<Vec<u8>>; NUM_TX_DESCS],
+    transmit_ring: Dma<[ixgbe_adv_tx_desc; NUM_TX_DESCS]>,
+    receive_buffers: [Option<Vec<u8>>; NUM_RX_DESCS],
+    receive_ring: Dma<[ixgbe_adv_rx_desc; NUM_RX_DESCS]>,
+    transmit_index: usize,
+    transmit_clean_index: usize,
+    receive_index: usize,
+    receive_clean_index: usize,
+    /// Set while skipping the buffers of a frame that did not fit into one buffer.
+    rx_discarding: bool,
+}
+
+fn wrap_ring(index: usize, ring_size: usize) -> usize {
+    (index + 1) & (ring_size - 1)
+}
+
+impl IxgbeVfDevice {
+    /// `bar` is BAR 0 of the VF, see `SriovCapability::vf_bar`.
+    pub fn new(bar: PciBarAddr) -> IxgbeVfDevice {
+        IxgbeVfDevice {
+            bar,
+            mac: [0; 6],
+            mailbox_bits: 0,
+            transmit_buffers: array_init::array_init(|_| None),
+            transmit_ring: allocate_dma().unwrap(),
+            receive_buffers: array_init::array_init(|_| None),
+            receive_ring: allocate_dma().unwrap(),
+            transmit_index: 0,
+            transmit_clean_index: 0,
+            receive_index: 0,
+            receive_clean_index: 0,
+            rx_discarding: false,
+        }
+    }
+
+    /// Resets the VF, asks the PF for its MAC address and starts its queue pair.
+    pub fn init(&mut self) -> Result<(), &'static str> {
+        self.write_reg(IxgbeVfRegs::VTEIMC, 0xffff_ffff);
+        self.write_reg(IxgbeVfRegs::VFCTRL, IXGBE_CTRL_RST as u32);
+
+        // the PF acknowledges a function level reset through the mailbox
+        if !self.wait_mailbox(IXGBE_VFMAILBOX_RSTD | IXGBE_VFMAILBOX_RSTI) {
+            return Err("VF reset timed out");
+        }
+        self.mailbox_bits &= !(IXGBE_VFMAILBOX_RSTD | IXGBE_VFMAILBOX_RSTI);
+
+        self.write_message(&[IXGBE_VF_RESET])?;
+        let reply = self.read_message()?;
+        if reply[0] != IXGBE_VF_RESET | IXGBE_VT_MSGTYPE_ACK {
+            return Err("PF refused the VF reset");
+        }
+        let low = reply[1].to_le_bytes();
+        let high = reply[2].to_le_bytes();
+        self.mac = [low[0], low[1], low[2], low[3], high[0], high[1]];
+
+        println!(
+            "VF MAC: {:>02X}:{:>02X}:{:>02X}:{:>02X}:{:>02X}:{:>02X}",
+            self.mac[0], self.mac[1], self.mac[2], self.mac[3], self.mac[4], self.mac[5]
+        );
+
+        self.init_rx();
+        self.init_tx();
+        Ok(())
+    }
+
+    /// Returns the MAC address assigned by the PF.
+    pub fn get_mac_addr(&self) -> [u8; 6] {
+        self.mac
+    }
+
+    /// Returns whether the link of the physical port is up.
+    pub fn link_up(&self) -> bool {
+        self.read_reg(IxgbeVfRegs::VFLINKS) & IXGBE_VFLINKS_UP != 0
+    }
+
+    fn init_rx(&mut self) {
+        self.write_reg(IxgbeVfRegs::VFRXDCTL, 0);
+
+        let ring = self.receive_ring.physical() as u64;
+        self.write_reg(IxgbeVfRegs::VFRDBAL, ring as u32);
+        self.write_reg(IxgbeVfRegs::VFRDBAH, (ring >> 32) as u32);
+        self.write_reg(
+            IxgbeVfRegs::VFRDLEN,
+            (self.receive_ring.len() * mem::size_of::<ixgbe_adv_rx_desc>()) as u32,
+        );
+        self.write_reg(
+            IxgbeVfRegs::VFSRRCTL,
+            IXGBE_SRRCTL_BSIZEPKT_2K | IXGBE_SRRCTL_DESCTYPE_ADV_ONEBUF | IXGBE_SRRCTL_DROP_EN,
+        );
+        self.write_reg(IxgbeVfRegs::VFRDH, 0);
+        self.write_reg(IxgbeVfRegs::VFRDT, 0);
+
+        self.write_reg(IxgbeVfRegs::VFRXDCTL, IXGBE_XDCTL_ENABLE);
+        self.wait_reg(IxgbeVfRegs::VFRXDCTL, IXGBE_XDCTL_ENABLE);
+    }
+
+    fn init_tx(&mut self) {
+        self.write_reg(IxgbeVfRegs::VFTXDCTL, 0);
+
+        let ring = self.transmit_ring.physical() as u64;
+        self.write_reg(IxgbeVfRegs::VFTDBAL, ring as u32);
+        self.write_reg(IxgbeVfRegs::VFTDBAH, (ring >> 32) as u32);
+        self.write_reg(
+            IxgbeVfRegs::VFTDLEN,
+            (self.transmit_ring.len() * mem::size_of::<ixgbe_adv_tx_desc>()) as u32,
+        );
+        self.write_reg(IxgbeVfRegs::VFTDH, 0);
+        self.write_reg(IxgbeVfRegs::VFTDT, 0);
+
+        // same thresholds as the PF driver: pthresh 32, hthresh 1, wthresh 8
+        self.write_reg(
+            IxgbeVfRegs::VFTXDCTL,
+            IXGBE_XDCTL_ENABLE | (8 << 16) | (1 << 8) | 32,
+        );
+        self.wait_reg(IxgbeVfRegs::VFTXDCTL, IXGBE_XDCTL_ENABLE);
+    }
+
+    /// Queues `packets` for transmission. Returns the number of packets queued, the rest stay
+    /// in `packets`.
+    pub fn submit(&mut self, packets: &mut VecDeque<Vec<u8>>) -> usize {
+        let num_descriptors = self.transmit_ring.len();
+        let mut sent = 0;
+
+        while let Some(packet) = packets.pop_front() {
+            let next_index = wrap_ring(self.transmit_index, num_descriptors);
+            if next_index == self.transmit_clean_index {
+                packets.push_front(packet);
+                break;
+            }
+
+            let pkt_len = packet.len() as u32;
+            let pkt_addr = packet.as_ptr() as u64;
+            unsafe {
+                let desc =
+                    self.transmit_ring.as_ptr().add(self.transmit_index) as *mut ixgbe_adv_tx_desc;
+                ptr::write_volatile(&mut (*desc).read.buffer_addr, pkt_addr);
+                ptr::write_volatile(
+                    &mut (*desc).read.cmd_type_len,
+                    IXGBE_ADVTXD_DCMD_EOP
+                        | IXGBE_ADVTXD_DCMD_RS
+                        | IXGBE_ADVTXD_DCMD_IFCS
+                        | IXGBE_ADVTXD_DCMD_DEXT
+                        | IXGBE_ADVTXD_DTYP_DATA
+                        | pkt_len,
+                );
+                ptr::write_volatile(
+                    &mut (*desc).read.olinfo_status,
+                    pkt_len << IXGBE_ADVTXD_PAYLEN_SHIFT,
+                );
+            }
+            self.transmit_buffers[self.transmit_index] = Some(packet);
+            self.transmit_index = next_index;
+            sent += 1;
+        }
+
+        if sent > 0 {
+            self.write_reg(IxgbeVfRegs::VFTDT, self.transmit_index as u32);
+        }
+        sent
+    }
+
+    /// Moves sent packets to `reap_queue`. Returns the number of packets reaped.
+    pub fn reap_tx(&mut self, reap_queue: &mut VecDeque<Vec<u8>>) -> usize {
+        let num_descriptors = self.transmit_ring.len();
+        let mut reaped = 0;
+
+        while self.transmit_clean_index != self.transmit_index {
+            let status = unsafe {
+                let desc = self.transmit_ring.as_ptr().add(self.transmit_clean_index);
+                ptr::read_volatile(&(*desc).wb.status)
+            };
+            if status & IXGBE_ADVTXD_STAT_DD == 0 {
+                break;
+            }
+            if let Some(packet) = self.transmit_buffers[self.transmit_clean_index].take() {
+                reap_queue.push_back(packet);
+            }
+            self.transmit_clean_index = wrap_ring(self.transmit_clean_index, num_descriptors);
+            reaped += 1;
+        }
+        reaped
+    }
+
+    /// Hands empty `buffers` to the device for reception. Returns the number of buffers
+    /// posted, the rest stay in `buffers`.
+    pub fn post_rx_buffers(&mut self, buffers: &mut VecDeque<Vec<u8>>) -> usize {
+        let num_descriptors = self.receive_ring.len();
+        let mut posted = 0;
+
+        while let Some(buffer) = buffers.pop_front() {
+            let next_index = wrap_ring(self.receive_index, num_descriptors);
+            if next_index == self.receive_clean_index {
+                buffers.push_front(buffer);
+                break;
+            }
+
+            let buf_addr = buffer.as_ptr() as u64;
+            unsafe {
+                let desc =
+                    self.receive_ring.as_ptr().add(self.receive_index) as *mut ixgbe_adv_rx_desc;
+                ptr::write_volatile(&mut (*desc).read.pkt_addr, buf_addr);
+                ptr::write_volatile(&mut (*desc).read.hdr_addr, 0);
+            }
+            self.receive_buffers[self.receive_index] = Some(buffer);
+            self.receive_index = next_index;
+            posted += 1;
+        }
+
+        if posted > 0 {
+            self.write_reg(IxgbeVfRegs::VFRDT, self.receive_index as u32);
+        }
+        posted
+    }
+
+    /// Moves received packets to `reap_queue`. Returns the number of packets received. Frames
+    /// that span several buffers are dropped and their buffers freed.
+    pub fn poll_rx(&mut self, reap_queue: &mut VecDeque<Vec<u8>>) -> usize {
+        let num_descriptors = self.receive_ring.len();
+        let mut received = 0;
+
+        while self.receive_clean_index != self.receive_index {
+            let (status, length) = unsafe {
+                let desc = self.receive_ring.as_ptr().add(self.receive_clean_index);
+                (
+                    ptr::read_volatile(&(*desc).wb.upper.status_error),
+                    ptr::read_volatile(&(*desc).wb.upper.length) as usize,
+                )
+            };
+            if status & IXGBE_RXDADV_STAT_DD == 0 {
+                break;
+            }
+            let packet = self.receive_buffers[self.receive_clean_index].take();
+            self.receive_clean_index = wrap_ring(self.receive_clean_index, num_descriptors);
+
+            // frames larger than one buffer are dropped, up to and including their last buffer
+            if status & IXGBE_RXDADV_STAT_EOP == 0 {
+                if !self.rx_discarding {
+                    println!("VF dropping a frame larger than the receive buffer");
+                    self.rx_discarding = true;
+                }
+                continue;
+            }
+            if mem::replace(&mut self.rx_discarding, false) {
+                continue;
+            }
+            if let Some(mut packet) = packet {
+                if length <= packet.capacity() {
+                    unsafe { packet.set_len(length) };
+                    reap_queue.push_back(packet);
+                    received += 1;
+                }
+            }
+        }
+        received
+    }
+
+    /// Returns the number of good packets received and transmitted by the VF.
+    pub fn get_stats(&self) -> (u32, u32) {
+        (
+            self.read_reg(IxgbeVfRegs::VFGPRC),
+            self.read_reg(IxgbeVfRegs::VFGPTC),
+        )
+    }
+
+    fn read_reg(&self, reg: IxgbeVfRegs) -> u32 {
+        unsafe { ptr::read_volatile((self.bar.base() + reg as u64) as *const u32) }
+    }
+
+    fn write_reg(&self, reg: IxgbeVfRegs, val: u32) {
+        unsafe { ptr::write_volatile((self.bar.base() + reg as u64) as *mut u32, val) }
+    }
+
+    fn wait_reg(&self, reg: IxgbeVfRegs, value: u32) {
+        while self.read_reg(reg) & value != value {
+            sys_ns_loopsleep(ONE_MS_IN_NS);
+        }
+    }
+
+    /// Reads VFMAILBOX, remembering the read-to-clear bits until they are consumed.
+    fn read_mailbox(&mut self) -> u32 {
+        let value = self.read_reg(IxgbeVfRegs::VFMAILBOX);
+        self.mailbox_bits |= value & IXGBE_VFMAILBOX_R2C_BITS;
+        value | self.mailbox_bits
+    }
+
+    /// Waits for any of `bits` to be set in VFMAILBOX.
+    fn wait_mailbox(&mut self, bits: u32) -> bool {
+        for _ in 0..MAILBOX_TIMEOUT_MS {
+            if self.read_mailbox() & bits != 0 {
+                return true;
+            }
+            sys_ns_loopsleep(ONE_MS_IN_NS);
+        }
+        false
+    }
+
+    fn lock_mailbox(&mut self) -> Result<(), &'static str> {
+        self.write_reg(IxgbeVfRegs::VFMAILBOX, IXGBE_VFMAILBOX_VFU);
+        if self.read_mailbox() & IXGBE_VFMAILBOX_VFU == 0 {
+            return Err("VF mailbox owned by the PF");
+        }
+        Ok(())
+    }
+
+    fn write_message(&mut self, msg: &[u32]) -> Result<(), &'static str> {
+        self.lock_mailbox()?;
+        // drop stale acks from earlier messages
+        self.mailbox_bits &= !(IXGBE_VFMAILBOX_PFACK | IXGBE_VFMAILBOX_PFSTS);
+        for (i, word) in msg.iter().enumerate() {
+            let offset = IxgbeVfRegs::VFMBMEM as u64 + 4 * i as u64;
+            unsafe { ptr::write_volatile((self.bar.base() + offset) as *mut u32, *word) };
+        }
+        self.write_reg(IxgbeVfRegs::VFMAILBOX, IXGBE_VFMAILBOX_REQ);
+
+        if !self.wait_mailbox(IXGBE_VFMAILBOX_PFACK) {
+            return Err("PF did not acknowledge the message");
+        }
+        self.mailbox_bits &= !IXGBE_VFMAILBOX_PFACK;
+        Ok(())
+    }
+
+    fn read_message(&mut self) -> Result<[u32; IXGBE_VF_MBX_SIZE], &'static str> {
+        if !self.wait_mailbox(IXGBE_VFMAILBOX_PFSTS) {
+            return Err("PF did not reply");
+        }
+        self.mailbox_bits &= !IXGBE_VFMAILBOX_PFSTS;
+
+        self.lock_mailbox()?;
+        let mut msg = [0u32; IXGBE_VF_MBX_SIZE];
+        for (i, word) in msg.iter_mut().enumerate() {
+            let offset = IxgbeVfRegs::VFMBMEM as u64 + 4 * i as u64;
+            *word = unsafe { ptr::read_volatile((self.bar.base() + offset) as *const u32) };
+        }
+        // releases the buffer and tells the PF we have read it
+        self.write_reg(IxgbeVfRegs::VFMAILBOX, IXGBE_VFMAILBOX_ACK);
+        Ok(msg)
+    }
+}
@@ -13,6 +13,13 @@ impl TrustedBridge for Bridge {
     }
 
     fn set_cr3(cr3: u64) {
+        // Drop what is left of the address space in the TLB if a shootdown
+        // hit it while this CPU was running something else
+        let cr3 = if crate::tlb::take_stale(cr3 as usize & 0xfff) {
+            cr3 & !(1 << 63)
+        } else {
+            cr3
+        };
         unsafe {
             asm!(
                 "mov {tmp}, [rip + {pcide}]",
@@ -41,4 +48,8 @@ impl TrustedBridge for Bridge {
     fn set_iommu_pt(bus: usize, device: usize, function: usize, pml4: u64) {
         todo!();
     }
+
+    fn invalidate_tlb(pcid: usize, va: usize, pages: usize) {
+        crate::tlb::shootdown(pcid, va, pages);
+    }
 }
@@ -180,4 +180,13 @@ pub unsafe fn init_cpu(cpu_id: usize) {
     cpu.syscall_sp = cpu.syscall_stack.bottom() as u64;
 
     msr::wrmsr(msr::IA32_GS_BASE, address as u64);
+
+    // Same as the power-on PAT except that PAT1/PAT5 (PWT only) are
+    // write-combining instead of write-through. User mappings select WB, WC
+    // or UC with the PWT and PCD bits and never set the PAT bit.
+    msr::wrmsr(msr::IA32_PAT, PAT_VALUE);
 }
+
+/// PAT entries, one byte each from PAT7 down to PAT0: UC, UC-, WC, WB, UC,
+/// UC-, WC, WB (UC = 0x00, WC = 0x01, WB = 0x06, UC- = 0x07).
+const PAT_VALUE: u64 = 0x0007_0106_0007_0106;
@@ -1,13 +1,25 @@
 //! IOAPIC.
 
 use core::mem::MaybeUninit;
+use core::ptr;
 
 use x86::apic::{ioapic::IoApic, ApicControl};
 
 pub static mut IOAPIC: MaybeUninit<IoApic> = MaybeUninit::zeroed();
 
+static mut IOAPIC_BASE: usize = 0;
+
+const IOREGSEL: usize = 0x00;
+const IOWIN: usize = 0x10;
+
+const REG_VER: u32 = 0x01;
+const REG_REDTBL: u32 = 0x10;
+
+const REDTBL_MASK: u32 = 1 << 16;
+
 pub unsafe fn init(ioapic_base: usize) {
     log::debug!("IOAPIC base: {:#x}", ioapic_base);
+    IOAPIC_BASE = ioapic_base;
     let mut ioapic = IoApic::new(ioapic_base);
     IOAPIC.write(ioapic);
 }
@@ -23,3 +35,33 @@ pub unsafe fn init_cpu() {
 
     log::debug!("Init CPU {} {} {}", cpu.id, xapic.id(), xapic.logical_id());
 }
+
+unsafe fn read(reg: u32) -> u32 {
+    ptr::write_volatile((IOAPIC_BASE + IOREGSEL) as *mut u32, reg);
+    ptr::read_volatile((IOAPIC_BASE + IOWIN) as *const u32)
+}
+
+unsafe fn write(reg: u32, value: u32) {
+    ptr::write_volatile((IOAPIC_BASE + IOREGSEL) as *mut u32, reg);
+    ptr::write_volatile((IOAPIC_BASE + IOWIN) as *mut u32, value);
+}
+
+/// Returns the number of input pins.
+pub fn num_pins() -> usize {
+    unsafe { ((read(REG_VER) >> 16) & 0xff) as usize + 1 }
+}
+
+/// Programs the redirection entry of `pin`, leaving it masked.
+pub unsafe fn set_redirection(pin: usize, low: u32, high: u32) {
+    let reg = REG_REDTBL + 2 * pin as u32;
+    write(reg, read(reg) | REDTBL_MASK);
+    write(reg + 1, high);
+    write(reg, low | REDTBL_MASK);
+}
+
+/// Masks or unmasks `pin`.
+pub unsafe fn set_masked(pin: usize, masked: bool) {
+    let reg = REG_REDTBL + 2 * pin as u32;
+    let low = read(reg);
+    write(reg, if masked { low | REDTBL_MASK } else { low & !REDTBL_MASK });
+}
@@ -8,7 +8,10 @@ use core::slice;
 
 use super::x86_xapic::XAPIC;
 use verified::define::PCID_ENABLE_MASK;
-use x86::apic::{ApicControl, ApicId};
+use x86::apic::{
+    ApicControl, ApicId, DeliveryMode, DeliveryStatus, DestinationMode, DestinationShorthand, Icr,
+    Level, TriggerMode,
+};
 use x86::msr;
 
 use super::Cycles;
@@ -56,6 +59,28 @@ pub fn end_of_interrupt() {
     xapic.eoi();
 }
 
+/// Sends the IRQ `irq` to the CPU `cpu_id`.
+pub fn send_ipi(cpu_id: usize, irq: usize) {
+    let xapic = unsafe {
+        (&mut *crate::cpu::get_current_cpu_field_ptr!(xapic, MaybeUninit<XAPIC>)).assume_init_mut()
+    };
+
+    // FIXME: X2APIC APIC ID
+    let icr = Icr::for_xapic(
+        (super::IRQ_OFFSET + irq) as u8,
+        ApicId::XApic(cpu_id as u8),
+        DestinationShorthand::NoShorthand,
+        DeliveryMode::Fixed,
+        DestinationMode::Physical,
+        DeliveryStatus::Idle,
+        Level::Assert,
+        TriggerMode::Edge,
+    );
+    unsafe {
+        xapic.send_ipi(icr);
+    }
+}
+
 /// Boots an application processor.
 pub unsafe fn boot_ap(cpu_id: u32, stack: u64, code: u64) {
     let xapic = unsafe {
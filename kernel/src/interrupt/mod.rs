@@ -8,7 +8,7 @@
 
 mod exception;
 mod idt;
-mod ioapic;
+pub mod ioapic;
 mod lapic;
 mod mps;
 pub mod x86_xapic;
@@ -25,7 +25,7 @@ use crate::boot::spin_forever;
 pub use exception::Exception;
 use exception::EXCEPTION_MAX;
 use idt::Idt;
-pub use lapic::{boot_ap, end_of_interrupt, set_timer};
+pub use lapic::{boot_ap, end_of_interrupt, send_ipi, set_timer};
 use verified::trap::Registers;
 
 /// The IRQ offset.
@@ -33,6 +33,11 @@ pub const IRQ_OFFSET: usize = 32;
 
 pub const IRQ_TIMER: usize = 0;
 pub const IRQ_IOMMU_FAULT: usize = 1;
+pub const IRQ_TLB_SHOOTDOWN: usize = 2;
+
+/// IRQs handed out to device MSIs through the interrupt remapping table.
+pub const IRQ_DEVICE_BASE: usize = 16;
+pub const NUM_DEVICE_IRQS: usize = 64;
 
 pub const IST_EXCEPTION: usize = 1;
 pub const IST_IRQ: usize = 2;
@@ -249,6 +254,14 @@ unsafe extern "C" fn general_protection_fault(regs: &mut Registers) {
 unsafe extern "C" fn page_fault(regs: &mut Registers) {
     let address: u64;
     asm!("mov {}, cr2", out(reg) address);
+    let error_code = PageFaultErrorCode(regs.error_code);
+    if !error_code.caused_by_non_present()
+        && error_code.caused_by_write()
+        && error_code.caused_in_user_mode()
+        && crate::kernel::resolve_cow_fault(address as usize)
+    {
+        return;
+    }
     log::info!("CPU {}: Page Fault (address {:#x}, error code {:?}): {:#x?}",
         crate::cpu::get_cpu_id(),
         address,
@@ -269,14 +282,35 @@ unsafe extern "C" fn timer(regs: &mut Registers) {
 }
 
 /// IOMMU fault handler.
-unsafe extern "C" fn iommu_fault(regs: &mut Registers) {
-    log::error!("CPU {}: IOMMU fault: {:#x?}",
-        crate::cpu::get_cpu_id(),
-        regs,
-    );
-    crate::iommu::dump_iommu_fault().unwrap();
-    crate::debugger::breakpoint(2);
-    spin_forever();
+///
+/// DMA faults are handed to the process owning the device instead of
+/// stopping the machine.
+unsafe extern "C" fn iommu_fault(_regs: &mut Registers) {
+    while let Some(fault) = crate::iommu::take_fault() {
+        crate::kernel::report_iommu_fault(fault);
+    }
+
+    end_of_interrupt();
+}
+
+/// TLB shootdown handler.
+unsafe extern "C" fn tlb_shootdown(_regs: &mut Registers) {
+    crate::tlb::handle_pending();
+
+    end_of_interrupt();
+}
+
+/// Device interrupt handler for the `N`th device IRQ.
+unsafe extern "C" fn device_irq<const N: usize>(_regs: &mut Registers) {
+    crate::kernel::handle_device_irq(N);
+
+    end_of_interrupt();
+}
+
+macro_rules! device_irq_handlers {
+    ($($n:literal)*) => {
+        [$(wrap_interrupt!(device_irq::<$n>)),*]
+    };
 }
 
 /// An interrupt.
@@ -428,6 +462,17 @@ pub unsafe fn init() {
     idt.interrupts[IRQ_IOMMU_FAULT].set_handler_fn(wrap_interrupt!(iommu_fault));
     idt.interrupts[IRQ_IOMMU_FAULT].attributes.set_privilege_level(Ring::Ring3);
 
+    idt.interrupts[IRQ_TLB_SHOOTDOWN].set_handler_fn(wrap_interrupt!(tlb_shootdown));
+
+    let device_irqs: [TrampolineHandlerFunc; NUM_DEVICE_IRQS] = device_irq_handlers!(
+        0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31
+        32 33 34 35 36 37 38 39 40 41 42 43 44 45 46 47 48 49 50 51 52 53 54 55 56 57 58 59 60 61
+        62 63
+    );
+    for (i, handler) in device_irqs.into_iter().enumerate() {
+        idt.interrupts[IRQ_DEVICE_BASE + i].set_handler_fn(handler);
+    }
+
     let ioapic_base = mps::probe_ioapic();
     ioapic::init(ioapic_base);
 }
@@ -440,6 +485,7 @@ pub unsafe fn init_cpu() {
     ioapic::init_cpu();
 
     GLOBAL_IDT.load();
+    crate::tlb::init_cpu();
 
     asm!("sti");
 }
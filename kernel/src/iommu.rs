@@ -3,20 +3,23 @@
 //! ## Glossary
 //!
 //! - PASID: Process Address Space Identifier that identifies the address space targeted by DMA requests.
+//! - IRTE: Interrupt Remapping Table Entry that picks the vector and CPU of an MSI.
+//! - IEC: Interrupt Entry Cache, the hardware cache of IRTEs.
 
-use core::fmt::{Debug, Formatter};
-use core::mem::MaybeUninit;
 use core::ops::Range;
-use core::pin::Pin;
 use core::ptr;
+use core::sync::atomic::{AtomicU32, Ordering};
 
 use bit_field::BitField;
 use bitfield_struct::bitfield;
 
-use astd::sync::Mutex;
+use astd::boot::{IommuScopeKind, IommuUnit, MAX_IOMMU_UNITS};
+use astd::heapless::Vec as ArrayVec;
+use astd::sync::{Mutex, MutexGuard};
 
-static IOMMU: Mutex<Option<RemappingHardware>> = Mutex::new(None);
-static mut NAIVE_MAP: NaiveIommuMap = NaiveIommuMap::new();
+type Units = ArrayVec<RemappingHardware, MAX_IOMMU_UNITS>;
+
+static IOMMU: Mutex<Units> = Mutex::new(ArrayVec::new());
 
 const MAXPHYADDR: u64 = 56;
 const PAGE_SZ: u64 = 4096;
@@ -24,47 +27,52 @@ const PAGE_MASK: u64 = !(PAGE_SZ - 1);
 const PHYSICAL_MASK: u64 = (1u64 << MAXPHYADDR) - 1u64;
 const PHYSICAL_PAGE_MASK: u64 = PAGE_MASK & PHYSICAL_MASK;
 
-pub struct RemappingHardware {
-    base: u64,
-    cap: u64,
-    ecap: u64,
-}
+/// Number of entries in the interrupt remapping table.
+pub const IRT_ENTRIES: usize = 256;
 
-pub struct NaiveIommuMap {
-    root_table: RootTable,
-    context_table: ContextTable,
-}
+/// Number of descriptors in the invalidation queue, which fills one page.
+const IQ_ENTRIES: usize = 256;
 
-#[repr(transparent)]
-struct RootTable {
-    entries: [MaybeUninit<RootTableEntry>; 256],
-}
+#[repr(C, align(4096))]
+struct InterruptRemapTable([u128; IRT_ENTRIES]);
 
-#[repr(transparent)]
-struct ContextTable {
-    entries: [MaybeUninit<ContextTableEntry>; 256],
-}
+#[repr(C, align(4096))]
+struct InvalidationQueue([u128; IQ_ENTRIES]);
 
-#[derive(Clone, Copy)]
-#[repr(C, packed)]
-struct RootTableEntry {
-    lower: u64,
-    upper: u64,
-}
+// Like the root table, these are handed to the hardware by their kernel address, which is
+// identity mapped.
+static mut INTERRUPT_REMAP_TABLE: InterruptRemapTable = InterruptRemapTable([0; IRT_ENTRIES]);
+// Every unit has its own invalidation queue, while all units share the interrupt remapping
+// table.
+static mut INVALIDATION_QUEUES: [InvalidationQueue; MAX_IOMMU_UNITS] =
+    [const { InvalidationQueue([0; IQ_ENTRIES]) }; MAX_IOMMU_UNITS];
 
-impl Debug for RootTableEntry {
-    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
-        let lower = self.lower;
-        let upper = self.upper;
-        write!(f, "RTE => lower: 0x{:016x} upper: 0x{:016x}", lower, upper)
-    }
+/// Written by the hardware when an invalidation wait descriptor of a unit completes.
+static INVALIDATION_STATUS: [AtomicU32; MAX_IOMMU_UNITS] =
+    [const { AtomicU32::new(0) }; MAX_IOMMU_UNITS];
+
+pub struct RemappingHardware {
+    base: u64,
+    cap: u64,
+    ecap: u64,
+    /// The index of the unit, which picks its invalidation queue.
+    index: usize,
+    /// The DRHD entry of the unit.
+    unit: IommuUnit,
+    /// Next free slot of the invalidation queue, once queued invalidation is enabled.
+    queue_tail: Option<usize>,
 }
 
+/// A DMA remapping fault, decoded from a fault recording register.
 #[derive(Debug, Clone, Copy)]
-#[repr(C, packed)]
-struct ContextTableEntry {
-    lower: u64,
-    upper: u64,
+pub struct DmaFault {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    /// Page address of the faulting request.
+    pub address: u64,
+    pub reason: u8,
+    pub write: bool,
 }
 
 #[bitfield(u128)]
@@ -109,6 +117,58 @@ struct FaultRecording {
     fault: bool,
 }
 
+/// An entry of the interrupt remapping table in remapped format.
+#[bitfield(u128)]
+struct InterruptRemapEntry {
+    #[bits(1)]
+    present: bool,
+
+    #[bits(1)]
+    fault_processing_disable: bool,
+
+    #[bits(1)]
+    destination_mode: bool,
+
+    #[bits(1)]
+    redirection_hint: bool,
+
+    #[bits(1)]
+    trigger_mode: bool,
+
+    #[bits(3)]
+    delivery_mode: u8,
+
+    #[bits(4)]
+    available: u8,
+
+    #[bits(3, default = 0)]
+    _reserved: u8,
+
+    #[bits(1)]
+    irte_mode: bool,
+
+    #[bits(8)]
+    vector: u8,
+
+    #[bits(8, default = 0)]
+    _reserved2: u8,
+
+    #[bits(32)]
+    destination_id: u32,
+
+    #[bits(16)]
+    source_id: u16,
+
+    #[bits(2)]
+    source_id_qualifier: u8,
+
+    #[bits(2)]
+    source_validation_type: u8,
+
+    #[bits(44, default = 0)]
+    _reserved3: u64,
+}
+
 impl RemappingHardware {
     const VER_REG: usize = 0;
     const CAP_REG: usize = 0x08;
@@ -121,6 +181,9 @@ impl RemappingHardware {
     const FECTL_REG: usize = 0x38;
     const FEDATA_REG: usize = 0x3c;
     const FEADDR_REG: usize = 0x40;
+    const IQT_REG: usize = 0x88;
+    const IQA_REG: usize = 0x90;
+    const IRTA_REG: usize = 0xb8;
 
     const IOTLB_REG_OFFSET: usize = 0x8;
 
@@ -128,8 +191,15 @@ impl RemappingHardware {
     const CAP_NFR: Range<usize> = 40..48;       // NFR: Number of Fault-recording Registers
     const CAP_FRO: Range<usize> = 24..34;       // FRO: Fault-recording Register offset
 
+    const ECAP_QI: usize = 1;                   // QI: Queued Invalidation support
+    const ECAP_IR: usize = 3;                   // IR: Interrupt Remapping support
+
     const GCMD_TE: usize = 31;                  // TE: Translation Enable
     const GCMD_SRTP: usize = 30;                // SRTP: Set Root Table Pointer
+    const GCMD_QIE: usize = 26;                 // QIE: Queued Invalidation Enable
+    const GCMD_IRE: usize = 25;                 // IRE: Interrupt Remapping Enable
+    const GCMD_SIRTP: usize = 24;               // SIRTP: Set Interrupt Remap Table Pointer
+    const GCMD_CFI: usize = 23;                 // CFI: Compatibility Format Interrupt
 
     const CCMD_ICC: usize = 63;                 // ICC: Invalidate Context-Cache
     const CCMD_CIRG: Range<usize> = 61..63;     // CIRG: Context Invalidation Request Granularity
@@ -144,6 +214,7 @@ impl RemappingHardware {
 
     const FSTS_FRI: Range<usize> = 8..16;       // FRI: Fault Record Index
     const FSTS_PPF: usize = 1;                  // PPF: Primary Pending Fault
+    const FSTS_PFO: usize = 0;                  // PFO: Primary Fault Overflow
 
     const FECTL_IM: usize = 31;                 // IM: Interrupt Mask
 
@@ -154,11 +225,32 @@ impl RemappingHardware {
 
     const FEDATA_IMD: Range<usize> = 0..16;     // IMD: Interrupt Message data
 
-    pub unsafe fn new(base: u64) -> Self {
+    const IRTA_S: Range<usize> = 0..4;          // S: table holds 2^(S+1) entries
+
+    const INV_TYPE: Range<usize> = 0..4;
+    const INV_TYPE_CONTEXT: u64 = 0x1;
+    const INV_TYPE_IOTLB: u64 = 0x2;
+    const INV_TYPE_IEC: u64 = 0x4;
+    const INV_TYPE_WAIT: u64 = 0x5;
+    const INV_GRANULARITY: Range<usize> = 4..6;
+    const INV_DID: Range<usize> = 16..32;
+    const INV_IEC_INDEX: usize = 4;             // G: invalidate a single entry instead of all
+    const INV_IEC_IIDX: Range<usize> = 32..48;  // IIDX: Interrupt Index
+    const INV_WAIT_SW: usize = 5;               // SW: write the status data when done
+    const INV_WAIT_DATA: Range<usize> = 32..64;
+    const INV_IOTLB_DR: usize = 6;              // DR: drain reads
+    const INV_IOTLB_DW: usize = 7;              // DW: drain writes
+
+    const IRTE_SVT_SID: u8 = 0b01;              // SVT: verify the requester id against SID
+
+    pub unsafe fn new(index: usize, unit: &IommuUnit) -> Self {
         let mut s = Self {
-            base,
+            base: unit.base,
             cap: 0,
             ecap: 0,
+            index,
+            unit: unit.clone(),
+            queue_tail: None,
         };
 
         s.cap = s.read_u64(Self::CAP_REG);
@@ -186,20 +278,56 @@ impl RemappingHardware {
     }
 
     pub fn get_fault_register_num(&self) -> usize {
-        self.cap.get_bits(Self::CAP_NFR) as usize
+        // NFR is the number of registers minus one
+        self.cap.get_bits(Self::CAP_NFR) as usize + 1
     }
 
-    pub fn get_fault_register(&self, index: usize) -> Option<FaultRecording> {
-        if index > self.get_fault_register_num() {
+    fn get_fault_register(&self, index: usize) -> Option<FaultRecording> {
+        if index >= self.get_fault_register_num() {
             return None;
         }
 
         let fro = 16 * self.cap.get_bits(Self::CAP_FRO) as usize;
-        let r: FaultRecording = unsafe { self.read_u128(fro).into() };
+        let r: FaultRecording = unsafe { self.read_u128(fro + 16 * index).into() };
 
         Some(r)
     }
 
+    unsafe fn clear_fault_register(&mut self, index: usize) {
+        let fro = 16 * self.cap.get_bits(Self::CAP_FRO) as usize;
+
+        // F is the top bit of the record and is cleared by writing 1
+        self.write_u32(fro + 16 * index + 12, 1 << 31);
+    }
+
+    /// Takes the first pending fault record, starting at the index reported in FSTS.
+    unsafe fn take_fault(&mut self) -> Option<FaultRecording> {
+        let start = self.get_fault_index()?;
+        let num = self.get_fault_register_num();
+
+        for i in 0..num {
+            let index = (start + i) % num;
+            let r = self.get_fault_register(index)?;
+            if r.fault() {
+                self.clear_fault_register(index);
+                return Some(r);
+            }
+        }
+
+        None
+    }
+
+    /// Clears the overflow flag, returning whether faults were dropped.
+    unsafe fn clear_fault_overflow(&mut self) -> bool {
+        let fsts = self.read_u32(Self::FSTS_REG);
+        if fsts.get_bit(Self::FSTS_PFO) {
+            self.write_u32(Self::FSTS_REG, 1 << Self::FSTS_PFO);
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn get_fault_index(&self) -> Option<usize> {
         let fsts = unsafe { self.read_u32(Self::FSTS_REG) };
         let fri = fsts.get_bits(Self::FSTS_FRI);
@@ -244,14 +372,31 @@ impl RemappingHardware {
     }
 
     unsafe fn invalidate_context_cache(&mut self) {
+        if self.queue_tail.is_some() {
+            let mut desc: u64 = 0;
+            desc.set_bits(Self::INV_TYPE, Self::INV_TYPE_CONTEXT);
+            desc.set_bits(Self::INV_GRANULARITY, Self::CCMD_CIRG_GLOBAL);
+            self.queue_invalidation(desc as u128);
+            self.wait_invalidation();
+            return;
+        }
+
         let mut ccmd = 0;
         ccmd.set_bit(Self::CCMD_ICC, true);
         ccmd.set_bits(Self::CCMD_CIRG, Self::CCMD_CIRG_GLOBAL);
         self.write_u64(Self::CCMD_REG, ccmd);
     }
 
-    unsafe fn invalidate_iotlb(&mut self, bus: usize, device: usize, function: usize, page: u64) {
-        let did = get_did(bus, device, function);
+    unsafe fn invalidate_iotlb(&mut self, did: u16, page: u64) {
+        if self.queue_tail.is_some() {
+            let (iirg, addr) = if page != 0 {
+                (Self::IOTLBCMD_IIRG_PAGE, PHYSICAL_PAGE_MASK & page)
+            } else {
+                (Self::IOTLBCMD_IIRG_DOMAIN, 0)
+            };
+            self.queue_iotlb_invalidation(iirg, did, addr);
+            return;
+        }
 
         let iro = 16 * self.ecap.get_bits(8..18);
         let iva_reg = iro as usize;
@@ -307,6 +452,15 @@ impl RemappingHardware {
         // not applicable to us
 
         // > and IOTLB, in that order.
+        self.invalidate_iotlb_global();
+    }
+
+    unsafe fn invalidate_iotlb_global(&mut self) {
+        if self.queue_tail.is_some() {
+            self.queue_iotlb_invalidation(Self::IOTLBCMD_IIRG_GLOBAL, 0, 0);
+            return;
+        }
+
         let iro = 16 * self.ecap.get_bits(8..18);
         let iotlb_reg = iro as usize + Self::IOTLB_REG_OFFSET;
 
@@ -317,294 +471,356 @@ impl RemappingHardware {
         self.write_u64(iotlb_reg, iotlb_cmd);
     }
 
-    unsafe fn read_u32(&self, offset: usize) -> u32 {
-        let p = (self.base as usize + offset) as *const u32;
-        ptr::read_volatile(p)
-    }
-
-    unsafe fn write_u32(&self, offset: usize, value: u32) {
-        let p = (self.base as usize + offset) as *mut u32;
-        ptr::write_volatile(p, value);
+    unsafe fn queue_iotlb_invalidation(&mut self, granularity: u64, did: u16, addr: u64) {
+        let mut desc: u64 = 0;
+        desc.set_bits(Self::INV_TYPE, Self::INV_TYPE_IOTLB);
+        desc.set_bits(Self::INV_GRANULARITY, granularity);
+        desc.set_bit(Self::INV_IOTLB_DR, true);
+        desc.set_bit(Self::INV_IOTLB_DW, true);
+        desc.set_bits(Self::INV_DID, did as u64);
+        self.queue_invalidation(((addr as u128) << 64) | desc as u128);
+        self.wait_invalidation();
     }
 
-    unsafe fn read_u64(&self, offset: usize) -> u64 {
-        let p = (self.base as usize + offset) as *const u64;
-        ptr::read_volatile(p)
-    }
+    /// Switches all cache invalidations to the invalidation queue. Register-based invalidation
+    /// must not be used afterwards.
+    pub unsafe fn enable_queued_invalidation(&mut self) -> Result<(), &'static str> {
+        if !self.ecap.get_bit(Self::ECAP_QI) {
+            return Err("Queued invalidation is not supported");
+        }
 
-    unsafe fn write_u64(&self, offset: usize, value: u64) {
-        let p = (self.base as usize + offset) as *mut u64;
-        ptr::write_volatile(p, value);
-    }
+        // QS = 0: a single page of 128-bit descriptors
+        let queue = ptr::addr_of!(INVALIDATION_QUEUES[self.index]) as u64;
+        self.write_u64(Self::IQT_REG, 0);
+        self.write_u64(Self::IQA_REG, queue & PHYSICAL_PAGE_MASK);
+        self.send_global_command(Self::GCMD_QIE, true);
+        self.queue_tail = Some(0);
 
-    unsafe fn read_u128(&self, offset: usize) -> u128 {
-        let p = (self.base as usize + offset) as *const u128;
-        ptr::read_volatile(p)
+        Ok(())
     }
-}
 
-impl NaiveIommuMap {
-    pub const fn new() -> Self {
-        Self {
-            root_table: RootTable::new(),
-            context_table: ContextTable::new(),
-        }
-    }
+    unsafe fn queue_invalidation(&mut self, desc: u128) {
+        let tail = self.queue_tail.expect("Queued invalidation is disabled");
+        let queue = ptr::addr_of_mut!(INVALIDATION_QUEUES[self.index]) as *mut u128;
+        ptr::write_volatile(queue.add(tail), desc);
 
-    pub fn root_table_addr(self: Pin<&mut Self>) -> u64 {
-        //log::info!("Context Table: {:?}", &self.context_table as *const ContextTable);
-        &self.root_table as *const RootTable as u64
+        let tail = (tail + 1) % IQ_ENTRIES;
+        self.queue_tail = Some(tail);
+        self.write_u64(Self::IQT_REG, (tail as u64) << 4);
     }
 
-    pub fn map(mut self: Pin<&mut Self>, bus: usize, device: usize, function: usize, pml4: u64) {
-        let did = get_did(bus, device, function);
-        let re = RootTableEntry::new()
-            .with_present(true)
-            .with_address(&self.context_table as *const ContextTable as u64);
+    /// Waits until all descriptors queued so far have been processed.
+    unsafe fn wait_invalidation(&mut self) {
+        let status = &INVALIDATION_STATUS[self.index];
+        status.store(0, Ordering::SeqCst);
 
-        let ce = ContextTableEntry::new()
-            .with_present(true)
-            .with_did(did)
-            .with_address(pml4);
+        let mut desc: u64 = 0;
+        desc.set_bits(Self::INV_TYPE, Self::INV_TYPE_WAIT);
+        desc.set_bit(Self::INV_WAIT_SW, true);
+        desc.set_bits(Self::INV_WAIT_DATA, 1);
+        self.queue_invalidation(((status.as_ptr() as u128) << 64) | desc as u128);
 
-        unsafe {
-            self.as_mut().root_table().write(bus, re);
-            self.as_mut().context_table().write(device, function, ce);
+        while status.load(Ordering::SeqCst) == 0 {
+            core::hint::spin_loop();
         }
-
-        for i in 0..256 {
-            let e = unsafe { self.as_mut().root_table().as_ref().read(i) };
-            log::debug!("bus {} = {:?}", i, e);
-        }
-        log::debug!("Setting IOPML4 to {:#x?}", pml4);
     }
 
-    fn root_table<'a>(self: Pin<&'a mut Self>) -> Pin<&'a mut RootTable> {
-        unsafe {
-            self.map_unchecked_mut(|s| &mut s.root_table)
+    unsafe fn invalidate_interrupt_entry(&mut self, index: Option<usize>) {
+        let mut desc: u64 = 0;
+        desc.set_bits(Self::INV_TYPE, Self::INV_TYPE_IEC);
+        if let Some(index) = index {
+            desc.set_bit(Self::INV_IEC_INDEX, true);
+            desc.set_bits(Self::INV_IEC_IIDX, index as u64);
         }
+        self.queue_invalidation(desc as u128);
+        self.wait_invalidation();
     }
 
-    fn context_table<'a>(self: Pin<&'a mut Self>) -> Pin<&'a mut ContextTable> {
-        unsafe {
-            self.map_unchecked_mut(|s| &mut s.context_table)
+    /// Points the hardware at the interrupt remapping table and enables remapping.
+    ///
+    /// Compatibility-format interrupts stay blocked, so every MSI has to go through an
+    /// IRTE set up by the kernel. Requires queued invalidation.
+    pub unsafe fn enable_interrupt_remapping(&mut self) -> Result<(), &'static str> {
+        if !self.ecap.get_bit(Self::ECAP_IR) {
+            return Err("Interrupt remapping is not supported");
         }
-    }
-}
-
-impl RootTable {
-    const fn new() -> Self {
-        Self {
-            entries: [MaybeUninit::new(RootTableEntry::new()); 256],
+        if self.queue_tail.is_none() {
+            return Err("Interrupt remapping requires queued invalidation");
         }
-        //unsafe { mem::transmute(MaybeUninit::<Self>::zeroed()) }
-    }
 
-    unsafe fn read(self: Pin<&Self>, index: usize) -> RootTableEntry {
-        assert!(index < self.entries.len(), "Out of range");
-        ptr::read_volatile(self.entry(index))
-    }
+        let table = ptr::addr_of!(INTERRUPT_REMAP_TABLE) as u64;
+        let mut irta = table & PHYSICAL_PAGE_MASK;
+        irta.set_bits(Self::IRTA_S, IRT_ENTRIES.trailing_zeros() as u64 - 1);
+        self.write_u64(Self::IRTA_REG, irta);
+        self.send_global_command(Self::GCMD_SIRTP, true);
+        self.invalidate_interrupt_entry(None);
 
-    unsafe fn write(self: Pin<&mut Self>, index: usize, value: RootTableEntry) {
-        assert!(index < self.entries.len(), "Out of range");
-        let e = self.entry_mut(index);
-        log::info!("Index: {:#x}", index);
-        log::info!("Writing to Root Table: {:?} <- {:#x?}", e, value);
-        ptr::write_volatile(e, value);
-    }
+        if self.global_status().get_bit(Self::GCMD_CFI) {
+            self.send_global_command(Self::GCMD_CFI, false);
+        }
+        self.send_global_command(Self::GCMD_IRE, true);
+
+        Ok(())
+    }
+
+    /// Routes interrupts that use `index` as their handle to `vector` on the CPU with `apic_id`,
+    /// accepting them only from the device `source_id`.
+    unsafe fn set_interrupt_entry(
+        &mut self,
+        index: usize,
+        source_id: u16,
+        vector: u8,
+        apic_id: u8,
+        level: bool,
+    ) {
+        let entry = InterruptRemapEntry::new()
+            .with_present(true)
+            .with_trigger_mode(level)
+            .with_vector(vector)
+            // xAPIC destination id lives in bits 15:8
+            .with_destination_id((apic_id as u32) << 8)
+            .with_source_id(source_id)
+            .with_source_validation_type(Self::IRTE_SVT_SID);
 
-    fn entry(self: Pin<&Self>, index: usize) -> *const RootTableEntry {
-        self.entries[index].as_ptr()
+        let table = ptr::addr_of_mut!(INTERRUPT_REMAP_TABLE) as *mut u64;
+        let raw: u128 = entry.into();
+        // keep the entry not present while the upper half changes
+        ptr::write_volatile(table.add(2 * index), 0);
+        self.invalidate_interrupt_entry(Some(index));
+        ptr::write_volatile(table.add(2 * index + 1), (raw >> 64) as u64);
+        ptr::write_volatile(table.add(2 * index), raw as u64);
+        self.invalidate_interrupt_entry(Some(index));
     }
 
-    fn entry_mut(mut self: Pin<&mut Self>, index: usize) -> *mut RootTableEntry {
-        self.entries[index].as_mut_ptr()
+    unsafe fn clear_interrupt_entry(&mut self, index: usize) {
+        let table = ptr::addr_of_mut!(INTERRUPT_REMAP_TABLE) as *mut u64;
+        ptr::write_volatile(table.add(2 * index), 0);
+        self.invalidate_interrupt_entry(Some(index));
     }
-}
 
-impl ContextTable {
-    const fn new() -> Self {
-        Self {
-            entries: [MaybeUninit::new(ContextTableEntry::new()); 256],
-        }
-    }
-
-    #[allow(dead_code)]
-    unsafe fn read(self: Pin<&Self>, device: usize, function: usize) -> ContextTableEntry {
-        let index = Self::index(device, function);
-        ptr::read_volatile(self.entry(index))
+    unsafe fn read_u32(&self, offset: usize) -> u32 {
+        let p = (self.base as usize + offset) as *const u32;
+        ptr::read_volatile(p)
     }
 
-    unsafe fn write(self: Pin<&mut Self>, device: usize, function: usize, value: ContextTableEntry) {
-        let index = Self::index(device, function);
-        let e = self.entry_mut(index);
-        log::info!("Index: {:#x}", index);
-        log::info!("Writing to Context Table: {:?} <- {:#x?}", e, value);
-        ptr::write_volatile(e, value);
+    unsafe fn write_u32(&self, offset: usize, value: u32) {
+        let p = (self.base as usize + offset) as *mut u32;
+        ptr::write_volatile(p, value);
     }
 
-    #[allow(dead_code)]
-    fn entry(self: Pin<&Self>, index: usize) -> *const ContextTableEntry {
-        self.entries[index].as_ptr()
+    unsafe fn read_u64(&self, offset: usize) -> u64 {
+        let p = (self.base as usize + offset) as *const u64;
+        ptr::read_volatile(p)
     }
 
-    fn entry_mut(mut self: Pin<&mut Self>, index: usize) -> *mut ContextTableEntry {
-        self.entries[index].as_mut_ptr()
+    unsafe fn write_u64(&self, offset: usize, value: u64) {
+        let p = (self.base as usize + offset) as *mut u64;
+        ptr::write_volatile(p, value);
     }
 
-    fn index(device: usize, function: usize) -> usize {
-        assert!(device < 32, "Device out of range");
-        assert!(function < 8, "Function out of range");
-
-        (device << 3) | function
+    unsafe fn read_u128(&self, offset: usize) -> u128 {
+        let p = (self.base as usize + offset) as *const u128;
+        ptr::read_volatile(p)
     }
 }
 
-impl RootTableEntry {
-    const fn new() -> Self {
-        Self {
-            upper: 0,
-            lower: 0,
-        }
-    }
-
-    #[allow(dead_code)]
-    fn present(&self) -> bool {
-        (self.lower & 0b1) == 1
+impl RemappingHardware {
+    /// Returns whether requests from `bus:device.function` go through this unit, not
+    /// counting INCLUDE_PCI_ALL.
+    fn covers(&self, bus: u8, device: u8, function: u8) -> bool {
+        self.unit.covers(bus, device, function)
     }
 
-    #[allow(dead_code)]
-    fn address(&self) -> u64 {
-        self.lower & PHYSICAL_PAGE_MASK
+    fn interrupt_remapping_enabled(&self) -> bool {
+        self.global_status().get_bit(Self::GCMD_IRE)
     }
+}
 
-    fn with_present(mut self, present: bool) -> Self {
-        self.lower = (self.lower & !(0b1)) | (if present { 1 } else { 0 });
-        self
+/// Locks the remapping units, failing if there are none.
+fn lock_units() -> Result<MutexGuard<'static, Units>, &'static str> {
+    let units = IOMMU.lock();
+    if units.is_empty() {
+        return Err("No IOMMU hardware");
     }
+    Ok(units)
+}
 
-    fn with_address(mut self, address: u64) -> Self {
-        let masked = PHYSICAL_PAGE_MASK & address;
-        self.lower = self.lower & !(PHYSICAL_PAGE_MASK) | masked;
-        self
-    }
+/// Picks the unit that remaps requests from `bus:device.function`: the unit listing the device
+/// in its scopes, or else the INCLUDE_PCI_ALL unit.
+fn unit_for(
+    units: &mut [RemappingHardware],
+    bus: u8,
+    device: u8,
+    function: u8,
+) -> Option<&mut RemappingHardware> {
+    let index = units
+        .iter()
+        .position(|iommu| iommu.covers(bus, device, function))
+        .or_else(|| units.iter().position(|iommu| iommu.unit.include_pci_all))?;
+    Some(&mut units[index])
 }
 
-impl ContextTableEntry {
-    const fn new() -> Self {
-        // AW: 010b: 48-bit AGAW (4-level page table)
-        let upper = 0b010;
+pub unsafe fn init_iommu() {
+    let boot_info = crate::boot::get_boot_info();
+    let mut units = IOMMU.lock();
 
-        Self {
-            upper,
-            lower: 0,
+    for unit in boot_info.iommu_units.iter() {
+        // The verified root table only describes segment 0.
+        if unit.segment != 0 {
+            log::warn!("Ignoring IOMMU @ {:#x} of PCI segment {}", unit.base, unit.segment);
+            continue;
         }
-    }
 
-    #[allow(dead_code)]
-    fn present(&self) -> bool {
-        (self.lower & 0b1) == 1
-    }
+        let mut iommu = RemappingHardware::new(units.len(), unit);
 
-    #[allow(dead_code)]
-    fn address(&self) -> u64 {
-        self.lower & PHYSICAL_PAGE_MASK
-    }
+        log::info!("IOMMU @ {:#x}", unit.base);
+        log::info!("Version: {:#x}", iommu.version());
+        log::info!("Global Status: {:#x}", iommu.global_status());
 
-    fn with_present(mut self, present: bool) -> Self {
-        self.lower = (self.lower & !(0b1)) | (if present { 1 } else { 0 });
-        self
-    }
+        iommu.set_fault_interrupt(0, 1);
 
-    fn with_did(mut self, did: u16) -> Self {
-        let mut upper = self.upper;
-        upper.set_bits(8..24, did as u64);
-        self.upper = upper;
-        self
-    }
+        match iommu.enable_queued_invalidation() {
+            Ok(()) => {
+                if let Err(e) = iommu.enable_interrupt_remapping() {
+                    log::warn!("Interrupt remapping disabled: {}", e);
+                }
+            }
+            Err(e) => log::warn!("Interrupt remapping disabled: {}", e),
+        }
 
-    fn with_address(mut self, address: u64) -> Self {
-        let masked = PHYSICAL_PAGE_MASK & address;
-        self.lower = self.lower & !(PHYSICAL_PAGE_MASK) | masked;
-        self
+        if units.push(iommu).is_err() {
+            unreachable!("more IOMMU units than boot info can hold");
+        }
     }
 }
 
-fn get_did(bus: usize, device: usize, function: usize) -> u16 {
-    assert!(bus < 256, "Bus out of range");
-    assert!(device < 32, "Device out of range");
-    assert!(function < 8, "Function out of range");
-
-    ((bus as u16) << 8) | ((device as u16) << 3) | (function as u16)
-}
-
-unsafe fn reload_root_table() {
-    let rt = Pin::new(&mut NAIVE_MAP);
-    let mut iommu = IOMMU.lock();
-    let iommu = iommu.as_mut().expect("No IOMMU hardware");
-    iommu.set_root_table_addr(rt.root_table_addr());
-}
-
-pub unsafe fn init_iommu() {
-    let boot_info = crate::boot::get_boot_info();
+/// Points the hardware at the verified kernel's root table and enables DMA
+/// remapping. Devices stay blocked until they are bound to an IOMMU table.
+///
+/// All units share the root table, each one only sees requests from the
+/// devices it covers.
+pub unsafe fn set_root_table(address: u64) -> Result<(), &'static str> {
+    let mut units = lock_units()?;
 
-    let mut iommu = if let Some(iommu_base) = boot_info.iommu_base {
-        RemappingHardware::new(iommu_base)
-    } else {
-        return;
-    };
+    for iommu in units.iter_mut() {
+        iommu.set_root_table_addr(address);
+        log::info!("Root Table: {:#x}", iommu.root_table_addr());
 
-    log::info!("Version: {:#x}", iommu.version());
-    log::info!("Global Status: {:#x}", iommu.global_status());
+        log::info!("Enabling translation");
+        iommu.enable_translation();
+    }
 
-    let rt = Pin::new(&mut NAIVE_MAP);
-    iommu.set_root_table_addr(rt.root_table_addr());
-    log::info!("Root Table: {:#x}", iommu.root_table_addr());
+    Ok(())
+}
 
-    iommu.set_fault_interrupt(0, 1);
+/// Drops cached context entries after a device was bound or unbound.
+pub unsafe fn flush_context_cache() -> Result<(), &'static str> {
+    let mut units = lock_units()?;
 
-    log::info!("Enabling translation");
-    iommu.enable_translation();
+    for iommu in units.iter_mut() {
+        iommu.invalidate_context_cache();
+        iommu.invalidate_iotlb_global();
+    }
 
-    *IOMMU.lock() = Some(iommu);
+    Ok(())
 }
 
-pub unsafe fn map(bus: usize, device: usize, function: usize, pml4: u64) -> Result<(), &'static str> {
-    if IOMMU.lock().is_none() {
-        return Err("No IOMMU available");
-    }
+/// Invalidates the IOTLB of domain `did` on every unit, since the domain may
+/// have devices behind any of them.
+pub unsafe fn invalidate_iotlb(did: u16, page: u64) -> Result<(), &'static str> {
+    let mut units = lock_units()?;
 
-    let rt = Pin::new(&mut NAIVE_MAP);
-    rt.map(bus, device, function, pml4);
-
-    // TODO: Reload device-specific context
-    reload_root_table();
+    for iommu in units.iter_mut() {
+        iommu.invalidate_iotlb(did, page);
+    }
 
     Ok(())
 }
 
-pub unsafe fn invalidate_iotlb(bus: usize, device: usize, function: usize, page: u64) -> Result<(), &'static str> {
-    let mut iommu = IOMMU.lock();
-    let iommu = iommu.as_mut().ok_or("No IOMMU hardware")?;
+/// Routes interrupts with handle `index` from the device `source_id` to `vector` on `apic_id`.
+/// `level` is set for level-triggered IOAPIC pins.
+pub unsafe fn set_interrupt_entry(
+    index: usize,
+    source_id: u16,
+    vector: u8,
+    apic_id: u8,
+    level: bool,
+) -> Result<(), &'static str> {
+    let mut units = lock_units()?;
 
-    iommu.invalidate_iotlb(bus, device, function, page);
+    if index >= IRT_ENTRIES {
+        return Err("Invalid interrupt remapping index");
+    }
+    let bus = (source_id >> 8) as u8;
+    let device = ((source_id >> 3) & 0x1f) as u8;
+    let function = (source_id & 0x7) as u8;
+    let iommu = unit_for(&mut units, bus, device, function).ok_or("Device is not behind an IOMMU")?;
+    if !iommu.interrupt_remapping_enabled() {
+        return Err("Interrupt remapping is disabled");
+    }
+    iommu.set_interrupt_entry(index, source_id, vector, apic_id, level);
 
     Ok(())
 }
 
-pub fn dump_iommu_fault() -> Result<(), &'static str> {
-    let mut iommu = IOMMU.lock();
-    let iommu = iommu.as_mut().ok_or("No IOMMU hardware")?;
+/// Returns whether interrupts have to go through the interrupt remapping table.
+pub fn interrupt_remapping_enabled() -> bool {
+    let units = IOMMU.lock();
+    !units.is_empty() && units.iter().all(|iommu| iommu.interrupt_remapping_enabled())
+}
 
-    let index = if let Some(index) = iommu.get_fault_index() {
-        index
-    } else {
-        return Err("No fault is pending");
-    };
+/// Returns the source id the IOAPIC uses in interrupt requests, from the DMAR device scopes.
+pub fn ioapic_source_id() -> Option<u16> {
+    let units = IOMMU.lock();
+    units.iter().flat_map(|iommu| iommu.unit.scopes.iter()).find_map(|scope| {
+        if scope.kind != IommuScopeKind::IoApic {
+            return None;
+        }
+        Some((scope.bus as u16) << 8 | (scope.device as u16) << 3 | scope.function as u16)
+    })
+}
 
-    let recording = iommu.get_fault_register(index);
+/// Blocks MSIs with handle `index`.
+pub unsafe fn clear_interrupt_entry(index: usize) -> Result<(), &'static str> {
+    let mut units = lock_units()?;
 
-    log::error!("{:#x?}", recording);
+    if index >= IRT_ENTRIES {
+        return Err("Invalid interrupt remapping index");
+    }
+    if !units.iter().any(|iommu| iommu.interrupt_remapping_enabled()) {
+        return Err("Interrupt remapping is disabled");
+    }
+    // The table is shared, so any unit remapping interrupts may have cached the entry.
+    for iommu in units.iter_mut().filter(|iommu| iommu.interrupt_remapping_enabled()) {
+        iommu.clear_interrupt_entry(index);
+    }
 
     Ok(())
 }
+
+/// Takes the next pending DMA remapping fault of any unit and clears its record.
+pub fn take_fault() -> Option<DmaFault> {
+    let mut units = IOMMU.lock();
+
+    let recording = units.iter_mut().find_map(|iommu| {
+        let recording = unsafe { iommu.take_fault() };
+        if recording.is_none() && unsafe { iommu.clear_fault_overflow() } {
+            log::warn!(
+                "IOMMU @ {:#x}: fault recording overflowed, some faults were lost",
+                iommu.unit.base
+            );
+        }
+        recording
+    })?;
+
+    let source_id = recording.source_id();
+    Some(DmaFault {
+        bus: (source_id >> 8) as u8,
+        device: ((source_id >> 3) & 0x1f) as u8,
+        function: (source_id & 0x7) as u8,
+        address: recording.fault_info() << 12,
+        reason: recording.fault_reason(),
+        // T1 is clear for writes
+        write: !recording.t1(),
+    })
+}
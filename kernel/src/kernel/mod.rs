@@ -1,10 +1,11 @@
 use astd::boot::PhysicalMemoryType;
 use astd::heapless::Vec as ArrayVec;
-use astd::sync::Mutex;
+use astd::sync::{Mutex, MutexGuard};
 use verified::define::NUM_CPUS;
 use core::arch::asm;
 use core::arch::x86_64::_rdtsc;
 use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use verified::kernel::Kernel;
 use verified::define as vdefine;
 use verified::trap::Registers as vRegisters;
@@ -14,6 +15,84 @@ use verified::bridge::TrustedBridge;
 use crate::cpu;
 static KERNEL: Mutex<Option<Kernel>> = Mutex::new(None);
 
+/// Takes the kernel lock. Syscalls and interrupts run with interrupts off, so
+/// a CPU spinning here keeps answering TLB shootdowns the holder may be
+/// waiting for.
+fn lock_kernel() -> MutexGuard<'static, Option<Kernel>> {
+    loop {
+        if let Some(guard) = KERNEL.try_lock() {
+            return guard;
+        }
+        crate::tlb::handle_pending();
+        core::hint::spin_loop();
+    }
+}
+/// DMA faults waiting to be picked up with `sys_take_iommu_fault`.
+///
+/// Lock order: `KERNEL`, then `IOMMU_FAULTS`.
+static IOMMU_FAULTS: Mutex<ArrayVec<asys::IommuFault, 64>> = Mutex::new(ArrayVec::new());
+/// DMA faults that were never handed out, because nobody owned the device or
+/// the queue was full.
+static DROPPED_IOMMU_FAULTS: AtomicUsize = AtomicUsize::new(0);
+/// Endpoints notified of DMA faults, see `sys_bind_iommu_fault`.
+///
+/// Lock order: `KERNEL`, then `IOMMU_FAULT_ENDPOINTS`.
+static IOMMU_FAULT_ENDPOINTS: Mutex<ArrayVec<FaultEndpoint, 16>> = Mutex::new(ArrayVec::new());
+
+#[derive(Clone, Copy)]
+struct FaultEndpoint {
+    proc: vdefine::ProcPtr,
+    pcid: vdefine::Pcid,
+    /// The thread that bound the endpoint. The binding lapses once it no longer holds it.
+    thread_ptr: vdefine::ThreadPtr,
+    endpoint_ptr: vdefine::EndpointPtr,
+    endpoint_index: usize,
+}
+/// Device interrupts handed out to containers. The handle of an interrupt is its index here,
+/// in the interrupt remapping table and among the device IRQ vectors.
+///
+/// Lock order: `KERNEL`, then `DEVICE_IRQS`.
+static DEVICE_IRQS: Mutex<[Option<DeviceIrq>; crate::interrupt::NUM_DEVICE_IRQS]> =
+    Mutex::new([None; crate::interrupt::NUM_DEVICE_IRQS]);
+
+#[derive(Clone, Copy)]
+struct DeviceIrq {
+    source: IrqSource,
+    container: vdefine::ContainerPtr,
+    /// The endpoint notified when the interrupt fires, see `sys_bind_irq`.
+    endpoint: Option<IrqEndpoint>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum IrqSource {
+    Msi { bdf: usize },
+    /// `bdf` is the device the pin was routed for. Only dom0 routes pins without one.
+    IoApic { pin: usize, bdf: Option<usize> },
+}
+
+impl IrqSource {
+    /// The PCI device raising the interrupt, if it is tied to one.
+    fn device(&self) -> Option<usize> {
+        match *self {
+            IrqSource::Msi { bdf } => Some(bdf),
+            IrqSource::IoApic { bdf, .. } => bdf,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct IrqEndpoint {
+    pcid: vdefine::Pcid,
+    /// The thread that bound the endpoint. The binding lapses once it no longer holds it.
+    thread_ptr: vdefine::ThreadPtr,
+    endpoint_ptr: vdefine::EndpointPtr,
+    endpoint_index: usize,
+    /// The interrupt fired but was not handed to a thread yet.
+    pending: bool,
+    /// A thread was notified and has not called `sys_ack_irq` yet.
+    in_service: bool,
+}
+
 use vstd::prelude::*;
 
 use verified::process_manager::spec_proof::ProcessManager;
@@ -27,6 +106,8 @@ use verified::array_vec::ArrayVec as vArrayVec;
 use verified::pagetable::pagemap::PageMap;
 use verified::define::PagePerm4k;
 use verified::va_range::VaRange4K as vVaRange4K;
+use verified::pagetable::entry::MapEntry as vMapEntry;
+use verified::util::page_ptr_util_u::{va_4k_range_valid, va_4k_valid};
 
 use vstd::simple_pptr::PointsTo;
 
@@ -41,8 +122,130 @@ impl PhysicalMemoryTypeExt for PhysicalMemoryType {
             Self::Domain => vdefine::PageState::Mapped4k,
             Self::PageTable => vdefine::PageState::Allocated4k,
             Self::Kernel | Self::Reserved => vdefine::PageState::Unavailable4k,
+            Self::Mmio => vdefine::PageState::Io,
+        }
+    }
+}
+
+/// Decode the PWT/PCD bits of a mapping request into a memory type.
+fn memory_type_from_bits(bits: usize) -> Option<vdefine::MemoryType> {
+    match bits & asys::MEM_TYPE_MASK {
+        asys::MEM_TYPE_WB => Some(vdefine::MemoryType::WriteBack),
+        asys::MEM_TYPE_WC => Some(vdefine::MemoryType::WriteCombining),
+        asys::MEM_TYPE_UC => Some(vdefine::MemoryType::Uncached),
+        _ => None,
+    }
+}
+
+fn map_entry_to_perm_bits(entry: &vMapEntry) -> usize {
+    let mut perm = 0;
+    if entry.write {
+        perm |= asys::MAP_WRITE;
+    }
+    if entry.execute_disable {
+        perm |= asys::MAP_NO_EXECUTE;
+    }
+    // PCD alone selects PAT2 (UC-), which is reported as uncached as well.
+    perm |= match (entry.write_through, entry.cache_disable) {
+        (_, true) => asys::MEM_TYPE_UC,
+        (true, false) => asys::MEM_TYPE_WC,
+        (false, false) => asys::MEM_TYPE_WB,
+    };
+    perm
+}
+
+/// The canonical form of a user address. The page tables index the upper half
+/// without the sign extension, user space sees it sign-extended.
+fn sign_extend_va(va: usize) -> usize {
+    if va & (1 << 47) != 0 {
+        va | 0xffff_0000_0000_0000
+    } else {
+        va
+    }
+}
+
+/// Inverse of `sign_extend_va`. Non-canonical addresses are left alone.
+fn strip_sign_extension(va: usize) -> usize {
+    let stripped = va & 0x0000_ffff_ffff_ffff;
+    if sign_extend_va(stripped) == va {
+        stripped
+    } else {
+        va
+    }
+}
+
+/// `range` pages from `va`, if they are all aligned user addresses and the
+/// range does not wrap, as `vVaRange4K::new` requires.
+fn user_va_range(va: usize, range: usize) -> Option<vVaRange4K> {
+    if !va_4k_valid(va) {
+        return None;
+    }
+    let end = range
+        .checked_mul(vdefine::PAGE_SZ_4k)
+        .and_then(|len| va.checked_add(len))?;
+    if end == usize::MAX || !va_4k_range_valid(va, range) {
+        return None;
+    }
+    Some(vVaRange4K::new(va, range))
+}
+
+/// Physical address of the page at `va` if the address space `pcid` maps it
+/// as writable, write-back memory.
+fn user_writable_page(kernel: &Kernel, pcid: vdefine::Pcid, va: usize) -> Option<usize> {
+    if !va_4k_valid(va) {
+        return None;
+    }
+    let entry = kernel.mem_man.resolve_pagetable_mapping(pcid, va)?;
+    if !entry.perm.write || entry.perm.write_through || entry.perm.cache_disable {
+        return None;
+    }
+    Some(entry.addr)
+}
+
+/// Whether `[va, va + len)` is mapped writable in the address space `pcid`.
+fn user_range_writable(kernel: &Kernel, pcid: vdefine::Pcid, va: usize, len: usize) -> bool {
+    let end = match va.checked_add(len) {
+        Some(end) => end,
+        None => return false,
+    };
+    let mut page = va & !(vdefine::PAGE_SZ_4k - 1);
+    while page < end {
+        if user_writable_page(kernel, pcid, page).is_none() {
+            return false;
+        }
+        page += vdefine::PAGE_SZ_4k;
+    }
+    true
+}
+
+/// Copy `values` to `va` in the address space `pcid`.
+///
+/// Every page the copy touches must be mapped writable, otherwise nothing is
+/// written and false is returned. The copy goes through the physical pages,
+/// so it works no matter which address space is loaded.
+fn copy_slice_to_user<T: Copy>(kernel: &Kernel, pcid: vdefine::Pcid, va: usize, values: &[T]) -> bool {
+    let len = core::mem::size_of_val(values);
+    if !user_range_writable(kernel, pcid, va, len) {
+        return false;
+    }
+
+    let src = values.as_ptr() as *const u8;
+    let mut offset = 0;
+    while offset < len {
+        let page_offset = (va + offset) & (vdefine::PAGE_SZ_4k - 1);
+        let chunk = core::cmp::min(len - offset, vdefine::PAGE_SZ_4k - page_offset);
+        let pa = user_writable_page(kernel, pcid, va + offset - page_offset).unwrap();
+        unsafe {
+            core::ptr::copy_nonoverlapping(src.add(offset), (pa + page_offset) as *mut u8, chunk);
         }
+        offset += chunk;
     }
+    true
+}
+
+/// Copy `value` to `va` in the address space `pcid`, see `copy_slice_to_user`.
+fn copy_to_user<T: Copy>(kernel: &Kernel, pcid: vdefine::Pcid, va: usize, value: &T) -> bool {
+    copy_slice_to_user(kernel, pcid, va, core::slice::from_ref(value))
 }
 
 pub fn kernel_test() {
@@ -73,7 +276,7 @@ pub fn kernel_new() {
         panic!("Thread size is over the page limit!")
     }
     log::trace!("Kernel objects are within page limit");
-    let mut my_int = KERNEL.lock();
+    let mut my_int = lock_kernel();
     *my_int = Some(Kernel::new());
 }
 
@@ -138,7 +341,7 @@ pub fn kernel_init(
     let page_perm_2: Tracked<PagePerm4k> = Tracked::assume_new();
     let dom0_page_map_perm: Tracked<PointsTo<PageMap>> = Tracked::assume_new();
 
-    KERNEL.lock().as_mut().unwrap().kernel_init(
+    lock_kernel().as_mut().unwrap().kernel_init(
         dom_0_container_ptr,
         dom_0_proc_ptr,
         dom_0_thread_ptr,
@@ -157,6 +360,11 @@ pub fn kernel_init(
     .unwrap()
     .schedule_idle_cpu(0, &mut dom0_pt_regs);
 
+    let root_table_addr = lock_kernel().as_ref().unwrap().mem_man.root_table.root_table_addr();
+    if let Err(e) = unsafe { crate::iommu::set_root_table(root_table_addr as u64) } {
+        log::info!("DMA remapping disabled: {}", e);
+    }
+
     log::info!("dom0 is running on CPU 0");
     let pcid_dom0 = 0;
     let cr3 = dom0_pagetable_ptr | vdefine::PCID_ENABLE_MASK | pcid_dom0;
@@ -167,12 +375,23 @@ pub fn kernel_init(
 
 pub extern "C" fn sys_mmap(va:usize, perm_bits:usize, range:usize, regs: &mut vRegisters) {
     let cpu_id = cpu::get_cpu_id();
-    let mut kernel = KERNEL.lock();
+    let mut kernel = lock_kernel();
     let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
 
+    let mem_type = match memory_type_from_bits(perm_bits) {
+        Some(mem_type) => mem_type,
+        None => {
+            log::info!{"sys_mmap: invalid memory type bits {:x}", perm_bits};
+            regs.rax = 1;
+            Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+            return;
+        }
+    };
+
     let ret_struc =  kernel.as_mut().unwrap().syscall_mmap(
         thread_info.0.unwrap(),
-        vVaRange4K::new(va, range)
+        vVaRange4K::new(va, range),
+        mem_type,
     );
     regs.rax = 
         if ret_struc.is_error(){
@@ -184,11 +403,521 @@ pub extern "C" fn sys_mmap(va:usize, perm_bits:usize, range:usize, regs: &mut vR
     Bridge::set_switch_decision(SwitchDecision::NoSwitching);
 }
 
+/// Change the permissions of an already mapped VA range.
+pub extern "C" fn sys_mprotect(va:usize, perm_bits:usize, range:usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+
+    let page_type = match perm_bits {
+        vdefine::READ => vdefine::PageType::R,
+        vdefine::READ_WRITE => vdefine::PageType::RW,
+        vdefine::READ_EXECUTE => vdefine::PageType::RX,
+        vdefine::READ_WRITE_EXECUTE => vdefine::PageType::RWX,
+        _ => {
+            log::info!{"sys_mprotect: invalid permission bits {:x}", perm_bits};
+            regs.rax = 1;
+            Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+            return;
+        }
+    };
+
+    let Some(va_range) = user_va_range(va, range) else {
+        log::info!{"sys_mprotect: invalid range {:x} + {} pages", va, range};
+        regs.rax = 1;
+        Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+        return;
+    };
+    let ret_struc =  kernel.as_mut().unwrap().syscall_mprotect(
+        thread_info.0.unwrap(),
+        va_range,
+        page_type,
+    );
+    regs.rax = 
+        if ret_struc.is_error(){
+            log::info!{"sys_mprotect failed"};
+            1
+        }else{
+            Bridge::invalidate_tlb(thread_info.4.unwrap(), va, range);
+            0
+        };
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+
+/// Report the caller's remaining container quota and mapped pages.
+///
+/// `usage` points to an `asys::MemUsage` in the caller's address space. Up
+/// to `max` `asys::ProcMemUsage` entries, one per process of the caller's
+/// container, are written to the array at `procs`; `usage.procs` is the
+/// number written. rax is 1 if any of the buffers is not writable.
+pub extern "C" fn sys_mem_usage(usage: usize, procs: usize, max: usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_ref().unwrap().get_current_cpu_info(cpu_id);
+    let thread_ptr = thread_info.0.unwrap();
+    let pcid = thread_info.4.unwrap();
+    let kernel = kernel.as_ref().unwrap();
+    let (quota, mapped_4k) = kernel.syscall_mem_usage(thread_ptr);
+
+    let mut written = 0;
+    let mut ok = true;
+    for proc_pcid in 0..vdefine::PCID_MAX {
+        if written == max {
+            break;
+        }
+        let Some(proc_mapped_4k) = kernel.syscall_proc_mem_usage(thread_ptr, proc_pcid) else {
+            continue;
+        };
+        let entry = asys::ProcMemUsage {
+            pcid: proc_pcid,
+            mapped_4k: proc_mapped_4k,
+        };
+        let va = procs.wrapping_add(written * size_of::<asys::ProcMemUsage>());
+        if !copy_to_user(kernel, pcid, va, &entry) {
+            ok = false;
+            break;
+        }
+        written += 1;
+    }
+
+    let value = asys::MemUsage {
+        mem_4k: quota.mem_4k,
+        mem_2m: quota.mem_2m,
+        mem_1g: quota.mem_1g,
+        pcid: quota.pcid,
+        ioid: quota.ioid,
+        mapped_4k,
+        procs: written,
+    };
+    regs.rax = if ok && copy_to_user(kernel, pcid, usage, &value) { 0 } else { 1 };
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+
+/// Write up to `max` mapped ranges of the caller's address space, starting at
+/// `start_va`, to the `asys::MappingRange` array at `buf`.
+///
+/// Addresses in the upper half are reported sign-extended. Returns the number
+/// of ranges written in rax, or `usize::MAX` if `buf` cannot hold `max`
+/// entries.
+pub extern "C" fn sys_dump_address_space(start_va: usize, buf: usize, max: usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_ref().unwrap().get_current_cpu_info(cpu_id);
+    let thread_ptr = thread_info.0.unwrap();
+    let pcid = thread_info.4.unwrap();
+    let kernel = kernel.as_ref().unwrap();
+
+    let buf_len = max.checked_mul(size_of::<asys::MappingRange>());
+    if !buf_len.is_some_and(|len| user_range_writable(kernel, pcid, buf, len)) {
+        regs.rax = usize::MAX as u64;
+        Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+        return;
+    }
+    let write = |index: usize, range: &asys::MappingRange| {
+        let range = asys::MappingRange {
+            va: sign_extend_va(range.va),
+            ..*range
+        };
+        // The whole buffer was checked above, this cannot fail.
+        let va = buf + index * size_of::<asys::MappingRange>();
+        copy_to_user(kernel, pcid, va, &range);
+    };
+
+    let mut va = strip_sign_extension(start_va);
+    let mut count = 0;
+    let mut current: Option<asys::MappingRange> = None;
+    while count < max {
+        let (found, entry) = match kernel.syscall_next_mapping(thread_ptr, va) {
+            Some(next) => next,
+            None => break,
+        };
+        let perm = map_entry_to_perm_bits(&entry);
+
+        if let Some(range) = current.as_mut() {
+            let size = range.len * range.page_size;
+            // A range never crosses into the upper half, whose reported
+            // addresses are not contiguous with the lower half.
+            let contiguous = range.va + size == found && found != 1 << 47;
+            if contiguous && range.pa + size == entry.addr && range.perm == perm {
+                range.len += 1;
+            } else {
+                write(count, range);
+                count += 1;
+                current = None;
+            }
+        }
+        if current.is_none() && count < max {
+            current = Some(asys::MappingRange {
+                va: found,
+                pa: entry.addr,
+                len: 1,
+                page_size: vdefine::PAGE_SZ_4k,
+                perm,
+            });
+        }
+        va = match found.checked_add(vdefine::PAGE_SZ_4k) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+    if let Some(range) = current {
+        write(count, &range);
+        count += 1;
+    }
+    regs.rax = count as u64;
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+
+/// IOMMU domain id of an IOid, see `verified::memory_manager::RootTable`.
+fn ioid_to_did(ioid: usize) -> u16 {
+    (ioid + 1) as u16
+}
+
+/// Point a PCI device owned by the caller at the caller's IOMMU table.
+pub extern "C" fn sys_set_device_iommu(bus: usize, device: usize, function: usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+
+    if bus >= 256 || device >= 32 || function >= 8 {
+        log::info!{"sys_set_device_iommu: invalid device {:x}:{:x}.{:x}", bus, device, function};
+        regs.rax = 1;
+        Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+        return;
+    }
+
+    let ret_struc = kernel.as_mut().unwrap().syscall_set_device_iommu(
+        thread_info.0.unwrap(),
+        bus as u8,
+        device as u8,
+        function as u8,
+    );
+    regs.rax = match ret_struc.error_code {
+        vdefine::RetValueType::Else => {
+            if let Err(e) = unsafe { crate::iommu::flush_context_cache() } {
+                log::info!{"sys_set_device_iommu: {}", e};
+            }
+            0
+        }
+        _ => {
+            log::info!{"sys_set_device_iommu failed"};
+            1
+        }
+    };
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+
+/// Flush IOTLB entries of a device bound to the caller's IOMMU table.
+pub extern "C" fn sys_invalidate_iotlb(bus: usize, device: usize, function: usize, regs: &mut vRegisters, page: u64) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let proc_ptr = thread_info.1.unwrap();
+    let ioid_op = kernel.as_ref().unwrap().proc_man.get_proc(proc_ptr).ioid;
+
+    let binding = if bus < 256 && device < 32 && function < 8 {
+        kernel.as_ref().unwrap().mem_man.get_pci_binding(bus as u8, device as u8, function as u8)
+    } else {
+        None
+    };
+    regs.rax = match (ioid_op, binding) {
+        (Some(ioid), Some((bound_ioid, _))) if ioid == bound_ioid => {
+            if let Ok(()) = unsafe { crate::iommu::invalidate_iotlb(ioid_to_did(ioid), page) } {
+                0
+            } else {
+                1
+            }
+        }
+        _ => {
+            log::info!{"sys_invalidate_iotlb: device is not bound to the caller"};
+            1
+        }
+    };
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+
+fn pci_from_bdf(bdf: usize) -> Option<(u8, u8, u8)> {
+    if bdf >= 1 << 16 {
+        return None;
+    }
+    Some(((bdf >> 8) as u8, ((bdf >> 3) & 0x1f) as u8, (bdf & 0x7) as u8))
+}
+
+/// Pages of `bars` the page allocator keeps track of.
+fn bar_pages(bars: &[crate::pci::Bar]) -> impl Iterator<Item = usize> + '_ {
+    bars.iter().flat_map(|&(base, size)| {
+        let start = base & !(vdefine::PAGE_SZ_4k - 1);
+        let end = base.saturating_add(size).min(vdefine::NUM_PAGES * vdefine::PAGE_SZ_4k);
+        (start..end).step_by(vdefine::PAGE_SZ_4k)
+    })
+}
+
+/// Whether the pages of `bars` can follow their device out of `container_ptr`:
+/// nobody maps them, and the unmapped ones are reserved for `container_ptr`.
+/// A device only changes owners once its registers are unmapped, so the old
+/// owner cannot keep driving it.
+fn device_bars_movable(kernel: &Kernel, container_ptr: vdefine::ContainerPtr, bars: &[crate::pci::Bar]) -> bool {
+    let page_alloc = &kernel.page_alloc;
+    bar_pages(bars).all(|page_ptr| match page_alloc.get_mmio_page_owner(page_ptr) {
+        Some(owner) => owner == container_ptr,
+        None => {
+            page_alloc.get_page_reference_counter(page_ptr) == 0
+                || !page_alloc.get_page_is_io_page(page_ptr)
+        }
+    })
+}
+
+/// The thread a receive on `endpoint_index` would take a PCI device from.
+fn pending_pci_sender(kernel: &Kernel, thread_ptr: vdefine::ThreadPtr, endpoint_index: usize) -> Option<vdefine::ThreadPtr> {
+    let endpoint_ptr = (*kernel.proc_man.get_thread(thread_ptr).endpoint_descriptors.get(endpoint_index))?;
+    let endpoint = kernel.proc_man.get_endpoint(endpoint_ptr);
+    if !endpoint.queue_state.is_send() || endpoint.queue.len() == 0 {
+        return None;
+    }
+    let sender_ptr = endpoint.queue.get_head();
+    kernel.proc_man.get_thread(sender_ptr).ipc_payload.get_payload_as_pci()?;
+    Some(sender_ptr)
+}
+
+/// Send ownership of a PCI device over an endpoint, blocking until received.
+///
+/// Fails while any of the device's BARs is mapped. The BAR pages move to the
+/// receiver's container along with the device.
+pub extern "C" fn sys_send_pci(endpoint_index: usize, bdf: usize, _: usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info_op = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let pcid = thread_info_op.4.unwrap();
+    let thread_ptr = thread_info_op.0.unwrap();
+
+    let (bus, dev, fun) = match pci_from_bdf(bdf) {
+        Some(pci) if endpoint_index < vdefine::MAX_NUM_ENDPOINT_DESCRIPTORS => pci,
+        _ => {
+            log::info!{"sys_send_pci: invalid arguments"};
+            regs.rax = 1;
+            Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+            return;
+        }
+    };
+    let bars = crate::pci::memory_bars(bus, dev, fun);
+    let container_ptr = kernel.as_ref().unwrap().proc_man.get_thread(thread_ptr).owning_container;
+    if !device_bars_movable(kernel.as_ref().unwrap(), container_ptr, &bars) {
+        log::info!{"sys_send_pci: {:02x}:{:02x}.{} is still mapped", bus, dev, fun};
+        regs.rax = 1;
+        Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+        return;
+    }
+    let ret_struc = kernel.as_mut().unwrap().syscall_send_pci_block(
+        thread_ptr,
+        endpoint_index,
+        bus,
+        dev,
+        fun,
+        &regs,
+    );
+    finish_pci_transfer(&mut kernel, cpu_id, pcid, bdf, &bars, ret_struc, regs);
+}
+
+/// Receive ownership of a PCI device over an endpoint, blocking until sent.
+///
+/// Fails while any of the device's BARs is mapped. The BAR pages move to the
+/// receiver's container along with the device.
+pub extern "C" fn sys_receive_pci(endpoint_index: usize, bdf: usize, _: usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info_op = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let pcid = thread_info_op.4.unwrap();
+    let thread_ptr = thread_info_op.0.unwrap();
+
+    let (bus, dev, fun) = match pci_from_bdf(bdf) {
+        Some(pci) if endpoint_index < vdefine::MAX_NUM_ENDPOINT_DESCRIPTORS => pci,
+        _ => {
+            log::info!{"sys_receive_pci: invalid arguments"};
+            regs.rax = 1;
+            Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+            return;
+        }
+    };
+    let bars = crate::pci::memory_bars(bus, dev, fun);
+    let sender_ptr = pending_pci_sender(kernel.as_ref().unwrap(), thread_ptr, endpoint_index);
+    if sender_ptr.is_some_and(|sender_ptr| {
+        let container_ptr = kernel.as_ref().unwrap().proc_man.get_thread(sender_ptr).owning_container;
+        !device_bars_movable(kernel.as_ref().unwrap(), container_ptr, &bars)
+    }) {
+        log::info!{"sys_receive_pci: {:02x}:{:02x}.{} is still mapped by the sender", bus, dev, fun};
+        regs.rax = 1;
+        Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+        return;
+    }
+    let ret_struc = kernel.as_mut().unwrap().syscall_receive_pci_block(
+        thread_ptr,
+        endpoint_index,
+        bus,
+        dev,
+        fun,
+        &regs,
+    );
+    finish_pci_transfer(&mut kernel, cpu_id, pcid, bdf, &bars, ret_struc, regs);
+}
+
+fn finish_pci_transfer(
+    kernel: &mut Option<Kernel>,
+    cpu_id: usize,
+    pcid: usize,
+    bdf: usize,
+    bars: &[crate::pci::Bar],
+    ret_struc: vdefine::SyscallReturnStruct,
+    regs: &mut vRegisters,
+) {
+    if !matches!(ret_struc.switch_decision, vdefine::SwitchDecision::NoThread)
+        && matches!(ret_struc.error_code, vdefine::RetValueType::Else)
+    {
+        // the registers follow the device to its new container
+        let (bus, dev, fun) = pci_from_bdf(bdf).unwrap();
+        if let Some(owner) = pci_device_owner(kernel.as_ref().unwrap(), bus, dev, fun) {
+            let container_ptr = kernel.as_ref().unwrap().proc_man.get_proc(owner).owning_container;
+            for page_ptr in bar_pages(bars) {
+                kernel.as_mut().unwrap().pass_mmio_page(page_ptr, container_ptr);
+            }
+        }
+        // the device was unbound from the sender's IOMMU table
+        if let Err(e) = unsafe { crate::iommu::flush_context_cache() } {
+            log::info!{"pci transfer: {}", e};
+        }
+        // and its interrupts still target the sender's CPUs
+        revoke_device_irqs(&mut *DEVICE_IRQS.lock(), bdf);
+    }
+    finish_blocking_call(kernel, cpu_id, pcid, ret_struc, regs);
+}
+
+/// Returns from a syscall that may have blocked the caller, switching to
+/// another thread if it did.
+fn finish_blocking_call(
+    kernel: &mut Option<Kernel>,
+    cpu_id: usize,
+    pcid: usize,
+    ret_struc: vdefine::SyscallReturnStruct,
+    regs: &mut vRegisters,
+) {
+    if matches!(ret_struc.switch_decision, vdefine::SwitchDecision::NoThread) {
+        let sche_ret = kernel.as_mut().unwrap().schedule_idle_cpu(cpu_id, regs);
+        if pcid != sche_ret.pcid.unwrap() {
+            Bridge::set_cr3((sche_ret.cr3.unwrap() | sche_ret.pcid.unwrap() | vdefine::PCID_ENABLE_MASK) as u64);
+        }
+        Bridge::set_switch_decision(SwitchDecision::SwitchToClean);
+        return;
+    }
+    regs.rax = match ret_struc.error_code {
+        vdefine::RetValueType::Else => 0,
+        _ => 1,
+    };
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+
+/// Give `mem_4k` pages of the caller container's quota to a child container.
+pub extern "C" fn sys_donate_quota(child: usize, mem_4k: usize, _: usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let ret_struc = kernel.as_mut().unwrap().syscall_donate_quota(
+        thread_info.0.unwrap(),
+        child,
+        mem_4k,
+    );
+    regs.rax = match ret_struc.error_code {
+        vdefine::RetValueType::Else => 0,
+        _ => {
+            log::info!{"sys_donate_quota failed"};
+            1
+        }
+    };
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+
+/// Take back `mem_4k` pages of unused quota from a child container.
+pub extern "C" fn sys_reclaim_quota(child: usize, mem_4k: usize, _: usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let ret_struc = kernel.as_mut().unwrap().syscall_reclaim_quota(
+        thread_info.0.unwrap(),
+        child,
+        mem_4k,
+    );
+    regs.rax = match ret_struc.error_code {
+        vdefine::RetValueType::Else => 0,
+        _ => {
+            log::info!{"sys_reclaim_quota failed"};
+            1
+        }
+    };
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+
+/// Map device registers owned by the caller's container into its address space.
+pub extern "C" fn sys_mmap_mmio(va: usize, pa: usize, len: usize, regs: &mut vRegisters, mem_type: usize) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+
+    let mem_type = match memory_type_from_bits(mem_type) {
+        Some(mem_type) => mem_type,
+        None => {
+            log::info!{"sys_mmap_mmio: invalid memory type bits {:x}", mem_type};
+            regs.rax = 1;
+            Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+            return;
+        }
+    };
+    let Some(va_range) = user_va_range(va, len) else {
+        log::info!{"sys_mmap_mmio: invalid range {:x} + {} pages", va, len};
+        regs.rax = 1;
+        Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+        return;
+    };
+
+    let ret_struc = kernel.as_mut().unwrap().syscall_mmap_mmio(
+        thread_info.0.unwrap(),
+        va_range,
+        pa,
+        mem_type,
+    );
+    regs.rax = match ret_struc.error_code {
+        vdefine::RetValueType::Else => 0,
+        _ => {
+            log::info!{"sys_mmap_mmio failed"};
+            1
+        }
+    };
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+
+/// Try to resolve a write fault on a copy-on-write page by giving the
+/// faulting process a private copy, or the page itself if nobody else maps
+/// it anymore. Returns false if the fault is not a copy-on-write fault and
+/// should be handled as an error.
+pub fn resolve_cow_fault(va: usize) -> bool {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let thread_ptr = match thread_info.0 {
+        Some(thread_ptr) => thread_ptr,
+        None => return false,
+    };
+    let page_va = va & !0xfff;
+    let ret_struc = kernel.as_mut().unwrap().kernel_resolve_cow_fault(thread_ptr, page_va);
+    if ret_struc.is_error() {
+        return false;
+    }
+    Bridge::invalidate_tlb(thread_info.4.unwrap(), page_va, 1);
+    true
+}
+
 /// This syscall does a send and runs the blocked thread if it belongs to the same container. 
 pub extern "C" fn sys_send_empty_try_schedule(endpoint_index:usize, _:usize, _:usize, regs: &mut vRegisters) {
     // log::info!("sys_send_empty_try_schedule regs at entrace: {:x?}", regs);
     let cpu_id = cpu::get_cpu_id();
-    let mut kernel = KERNEL.lock();
+    let mut kernel = lock_kernel();
     let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
     let sender_thread_ptr = thread_info.0.unwrap();
     let sender_proc_ptr = thread_info.0.unwrap();
@@ -205,7 +934,7 @@ pub extern "C" fn sys_send_empty_try_schedule(endpoint_index:usize, _:usize, _:u
 /// Resolve VA to PA.
 pub extern "C" fn sys_resolve(va:usize,_:usize, _:usize, regs: &mut vRegisters){
     let cpu_id = cpu::get_cpu_id();    
-    let mut kernel = KERNEL.lock();
+    let mut kernel = lock_kernel();
     let thread_info = kernel.as_ref().unwrap().get_current_cpu_info(cpu_id);
     let thread_ptr = thread_info.0.unwrap();
     let ret_struc = kernel.as_ref().unwrap().syscall_resolve_va(
@@ -225,7 +954,7 @@ pub extern "C" fn sys_resolve(va:usize,_:usize, _:usize, regs: &mut vRegisters){
 
 pub extern "C" fn sys_resolve_io(va:usize,_:usize, _:usize, regs: &mut vRegisters){
     let cpu_id = cpu::get_cpu_id();    
-    let mut kernel = KERNEL.lock();
+    let mut kernel = lock_kernel();
     let thread_info = kernel.as_ref().unwrap().get_current_cpu_info(cpu_id);
     let thread_ptr = thread_info.0.unwrap();
     let ret_struc = kernel.as_ref().unwrap().syscall_io_resolve_va(
@@ -246,7 +975,7 @@ pub extern "C" fn sys_resolve_io(va:usize,_:usize, _:usize, regs: &mut vRegister
 
 pub extern "C" fn sys_new_endpoint(endpoint_index:usize, _:usize, _:usize, regs: &mut vRegisters) {
     let cpu_id = cpu::get_cpu_id();
-    let mut kernel = KERNEL.lock();
+    let mut kernel = lock_kernel();
     let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
     let ret_struc =  kernel.as_mut().unwrap().syscall_new_endpoint(
         thread_info.0.unwrap(),
@@ -265,7 +994,7 @@ pub extern "C" fn sys_new_endpoint(endpoint_index:usize, _:usize, _:usize, regs:
 /// create a new process, pass an endpoint, and pass some physical pages
 pub fn sys_new_proc(endpoint_index:usize, ip:usize, sp:usize, regs: &mut vRegisters, va:usize, range:usize) {
     let cpu_id = cpu::get_cpu_id();
-    let mut kernel = KERNEL.lock();
+    let mut kernel = lock_kernel();
     let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
     let mut new_proc_pt_regs = *regs;
     new_proc_pt_regs.rip = ip as u64;
@@ -287,17 +1016,36 @@ pub fn sys_new_proc(endpoint_index:usize, ip:usize, sp:usize, regs: &mut vRegist
     Bridge::set_switch_decision(SwitchDecision::NoSwitching);
 }
 
-// pub fn sys_new_proc_with_iommu(endpoint_index:usize, ip:usize, sp:usize,regs: &mut vRegisters) -> usize{
-//     let cpu_id = cpu::get_cpu_id();
-//     let new_proc_pt_regs = vRegisters::new_empty();
-//     let ret_struc =  KERNEL.lock().as_mut().unwrap().syscall_new_proc_with_iommu(
-//         cpu_id,
-//         endpoint_index,
-//         new_proc_pt_regs,
-//     );
-//     Bridge::set_switch_decision(SwitchDecision::NoSwitching);
-//     ret_struc.0.error_code
-// }
+/// create a new process with its own IOMMU table and pass an endpoint
+pub fn sys_new_proc_with_iommu(endpoint_index:usize, ip:usize, sp:usize, regs: &mut vRegisters) {
+    // No memory is shared; the empty range starts at the first user-space address.
+    let va = vdefine::KERNEL_MEM_END_L4INDEX << 39;
+    sys_new_proc_with_iommu_pass_mem(endpoint_index, ip, sp, regs, va, 0);
+}
+
+/// create a new process with its own IOMMU table, pass an endpoint and share `range` pages at `va`
+pub fn sys_new_proc_with_iommu_pass_mem(endpoint_index:usize, ip:usize, sp:usize, regs: &mut vRegisters, va:usize, range:usize) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let mut new_proc_pt_regs = *regs;
+    new_proc_pt_regs.rip = ip as u64;
+    new_proc_pt_regs.rsp = sp as u64;
+    let ret_struc = kernel.as_mut().unwrap().syscall_new_proc_with_endpoint_iommu(
+        thread_info.0.unwrap(),
+        endpoint_index,
+        &new_proc_pt_regs,
+        vVaRange4K::new(va, range)
+    );
+    regs.rax = 
+        if ret_struc.is_error(){
+            log::info!{"sys_new_proc_with_iommu_pass_mem failed"};
+            1
+        }else{
+            0
+        };
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
 
 /// create a new thread and pass an endpoint
 pub extern "C" fn sys_new_thread(endpoint_index:usize, ip:usize, sp:usize, regs: &mut vRegisters){
@@ -305,7 +1053,7 @@ pub extern "C" fn sys_new_thread(endpoint_index:usize, ip:usize, sp:usize, regs:
     let mut new_thread_pt_regs = regs.clone();
     new_thread_pt_regs.rip = ip as u64;
     new_thread_pt_regs.rsp = sp as u64;
-    let mut kernel = KERNEL.lock();
+    let mut kernel = lock_kernel();
     let thread_info = kernel.as_ref().unwrap().get_current_cpu_info(cpu_id);
     let ret_struc =  kernel.as_mut().unwrap().syscall_new_thread_with_endpoint(
         thread_info.0.unwrap(),
@@ -324,7 +1072,7 @@ pub extern "C" fn sys_new_thread(endpoint_index:usize, ip:usize, sp:usize, regs:
 
 // pub fn sys_send_empty_no_wait(endpoint_index:usize,_:usize, _:usize, regs: &mut vRegisters){
 //     let cpu_id = cpu::get_cpu_id();
-//     let mut kernel = KERNEL.lock();
+//     let mut kernel = lock_kernel();
 //     let ret_struc =  kernel.as_mut().unwrap().syscall_send_empty_no_wait(
 //         cpu_id,
 //         endpoint_index,
@@ -337,7 +1085,7 @@ pub extern "C" fn sys_new_thread(endpoint_index:usize, ip:usize, sp:usize, regs:
 // pub extern "C" fn sys_send_empty(endpoint_index:usize, _:usize, _:usize, regs: &mut vRegisters){
 //     // log::info!("regs {:x?}", regs);
 //     let cpu_id = cpu::get_cpu_id();
-//     let mut kernel = KERNEL.lock();
+//     let mut kernel = lock_kernel();
 //     let ret_struc =  kernel.as_mut().unwrap().syscall_send_empty_wait(
 //         cpu_id,
 //         regs,
@@ -382,7 +1130,7 @@ pub extern "C" fn sys_receive_empty(endpoint_index:usize, _:usize, _:usize, regs
     // };
     // log::info!("sys_receive_empty regs at entrance: \n{:x?}", regs);
     let cpu_id = cpu::get_cpu_id();
-    let mut kernel = KERNEL.lock();
+    let mut kernel = lock_kernel();
     let thread_info_op = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
     let pcid = thread_info_op.4.unwrap();
     //     log::info!{
@@ -417,24 +1165,9 @@ pub extern "C" fn sys_receive_empty(endpoint_index:usize, _:usize, _:usize, regs
     Bridge::set_switch_decision(SwitchDecision::SwitchToClean);
 }
 
-// pub extern "C" fn sys_new_proc_with_iommu_pass_mem(endpoint_index:usize, ip:usize, sp:usize, regs: &mut vRegisters, va:usize, range:usize){
+// pub extern "C" fn sched_get_next_thread(regs: &mut vRegisters) -> bool{
 //     let cpu_id = cpu::get_cpu_id();
-//     let mut new_proc_pt_regs = *regs;
-//     new_proc_pt_regs.rip = ip as u64;
-//     new_proc_pt_regs.rsp = sp as u64;
-//     let ret_struc =  KERNEL.lock().as_mut().unwrap().syscall_new_proc_with_iommu_pass_mem(
-//         cpu_id,
-//         endpoint_index,
-//         new_proc_pt_regs,
-//         va,
-//         range
-//     );
-//     regs.rax = ret_struc.0.error_code as u64;
-// }
-
-// pub extern "C" fn sched_get_next_thread(regs: &mut vRegisters) -> bool{
-//     let cpu_id = cpu::get_cpu_id();
-//     let mut kernel = KERNEL.lock();
+//     let mut kernel = lock_kernel();
 //     let thread_info_op = kernel.as_mut().unwrap().until_get_current_thread_info(cpu_id);
 //     let ret_struc =  kernel.as_mut().unwrap().kernel_idle_pop_sched(
 //         cpu_id,
@@ -492,62 +1225,10 @@ pub extern "C" fn sys_receive_empty(endpoint_index:usize, _:usize, _:usize, regs
 //     }
 // }
 
-// pub extern "C" fn sys_receive_pages(endpoint_index:usize, va:usize, range:usize, regs: &mut vRegisters){
-//     let cpu_id = cpu::get_cpu_id();
-//     let mut kernel = KERNEL.lock();
-//     let thread_info_op = kernel.as_mut().unwrap().until_get_current_thread_info(cpu_id);
-//     // log::info!("cpu_list addr {:p}",&(kernel.as_ref().unwrap().cpu_list.ar));
-//     // log::info!("sys_receive_pagestrap frame {:x?} thead_info {:x?} thread{:x?}",regs,thread_info_op,kernel.as_mut().unwrap().cpu_list.ar[cpu_id].current_t, );
-//     let ret_struc =  kernel.as_mut().unwrap().syscall_receive_pages_wait(
-//         cpu_id,
-//         regs,
-//         endpoint_index,
-//         va,
-//         range
-//     );
-//     drop(kernel);
-//     if ret_struc.error_code == vdefine::NO_NEXT_THREAD {
-//         loop{
-//             unsafe{
-//                 let has_next_thread = sched_get_next_thread(regs);
-//                 if has_next_thread == false{
-//                     for i in 0..1000{
-//                         asm!("nop");
-//                     }
-//                 }else{
-//                     break;
-//                 }
-//             }
-//         }
-//     }else{
-//         if thread_info_op.is_none(){
-//             log::info!("fatal: syscall coming from null cpu");
-//         }else{
-//             if thread_info_op.unwrap().1 != ret_struc.cr3 {
-//                 Bridge::set_cr3((ret_struc.cr3 | ret_struc.pcid | vdefine::PCID_ENABLE_MASK) as u64);
-//             }
-
-//             if ret_struc.error_code == vdefine::NO_ERROR_CODE{
-//                 Bridge::set_switch_decision(SwitchDecision::SwitchToPreempted);
-//             }else{
-                
-//                 regs.rax = ret_struc.error_code as u64;
-
-//                 if thread_info_op.unwrap().2 != ret_struc.thread_ptr{
-//                     Bridge::set_switch_decision(SwitchDecision::SwitchToClean);
-//                 }else{
-//                     // log::info!("sys_receive_pages NoSwitching trap frame {:x?}",regs);
-//                     Bridge::set_switch_decision(SwitchDecision::NoSwitching);
-//                 }
-//             }
-//         }
-//     }
-// }
-
 // pub extern "C" fn sys_send_pages_no_wait(endpoint_index:usize, va:usize, range:usize, regs: &mut vRegisters){
 //     // log::info!("regs {:x?}", regs);
 //     let cpu_id = cpu::get_cpu_id();
-//     let mut kernel = KERNEL.lock();
+//     let mut kernel = lock_kernel();
 //     // log::info!("sys_send_pages_no_wait frame {:x?} thead_info {:x?}",regs,kernel.as_mut().unwrap().cpu_list.ar[0].current_t, );
 //     let thread_info_op = kernel.as_mut().unwrap().until_get_current_thread_info(0);
 //     // log::info!("sys_send_pages_no_wait frame {:x?} thead_info {:x?} thread{:x?}",regs,thread_info_op,kernel.as_mut().unwrap().cpu_list.ar[0].current_t, );
@@ -562,61 +1243,107 @@ pub extern "C" fn sys_receive_empty(endpoint_index:usize, _:usize, _:usize, regs
 //     regs.rax = ret_struc.error_code as u64;
 // }
 
-// pub extern "C" fn sys_send_pages(endpoint_index:usize, va:usize, range:usize, regs: &mut vRegisters){
-//     let cpu_id = cpu::get_cpu_id();
-//     let mut kernel = KERNEL.lock();
-//     let thread_info_op = kernel.as_mut().unwrap().until_get_current_thread_info(cpu_id);
-//     let ret_struc =  kernel.as_mut().unwrap().syscall_send_pages_wait(
-//         cpu_id,
-//         regs,
-//         endpoint_index,
-//         va,
-//         range
-//     );
-//     drop(kernel);
-//     if ret_struc.error_code == vdefine::NO_NEXT_THREAD {
-//         loop{
-//             unsafe{
-//                 let has_next_thread = sched_get_next_thread(regs);
-//                 if has_next_thread == false{
-//                     for i in 0..1000{
-//                         asm!("nop");
-//                     }
-//                 }else{
-//                     break;
-//                 }
-//             }
-//         }
-//     }else{
-//         if thread_info_op.is_none(){
-//             log::info!("fatal: syscall coming from null cpu");
-//         }else{
-//             if thread_info_op.unwrap().1 != ret_struc.cr3 {
-//                 Bridge::set_cr3((ret_struc.cr3 | ret_struc.pcid | vdefine::PCID_ENABLE_MASK) as u64);
-//             }
+/// Share `range` pages at `va` with the thread receiving on the endpoint,
+/// blocking until it does. With `SEND_PAGES_READ_ONLY` in `flags` the
+/// receiver gets a read-only mapping, with `SEND_PAGES_COPY_ON_WRITE` both
+/// sides keep read-only mappings and get a private copy on their first write,
+/// and with `SEND_PAGES_MOVE` the pages are unmapped from the sender.
+pub extern "C" fn sys_send_pages(endpoint_index: usize, va: usize, range: usize, regs: &mut vRegisters, flags: usize) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info_op = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let pcid = thread_info_op.4.unwrap();
+    let thread_ptr = thread_info_op.0.unwrap();
 
-//             if ret_struc.error_code == vdefine::NO_ERROR_CODE{
-//                 Bridge::set_switch_decision(SwitchDecision::SwitchToPreempted);
-//             }else{
-                
-//                 regs.rax = ret_struc.error_code as u64;
+    let read_only = flags & asys::SEND_PAGES_READ_ONLY != 0;
+    let copy_on_write = flags & asys::SEND_PAGES_COPY_ON_WRITE != 0;
+    let move_pages = flags & asys::SEND_PAGES_MOVE != 0;
+    if endpoint_index >= vdefine::MAX_NUM_ENDPOINT_DESCRIPTORS
+        || flags & !(asys::SEND_PAGES_READ_ONLY | asys::SEND_PAGES_COPY_ON_WRITE | asys::SEND_PAGES_MOVE) != 0
+        || (copy_on_write && (read_only || move_pages))
+    {
+        log::info!{"sys_send_pages: invalid arguments"};
+        regs.rax = 1;
+        Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+        return;
+    }
+    let ret_struc = kernel.as_mut().unwrap().syscall_send_pages(
+        thread_ptr,
+        endpoint_index,
+        vVaRange4K::new(va, range),
+        read_only,
+        move_pages,
+        copy_on_write,
+        &regs,
+    );
+    if (copy_on_write || move_pages)
+        && !matches!(ret_struc.switch_decision, vdefine::SwitchDecision::NoThread)
+        && matches!(ret_struc.error_code, vdefine::RetValueType::Else)
+    {
+        // Our mappings were made read-only or removed
+        Bridge::invalidate_tlb(pcid, va, range);
+    }
+    finish_blocking_call(&mut kernel, cpu_id, pcid, ret_struc, regs);
+}
 
-//                 if thread_info_op.unwrap().2 != ret_struc.thread_ptr{
-//                     Bridge::set_switch_decision(SwitchDecision::SwitchToClean);
-//                 }else{
-//                     Bridge::set_switch_decision(SwitchDecision::NoSwitching);
-//                 }
-//             }
-//         }
-//     }
-// }
+/// The sender range a receive on `endpoint_index` would share copy-on-write
+/// or move, as `(pcid, va, pages)`. The sender's mappings of it turn
+/// read-only or go away when the receive goes through.
+fn pending_unmapping_send(kernel: &Kernel, thread_ptr: vdefine::ThreadPtr, endpoint_index: usize) -> Option<(usize, usize, usize)> {
+    let endpoint_ptr = (*kernel.proc_man.get_thread(thread_ptr).endpoint_descriptors.get(endpoint_index))?;
+    let endpoint = kernel.proc_man.get_endpoint(endpoint_ptr);
+    if !endpoint.queue_state.is_send() || endpoint.queue.len() == 0 {
+        return None;
+    }
+    let sender = kernel.proc_man.get_thread(endpoint.queue.get_head());
+    if !sender.ipc_payload.get_payload_pages_copy_on_write()
+        && !sender.ipc_payload.get_payload_pages_move()
+    {
+        return None;
+    }
+    let va_range = sender.ipc_payload.get_payload_as_va_range()?;
+    Some((kernel.proc_man.get_proc(sender.owning_proc).pcid, va_range.start, va_range.len))
+}
+
+/// Receive pages shared over an endpoint at `va`, blocking until they are
+/// sent. `range` must match the number of pages sent.
+pub extern "C" fn sys_receive_pages(endpoint_index: usize, va: usize, range: usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info_op = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let pcid = thread_info_op.4.unwrap();
+    let thread_ptr = thread_info_op.0.unwrap();
+
+    if endpoint_index >= vdefine::MAX_NUM_ENDPOINT_DESCRIPTORS {
+        log::info!{"sys_receive_pages: invalid arguments"};
+        regs.rax = 1;
+        Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+        return;
+    }
+    let unmapping_send = pending_unmapping_send(kernel.as_ref().unwrap(), thread_ptr, endpoint_index);
+    let ret_struc = kernel.as_mut().unwrap().syscall_receive_pages(
+        thread_ptr,
+        endpoint_index,
+        vVaRange4K::new(va, range),
+        &regs,
+    );
+    if let Some((sender_pcid, sender_va, pages)) = unmapping_send {
+        if !matches!(ret_struc.switch_decision, vdefine::SwitchDecision::NoThread)
+            && matches!(ret_struc.error_code, vdefine::RetValueType::Else)
+        {
+            // The sender's mappings were made read-only or removed
+            Bridge::invalidate_tlb(sender_pcid, sender_va, pages);
+        }
+    }
+    finish_blocking_call(&mut kernel, cpu_id, pcid, ret_struc, regs);
+}
 
 pub extern "C" fn sys_get_iommu_cr3(endpoint_index:usize, _:usize, _:usize, regs: &mut vRegisters){
     // log::info!("regs {:x?}", regs);
     
     Bridge::set_switch_decision(SwitchDecision::NoSwitching);
     let cpu_id = cpu::get_cpu_id();
-    let mut kernel = KERNEL.lock();
+    let mut kernel = lock_kernel();
     let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
     let proc_ptr = thread_info.1.unwrap();
     let ioid_op = kernel.as_ref().unwrap().proc_man.get_proc(proc_ptr).ioid;
@@ -631,7 +1358,7 @@ pub extern "C" fn sys_get_iommu_cr3(endpoint_index:usize, _:usize, _:usize, regs
 
 pub extern "C" fn sys_iommu_mmap(va:usize, perm_bits:usize, range:usize, regs: &mut vRegisters) {
     let cpu_id = cpu::get_cpu_id();
-    let mut kernel = KERNEL.lock();
+    let mut kernel = lock_kernel();
     let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
 
     log::trace!{"iommaping from {:x?} with {:#?} pages", va, range};
@@ -655,4 +1382,714 @@ pub extern "C" fn sys_iommu_mmap(va:usize, perm_bits:usize, range:usize, regs: &
             0
         };
     Bridge::set_switch_decision(SwitchDecision::NoSwitching);
-}
\ No newline at end of file
+}
+
+/// Remove `range` pages of IOMMU mappings starting at `va` from the caller's IOMMU table.
+pub extern "C" fn sys_io_munmap(va: usize, range: usize, _: usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let ioid_op = kernel.as_ref().unwrap().proc_man.get_proc(thread_info.1.unwrap()).ioid;
+
+    let ret_struc = kernel.as_mut().unwrap().syscall_io_munmap(
+        thread_info.0.unwrap(),
+        vVaRange4K::new(va, range),
+    );
+    regs.rax = match (ret_struc.error_code, ioid_op) {
+        (vdefine::RetValueType::Else, Some(ioid)) => {
+            // Freed pages must not be handed out while a device may still hold a stale
+            // translation, so flush while the kernel lock is still held. If the flush cannot
+            // be issued, cut the domain's devices off instead.
+            match unsafe { crate::iommu::invalidate_iotlb(ioid_to_did(ioid), 0) } {
+                Ok(()) => 0,
+                Err(e) => {
+                    log::info!{"sys_io_munmap: {}", e};
+                    quarantine_iommu_domain(kernel.as_mut().unwrap(), ioid);
+                    1
+                }
+            }
+        }
+        _ => {
+            log::info!{"sys_io_munmap failed"};
+            1
+        }
+    };
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+
+
+/// Detaches every device bound to the IOMMU table `ioid`.
+fn quarantine_iommu_domain(kernel: &mut Kernel, ioid: usize) {
+    for bus in 0..=255u8 {
+        for dev in 0..32u8 {
+            for fun in 0..8u8 {
+                if matches!(kernel.mem_man.get_pci_binding(bus, dev, fun), Some((bound, _)) if bound == ioid) {
+                    kernel.kernel_quarantine_device(bus, dev, fun);
+                }
+            }
+        }
+    }
+    if let Err(e) = unsafe { crate::iommu::flush_context_cache() } {
+        log::info!{"quarantine_iommu_domain: {}", e};
+    }
+}
+
+/// Queue a DMA fault for the process owning the device and notify the fault endpoint bound by
+/// the owner or by its parent. Called from the IOMMU fault interrupt.
+pub fn report_iommu_fault(fault: crate::iommu::DmaFault) {
+    let mut kernel = lock_kernel();
+    let kernel = kernel.as_mut().unwrap();
+    let bdf = asys::pci_bdf(fault.bus as usize, fault.device as usize, fault.function as usize);
+    let owner = match pci_device_owner(kernel, fault.bus, fault.device, fault.function) {
+        Some(owner) => owner,
+        None => {
+            // nobody could ever take it
+            drop_iommu_fault(bdf, fault.reason);
+            return;
+        }
+    };
+    let mut faults = IOMMU_FAULTS.lock();
+    if let Some(pending) = faults.iter_mut().find(|f| f.bdf == bdf && f.reason == fault.reason) {
+        // the waiter was notified when the first fault was queued
+        pending.address = fault.address;
+        pending.write = fault.write;
+        pending.count += 1;
+        return;
+    }
+    if faults.is_full() {
+        // make room, preferring faults whose device lost its owner since
+        let index = faults
+            .iter()
+            .position(|f| {
+                let (bus, dev, fun) = pci_from_bdf(f.bdf).unwrap();
+                pci_device_owner(kernel, bus, dev, fun).is_none()
+            })
+            .unwrap_or(0);
+        let evicted = faults.remove(index);
+        drop_iommu_fault(evicted.bdf, evicted.reason);
+    }
+    let report = asys::IommuFault {
+        bdf,
+        address: fault.address,
+        reason: fault.reason,
+        write: fault.write,
+        count: 1,
+    };
+    let _ = faults.push(report);
+    drop(faults);
+
+    let parent = kernel.proc_man.get_proc(owner).parent;
+    let mut endpoints = IOMMU_FAULT_ENDPOINTS.lock();
+    endpoints.retain(|e| {
+        kernel.thread_holds_endpoint(e.pcid, e.thread_ptr, e.endpoint_index, e.endpoint_ptr)
+    });
+    let endpoint = endpoints
+        .iter()
+        .find(|e| e.proc == owner)
+        .or_else(|| endpoints.iter().find(|e| Some(e.proc) == parent));
+    if let Some(endpoint) = endpoint {
+        // Nobody may be receiving right now. The fault stays queued, so the next
+        // `sys_wait_iommu_fault` returns right away.
+        kernel.kernel_notify_endpoint(endpoint.endpoint_ptr);
+    }
+}
+
+/// Counts and logs a DMA fault that will never be handed out.
+fn drop_iommu_fault(bdf: usize, reason: u8) {
+    let dropped = DROPPED_IOMMU_FAULTS.fetch_add(1, Ordering::Relaxed) + 1;
+    log::info!{"dropped DMA fault of {:#06x}, reason {:#x} ({} dropped so far)", bdf, reason, dropped};
+}
+
+/// Process owning `bus:dev.fun`, if any.
+fn pci_device_owner(kernel: &Kernel, bus: u8, dev: u8, fun: u8) -> Option<usize> {
+    let mem_man = &kernel.mem_man;
+    let ioid = match mem_man.get_pci_binding(bus, dev, fun) {
+        Some((ioid, _)) => ioid,
+        None => (0..vdefine::IOID_MAX).find(|&ioid| {
+            mem_man.ioid_to_proc_ptr.get(ioid).is_some()
+                && mem_man.pci_device_owned(ioid, bus, dev, fun)
+        })?,
+    };
+    *mem_man.ioid_to_proc_ptr.get(ioid)
+}
+
+/// Whether `fault` was raised by a device of `proc` or of one of its children.
+fn iommu_fault_visible(kernel: &Kernel, fault: &asys::IommuFault, proc: vdefine::ProcPtr) -> bool {
+    let (bus, dev, fun) = pci_from_bdf(fault.bdf).unwrap();
+    match pci_device_owner(kernel, bus, dev, fun) {
+        Some(owner) => owner == proc || kernel.proc_man.get_proc(owner).parent == Some(proc),
+        None => false,
+    }
+}
+
+/// Take the oldest DMA fault raised by a device of the caller or of one of its children,
+/// optionally detaching the device from its IOMMU table.
+pub extern "C" fn sys_take_iommu_fault(buf: usize, quarantine: usize, _: usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let caller = thread_info.1.unwrap();
+    let pcid = thread_info.4.unwrap();
+
+    let mut faults = IOMMU_FAULTS.lock();
+    let found = faults.iter().position(|f| iommu_fault_visible(kernel.as_ref().unwrap(), f, caller));
+    let index = match found {
+        Some(index) => index,
+        None => {
+            regs.rax = 1;
+            Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+            return;
+        }
+    };
+    // the fault stays queued if it cannot be handed out
+    if !copy_to_user(kernel.as_ref().unwrap(), pcid, buf, &faults[index]) {
+        log::info!{"sys_take_iommu_fault: invalid buffer {:#x}", buf};
+        regs.rax = 1;
+        Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+        return;
+    }
+    let fault = faults.remove(index);
+    drop(faults);
+
+    if quarantine != 0 {
+        let (bus, dev, fun) = pci_from_bdf(fault.bdf).unwrap();
+        kernel.as_mut().unwrap().kernel_quarantine_device(bus, dev, fun);
+        if let Err(e) = unsafe { crate::iommu::flush_context_cache() } {
+            log::info!{"sys_take_iommu_fault: {}", e};
+        }
+    }
+    regs.rax = 0;
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+
+/// Notify one of the caller's endpoints whenever a device of the caller or of one of its
+/// children raises a DMA fault. Binding again replaces the endpoint.
+pub extern "C" fn sys_bind_iommu_fault(endpoint_index: usize, _: usize, _: usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let kernel = kernel.as_ref().unwrap();
+    let caller = thread_info.1.unwrap();
+    let endpoint_ptr = if endpoint_index < vdefine::MAX_NUM_ENDPOINT_DESCRIPTORS {
+        *kernel.proc_man.get_thread(thread_info.0.unwrap()).endpoint_descriptors.get(endpoint_index)
+    } else {
+        None
+    };
+
+    let mut endpoints = IOMMU_FAULT_ENDPOINTS.lock();
+    let bound = endpoints.iter().position(|e| e.proc == caller);
+    regs.rax = match (endpoint_ptr, bound) {
+        (Some(endpoint_ptr), Some(index)) => {
+            endpoints[index].thread_ptr = thread_info.0.unwrap();
+            endpoints[index].endpoint_ptr = endpoint_ptr;
+            endpoints[index].endpoint_index = endpoint_index;
+            0
+        }
+        (Some(endpoint_ptr), None) => {
+            let binding = FaultEndpoint {
+                proc: caller,
+                pcid: thread_info.4.unwrap(),
+                thread_ptr: thread_info.0.unwrap(),
+                endpoint_ptr,
+                endpoint_index,
+            };
+            match endpoints.push(binding) {
+                Ok(()) => 0,
+                Err(_) => {
+                    log::info!{"sys_bind_iommu_fault: too many fault endpoints"};
+                    1
+                }
+            }
+        }
+        (None, _) => {
+            log::info!{"sys_bind_iommu_fault: invalid endpoint {}", endpoint_index};
+            1
+        }
+    };
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+
+/// Wait until a device of the caller or of one of its children raises a DMA fault. Returns
+/// right away if such a fault is queued, otherwise blocks receiving on the bound endpoint.
+pub extern "C" fn sys_wait_iommu_fault(_: usize, _: usize, _: usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let pcid = thread_info.4.unwrap();
+    let thread_ptr = thread_info.0.unwrap();
+    let caller = thread_info.1.unwrap();
+
+    let queued = IOMMU_FAULTS
+        .lock()
+        .iter()
+        .any(|f| iommu_fault_visible(kernel.as_ref().unwrap(), f, caller));
+    if queued {
+        regs.rax = 0;
+        Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+        return;
+    }
+    let bound = IOMMU_FAULT_ENDPOINTS.lock().iter().find(|e| e.proc == caller).map(|e| e.endpoint_index);
+    let endpoint_index = match bound {
+        Some(endpoint_index) => endpoint_index,
+        None => {
+            log::info!{"sys_wait_iommu_fault: no fault endpoint bound"};
+            regs.rax = 1;
+            Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+            return;
+        }
+    };
+
+    // the thread resumes with rax = 0 once notified
+    regs.rax = 0;
+    let ret_struc = kernel.as_mut().unwrap().syscall_receive_empty_block(
+        thread_ptr,
+        endpoint_index,
+        &regs,
+    );
+    if matches!(ret_struc.switch_decision, vdefine::SwitchDecision::NoThread) {
+        let sche_ret = kernel.as_mut().unwrap().schedule_idle_cpu(cpu_id, regs);
+        if pcid != sche_ret.pcid.unwrap() {
+            Bridge::set_cr3((sche_ret.cr3.unwrap() | sche_ret.pcid.unwrap() | vdefine::PCID_ENABLE_MASK) as u64);
+        }
+        Bridge::set_switch_decision(SwitchDecision::SwitchToClean);
+        return;
+    }
+    regs.rax = match ret_struc.error_code {
+        vdefine::RetValueType::Else => 0,
+        _ => 1,
+    };
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+
+/// Remappable-format MSI address for an interrupt remapping table handle.
+fn msi_remappable_address(handle: usize) -> u64 {
+    let mut address = 0xfee0_0000u64;
+    address |= ((handle & 0x7fff) as u64) << 5;
+    address |= 1 << 4; // interrupt format: remappable
+    address |= ((handle >> 15) as u64 & 1) << 2;
+    address
+}
+
+fn device_irq_vector(handle: usize) -> usize {
+    crate::interrupt::IRQ_OFFSET + crate::interrupt::IRQ_DEVICE_BASE + handle
+}
+
+/// Whether `cpu` belongs to `container`, so its devices may interrupt it.
+fn container_owns_cpu(kernel: &Kernel, container: vdefine::ContainerPtr, cpu: usize) -> bool {
+    cpu < NUM_CPUS && *kernel.proc_man.get_container(container).owned_cpus.data.get(cpu)
+}
+
+/// Route MSIs of a PCI device owned by the caller to a CPU of the caller's container.
+pub extern "C" fn sys_set_device_msi(bdf: usize, cpu: usize, buf: usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let kernel = kernel.as_ref().unwrap();
+    let pcid = thread_info.4.unwrap();
+    let proc = kernel.proc_man.get_proc(thread_info.1.unwrap());
+    let container = proc.owning_container;
+
+    let owned = match (pci_from_bdf(bdf), proc.ioid) {
+        (Some((bus, dev, fun)), Some(ioid)) => kernel.mem_man.pci_device_owned(ioid, bus, dev, fun),
+        _ => false,
+    };
+    // Only CPUs of the caller's container may be interrupted by its devices.
+    if !owned || !container_owns_cpu(kernel, container, cpu) {
+        log::info!{"sys_set_device_msi: device {:#x} or CPU {} not owned", bdf, cpu};
+        regs.rax = 1;
+        Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+        return;
+    }
+    if !user_range_writable(kernel, pcid, buf, size_of::<asys::MsiMessage>()) {
+        log::info!{"sys_set_device_msi: invalid buffer {:#x}", buf};
+        regs.rax = 1;
+        Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+        return;
+    }
+
+    let mut irqs = DEVICE_IRQS.lock();
+    let handle = match irqs.iter().position(|irq| irq.is_none()) {
+        Some(handle) => handle,
+        None => {
+            log::info!{"sys_set_device_msi: out of device vectors"};
+            regs.rax = 1;
+            Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+            return;
+        }
+    };
+    let vector = device_irq_vector(handle);
+    let ret = unsafe {
+        crate::iommu::set_interrupt_entry(handle, bdf as u16, vector as u8, cpu as u8, false)
+    };
+    if let Err(e) = ret {
+        log::info!{"sys_set_device_msi: {}", e};
+        regs.rax = 1;
+        Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+        return;
+    }
+    irqs[handle] = Some(DeviceIrq {
+        source: IrqSource::Msi { bdf },
+        container,
+        endpoint: None,
+    });
+
+    let message = asys::MsiMessage {
+        handle,
+        address: msi_remappable_address(handle),
+        data: 0,
+        vector: vector as u8,
+    };
+    // checked above, and the kernel lock keeps the mapping in place
+    copy_to_user(kernel, pcid, buf, &message);
+    regs.rax = 0;
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+
+/// Route the legacy IOAPIC pin of a PCI device owned by the caller to a CPU of the caller's
+/// container. The pin has to be the one the firmware routed the device's INTx line to. Only
+/// dom0 may route a pin without naming a device.
+pub extern "C" fn sys_set_ioapic_irq(pin: usize, cpu: usize, buf: usize, regs: &mut vRegisters, bdf: usize) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let kernel = kernel.as_ref().unwrap();
+    let pcid = thread_info.4.unwrap();
+    let proc = kernel.proc_man.get_proc(thread_info.1.unwrap());
+    let container = proc.owning_container;
+
+    let device = pci_from_bdf(bdf);
+    let allowed = match (device, proc.ioid) {
+        (Some((bus, dev, fun)), Some(ioid)) => {
+            kernel.mem_man.pci_device_owned(ioid, bus, dev, fun)
+                && crate::pci::interrupt_line(bus, dev, fun) == Some(pin)
+        }
+        (Some(_), None) => false,
+        (None, _) => container == kernel.proc_man.root_container,
+    };
+    if !allowed {
+        log::info!{"sys_set_ioapic_irq: pin {} is not routed for device {:#x}", pin, bdf};
+        regs.rax = 1;
+        Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+        return;
+    }
+    if !user_range_writable(kernel, pcid, buf, size_of::<usize>()) {
+        log::info!{"sys_set_ioapic_irq: invalid buffer {:#x}", buf};
+        regs.rax = 1;
+        Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+        return;
+    }
+
+    let mut irqs = DEVICE_IRQS.lock();
+    let taken = irqs
+        .iter()
+        .flatten()
+        .any(|irq| matches!(irq.source, IrqSource::IoApic { pin: taken, .. } if taken == pin));
+    let num_pins = crate::interrupt::ioapic::num_pins();
+    if pin >= num_pins || taken || !container_owns_cpu(kernel, container, cpu) {
+        log::info!{"sys_set_ioapic_irq: pin {} or CPU {} not available", pin, cpu};
+        regs.rax = 1;
+        Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+        return;
+    }
+    let handle = match irqs.iter().position(|irq| irq.is_none()) {
+        Some(handle) => handle,
+        None => {
+            log::info!{"sys_set_ioapic_irq: out of device vectors"};
+            regs.rax = 1;
+            Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+            return;
+        }
+    };
+    let vector = device_irq_vector(handle) as u32;
+
+    // PCI interrupts are level-triggered and active low
+    let mut low = vector | 1 << 15 | 1 << 13;
+    let high;
+    if crate::iommu::interrupt_remapping_enabled() {
+        let ret = crate::iommu::ioapic_source_id().ok_or("IOAPIC is not behind an IOMMU").and_then(
+            |sid| unsafe {
+                crate::iommu::set_interrupt_entry(handle, sid, vector as u8, cpu as u8, true)
+            },
+        );
+        if let Err(e) = ret {
+            log::info!{"sys_set_ioapic_irq: {}", e};
+            regs.rax = 1;
+            Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+            return;
+        }
+        // remappable format: the handle replaces the destination
+        low |= ((handle >> 15) as u32 & 1) << 11;
+        high = 1 << 16 | ((handle & 0x7fff) as u32) << 17;
+    } else {
+        high = (cpu as u32) << 24;
+    }
+    // stays masked until the pin is bound to an endpoint
+    unsafe {
+        crate::interrupt::ioapic::set_redirection(pin, low, high);
+    }
+    irqs[handle] = Some(DeviceIrq {
+        source: IrqSource::IoApic { pin, bdf: device.map(|_| bdf) },
+        container,
+        endpoint: None,
+    });
+
+    // checked above, and the kernel lock keeps the mapping in place
+    copy_to_user(kernel, pcid, buf, &handle);
+    regs.rax = 0;
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+
+/// Blocks the device interrupt `handle` and frees its vector: masks its pin and clears its
+/// interrupt remapping table entry, which also flushes the entry from the IOMMUs' caches.
+fn revoke_device_irq(irqs: &mut [Option<DeviceIrq>], handle: usize) -> Result<(), &'static str> {
+    let irq = irqs[handle].ok_or("Interrupt is not set up")?;
+    if let IrqSource::IoApic { pin, .. } = irq.source {
+        unsafe { crate::interrupt::ioapic::set_masked(pin, true) };
+    }
+    match unsafe { crate::iommu::clear_interrupt_entry(handle) } {
+        Ok(()) => {}
+        // compatibility format, masking the pin is enough
+        Err(_) if matches!(irq.source, IrqSource::IoApic { .. }) => {}
+        Err(e) => return Err(e),
+    }
+    irqs[handle] = None;
+    Ok(())
+}
+
+/// Revokes every interrupt of the PCI device `bdf`, so a device that changed owners or whose
+/// owner is gone cannot interrupt the old owner's CPUs.
+fn revoke_device_irqs(irqs: &mut [Option<DeviceIrq>], bdf: usize) {
+    for handle in 0..irqs.len() {
+        let of_device = match irqs[handle] {
+            Some(irq) => irq.source.device() == Some(bdf),
+            None => false,
+        };
+        if of_device {
+            if let Err(e) = revoke_device_irq(irqs, handle) {
+                log::info!{"revoke_device_irqs: {}", e};
+            }
+        }
+    }
+}
+
+/// Block a device interrupt set up by the caller's container.
+pub extern "C" fn sys_clear_device_irq(handle: usize, _: usize, _: usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let caller = thread_info.1.unwrap();
+    let container = kernel.as_ref().unwrap().proc_man.get_proc(caller).owning_container;
+
+    let mut irqs = DEVICE_IRQS.lock();
+    regs.rax = match irqs.get(handle).copied() {
+        Some(Some(irq)) if irq.container == container => match revoke_device_irq(&mut *irqs, handle) {
+            Ok(()) => 0,
+            Err(e) => {
+                log::info!{"sys_clear_device_irq: {}", e};
+                1
+            }
+        },
+        _ => {
+            log::info!{"sys_clear_device_irq: invalid handle {}", handle};
+            1
+        }
+    };
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+
+/// Deliver the device interrupt `handle` to its endpoint. Called from the IDT handler of the
+/// interrupt's vector.
+pub fn handle_device_irq(handle: usize) {
+    let mut kernel = lock_kernel();
+    let mut irqs = DEVICE_IRQS.lock();
+    let source = match irqs[handle] {
+        Some(irq) => irq.source,
+        None => {
+            log::debug!("spurious device interrupt {}", handle);
+            return;
+        }
+    };
+    // The owner of the device may have exited since the interrupt was set up.
+    if let Some(bdf) = source.device() {
+        let (bus, dev, fun) = pci_from_bdf(bdf).unwrap();
+        let owner = pci_device_owner(kernel.as_ref().unwrap(), bus, dev, fun);
+        let container = irqs[handle].unwrap().container;
+        if owner.map(|owner| kernel.as_ref().unwrap().proc_man.get_proc(owner).owning_container) != Some(container) {
+            revoke_device_irqs(&mut *irqs, bdf);
+            return;
+        }
+    }
+    let irq = irqs[handle].as_mut().unwrap();
+    // A level-triggered pin keeps firing until the driver serviced the device.
+    if let IrqSource::IoApic { pin, .. } = irq.source {
+        unsafe { crate::interrupt::ioapic::set_masked(pin, true) };
+    }
+    let endpoint = match irq.endpoint.as_mut() {
+        Some(endpoint) => endpoint,
+        None => return,
+    };
+    let kernel = kernel.as_mut().unwrap();
+    if !kernel.thread_holds_endpoint(endpoint.pcid, endpoint.thread_ptr, endpoint.endpoint_index, endpoint.endpoint_ptr) {
+        // the binding thread is gone, a pin stays masked until the interrupt is bound again
+        irq.endpoint = None;
+        return;
+    }
+    if endpoint.in_service || endpoint.pending {
+        endpoint.pending = true;
+        return;
+    }
+    let ret_struc = kernel.kernel_notify_endpoint(endpoint.endpoint_ptr);
+    match ret_struc.error_code {
+        vdefine::RetValueType::Else => endpoint.in_service = true,
+        _ => endpoint.pending = true,
+    }
+}
+
+/// Notify one of the caller's endpoints whenever the device interrupt `handle` fires.
+pub extern "C" fn sys_bind_irq(handle: usize, endpoint_index: usize, _: usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let kernel = kernel.as_ref().unwrap();
+    let container = kernel.proc_man.get_proc(thread_info.1.unwrap()).owning_container;
+    let endpoint_ptr = if endpoint_index < vdefine::MAX_NUM_ENDPOINT_DESCRIPTORS {
+        *kernel.proc_man.get_thread(thread_info.0.unwrap()).endpoint_descriptors.get(endpoint_index)
+    } else {
+        None
+    };
+
+    let mut irqs = DEVICE_IRQS.lock();
+    regs.rax = match (irqs.get_mut(handle), endpoint_ptr) {
+        (Some(Some(irq)), Some(endpoint_ptr)) if irq.container == container => {
+            irq.endpoint = Some(IrqEndpoint {
+                pcid: thread_info.4.unwrap(),
+                thread_ptr: thread_info.0.unwrap(),
+                endpoint_ptr,
+                endpoint_index,
+                pending: false,
+                in_service: false,
+            });
+            if let IrqSource::IoApic { pin, .. } = irq.source {
+                unsafe { crate::interrupt::ioapic::set_masked(pin, false) };
+            }
+            0
+        }
+        _ => {
+            log::info!{"sys_bind_irq: invalid handle {} or endpoint {}", handle, endpoint_index};
+            1
+        }
+    };
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+
+/// Wait until the device interrupt `handle` fires. Returns right away if it already fired,
+/// otherwise blocks receiving on the bound endpoint.
+pub extern "C" fn sys_wait_irq(handle: usize, _: usize, _: usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let pcid = thread_info.4.unwrap();
+    let thread_ptr = thread_info.0.unwrap();
+    let caller = thread_info.1.unwrap();
+    let container = kernel.as_ref().unwrap().proc_man.get_proc(caller).owning_container;
+
+    let mut irqs = DEVICE_IRQS.lock();
+    let endpoint = match irqs.get_mut(handle) {
+        Some(Some(irq)) if irq.container == container => irq.endpoint.as_mut(),
+        _ => None,
+    };
+    let endpoint_index = match endpoint {
+        Some(endpoint) if endpoint.pending => {
+            endpoint.pending = false;
+            endpoint.in_service = true;
+            regs.rax = 0;
+            Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+            return;
+        }
+        // the previous interrupt has to be acknowledged first
+        Some(endpoint) if !endpoint.in_service => endpoint.endpoint_index,
+        _ => {
+            log::info!{"sys_wait_irq: invalid handle {}", handle};
+            regs.rax = 1;
+            Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+            return;
+        }
+    };
+    drop(irqs);
+
+    // the thread resumes with rax = 0 once notified
+    regs.rax = 0;
+    let ret_struc = kernel.as_mut().unwrap().syscall_receive_empty_block(
+        thread_ptr,
+        endpoint_index,
+        &regs,
+    );
+    if matches!(ret_struc.switch_decision, vdefine::SwitchDecision::NoThread) {
+        let sche_ret = kernel.as_mut().unwrap().schedule_idle_cpu(cpu_id, regs);
+        if pcid != sche_ret.pcid.unwrap() {
+            Bridge::set_cr3((sche_ret.cr3.unwrap() | sche_ret.pcid.unwrap() | vdefine::PCID_ENABLE_MASK) as u64);
+        }
+        Bridge::set_switch_decision(SwitchDecision::SwitchToClean);
+        return;
+    }
+    regs.rax = match ret_struc.error_code {
+        vdefine::RetValueType::Else => 0,
+        _ => 1,
+    };
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+
+/// Acknowledge the device interrupt `handle` after servicing the device, so it can fire again.
+pub extern "C" fn sys_ack_irq(handle: usize, _: usize, _: usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let caller = thread_info.1.unwrap();
+    let container = kernel.as_ref().unwrap().proc_man.get_proc(caller).owning_container;
+
+    let mut irqs = DEVICE_IRQS.lock();
+    regs.rax = match irqs.get_mut(handle) {
+        Some(Some(irq)) if irq.container == container && irq.endpoint.is_some() => {
+            let endpoint = irq.endpoint.as_mut().unwrap();
+            endpoint.in_service = false;
+            if let IrqSource::IoApic { pin, .. } = irq.source {
+                if !endpoint.pending {
+                    unsafe { crate::interrupt::ioapic::set_masked(pin, false) };
+                }
+            }
+            0
+        }
+        _ => {
+            log::info!{"sys_ack_irq: invalid handle {}", handle};
+            1
+        }
+    };
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
+/// Return the `index`-th PCIe ECAM region found by the loader.
+pub extern "C" fn sys_pci_ecam(index: usize, buf: usize, _: usize, regs: &mut vRegisters) {
+    let cpu_id = cpu::get_cpu_id();
+    let mut kernel = lock_kernel();
+    let thread_info = kernel.as_mut().unwrap().get_current_cpu_info(cpu_id);
+    let pcid = thread_info.4.unwrap();
+
+    let boot_info = crate::boot::get_boot_info();
+    regs.rax = match boot_info.ecam_regions.get(index) {
+        Some(region) => {
+            let ecam = asys::PciEcam {
+                base: region.base,
+                segment: region.segment,
+                start_bus: region.start_bus,
+                end_bus: region.end_bus,
+            };
+            if copy_to_user(kernel.as_ref().unwrap(), pcid, buf, &ecam) {
+                0
+            } else {
+                log::info!{"sys_pci_ecam: invalid buffer {:#x}", buf};
+                1
+            }
+        }
+        None => 1,
+    };
+    Bridge::set_switch_decision(SwitchDecision::NoSwitching);
+}
@@ -41,9 +41,11 @@ mod interrupt;
 mod iommu;
 mod kernel;
 mod logging;
+mod pci;
 mod scripts;
 mod syscalls;
 mod thread;
+mod tlb;
 mod utils;
 mod ring_buffer;
 
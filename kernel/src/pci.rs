@@ -0,0 +1,132 @@
+//! PCI configuration space, read through the ECAM windows found by the
+//! loader.
+//!
+//! The kernel leaves device management to dom0. It only looks at the memory
+//! BARs of a device when the device changes owners, so its registers move to
+//! the new owner and are unmapped first, and at its interrupt line when a
+//! driver routes the device's legacy interrupt.
+
+use core::ptr;
+
+use astd::heapless::Vec as ArrayVec;
+
+const COMMAND: usize = 0x04;
+const COMMAND_MEMORY: u16 = 1 << 1;
+const HEADER_TYPE: usize = 0x0e;
+const BAR0: usize = 0x10;
+const INTERRUPT_LINE: usize = 0x3c;
+const INTERRUPT_PIN: usize = 0x3d;
+const MAX_BARS: usize = 6;
+
+/// A memory BAR as `(physical base, size in bytes)`.
+pub type Bar = (usize, usize);
+
+/// Physical address of the configuration space of `bus:dev.fun` in segment 0,
+/// the only segment device ownership is tracked for.
+fn config_space(bus: u8, dev: u8, fun: u8) -> Option<usize> {
+    let boot_info = crate::boot::get_boot_info();
+    let region = boot_info
+        .ecam_regions
+        .iter()
+        .find(|region| region.segment == 0 && region.start_bus <= bus && bus <= region.end_bus)?;
+    let offset =
+        ((bus - region.start_bus) as usize) << 20 | (dev as usize) << 15 | (fun as usize) << 12;
+    Some(region.base as usize + offset)
+}
+
+unsafe fn read16(config: usize, offset: usize) -> u16 {
+    ptr::read_volatile((config + offset) as *const u16)
+}
+
+unsafe fn write16(config: usize, offset: usize, value: u16) {
+    ptr::write_volatile((config + offset) as *mut u16, value)
+}
+
+unsafe fn read32(config: usize, offset: usize) -> u32 {
+    ptr::read_volatile((config + offset) as *const u32)
+}
+
+unsafe fn write32(config: usize, offset: usize, value: u32) {
+    ptr::write_volatile((config + offset) as *mut u32, value)
+}
+
+unsafe fn read8(config: usize, offset: usize) -> u8 {
+    ptr::read_volatile((config + offset) as *const u8)
+}
+
+/// Writes all ones to the BAR register at `offset` and returns what sticks,
+/// restoring the register afterwards.
+unsafe fn probe32(config: usize, offset: usize) -> (u32, u32) {
+    let value = read32(config, offset);
+    write32(config, offset, u32::MAX);
+    let mask = read32(config, offset);
+    write32(config, offset, value);
+    (value, mask)
+}
+
+/// Returns the memory BARs of `bus:dev.fun`. A missing device has none.
+///
+/// Sizing a BAR briefly overwrites it, so memory decoding is turned off while
+/// the BARs are probed.
+pub fn memory_bars(bus: u8, dev: u8, fun: u8) -> ArrayVec<Bar, MAX_BARS> {
+    let mut bars = ArrayVec::new();
+    let Some(config) = config_space(bus, dev, fun) else {
+        return bars;
+    };
+    unsafe {
+        if read16(config, 0) == 0xffff {
+            return bars;
+        }
+        let count = match read8(config, HEADER_TYPE) & 0x7f {
+            0 => 6,
+            1 => 2,
+            _ => 0,
+        };
+
+        let command = read16(config, COMMAND);
+        write16(config, COMMAND, command & !COMMAND_MEMORY);
+        let mut index = 0;
+        while index < count {
+            let offset = BAR0 + index * 4;
+            let (low, low_mask) = probe32(config, offset);
+            index += 1;
+            if low & 1 != 0 {
+                // I/O space
+                continue;
+            }
+            let mut base = (low & !0xf) as u64;
+            let mut mask = (low_mask & !0xf) as u64 | 0xffff_ffff_0000_0000;
+            if (low >> 1) & 0x3 == 0x2 && index < count {
+                let (high, high_mask) = probe32(config, offset + 4);
+                index += 1;
+                base |= (high as u64) << 32;
+                mask = (mask & 0xffff_ffff) | (high_mask as u64) << 32;
+            }
+            if mask as u32 & !0xf == 0 && mask >> 32 == 0xffff_ffff {
+                // not implemented
+                continue;
+            }
+            let size = (!mask).wrapping_add(1);
+            if size != 0 {
+                let _ = bars.push((base as usize, size as usize));
+            }
+        }
+        write16(config, COMMAND, command);
+    }
+    bars
+}
+
+/// Returns the IOAPIC input the firmware routed the INTx line of `bus:dev.fun`
+/// to, or `None` if the device does not use INTx.
+pub fn interrupt_line(bus: u8, dev: u8, fun: u8) -> Option<usize> {
+    let config = config_space(bus, dev, fun)?;
+    unsafe {
+        if read16(config, 0) == 0xffff || read8(config, INTERRUPT_PIN) == 0 {
+            return None;
+        }
+        match read8(config, INTERRUPT_LINE) {
+            0xff => None,
+            line => Some(line as usize),
+        }
+    }
+}
@@ -43,6 +43,10 @@ pub unsafe fn init_cpu() {
     // Set LSTAR - 32-bit syscall entry
     msr::wrmsr(msr::IA32_CSTAR, SYSCALL32_ENTRY);
 
+    // Set FMASK - clear IF on entry, so interrupt handlers that take the kernel
+    // lock cannot run in the middle of a syscall on the same CPU
+    msr::wrmsr(msr::IA32_FMASK, 1 << 9);
+
     // Enable EFER Bit 0 - System Call Extensions
     let mut efer = msr::rdmsr(msr::IA32_EFER);
     efer |= 0x1;
@@ -56,21 +60,40 @@ pub unsafe fn init_cpu() {
     SYSCALLS[asys::__NR_MRESOLVE_IO] = kernel::sys_resolve_io as u64;
     SYSCALLS[asys::__NR_NEW_END] = kernel::sys_new_endpoint as u64;
     SYSCALLS[asys::__NR_NEW_PROC] = kernel::sys_new_proc as u64;
-    // SYSCALLS[asys::__NR_NEW_PROC_W_IO] = kernel::sys_new_proc_with_iommu as u64;
+    SYSCALLS[asys::__NR_NEW_PROC_W_IO] = kernel::sys_new_proc_with_iommu as u64;
     SYSCALLS[asys::__NR_NEW_THREAD] = kernel::sys_new_thread as u64;
     // SYSCALLS[asys::__NR_SEND_EMPTY_NW] = kernel::sys_send_empty_no_wait as u64;
     SYSCALLS[asys::__NR_LOG] = sys_log as u64;
     // SYSCALLS[asys::__NR_SEND_EMPTY] = kernel::sys_send_empty as u64;
     SYSCALLS[asys::__NR_RECEIVE_EMPTY] = kernel::sys_receive_empty as u64;
-    // SYSCALLS[asys::__NR_NEW_PROC_W_IO_MEM] = kernel::sys_new_proc_with_iommu_pass_mem as u64;
+    SYSCALLS[asys::__NR_NEW_PROC_W_IO_MEM] = kernel::sys_new_proc_with_iommu_pass_mem as u64;
     // SYSCALLS[asys::__NR_SEND_PAGE_NW] = kernel::sys_send_pages_no_wait as u64;
-    // SYSCALLS[asys::__NR_RECEIVE_PAGE] = kernel::sys_receive_pages as u64;
-    // SYSCALLS[asys::__NR_SEND_PAGE] = kernel::sys_send_pages as u64;
+    SYSCALLS[asys::__NR_RECEIVE_PAGE] = kernel::sys_receive_pages as u64;
+    SYSCALLS[asys::__NR_SEND_PAGE] = kernel::sys_send_pages as u64;
     SYSCALLS[asys::__NR_RD_IO_CR3] = kernel::sys_get_iommu_cr3 as u64;
     SYSCALLS[asys::__NR_IO_MMAP] = kernel::sys_iommu_mmap as u64;
-    SYSCALLS[asys::__NR_SET_DEVICE_IOMMU] = sys_set_device_iommu as u64;
-    SYSCALLS[asys::__NR_INVALIDATE_IOTLB] = sys_invalidate_iotlb as u64;
+    SYSCALLS[asys::__NR_SET_DEVICE_IOMMU] = kernel::sys_set_device_iommu as u64;
+    SYSCALLS[asys::__NR_INVALIDATE_IOTLB] = kernel::sys_invalidate_iotlb as u64;
     SYSCALLS[asys::__NR_SEND_EMPTY_TRY_SCH] = kernel::sys_send_empty_try_schedule as u64;
+    SYSCALLS[asys::__NR_MPROTECT] = kernel::sys_mprotect as u64;
+    SYSCALLS[asys::__NR_MEM_USAGE] = kernel::sys_mem_usage as u64;
+    SYSCALLS[asys::__NR_DONATE_QUOTA] = kernel::sys_donate_quota as u64;
+    SYSCALLS[asys::__NR_RECLAIM_QUOTA] = kernel::sys_reclaim_quota as u64;
+    SYSCALLS[asys::__NR_MMAP_MMIO] = kernel::sys_mmap_mmio as u64;
+    SYSCALLS[asys::__NR_DUMP_ADDRESS_SPACE] = kernel::sys_dump_address_space as u64;
+    SYSCALLS[asys::__NR_SEND_PCI] = kernel::sys_send_pci as u64;
+    SYSCALLS[asys::__NR_RECEIVE_PCI] = kernel::sys_receive_pci as u64;
+    SYSCALLS[asys::__NR_IO_MUNMAP] = kernel::sys_io_munmap as u64;
+    SYSCALLS[asys::__NR_TAKE_IOMMU_FAULT] = kernel::sys_take_iommu_fault as u64;
+    SYSCALLS[asys::__NR_SET_DEVICE_MSI] = kernel::sys_set_device_msi as u64;
+    SYSCALLS[asys::__NR_CLEAR_DEVICE_IRQ] = kernel::sys_clear_device_irq as u64;
+    SYSCALLS[asys::__NR_SET_IOAPIC_IRQ] = kernel::sys_set_ioapic_irq as u64;
+    SYSCALLS[asys::__NR_BIND_IRQ] = kernel::sys_bind_irq as u64;
+    SYSCALLS[asys::__NR_WAIT_IRQ] = kernel::sys_wait_irq as u64;
+    SYSCALLS[asys::__NR_ACK_IRQ] = kernel::sys_ack_irq as u64;
+    SYSCALLS[asys::__NR_PCI_ECAM] = kernel::sys_pci_ecam as u64;
+    SYSCALLS[asys::__NR_BIND_IOMMU_FAULT] = kernel::sys_bind_iommu_fault as u64;
+    SYSCALLS[asys::__NR_WAIT_IOMMU_FAULT] = kernel::sys_wait_iommu_fault as u64;
 }
 
 #[cfg(debug_assertions)]
@@ -263,18 +286,4 @@ extern "C" fn sys_log(data: *const u8, len: usize, level: log::Level) -> isize {
     0
 }
 
-extern "C" fn sys_set_device_iommu(bus: usize, device: usize, function: usize, regs: &mut Registers, pml4: u64) {
-    if let Ok(()) = unsafe { crate::iommu::map(bus, device, function, pml4) } {
-        regs.rax = 0;
-    } else {
-        regs.rax = 1;
-    }
-}
 
-extern "C" fn sys_invalidate_iotlb(bus: usize, device: usize, function: usize, regs: &mut Registers, page: u64) {
-    if let Ok(()) = unsafe { crate::iommu::invalidate_iotlb(bus, device, function, page) } {
-        regs.rax = 0;
-    } else {
-        regs.rax = 1;
-    }
-}
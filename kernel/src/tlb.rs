@@ -0,0 +1,131 @@
+//! TLB shootdown.
+//!
+//! With PCIDs, TLB entries of an address space survive CR3 switches, so a
+//! page table change must be flushed from every CPU that ever ran the
+//! address space, not only from the CPUs running it right now:
+//!
+//! - CPUs currently running the address space flush the pages with `invlpg`
+//!   when they get the shootdown IPI.
+//! - Every other CPU marks the PCID stale and flushes all of its entries
+//!   the next time it loads it, see `take_stale`.
+//!
+//! Shootdowns are only started with the kernel lock held, so at most one is
+//! in flight. A CPU waiting for the kernel lock with interrupts disabled must
+//! call `handle_pending` while it spins, otherwise it would never ack.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use verified::define::{NUM_CPUS, PCID_MAX};
+
+use crate::cpu;
+use crate::interrupt;
+
+/// Above this many pages, flush the whole PCID instead of each page.
+const FULL_FLUSH_PAGES: usize = 64;
+
+const PCID_MASK: u64 = 0xfff;
+const CR3_NOFLUSH: u64 = 1 << 63;
+
+/// CPUs that take shootdown IPIs.
+static ONLINE_CPUS: AtomicU64 = AtomicU64::new(0);
+
+/// CPUs that still have to handle the current request.
+static PENDING_CPUS: AtomicU64 = AtomicU64::new(0);
+
+static REQUEST_PCID: AtomicUsize = AtomicUsize::new(0);
+static REQUEST_VA: AtomicUsize = AtomicUsize::new(0);
+static REQUEST_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+const STALE_ZERO: AtomicU64 = AtomicU64::new(0);
+const STALE_ROW: [AtomicU64; PCID_MAX / 64] = [STALE_ZERO; PCID_MAX / 64];
+
+/// PCIDs each CPU has to flush the next time it loads them.
+static STALE_PCIDS: [[AtomicU64; PCID_MAX / 64]; NUM_CPUS] = [STALE_ROW; NUM_CPUS];
+
+/// Starts taking shootdown IPIs on the current CPU.
+///
+/// Must be called after the IDT is loaded.
+pub fn init_cpu() {
+    ONLINE_CPUS.fetch_or(1 << cpu::get_cpu_id(), Ordering::SeqCst);
+}
+
+/// Flushes `pages` pages starting at `va` of the address space `pcid` from
+/// every CPU, and returns once they are all done.
+pub fn shootdown(pcid: usize, va: usize, pages: usize) {
+    if pages == 0 {
+        return;
+    }
+
+    let cpu_id = cpu::get_cpu_id();
+    let targets = ONLINE_CPUS.load(Ordering::SeqCst) & !(1 << cpu_id);
+
+    REQUEST_PCID.store(pcid, Ordering::SeqCst);
+    REQUEST_VA.store(va, Ordering::SeqCst);
+    REQUEST_PAGES.store(pages, Ordering::SeqCst);
+    PENDING_CPUS.store(targets, Ordering::SeqCst);
+
+    for target in 0..NUM_CPUS {
+        if targets & (1 << target) != 0 {
+            interrupt::send_ipi(target, interrupt::IRQ_TLB_SHOOTDOWN);
+        }
+    }
+
+    flush_local(cpu_id, pcid, va, pages);
+
+    while PENDING_CPUS.load(Ordering::SeqCst) != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+/// Handles the shootdown request for the current CPU, if there is one.
+pub fn handle_pending() {
+    let cpu_id = cpu::get_cpu_id();
+    let bit = 1 << cpu_id;
+    if PENDING_CPUS.load(Ordering::SeqCst) & bit == 0 {
+        return;
+    }
+
+    flush_local(
+        cpu_id,
+        REQUEST_PCID.load(Ordering::SeqCst),
+        REQUEST_VA.load(Ordering::SeqCst),
+        REQUEST_PAGES.load(Ordering::SeqCst),
+    );
+    PENDING_CPUS.fetch_and(!bit, Ordering::SeqCst);
+}
+
+/// Returns whether the current CPU has to flush `pcid` before using it
+/// again, and clears the mark.
+pub fn take_stale(pcid: usize) -> bool {
+    let word = &STALE_PCIDS[cpu::get_cpu_id()][pcid / 64];
+    let bit = 1 << (pcid % 64);
+    word.fetch_and(!bit, Ordering::SeqCst) & bit != 0
+}
+
+fn flush_local(cpu_id: usize, pcid: usize, va: usize, pages: usize) {
+    let cr3: u64;
+    unsafe {
+        asm!("mov {}, cr3", out(reg) cr3);
+    }
+
+    if (cr3 & PCID_MASK) as usize != pcid {
+        STALE_PCIDS[cpu_id][pcid / 64].fetch_or(1 << (pcid % 64), Ordering::SeqCst);
+        return;
+    }
+
+    if pages > FULL_FLUSH_PAGES {
+        // Reloading CR3 without the no-flush bit drops every entry of the
+        // current PCID
+        unsafe {
+            asm!("mov cr3, {}", in(reg) cr3 & !CR3_NOFLUSH);
+        }
+        return;
+    }
+
+    for i in 0..pages {
+        unsafe {
+            asm!("invlpg [{}]", in(reg) va + i * 4096);
+        }
+    }
+}
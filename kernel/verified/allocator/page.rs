@@ -8,6 +8,7 @@ pub struct Page {
     pub addr: PagePtr,
     pub state: PageState,
     pub is_io_page: bool,
+    pub is_cow: bool,
     pub rev_pointer: SLLIndex,
     pub ref_count: usize,
     pub owning_container: Option<ContainerPtr>,
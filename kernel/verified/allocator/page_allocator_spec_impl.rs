@@ -78,6 +78,7 @@ impl PageAllocator {
                             addr: page_index2page_ptr(index),
                             state: PageState::Unavailable4k,
                             is_io_page: false,
+                            is_cow: false,
                             rev_pointer: 0,
                             ref_count: 0,
                             owning_container: None,
@@ -93,6 +94,7 @@ impl PageAllocator {
                             addr: page_index2page_ptr(index),
                             state: PageState::Unavailable2m,
                             is_io_page: false,
+                            is_cow: false,
                             rev_pointer: 0,
                             ref_count: 0,
                             owning_container: None,
@@ -108,6 +110,7 @@ impl PageAllocator {
                             addr: page_index2page_ptr(index),
                             state: PageState::Unavailable1g,
                             is_io_page: false,
+                            is_cow: false,
                             rev_pointer: 0,
                             ref_count: 0,
                             owning_container: None,
@@ -123,6 +126,7 @@ impl PageAllocator {
                             addr: page_index2page_ptr(index),
                             state: PageState::Pagetable,
                             is_io_page: false,
+                            is_cow: false,
                             rev_pointer: 0,
                             ref_count: 0,
                             owning_container: None,
@@ -138,6 +142,7 @@ impl PageAllocator {
                             addr: page_index2page_ptr(index),
                             state: PageState::Allocated4k,
                             is_io_page: false,
+                            is_cow: false,
                             rev_pointer: 0,
                             ref_count: 0,
                             owning_container: None,
@@ -153,6 +158,7 @@ impl PageAllocator {
                             addr: page_index2page_ptr(index),
                             state: PageState::Allocated2m,
                             is_io_page: false,
+                            is_cow: false,
                             rev_pointer: 0,
                             ref_count: 0,
                             owning_container: None,
@@ -168,6 +174,7 @@ impl PageAllocator {
                             addr: page_index2page_ptr(index),
                             state: PageState::Allocated1g,
                             is_io_page: false,
+                            is_cow: false,
                             rev_pointer: 0,
                             ref_count: 0,
                             owning_container: None,
@@ -184,6 +191,7 @@ impl PageAllocator {
                             addr: page_index2page_ptr(index),
                             state: PageState::Free4k,
                             is_io_page: false,
+                            is_cow: false,
                             rev_pointer: node_ref,
                             ref_count: 0,
                             owning_container: None,
@@ -200,6 +208,7 @@ impl PageAllocator {
                             addr: page_index2page_ptr(index),
                             state: PageState::Free2m,
                             is_io_page: false,
+                            is_cow: false,
                             rev_pointer: node_ref,
                             ref_count: 0,
                             owning_container: None,
@@ -216,6 +225,7 @@ impl PageAllocator {
                             addr: page_index2page_ptr(index),
                             state: PageState::Free1g,
                             is_io_page: false,
+                            is_cow: false,
                             rev_pointer: node_ref,
                             ref_count: 0,
                             owning_container: None,
@@ -231,6 +241,7 @@ impl PageAllocator {
                             addr: page_index2page_ptr(index),
                             state: PageState::Mapped4k,
                             is_io_page: false,
+                            is_cow: false,
                             rev_pointer: 0,
                             ref_count: 1,
                             owning_container: Some(container_ptr),
@@ -246,6 +257,7 @@ impl PageAllocator {
                             addr: page_index2page_ptr(index),
                             state: PageState::Mapped2m,
                             is_io_page: false,
+                            is_cow: false,
                             rev_pointer: 0,
                             ref_count: 1,
                             owning_container: Some(container_ptr),
@@ -261,6 +273,7 @@ impl PageAllocator {
                             addr: page_index2page_ptr(index),
                             state: PageState::Mapped1g,
                             is_io_page: false,
+                            is_cow: false,
                             rev_pointer: 0,
                             ref_count: 1,
                             owning_container: Some(container_ptr),
@@ -276,6 +289,7 @@ impl PageAllocator {
                             addr: page_index2page_ptr(index),
                             state: PageState::Merged2m,
                             is_io_page: false,
+                            is_cow: false,
                             rev_pointer: 0,
                             ref_count: 0,
                             owning_container: None,
@@ -291,6 +305,7 @@ impl PageAllocator {
                             addr: page_index2page_ptr(index),
                             state: PageState::Merged1g,
                             is_io_page: false,
+                            is_cow: false,
                             rev_pointer: 0,
                             ref_count: 0,
                             owning_container: None,
@@ -306,8 +321,9 @@ impl PageAllocator {
                             addr: page_index2page_ptr(index),
                             state: PageState::Io,
                             is_io_page: true,
+                            is_cow: false,
                             rev_pointer: 0,
-                            ref_count: 1,
+                            ref_count: 0,
                             owning_container: Some(container_ptr),
                             mappings: Ghost(Set::<(Pcid, VAddr)>::empty()),
                             io_mappings: Ghost(Set::<(IOid, VAddr)>::empty()),
@@ -324,6 +340,47 @@ impl PageAllocator {
         ||| self.mapped_pages_1g().contains(p)
     }
 
+    /// `p` is shared copy-on-write. Its mappings are read-only and the first
+    /// write through one of them gets a private copy, see `kernel_cow_fault`.
+    pub open spec fn page_is_cow(&self, p: PagePtr) -> bool {
+        self.page_array@[page_ptr2page_index(p) as int].is_cow
+    }
+
+    /// `p` lies in the `Io` region. Such a page is not put on the free list when its last
+    /// mapping goes away.
+    pub open spec fn page_is_io_page(&self, p: PagePtr) -> bool {
+        self.page_array@[page_ptr2page_index(p) as int].is_io_page
+    }
+
+    /// `p` is a device (MMIO) page that is reserved for a container but not
+    /// mapped anywhere.
+    pub open spec fn page_is_mmio(&self, p: PagePtr) -> bool {
+        &&& page_ptr_valid(p)
+        &&& self.page_array@[page_ptr2page_index(p) as int].state == PageState::Io
+    }
+
+    /// Device pages that are reserved but not mapped. The allocator keeps
+    /// their permissions from boot until they are mapped.
+    pub open spec fn mmio_pages_4k(&self) -> Set<PagePtr> {
+        Set::new(|p: PagePtr| self.page_is_mmio(p))
+    }
+
+    pub open spec fn mmio_page_owner(&self, p: PagePtr) -> ContainerPtr
+        recommends
+            self.page_is_mmio(p),
+    {
+        self.page_array@[page_ptr2page_index(p) as int].owning_container.unwrap()
+    }
+
+    /// The contents of `p` are all zero. Pages are scrubbed right before they
+    /// are handed to a user mapping, so nothing leaks between containers.
+    pub open spec fn page_is_zeroed_4k(&self, p: PagePtr) -> bool {
+        &&& self.page_perms_4k@.dom().contains(p)
+        &&& forall|i: int|
+            #![trigger self.page_perms_4k@[p].value()@[i]]
+            0 <= i < PAGE_SZ_4k ==> self.page_perms_4k@[p].value()@[i] == 0u8
+    }
+
     pub closed spec fn free_pages_4k(&self) -> Set<PagePtr> {
         self.free_pages_4k@.to_set()
     }
@@ -387,6 +444,11 @@ impl PageAllocator {
         &&& forall|i: int|
             #![trigger self.page_array@[i].io_mappings]
             0 <= i < NUM_PAGES ==> self.page_array@[i].io_mappings@.finite()
+        &&& forall|i: int|
+            #![trigger self.page_array@[i].is_cow]
+            0 <= i < NUM_PAGES && self.page_array@[i].is_cow
+                ==> self.page_array@[i].io_mappings@ =~= Set::empty()
+                && self.page_array@[i].is_io_page == false
     }
 
     pub open spec fn free_pages_4k_wf(&self) -> bool {
@@ -612,6 +674,7 @@ impl PageAllocator {
 
     pub open spec fn perm_wf(&self) -> bool {
         &&& self.page_perms_4k@.dom() =~= self.mapped_pages_4k@ + self.free_pages_4k@.to_set()
+            + self.mmio_pages_4k()
         &&& forall|p: PagePtr|
             #![trigger self.page_perms_4k@[p].is_init()]
             #![trigger self.page_perms_4k@[p].addr()]
@@ -651,7 +714,8 @@ impl PageAllocator {
             #![trigger self.page_array@[i], self.page_array@[i].owning_container.is_Some()]
             0 <= i < NUM_PAGES && self.page_array@[i].owning_container.is_Some() ==> (
             self.page_array@[i].state == PageState::Mapped4k || self.page_array@[i].state
-                == PageState::Mapped2m || self.page_array@[i].state == PageState::Mapped1g)
+                == PageState::Mapped2m || self.page_array@[i].state == PageState::Mapped1g
+                || self.page_array@[i].state == PageState::Io)
         &&& forall|i: usize|
             #![trigger self.page_array@[i as int].state, self.page_array@[i as int].owning_container]
             0 <= i < NUM_PAGES && self.page_array@[i as int].state == PageState::Mapped4k
@@ -705,6 +769,13 @@ impl PageAllocator {
             ) as int].owning_container.unwrap() == c_ptr
     }
 
+    pub open spec fn io_pages_wf(&self) -> bool {
+        &&& forall|i: int|
+            #![trigger self.page_array@[i].state]
+            0 <= i < NUM_PAGES && self.page_array@[i].state == PageState::Io
+                ==> self.page_array@[i].is_io_page && self.page_array@[i].owning_container.is_Some()
+    }
+
     pub open spec fn mapped_pages_have_reference_counter(&self) -> bool {
         &&& forall|i: int|
             #![trigger self.page_array@[i].ref_count]
@@ -733,6 +804,7 @@ impl PageAllocator {
         &&& self.hugepages_wf()
         &&& self.perm_wf()
         &&& self.container_wf()
+        &&& self.io_pages_wf()
         &&& self.mapped_pages_have_reference_counter()
     }
 }
@@ -775,6 +847,22 @@ impl PageAllocator {
     {
     }
 
+    /// A page whose only reference is a page table mapping is not reachable
+    /// by any device.
+    pub proof fn single_mapping_has_no_io_mappings(&self, page_ptr: PagePtr, pcid: Pcid, va: VAddr)
+        requires
+            self.wf(),
+            self.mapped_pages_4k().contains(page_ptr),
+            self.page_mappings(page_ptr).contains((pcid, va)),
+            self.page_mappings(page_ptr).len() + self.page_io_mappings(page_ptr).len() == 1,
+        ensures
+            self.page_io_mappings(page_ptr) =~= Set::empty(),
+    {
+        page_ptr_lemma1();
+        lemma_set_empty_equivalency_len(self.page_mappings(page_ptr));
+        lemma_set_empty_equivalency_len(self.page_io_mappings(page_ptr));
+    }
+
     pub proof fn free_pages_are_not_mapped(&self)
         requires
             self.wf(),
@@ -983,6 +1071,109 @@ impl PageAllocator {
         self.page_array.get(page_ptr2page_index(page_ptr)).ref_count
     }
 
+    pub fn get_page_is_io_page(&self, page_ptr: PagePtr) -> (ret: bool)
+        requires
+            self.wf(),
+            self.page_is_mapped(page_ptr),
+        ensures
+            ret == self.page_array@[page_ptr2page_index(page_ptr) as int].is_io_page,
+    {
+        self.page_array.get(page_ptr2page_index(page_ptr)).is_io_page
+    }
+
+    pub fn get_page_is_cow(&self, page_ptr: PagePtr) -> (ret: bool)
+        requires
+            self.wf(),
+            self.page_is_mapped(page_ptr),
+        ensures
+            ret == self.page_is_cow(page_ptr),
+    {
+        self.page_array.get(page_ptr2page_index(page_ptr)).is_cow
+    }
+
+    pub fn set_page_is_cow(&mut self, target_ptr: PagePtr, is_cow: bool)
+        requires
+            old(self).wf(),
+            old(self).mapped_pages_4k().contains(target_ptr),
+            is_cow ==> old(self).page_io_mappings(target_ptr) =~= Set::empty()
+                && old(self).page_array@[page_ptr2page_index(target_ptr) as int].is_io_page
+                == false,
+        ensures
+            self.wf(),
+            self.free_pages_4k.len() == old(self).free_pages_4k.len(),
+            self.free_pages_4k() =~= old(self).free_pages_4k(),
+            self.free_pages_2m() =~= old(self).free_pages_2m(),
+            self.free_pages_1g() =~= old(self).free_pages_1g(),
+            self.allocated_pages_4k() =~= old(self).allocated_pages_4k(),
+            self.allocated_pages_2m() =~= old(self).allocated_pages_2m(),
+            self.allocated_pages_1g() =~= old(self).allocated_pages_1g(),
+            self.mapped_pages_4k() =~= old(self).mapped_pages_4k(),
+            self.mapped_pages_2m() =~= old(self).mapped_pages_2m(),
+            self.mapped_pages_1g() =~= old(self).mapped_pages_1g(),
+            forall|p: PagePtr|
+                #![trigger self.page_mappings(p)]
+                #![trigger self.page_io_mappings(p)]
+                self.page_mappings(p) =~= old(self).page_mappings(p) && self.page_io_mappings(p)
+                    =~= old(self).page_io_mappings(p),
+            forall|i: int|
+                #![trigger self.page_array@[i].is_io_page]
+                0 <= i < NUM_PAGES ==> self.page_array@[i].is_io_page == old(
+                    self,
+                ).page_array@[i].is_io_page,
+            forall|p: PagePtr|
+                #![trigger self.page_is_cow(p)]
+                page_ptr_valid(p) && p != target_ptr ==> self.page_is_cow(p) == old(
+                    self,
+                ).page_is_cow(p),
+            self.page_is_cow(target_ptr) == is_cow,
+            self.container_map_4k@.dom() =~= old(self).container_map_4k@.dom(),
+            forall|p: PagePtr| #![auto] self.page_is_mapped(p) <==> old(self).page_is_mapped(p),
+            forall|c: ContainerPtr|
+                #![auto]
+                self.container_map_4k@.dom().contains(c) ==> self.get_container_owned_pages(c)
+                    =~= old(self).get_container_owned_pages(c),
+    {
+        proof {
+            page_ptr_lemma1();
+            self.free_pages_1g.wf_to_no_duplicates();
+            self.free_pages_2m.wf_to_no_duplicates();
+            self.free_pages_4k.wf_to_no_duplicates();
+        }
+        assert(page_ptr_valid(target_ptr));
+        self.set_is_cow(page_ptr2page_index(target_ptr), is_cow);
+
+        assert(self.page_array_wf());
+        assert(self.free_pages_4k_wf());
+        assert(self.free_pages_2m_wf()) by {
+            page_ptr_2m_lemma();
+        };
+        assert(self.free_pages_1g_wf()) by {
+            page_ptr_1g_lemma();
+        };
+        assert(self.allocated_pages_4k_wf());
+        assert(self.allocated_pages_2m_wf()) by {
+            page_ptr_2m_lemma();
+        };
+        assert(self.allocated_pages_1g_wf()) by {
+            page_ptr_1g_lemma();
+        };
+        assert(self.mapped_pages_4k_wf());
+        assert(self.mapped_pages_2m_wf()) by {
+            page_ptr_2m_lemma();
+        };
+        assert(self.mapped_pages_1g_wf()) by {
+            page_ptr_1g_lemma();
+        };
+        assert(self.merged_pages_wf()) by {
+            page_ptr_page_index_truncate_lemma();
+        };
+        assert(self.hugepages_wf()) by {
+            page_index_lemma();
+            page_ptr_2m_lemma();
+            page_ptr_1g_lemma();
+        };
+    }
+
     pub fn alloc_page_2m(&mut self) -> (ret: (PagePtr, Tracked<PagePerm2m>))
         requires
             old(self).wf(),
@@ -1185,6 +1376,10 @@ impl PageAllocator {
             old(self).free_pages_4k().contains(ret.0),
             forall|p: PagePtr| #![auto] self.page_is_mapped(p) == old(self).page_is_mapped(p),
             self.free_pages_4k.len() == old(self).free_pages_4k.len() - 1,
+            forall|p: PagePtr|
+                #![trigger self.page_is_mmio(p)]
+                self.page_is_mmio(p) == old(self).page_is_mmio(p) && (self.page_is_mmio(p)
+                    ==> self.mmio_page_owner(p) == old(self).mmio_page_owner(p)),
     {
         proof {
             page_ptr_lemma1();
@@ -1432,6 +1627,8 @@ impl PageAllocator {
             self.page_io_mappings(ret) =~= Set::<(IOid, VAddr)>::empty(),
             old(self).allocated_pages_4k().contains(ret) == false,
             page_ptr_valid(ret),
+            self.page_is_zeroed_4k(ret),
+            self.page_is_cow(ret) == false,
             old(self).container_map_4k@.dom() =~= self.container_map_4k@.dom(),
             old(self).container_map_2m@.dom() =~= self.container_map_2m@.dom(),
             old(self).container_map_1g@.dom() =~= self.container_map_1g@.dom(),
@@ -1465,6 +1662,7 @@ impl PageAllocator {
             Ghost(Set::<(Pcid, VAddr)>::empty().insert((pcid, va))),
         );
         self.set_io_mapping(page_ptr2page_index(ret), Ghost(Set::<(IOid, VAddr)>::empty()));
+        self.set_is_cow(page_ptr2page_index(ret), false);
         self.set_owning_container(page_ptr2page_index(ret), Some(c_ptr));
         proof {
             self.container_map_4k@ = self.container_map_4k@.insert(
@@ -1508,6 +1706,7 @@ impl PageAllocator {
             page_ptr_1g_lemma();
 
         };
+        self.zero_page_4k(ret);
         return ret;
     }
 
@@ -1544,6 +1743,7 @@ impl PageAllocator {
             self.page_io_mappings(ret).contains((ioid, va)),
             old(self).allocated_pages_4k().contains(ret) == false,
             page_ptr_valid(ret),
+            self.page_is_zeroed_4k(ret),
             old(self).container_map_4k@.dom() =~= self.container_map_4k@.dom(),
             old(self).container_map_2m@.dom() =~= self.container_map_2m@.dom(),
             old(self).container_map_1g@.dom() =~= self.container_map_1g@.dom(),
@@ -1573,6 +1773,7 @@ impl PageAllocator {
         self.set_state(page_ptr2page_index(ret), PageState::Mapped4k);
         self.set_ref_count(page_ptr2page_index(ret), 1);
         self.set_mapping(page_ptr2page_index(ret), Ghost(Set::<(Pcid, VAddr)>::empty()));
+        self.set_is_cow(page_ptr2page_index(ret), false);
         self.set_io_mapping(
             page_ptr2page_index(ret),
             Ghost(Set::<(IOid, VAddr)>::empty().insert((ioid, va))),
@@ -1620,9 +1821,213 @@ impl PageAllocator {
             page_ptr_1g_lemma();
 
         };
+        self.zero_page_4k(ret);
         return ret;
     }
 
+    pub fn get_mmio_page_owner(&self, page_ptr: PagePtr) -> (ret: Option<ContainerPtr>)
+        requires
+            self.wf(),
+            page_ptr_valid(page_ptr),
+        ensures
+            ret.is_Some() == self.page_is_mmio(page_ptr),
+            ret.is_Some() ==> ret.unwrap() == self.mmio_page_owner(page_ptr),
+    {
+        let page = self.page_array.get(page_ptr2page_index(page_ptr));
+        match page.state {
+            PageState::Io => page.owning_container,
+            _ => None,
+        }
+    }
+
+    /// Reserves the unmapped device page `target_ptr` for `c_ptr` instead of
+    /// its current owner.
+    pub fn set_mmio_page_owner(&mut self, target_ptr: PagePtr, c_ptr: ContainerPtr)
+        requires
+            old(self).wf(),
+            old(self).page_is_mmio(target_ptr),
+        ensures
+            self.wf(),
+            self.free_pages_4k.len() == old(self).free_pages_4k.len(),
+            self.free_pages_4k() =~= old(self).free_pages_4k(),
+            self.free_pages_2m() =~= old(self).free_pages_2m(),
+            self.free_pages_1g() =~= old(self).free_pages_1g(),
+            self.allocated_pages_4k() =~= old(self).allocated_pages_4k(),
+            self.allocated_pages_2m() =~= old(self).allocated_pages_2m(),
+            self.allocated_pages_1g() =~= old(self).allocated_pages_1g(),
+            self.mapped_pages_4k() =~= old(self).mapped_pages_4k(),
+            self.mapped_pages_2m() =~= old(self).mapped_pages_2m(),
+            self.mapped_pages_1g() =~= old(self).mapped_pages_1g(),
+            self.container_map_4k@ =~= old(self).container_map_4k@,
+            self.container_map_2m@ =~= old(self).container_map_2m@,
+            self.container_map_1g@ =~= old(self).container_map_1g@,
+            forall|p: PagePtr|
+                #![trigger self.page_mappings(p)]
+                #![trigger self.page_io_mappings(p)]
+                self.page_mappings(p) =~= old(self).page_mappings(p) && self.page_io_mappings(p)
+                    =~= old(self).page_io_mappings(p),
+            forall|p: PagePtr| #![auto] self.page_is_mapped(p) <==> old(self).page_is_mapped(p),
+            forall|p: PagePtr| #![auto] self.page_is_io_page(p) == old(self).page_is_io_page(p),
+            forall|p: PagePtr| #![auto] self.page_is_cow(p) == old(self).page_is_cow(p),
+            forall|p: PagePtr| #![auto] self.page_is_mmio(p) == old(self).page_is_mmio(p),
+            forall|p: PagePtr|
+                #![auto]
+                self.page_is_mmio(p) && p != target_ptr ==> self.mmio_page_owner(p) == old(
+                    self,
+                ).mmio_page_owner(p),
+            self.mmio_page_owner(target_ptr) == c_ptr,
+    {
+        proof {
+            page_ptr_lemma1();
+            self.free_pages_1g.wf_to_no_duplicates();
+            self.free_pages_2m.wf_to_no_duplicates();
+            self.free_pages_4k.wf_to_no_duplicates();
+        }
+        assert(page_ptr_valid(target_ptr));
+        self.set_owning_container(page_ptr2page_index(target_ptr), Some(c_ptr));
+
+        assert(self.page_array_wf());
+        assert(self.free_pages_4k_wf());
+        assert(self.free_pages_2m_wf()) by {
+            page_ptr_2m_lemma();
+        };
+        assert(self.free_pages_1g_wf()) by {
+            page_ptr_1g_lemma();
+        };
+        assert(self.allocated_pages_4k_wf());
+        assert(self.allocated_pages_2m_wf()) by {
+            page_ptr_2m_lemma();
+        };
+        assert(self.allocated_pages_1g_wf()) by {
+            page_ptr_1g_lemma();
+        };
+        assert(self.mapped_pages_4k_wf());
+        assert(self.mapped_pages_2m_wf()) by {
+            page_ptr_2m_lemma();
+        };
+        assert(self.mapped_pages_1g_wf()) by {
+            page_ptr_1g_lemma();
+        };
+        assert(self.merged_pages_wf()) by {
+            page_ptr_page_index_truncate_lemma();
+        };
+        assert(self.hugepages_wf()) by {
+            page_index_lemma();
+            page_ptr_2m_lemma();
+            page_ptr_1g_lemma();
+        };
+        assert(self.io_pages_wf());
+    }
+
+    pub fn map_mmio_4k(&mut self, target_ptr: PagePtr, pcid: Pcid, va: VAddr)
+        requires
+            old(self).wf(),
+            old(self).page_is_mmio(target_ptr),
+            old(self).container_map_4k@.dom().contains(old(self).mmio_page_owner(target_ptr)),
+        ensures
+            self.wf(),
+            self.free_pages_4k() =~= old(self).free_pages_4k(),
+            self.free_pages_2m() =~= old(self).free_pages_2m(),
+            self.free_pages_1g() =~= old(self).free_pages_1g(),
+            self.allocated_pages_4k() =~= old(self).allocated_pages_4k(),
+            self.allocated_pages_2m() =~= old(self).allocated_pages_2m(),
+            self.allocated_pages_1g() =~= old(self).allocated_pages_1g(),
+            self.mapped_pages_4k() =~= old(self).mapped_pages_4k().insert(target_ptr),
+            self.mapped_pages_2m() =~= old(self).mapped_pages_2m(),
+            self.mapped_pages_1g() =~= old(self).mapped_pages_1g(),
+            forall|p: PagePtr|
+                #![trigger self.page_is_mapped(p)]
+                #![trigger self.page_mappings(p)]
+                self.page_is_mapped(p) && p != target_ptr ==> self.page_mappings(p) =~= old(
+                    self,
+                ).page_mappings(p) && self.page_io_mappings(p) =~= old(self).page_io_mappings(p),
+            self.page_mappings(target_ptr) =~= Set::<(Pcid, VAddr)>::empty().insert((pcid, va)),
+            self.page_io_mappings(target_ptr) =~= Set::<(IOid, VAddr)>::empty(),
+            old(self).container_map_4k@.dom() =~= self.container_map_4k@.dom(),
+            old(self).container_map_2m@.dom() =~= self.container_map_2m@.dom(),
+            old(self).container_map_1g@.dom() =~= self.container_map_1g@.dom(),
+            forall|p: PagePtr| #![auto] self.page_is_mapped(p) <== old(self).page_is_mapped(p),
+            !old(self).page_is_mapped(target_ptr),
+            self.page_is_mapped(target_ptr),
+            self.free_pages_4k.len() == old(self).free_pages_4k.len(),
+            forall|c: ContainerPtr|
+                #![auto]
+                self.container_map_4k@.dom().contains(c) && old(self).mmio_page_owner(target_ptr)
+                    != c ==> self.get_container_owned_pages(c) =~= old(
+                    self,
+                ).get_container_owned_pages(c),
+            self.get_container_owned_pages(old(self).mmio_page_owner(target_ptr)) =~= old(
+                self,
+            ).get_container_owned_pages(old(self).mmio_page_owner(target_ptr)).insert(target_ptr),
+            self.page_is_mmio(target_ptr) == false,
+            forall|p: PagePtr|
+                #![trigger self.page_is_mmio(p)]
+                p != target_ptr ==> self.page_is_mmio(p) == old(self).page_is_mmio(p) && (
+                self.page_is_mmio(p) ==> self.mmio_page_owner(p) == old(self).mmio_page_owner(p)),
+    {
+        proof {
+            page_ptr_lemma1();
+            self.free_pages_1g.wf_to_no_duplicates();
+            self.free_pages_2m.wf_to_no_duplicates();
+            self.free_pages_4k.wf_to_no_duplicates();
+        }
+        let c_ptr = self.page_array.get(page_ptr2page_index(target_ptr)).owning_container.unwrap();
+        self.set_state(page_ptr2page_index(target_ptr), PageState::Mapped4k);
+        self.set_ref_count(page_ptr2page_index(target_ptr), 1);
+        self.set_mapping(
+            page_ptr2page_index(target_ptr),
+            Ghost(Set::<(Pcid, VAddr)>::empty().insert((pcid, va))),
+        );
+        self.set_io_mapping(page_ptr2page_index(target_ptr), Ghost(Set::<(IOid, VAddr)>::empty()));
+        proof {
+            self.container_map_4k@ = self.container_map_4k@.insert(
+                c_ptr,
+                self.container_map_4k@[c_ptr].insert(target_ptr),
+            );
+        }
+        assert(self.page_array@[page_ptr2page_index(target_ptr) as int].is_io_page == true);
+        // The permission of a device page stays with the allocator, it only
+        // moves from the reserved set to the mapped set.
+        proof {
+            self.mapped_pages_4k@ = self.mapped_pages_4k@.insert(target_ptr);
+        }
+        assert(self.page_perms_4k@.dom() =~= self.mapped_pages_4k@ + self.free_pages_4k@.to_set()
+            + self.mmio_pages_4k());
+
+        assert(self.page_array_wf());
+        assert(self.free_pages_4k_wf());
+        assert(self.free_pages_2m_wf()) by {
+            page_ptr_2m_lemma();
+        };
+        assert(self.free_pages_1g_wf()) by {
+            page_ptr_1g_lemma();
+        };
+        assert(self.allocated_pages_4k_wf());
+        assert(self.allocated_pages_2m_wf()) by {
+            page_ptr_2m_lemma();
+        };
+        assert(self.allocated_pages_1g_wf()) by {
+            page_ptr_1g_lemma();
+        };
+        assert(self.mapped_pages_4k_wf());
+        assert(self.mapped_pages_2m_wf()) by {
+            page_ptr_2m_lemma();
+        };
+        assert(self.mapped_pages_1g_wf()) by {
+            page_ptr_1g_lemma();
+        };
+        assert(self.merged_pages_wf()) by {
+            page_ptr_page_index_truncate_lemma();
+        };
+        assert(self.hugepages_wf()) by {
+            page_index_lemma();
+            page_ptr_2m_lemma();
+            page_ptr_1g_lemma();
+        };
+        assert(self.perm_wf());
+        assert(self.io_pages_wf());
+    }
+
     pub fn alloc_and_map_2m(&mut self, pcid: Pcid, va: VAddr, c_ptr: ContainerPtr) -> (ret: PagePtr)
         requires
             old(self).wf(),
@@ -1795,10 +2200,110 @@ impl PageAllocator {
         };
     }
 
+    pub fn transfer_page_owner_4k(&mut self, target_ptr: PagePtr, c_ptr: ContainerPtr) -> (ret:
+        ContainerPtr)
+        requires
+            old(self).wf(),
+            old(self).mapped_pages_4k().contains(target_ptr),
+            old(self).container_map_4k@.dom().contains(c_ptr),
+        ensures
+            self.wf(),
+            self.free_pages_4k.len() == old(self).free_pages_4k.len(),
+            self.free_pages_4k() =~= old(self).free_pages_4k(),
+            self.free_pages_2m() =~= old(self).free_pages_2m(),
+            self.free_pages_1g() =~= old(self).free_pages_1g(),
+            self.allocated_pages_4k() =~= old(self).allocated_pages_4k(),
+            self.allocated_pages_2m() =~= old(self).allocated_pages_2m(),
+            self.allocated_pages_1g() =~= old(self).allocated_pages_1g(),
+            self.mapped_pages_4k() =~= old(self).mapped_pages_4k(),
+            self.mapped_pages_2m() =~= old(self).mapped_pages_2m(),
+            self.mapped_pages_1g() =~= old(self).mapped_pages_1g(),
+            forall|p: PagePtr|
+                #![trigger self.page_is_mapped(p)]
+                #![trigger self.page_mappings(p)]
+                self.page_is_mapped(p) ==> self.page_mappings(p) =~= old(self).page_mappings(p)
+                    && self.page_io_mappings(p) =~= old(self).page_io_mappings(p),
+            forall|p: PagePtr| #![auto] self.page_is_mapped(p) <==> old(self).page_is_mapped(p),
+            old(self).container_map_4k@.dom() =~= self.container_map_4k@.dom(),
+            old(self).container_map_2m@.dom() =~= self.container_map_2m@.dom(),
+            old(self).container_map_1g@.dom() =~= self.container_map_1g@.dom(),
+            old(self).container_map_4k@.dom().contains(ret),
+            old(self).get_container_owned_pages(ret).contains(target_ptr),
+            ret == c_ptr ==> forall|c: ContainerPtr|
+                #![auto]
+                self.container_map_4k@.dom().contains(c) ==> self.get_container_owned_pages(c)
+                    =~= old(self).get_container_owned_pages(c),
+            ret != c_ptr ==> forall|c: ContainerPtr|
+                #![auto]
+                self.container_map_4k@.dom().contains(c) && c != c_ptr && c != ret
+                    ==> self.get_container_owned_pages(c) =~= old(self).get_container_owned_pages(
+                    c,
+                ),
+            ret != c_ptr ==> self.get_container_owned_pages(ret) =~= old(
+                self,
+            ).get_container_owned_pages(ret).remove(target_ptr),
+            ret != c_ptr ==> self.get_container_owned_pages(c_ptr) =~= old(
+                self,
+            ).get_container_owned_pages(c_ptr).insert(target_ptr),
+    {
+        proof {
+            page_ptr_lemma1();
+            self.free_pages_1g.wf_to_no_duplicates();
+            self.free_pages_2m.wf_to_no_duplicates();
+            self.free_pages_4k.wf_to_no_duplicates();
+        }
+        assert(page_ptr_valid(target_ptr));
+        let old_c_ptr = self.page_array.get(page_ptr2page_index(target_ptr)).owning_container.unwrap();
+        if old_c_ptr == c_ptr {
+            return old_c_ptr;
+        }
+        self.set_owning_container(page_ptr2page_index(target_ptr), Some(c_ptr));
+        proof {
+            self.container_map_4k@ = self.container_map_4k@.insert(
+                old_c_ptr,
+                self.container_map_4k@[old_c_ptr].remove(target_ptr),
+            ).insert(c_ptr, self.container_map_4k@[c_ptr].insert(target_ptr));
+        }
+
+        assert(self.page_array_wf());
+        assert(self.free_pages_4k_wf());
+        assert(self.free_pages_2m_wf()) by {
+            page_ptr_2m_lemma();
+        };
+        assert(self.free_pages_1g_wf()) by {
+            page_ptr_1g_lemma();
+        };
+        assert(self.allocated_pages_4k_wf());
+        assert(self.allocated_pages_2m_wf()) by {
+            page_ptr_2m_lemma();
+        };
+        assert(self.allocated_pages_1g_wf()) by {
+            page_ptr_1g_lemma();
+        };
+        assert(self.mapped_pages_4k_wf());
+        assert(self.mapped_pages_2m_wf()) by {
+            page_ptr_2m_lemma();
+        };
+        assert(self.mapped_pages_1g_wf()) by {
+            page_ptr_1g_lemma();
+        };
+        assert(self.merged_pages_wf()) by {
+            page_ptr_page_index_truncate_lemma();
+        };
+        assert(self.hugepages_wf()) by {
+            page_index_lemma();
+            page_ptr_2m_lemma();
+            page_ptr_1g_lemma();
+
+        };
+        old_c_ptr
+    }
+
     pub fn add_io_mapping_4k(&mut self, target_ptr: PagePtr, ioid: IOid, va: VAddr)
         requires
             old(self).wf(),
             old(self).mapped_pages_4k().contains(target_ptr),
+            old(self).page_is_cow(target_ptr) == false,
             old(self).page_io_mappings(target_ptr).contains((ioid, va)) == false,
             old(self).page_mappings(target_ptr).len() + old(self).page_io_mappings(target_ptr).len()
                 < usize::MAX,
@@ -1923,8 +2428,9 @@ impl PageAllocator {
         let c_ptr = self.page_array.get(page_ptr2page_index(target_ptr)).owning_container.unwrap();
         self.set_ref_count(page_ptr2page_index(target_ptr), 0);
         self.set_mapping(page_ptr2page_index(target_ptr), Ghost(Set::empty()));
-        self.set_state(page_ptr2page_index(target_ptr), PageState::Unavailable4k);
-        self.set_owning_container(page_ptr2page_index(target_ptr), None);
+        // Device pages are never freed. Once the last mapping is gone the page
+        // is reserved for its owning container again.
+        self.set_state(page_ptr2page_index(target_ptr), PageState::Io);
         proof {
             self.mapped_pages_4k@ = self.mapped_pages_4k@.remove(target_ptr);
         }
@@ -1934,7 +2440,8 @@ impl PageAllocator {
                 self.container_map_4k@[c_ptr].remove(target_ptr),
             );
         }
-        let tracked mut removed_perm = self.page_perms_4k.borrow_mut().tracked_remove(target_ptr);
+        assert(self.page_perms_4k@.dom() =~= self.mapped_pages_4k@ + self.free_pages_4k@.to_set()
+            + self.mmio_pages_4k());
         assert(self.page_array_wf());
         assert(self.free_pages_4k_wf());
         assert(self.free_pages_2m_wf()) by {
@@ -2191,6 +2698,7 @@ impl PageAllocator {
             old(self).page_array@[page_ptr2page_index(target_ptr) as int].ref_count == 1,
         ensures
             self.wf(),
+            self.free_pages_4k.len() == old(self).free_pages_4k.len(),
             self.allocated_pages_4k() =~= old(self).allocated_pages_4k(),
             self.allocated_pages_2m() =~= old(self).allocated_pages_2m(),
             self.allocated_pages_1g() =~= old(self).allocated_pages_1g(),
@@ -2205,6 +2713,9 @@ impl PageAllocator {
             // self.container_map_4k@ =~= old(self).container_map_4k@,
             self.container_map_2m@ =~= old(self).container_map_2m@,
             self.container_map_1g@ =~= old(self).container_map_1g@,
+            self.free_pages_4k() =~= old(self).free_pages_4k(),
+            forall|p: PagePtr| #![auto] self.page_is_io_page(p) == old(self).page_is_io_page(p),
+            !self.page_is_mapped(target_ptr),
             self.container_map_4k@ =~= old(self).container_map_4k@.insert(
                 old(self).page_array@[page_ptr2page_index(
                     target_ptr,
@@ -2279,6 +2790,7 @@ impl PageAllocator {
             old(self).page_array@[page_ptr2page_index(target_ptr) as int].ref_count == 1,
         ensures
             self.wf(),
+            self.free_pages_4k.len() == old(self).free_pages_4k.len() + 1,
             self.allocated_pages_4k() =~= old(self).allocated_pages_4k(),
             self.allocated_pages_2m() =~= old(self).allocated_pages_2m(),
             self.allocated_pages_1g() =~= old(self).allocated_pages_1g(),
@@ -2293,6 +2805,9 @@ impl PageAllocator {
             // self.container_map_4k@ =~= old(self).container_map_4k@,
             self.container_map_2m@ =~= old(self).container_map_2m@,
             self.container_map_1g@ =~= old(self).container_map_1g@,
+            self.free_pages_4k() =~= old(self).free_pages_4k().insert(target_ptr),
+            forall|p: PagePtr| #![auto] self.page_is_io_page(p) == old(self).page_is_io_page(p),
+            !self.page_is_mapped(target_ptr),
             self.container_map_4k@ =~= old(self).container_map_4k@.insert(
                 old(self).page_array@[page_ptr2page_index(
                     target_ptr,
@@ -2331,6 +2846,9 @@ impl PageAllocator {
                 self.container_map_4k@[c_ptr].remove(target_ptr),
             );
         }
+        assert(self.free_pages_4k@.to_set() =~= old(self).free_pages_4k@.to_set().insert(
+            target_ptr,
+        ));
         assert(self.page_array_wf());
         assert(self.free_pages_4k_wf());
         assert(self.free_pages_2m_wf()) by {
@@ -2372,6 +2890,7 @@ impl PageAllocator {
             old(self).page_array@[page_ptr2page_index(target_ptr) as int].ref_count != 1,
         ensures
             self.wf(),
+            self.free_pages_4k.len() == old(self).free_pages_4k.len(),
             self.allocated_pages_4k() =~= old(self).allocated_pages_4k(),
             self.allocated_pages_2m() =~= old(self).allocated_pages_2m(),
             self.allocated_pages_1g() =~= old(self).allocated_pages_1g(),
@@ -2386,6 +2905,8 @@ impl PageAllocator {
             self.container_map_4k@ =~= old(self).container_map_4k@,
             self.container_map_2m@ =~= old(self).container_map_2m@,
             self.container_map_1g@ =~= old(self).container_map_1g@,
+            self.free_pages_4k() =~= old(self).free_pages_4k(),
+            forall|p: PagePtr| #![auto] self.page_is_io_page(p) == old(self).page_is_io_page(p),
     {
         proof {
             page_ptr_lemma1();
@@ -2445,6 +2966,12 @@ impl PageAllocator {
             old(self).page_io_mappings(target_ptr).contains((ioid, va)),
         ensures
             self.wf(),
+            old(self).page_array@[page_ptr2page_index(target_ptr) as int].ref_count == 1
+                && old(self).page_array@[page_ptr2page_index(target_ptr) as int].is_io_page == false
+                ==> self.free_pages_4k.len() == old(self).free_pages_4k.len() + 1,
+            !(old(self).page_array@[page_ptr2page_index(target_ptr) as int].ref_count == 1
+                && old(self).page_array@[page_ptr2page_index(target_ptr) as int].is_io_page == false)
+                ==> self.free_pages_4k.len() == old(self).free_pages_4k.len(),
             self.allocated_pages_4k() =~= old(self).allocated_pages_4k(),
             self.allocated_pages_2m() =~= old(self).allocated_pages_2m(),
             self.allocated_pages_1g() =~= old(self).allocated_pages_1g(),
@@ -2459,6 +2986,15 @@ impl PageAllocator {
             // self.container_map_4k@ =~= old(self).container_map_4k@,
             self.container_map_2m@ =~= old(self).container_map_2m@,
             self.container_map_1g@ =~= old(self).container_map_1g@,
+            old(self).page_array@[page_ptr2page_index(target_ptr) as int].ref_count == 1
+                && old(self).page_array@[page_ptr2page_index(target_ptr) as int].is_io_page == false
+                ==> self.free_pages_4k() =~= old(self).free_pages_4k().insert(target_ptr),
+            !(old(self).page_array@[page_ptr2page_index(target_ptr) as int].ref_count == 1
+                && old(self).page_array@[page_ptr2page_index(target_ptr) as int].is_io_page == false)
+                ==> self.free_pages_4k() =~= old(self).free_pages_4k(),
+            forall|p: PagePtr| #![auto] self.page_is_io_page(p) == old(self).page_is_io_page(p),
+            old(self).page_array@[page_ptr2page_index(target_ptr) as int].ref_count == 1
+                ==> !self.page_is_mapped(target_ptr),
             ret.is_None() ==> self.container_map_4k@ =~= old(self).container_map_4k@,
             ret.is_Some() ==> self.container_map_4k@ =~= old(self).container_map_4k@.insert(
                 ret.unwrap(),
@@ -2641,6 +3177,7 @@ impl PageAllocator {
                         addr: page_index2page_ptr(target_page_idx + index),
                         state: PageState::Merged2m,
                         is_io_page: false,
+                        is_cow: false,
                         rev_pointer: 0,
                         ref_count: 0,
                         owning_container: None,
@@ -2681,6 +3218,7 @@ impl PageAllocator {
                     addr: page_index2page_ptr(target_page_idx),
                     state: PageState::Free2m,
                     is_io_page: false,
+                    is_cow: false,
                     rev_pointer: node_ref,
                     ref_count: 0,
                     owning_container: None,
@@ -2791,6 +3329,7 @@ impl PageAllocator {
                         addr: page_index2page_ptr(target_page_idx),
                         state: PageState::Free4k,
                         is_io_page: false,
+                        is_cow: false,
                         rev_pointer: node_ref,
                         ref_count: 0,
                         owning_container: None,
@@ -2965,6 +3504,7 @@ impl PageAllocator {
                         addr: page_index2page_ptr(target_page_idx + index),
                         state: PageState::Free4k,
                         is_io_page: false,
+                        is_cow: false,
                         rev_pointer: node_ref,
                         ref_count: 0,
                         owning_container: None,
@@ -31,6 +31,9 @@ impl PageAllocator {
             self.page_array@[index as int].is_io_page =~= old(
                 self,
             ).page_array@[index as int].is_io_page,
+            self.page_array@[index as int].is_cow =~= old(
+                self,
+            ).page_array@[index as int].is_cow,
             self.page_array@[index as int].rev_pointer =~= old(
                 self,
             ).page_array@[index as int].rev_pointer,
@@ -84,6 +87,9 @@ impl PageAllocator {
             self.page_array@[index as int].is_io_page =~= old(
                 self,
             ).page_array@[index as int].is_io_page,
+            self.page_array@[index as int].is_cow =~= old(
+                self,
+            ).page_array@[index as int].is_cow,
             // self.page_array@[index as int].rev_pointer =~= old(self).page_array@[index as int].rev_pointer,
             self.page_array@[index as int].ref_count =~= old(
                 self,
@@ -135,6 +141,9 @@ impl PageAllocator {
             self.page_array@[index as int].is_io_page =~= old(
                 self,
             ).page_array@[index as int].is_io_page,
+            self.page_array@[index as int].is_cow =~= old(
+                self,
+            ).page_array@[index as int].is_cow,
             self.page_array@[index as int].rev_pointer =~= old(
                 self,
             ).page_array@[index as int].rev_pointer,
@@ -185,6 +194,9 @@ impl PageAllocator {
             self.page_array@[index as int].is_io_page =~= old(
                 self,
             ).page_array@[index as int].is_io_page,
+            self.page_array@[index as int].is_cow =~= old(
+                self,
+            ).page_array@[index as int].is_cow,
             self.page_array@[index as int].rev_pointer =~= old(
                 self,
             ).page_array@[index as int].rev_pointer,
@@ -235,6 +247,9 @@ impl PageAllocator {
             self.page_array@[index as int].is_io_page =~= old(
                 self,
             ).page_array@[index as int].is_io_page,
+            self.page_array@[index as int].is_cow =~= old(
+                self,
+            ).page_array@[index as int].is_cow,
             self.page_array@[index as int].rev_pointer =~= old(
                 self,
             ).page_array@[index as int].rev_pointer,
@@ -285,6 +300,9 @@ impl PageAllocator {
             self.page_array@[index as int].is_io_page =~= old(
                 self,
             ).page_array@[index as int].is_io_page,
+            self.page_array@[index as int].is_cow =~= old(
+                self,
+            ).page_array@[index as int].is_cow,
             self.page_array@[index as int].rev_pointer =~= old(
                 self,
             ).page_array@[index as int].rev_pointer,
@@ -317,6 +335,96 @@ impl PageAllocator {
     {
         self.page_array.ar[index].owning_container = owning_container_op;
     }
+
+    #[verifier(external_body)]
+    pub fn set_is_cow(&mut self, index: usize, is_cow: bool)
+        requires
+            old(self).page_array.wf(),
+            0 <= index < NUM_PAGES,
+        ensures
+            self.page_array.wf(),
+            forall|i: int|
+                #![trigger self.page_array@[i]]
+                #![trigger old(self).page_array@[i]]
+                0 <= i < NUM_PAGES && i != index ==> self.page_array@[i] =~= old(
+                    self,
+                ).page_array@[i],
+            self.page_array@[index as int].addr =~= old(self).page_array@[index as int].addr,
+            self.page_array@[index as int].state =~= old(self).page_array@[index as int].state,
+            self.page_array@[index as int].is_io_page =~= old(
+                self,
+            ).page_array@[index as int].is_io_page,
+            self.page_array@[index as int].rev_pointer =~= old(
+                self,
+            ).page_array@[index as int].rev_pointer,
+            self.page_array@[index as int].ref_count =~= old(
+                self,
+            ).page_array@[index as int].ref_count,
+            self.page_array@[index as int].owning_container =~= old(
+                self,
+            ).page_array@[index as int].owning_container,
+            self.page_array@[index as int].mappings =~= old(
+                self,
+            ).page_array@[index as int].mappings,
+            self.page_array@[index as int].io_mappings =~= old(
+                self,
+            ).page_array@[index as int].io_mappings,
+            self.page_array@[index as int].is_cow =~= is_cow,
+            self.free_pages_4k == old(self).free_pages_4k,
+            self.free_pages_2m == old(self).free_pages_2m,
+            self.free_pages_1g == old(self).free_pages_1g,
+            self.allocated_pages_4k == old(self).allocated_pages_4k,
+            self.allocated_pages_2m == old(self).allocated_pages_2m,
+            self.allocated_pages_1g == old(self).allocated_pages_1g,
+            self.mapped_pages_4k == old(self).mapped_pages_4k,
+            self.mapped_pages_2m == old(self).mapped_pages_2m,
+            self.mapped_pages_1g == old(self).mapped_pages_1g,
+            self.page_perms_4k == old(self).page_perms_4k,
+            self.page_perms_2m == old(self).page_perms_2m,
+            self.page_perms_1g == old(self).page_perms_1g,
+            self.container_map_4k == old(self).container_map_4k,
+            self.container_map_2m == old(self).container_map_2m,
+            self.container_map_1g == old(self).container_map_1g,
+    {
+        self.page_array.ar[index].is_cow = is_cow;
+    }
+
+    #[verifier(external_body)]
+    pub fn zero_page_4k(&mut self, page_ptr: PagePtr)
+        requires
+            old(self).perm_wf(),
+            old(self).mapped_pages_4k@.contains(page_ptr),
+            page_ptr_valid(page_ptr),
+        ensures
+            self.page_array == old(self).page_array,
+            self.free_pages_4k == old(self).free_pages_4k,
+            self.free_pages_2m == old(self).free_pages_2m,
+            self.free_pages_1g == old(self).free_pages_1g,
+            self.allocated_pages_4k == old(self).allocated_pages_4k,
+            self.allocated_pages_2m == old(self).allocated_pages_2m,
+            self.allocated_pages_1g == old(self).allocated_pages_1g,
+            self.mapped_pages_4k == old(self).mapped_pages_4k,
+            self.mapped_pages_2m == old(self).mapped_pages_2m,
+            self.mapped_pages_1g == old(self).mapped_pages_1g,
+            self.page_perms_4k@.dom() =~= old(self).page_perms_4k@.dom(),
+            forall|p: PagePtr|
+                #![trigger self.page_perms_4k@[p]]
+                self.page_perms_4k@.dom().contains(p) && p != page_ptr ==> self.page_perms_4k@[p]
+                    == old(self).page_perms_4k@[p],
+            self.page_perms_4k@[page_ptr].is_init(),
+            self.page_perms_4k@[page_ptr].addr() == page_ptr,
+            self.page_is_zeroed_4k(page_ptr),
+            self.page_perms_2m == old(self).page_perms_2m,
+            self.page_perms_1g == old(self).page_perms_1g,
+            self.container_map_4k == old(self).container_map_4k,
+            self.container_map_2m == old(self).container_map_2m,
+            self.container_map_1g == old(self).container_map_1g,
+            old(self).perm_wf() ==> self.perm_wf(),
+    {
+        unsafe {
+            core::ptr::write_bytes(page_ptr as *mut u8, 0, PAGE_SZ_4k);
+        }
+    }
 }
 
 #[verifier(external_body)]
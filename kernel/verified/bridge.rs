@@ -2,6 +2,7 @@ pub trait TrustedBridge {
     fn set_switch_decision(decision: SwitchDecision);
     fn set_cr3(cr3: u64);
     fn set_iommu_pt(bus: usize, device: usize, function: usize, pml4: u64);
+    fn invalidate_tlb(pcid: usize, va: usize, pages: usize);
 }
 
 #[derive(Debug)]
@@ -94,6 +94,18 @@ pub enum PageType {
     RWX,
 }
 
+/// Memory type of a user mapping, selected through the PWT and PCD bits.
+///
+/// The PAT bit is never set. The boot code programs IA32_PAT so that entry 0
+/// (no bits) is WB, entry 1 (PWT) is WC, entry 2 (PCD) is UC- and entry 3
+/// (PWT | PCD) is UC.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemoryType {
+    WriteBack,
+    WriteCombining,
+    Uncached,
+}
+
 #[allow(inconsistent_fields)]
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum PageState {
@@ -242,6 +254,10 @@ pub const PAGE_ENTRY_WRITE_SHIFT: u64 = 1;
 
 pub const PAGE_ENTRY_USER_SHIFT: u64 = 2;
 
+pub const PAGE_ENTRY_WRITE_THROUGH_SHIFT: u64 = 3;
+
+pub const PAGE_ENTRY_CACHE_DISABLE_SHIFT: u64 = 4;
+
 pub const PAGE_ENTRY_PS_SHIFT: u64 = 7;
 
 pub const PAGE_ENTRY_EXECUTE_SHIFT: u64 = 63;
@@ -252,6 +268,10 @@ pub const PAGE_ENTRY_WRITE_MASK: u64 = 0x1u64 << PAGE_ENTRY_WRITE_SHIFT;
 
 pub const PAGE_ENTRY_USER_MASK: u64 = 0x1u64 << PAGE_ENTRY_USER_SHIFT;
 
+pub const PAGE_ENTRY_WRITE_THROUGH_MASK: u64 = 0x1u64 << PAGE_ENTRY_WRITE_THROUGH_SHIFT;
+
+pub const PAGE_ENTRY_CACHE_DISABLE_MASK: u64 = 0x1u64 << PAGE_ENTRY_CACHE_DISABLE_SHIFT;
+
 pub const PAGE_ENTRY_PS_MASK: u64 = 0x1u64 << PAGE_ENTRY_PS_SHIFT;
 
 pub const PAGE_ENTRY_EXECUTE_MASK: u64 = 0x1u64 << PAGE_ENTRY_EXECUTE_SHIFT;
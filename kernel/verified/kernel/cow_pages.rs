@@ -0,0 +1,438 @@
+use vstd::prelude::*;
+verus! {
+
+use crate::util::page_ptr_util_u::*;
+use crate::define::*;
+use crate::pagetable::entry::*;
+use crate::kernel::Kernel;
+use crate::va_range::VaRange4K;
+
+/// The common outcome of a successful send/receive of pages in
+/// copy-on-write mode. Both sides end up with read-only mappings of the same
+/// pages, and the first one to write gets a private copy.
+pub open spec fn pages_shared_cow_spec(
+    old: Kernel,
+    new: Kernel,
+    blocking_endpoint_ptr: EndpointPtr,
+    woken_thread_ptr: ThreadPtr,
+    sender_proc_ptr: ProcPtr,
+    sender_va_range: VaRange4K,
+    receiver_proc_ptr: ProcPtr,
+    receiver_va_range: VaRange4K,
+) -> bool {
+    let receiver_container_ptr = old.get_proc(receiver_proc_ptr).owning_container;
+    // things that did not change
+    &&& old.thread_dom() =~= new.thread_dom()
+    &&& old.proc_dom() =~= new.proc_dom()
+    &&& old.container_dom() =~= new.container_dom()
+    &&& old.endpoint_dom() =~= new.endpoint_dom()
+    &&& forall|t_ptr: ThreadPtr|
+        #![trigger new.get_thread(t_ptr)]
+        old.thread_dom().contains(t_ptr) && t_ptr != woken_thread_ptr ==> new.get_thread(t_ptr)
+            =~= old.get_thread(t_ptr)
+    &&& forall|p_ptr: ProcPtr|
+        #![trigger new.get_proc(p_ptr)]
+        new.proc_dom().contains(p_ptr) ==> new.get_proc(p_ptr) =~= old.get_proc(p_ptr)
+    &&& forall|c: ContainerPtr|
+        #![trigger new.get_container(c)]
+        new.container_dom().contains(c) && c != receiver_container_ptr ==> old.get_container(c)
+            =~= new.get_container(c)
+    &&& forall|e_ptr: EndpointPtr|
+        #![trigger new.get_endpoint(e_ptr)]
+        new.endpoint_dom().contains(e_ptr) && e_ptr != blocking_endpoint_ptr
+            ==> old.get_endpoint(e_ptr) =~= new.get_endpoint(e_ptr)
+    &&& forall|p_ptr: ProcPtr|
+        #![trigger new.get_address_space(p_ptr)]
+        new.proc_dom().contains(p_ptr) && p_ptr != sender_proc_ptr && p_ptr != receiver_proc_ptr
+            ==> new.get_address_space(p_ptr) =~= old.get_address_space(p_ptr)
+    &&& new.get_physical_page_mapping().dom() =~= old.get_physical_page_mapping().dom()
+    &&& forall|page_ptr: PagePtr|
+        #![trigger new.get_physical_page_mapping()[page_ptr]]
+        old.get_physical_page_mapping().dom().contains(page_ptr) && (forall|i: int|
+            #![auto]
+            0 <= i < sender_va_range.len ==> old.get_address_space(
+                sender_proc_ptr,
+            )[sender_va_range@[i]].addr != page_ptr) ==> old.get_physical_page_mapping()[page_ptr]
+            == new.get_physical_page_mapping()[page_ptr]
+    //Things that changed
+    &&& new.get_endpoint(blocking_endpoint_ptr).queue@ =~= old.get_endpoint(
+        blocking_endpoint_ptr,
+    ).queue@.skip(1)
+    &&& new.get_endpoint(blocking_endpoint_ptr).owning_threads@ =~= old.get_endpoint(
+        blocking_endpoint_ptr,
+    ).owning_threads@
+    &&& new.get_endpoint(blocking_endpoint_ptr).queue_state =~= old.get_endpoint(
+        blocking_endpoint_ptr,
+    ).queue_state
+    &&& forall|page_ptr: PagePtr|
+        #![trigger new.get_physical_page_mapping()[page_ptr]]
+        old.get_physical_page_mapping().dom().contains(page_ptr)
+            && new.get_physical_page_mapping()[page_ptr] != old.get_physical_page_mapping()[page_ptr]
+            ==> (forall|p_ptr: Pcid, va: VAddr|
+            #![auto]
+            new.get_physical_page_mapping()[page_ptr].contains((p_ptr, va))
+                && !old.get_physical_page_mapping()[page_ptr].contains((p_ptr, va)) ==> p_ptr
+                == receiver_proc_ptr && receiver_va_range@.contains(va))
+    &&& forall|i: int|
+        #![auto]
+        0 <= i < sender_va_range.len ==> new.get_address_space(sender_proc_ptr).dom().contains(
+            sender_va_range@[i],
+        ) && new.get_address_space(sender_proc_ptr)[sender_va_range@[i]] == spec_share_map_entry(
+            old.get_address_space(sender_proc_ptr)[sender_va_range@[i]],
+            true,
+        ) && new.get_address_space(receiver_proc_ptr).dom().contains(receiver_va_range@[i])
+            && new.get_address_space(receiver_proc_ptr)[receiver_va_range@[i]]
+            == spec_share_map_entry(
+            old.get_address_space(sender_proc_ptr)[sender_va_range@[i]],
+            true,
+        )
+    &&& forall|va: VAddr|
+        #![auto]
+        receiver_va_range@.contains(va) == false && old.get_address_space(
+            receiver_proc_ptr,
+        ).dom().contains(va) ==> new.get_address_space(receiver_proc_ptr)[va]
+            == old.get_address_space(receiver_proc_ptr)[va]
+    &&& forall|va: VAddr|
+        #![auto]
+        sender_va_range@.contains(va) == false && old.get_address_space(
+            sender_proc_ptr,
+        ).dom().contains(va) ==> new.get_address_space(sender_proc_ptr).dom().contains(va)
+            && new.get_address_space(sender_proc_ptr)[va] == old.get_address_space(
+            sender_proc_ptr,
+        )[va]
+}
+
+impl Kernel {
+    /// Pages can only be shared copy-on-write if no device can reach them:
+    /// either the source mapping is their only reference, or they are already
+    /// copy-on-write. A device writing to a page the fault handler has copied
+    /// away from would be silently lost.
+    pub open spec fn address_space_range_cow_shareable(
+        &self,
+        target_proc_ptr: ProcPtr,
+        va_range: &VaRange4K,
+    ) -> bool {
+        &&& self.address_space_range_shareable(target_proc_ptr, va_range)
+        &&& forall|j: int|
+            #![auto]
+            0 <= j < va_range.len ==> self.page_alloc.page_array@[page_ptr2page_index(
+                self.get_address_space(target_proc_ptr)[va_range@[j]].addr,
+            ) as int].is_io_page == false && (self.get_physical_page_reference_counter(
+                self.get_address_space(target_proc_ptr)[va_range@[j]].addr,
+            ) == 1 || self.page_alloc.page_is_cow(
+                self.get_address_space(target_proc_ptr)[va_range@[j]].addr,
+            ))
+    }
+
+    /// None of the pages in the range are copy-on-write, so they can be
+    /// handed to a device.
+    pub open spec fn address_space_range_not_cow(
+        &self,
+        target_proc_ptr: ProcPtr,
+        va_range: &VaRange4K,
+    ) -> bool {
+        forall|j: int|
+            #![auto]
+            0 <= j < va_range.len ==> self.page_alloc.page_is_cow(
+                self.get_address_space(target_proc_ptr)[va_range@[j]].addr,
+            ) == false
+    }
+
+    pub fn check_address_space_va_range_cow_shareable(
+        &self,
+        target_proc_ptr: ProcPtr,
+        va_range: &VaRange4K,
+    ) -> (ret: bool)
+        requires
+            self.wf(),
+            self.proc_dom().contains(target_proc_ptr),
+            va_range.wf(),
+        ensures
+            ret == self.address_space_range_cow_shareable(target_proc_ptr, va_range),
+    {
+        if self.check_address_space_va_range_shareable(target_proc_ptr, va_range) == false {
+            return false;
+        }
+        let target_pcid = self.proc_man.get_proc(target_proc_ptr).pcid;
+        for i in 0..va_range.len
+            invariant
+                self.mem_man.pcid_active(target_pcid),
+                target_pcid == self.get_proc(target_proc_ptr).pcid,
+                0 <= i <= va_range.len,
+                self.wf(),
+                self.proc_dom().contains(target_proc_ptr),
+                va_range.wf(),
+                self.address_space_range_shareable(target_proc_ptr, va_range),
+                forall|j: int|
+                    #![auto]
+                    0 <= j < i ==> self.page_alloc.page_array@[page_ptr2page_index(
+                        self.get_address_space(target_proc_ptr)[va_range@[j]].addr,
+                    ) as int].is_io_page == false && (self.get_physical_page_reference_counter(
+                        self.get_address_space(target_proc_ptr)[va_range@[j]].addr,
+                    ) == 1 || self.page_alloc.page_is_cow(
+                        self.get_address_space(target_proc_ptr)[va_range@[j]].addr,
+                    )),
+        {
+            let entry = self.mem_man.resolve_pagetable_mapping(
+                target_pcid,
+                va_range.index(i),
+            ).unwrap();
+            assert(self.page_alloc.page_is_mapped(entry.addr));
+            if self.page_alloc.get_page_is_io_page(entry.addr) {
+                return false;
+            }
+            if self.page_alloc.get_page_reference_counter(entry.addr) != 1
+                && self.page_alloc.get_page_is_cow(entry.addr) == false {
+                return false;
+            }
+        }
+        return true;
+    }
+
+    pub fn check_address_space_va_range_not_cow(
+        &self,
+        target_proc_ptr: ProcPtr,
+        va_range: &VaRange4K,
+    ) -> (ret: bool)
+        requires
+            self.wf(),
+            self.proc_dom().contains(target_proc_ptr),
+            va_range.wf(),
+            self.address_space_range_exists(target_proc_ptr, va_range),
+        ensures
+            ret == self.address_space_range_not_cow(target_proc_ptr, va_range),
+    {
+        let target_pcid = self.proc_man.get_proc(target_proc_ptr).pcid;
+        for i in 0..va_range.len
+            invariant
+                self.mem_man.pcid_active(target_pcid),
+                target_pcid == self.get_proc(target_proc_ptr).pcid,
+                0 <= i <= va_range.len,
+                self.wf(),
+                self.proc_dom().contains(target_proc_ptr),
+                va_range.wf(),
+                self.address_space_range_exists(target_proc_ptr, va_range),
+                forall|j: int|
+                    #![auto]
+                    0 <= j < i ==> self.page_alloc.page_is_cow(
+                        self.get_address_space(target_proc_ptr)[va_range@[j]].addr,
+                    ) == false,
+        {
+            let entry = self.mem_man.resolve_pagetable_mapping(
+                target_pcid,
+                va_range.index(i),
+            ).unwrap();
+            assert(self.page_alloc.page_is_mapped(entry.addr));
+            if self.page_alloc.get_page_is_cow(entry.addr) {
+                return false;
+            }
+        }
+        return true;
+    }
+
+    /// Makes the source mapping of a page read-only and marks the page
+    /// copy-on-write, so the next write through any of its mappings faults.
+    pub fn mark_mapping_cow(&mut self, target_proc_ptr: ProcPtr, target_va: VAddr)
+        requires
+            old(self).wf(),
+            old(self).proc_dom().contains(target_proc_ptr),
+            va_4k_valid(target_va),
+            old(self).get_address_space(target_proc_ptr).dom().contains(target_va),
+            old(self).page_alloc.page_array@[page_ptr2page_index(
+                old(self).get_address_space(target_proc_ptr)[target_va].addr,
+            ) as int].is_io_page == false,
+            old(self).get_physical_page_reference_counter(
+                old(self).get_address_space(target_proc_ptr)[target_va].addr,
+            ) == 1 || old(self).page_alloc.page_is_cow(
+                old(self).get_address_space(target_proc_ptr)[target_va].addr,
+            ),
+        ensures
+            self.wf(),
+            self.proc_man =~= old(self).proc_man,
+            self.page_mapping =~= old(self).page_mapping,
+            self.page_io_mapping =~= old(self).page_io_mapping,
+            self.get_num_of_free_pages() == old(self).get_num_of_free_pages(),
+            forall|p: PagePtr|
+                #![trigger self.page_alloc.page_is_mapped(p)]
+                self.page_alloc.page_is_mapped(p) == old(self).page_alloc.page_is_mapped(p),
+            forall|p: PagePtr|
+                #![trigger self.get_physical_page_reference_counter(p)]
+                self.get_physical_page_reference_counter(p) == old(
+                    self,
+                ).get_physical_page_reference_counter(p),
+            forall|i: int|
+                #![trigger self.page_alloc.page_array@[i].is_io_page]
+                0 <= i < NUM_PAGES ==> self.page_alloc.page_array@[i].is_io_page == old(
+                    self,
+                ).page_alloc.page_array@[i].is_io_page,
+            forall|p: PagePtr|
+                #![trigger self.page_alloc.page_is_cow(p)]
+                p != old(self).get_address_space(target_proc_ptr)[target_va].addr
+                    ==> self.page_alloc.page_is_cow(p) == old(self).page_alloc.page_is_cow(p),
+            self.page_alloc.page_is_cow(
+                old(self).get_address_space(target_proc_ptr)[target_va].addr,
+            ),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) && p_ptr != target_proc_ptr
+                    ==> self.get_address_space(p_ptr) =~= old(self).get_address_space(p_ptr),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) && self.get_proc(p_ptr).ioid.is_Some()
+                    ==> self.get_io_space(p_ptr) =~= old(self).get_io_space(p_ptr),
+            self.get_address_space(target_proc_ptr) =~= old(self).get_address_space(
+                target_proc_ptr,
+            ).insert(
+                target_va,
+                spec_share_map_entry(old(self).get_address_space(target_proc_ptr)[target_va], true),
+            ),
+    {
+        proof {
+            self.proc_man.pcid_unique(target_proc_ptr);
+        }
+        let target_pcid = self.proc_man.get_proc(target_proc_ptr).pcid;
+        let old_entry = page_entry_to_map_entry(
+            &self.mem_man.resolve_pagetable_mapping(target_pcid, target_va).unwrap(),
+        );
+        let page_ptr = old_entry.addr;
+        assert(self.page_alloc.page_is_mapped(page_ptr));
+        proof {
+            if self.get_physical_page_reference_counter(page_ptr) == 1 {
+                self.page_alloc.single_mapping_has_no_io_mappings(
+                    page_ptr,
+                    target_pcid,
+                    target_va,
+                );
+            }
+        }
+        let new_entry = share_map_entry(old_entry, true);
+        self.mem_man.pagetable_protect_4k_page(target_pcid, target_va, &new_entry);
+        self.page_alloc.set_page_is_cow(page_ptr, true);
+        assert(self.wf()) by {
+            assert(self.mem_man.wf());
+            assert(self.page_alloc.wf());
+            assert(self.proc_man.wf());
+            assert(self.memory_wf());
+            assert(self.mapping_wf());
+            assert(self.pcid_ioid_wf());
+            assert(self.page_mapping_wf());
+        };
+    }
+
+    pub fn range_mark_cow(&mut self, target_proc_ptr: ProcPtr, va_range: &VaRange4K)
+        requires
+            old(self).wf(),
+            old(self).proc_dom().contains(target_proc_ptr),
+            va_range.wf(),
+            old(self).address_space_range_cow_shareable(target_proc_ptr, va_range),
+        ensures
+            self.wf(),
+            self.proc_man =~= old(self).proc_man,
+            self.page_mapping =~= old(self).page_mapping,
+            self.page_io_mapping =~= old(self).page_io_mapping,
+            self.get_num_of_free_pages() == old(self).get_num_of_free_pages(),
+            forall|p: PagePtr|
+                #![trigger self.page_alloc.page_is_mapped(p)]
+                self.page_alloc.page_is_mapped(p) == old(self).page_alloc.page_is_mapped(p),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) && p_ptr != target_proc_ptr
+                    ==> self.get_address_space(p_ptr) =~= old(self).get_address_space(p_ptr),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) && self.get_proc(p_ptr).ioid.is_Some()
+                    ==> self.get_io_space(p_ptr) =~= old(self).get_io_space(p_ptr),
+            self.get_address_space(target_proc_ptr).dom() =~= old(self).get_address_space(
+                target_proc_ptr,
+            ).dom(),
+            forall|va: VAddr|
+                #![auto]
+                va_range@.contains(va) == false && old(self).get_address_space(
+                    target_proc_ptr,
+                ).dom().contains(va) ==> self.get_address_space(target_proc_ptr)[va] == old(
+                    self,
+                ).get_address_space(target_proc_ptr)[va],
+            forall|i: int|
+                #![auto]
+                0 <= i < va_range.len ==> self.get_address_space(target_proc_ptr)[va_range@[i]]
+                    == spec_share_map_entry(
+                    old(self).get_address_space(target_proc_ptr)[va_range@[i]],
+                    true,
+                ),
+            self.address_space_range_shareable(target_proc_ptr, va_range),
+    {
+        proof {
+            va_range.va_range_lemma();
+        }
+        for index in 0..va_range.len
+            invariant
+                self.wf(),
+                self.proc_dom().contains(target_proc_ptr),
+                va_range.wf(),
+                self.proc_man =~= old(self).proc_man,
+                self.page_mapping =~= old(self).page_mapping,
+                self.page_io_mapping =~= old(self).page_io_mapping,
+                self.get_num_of_free_pages() == old(self).get_num_of_free_pages(),
+                forall|p: PagePtr|
+                    #![trigger self.page_alloc.page_is_mapped(p)]
+                    self.page_alloc.page_is_mapped(p) == old(self).page_alloc.page_is_mapped(p),
+                forall|p: PagePtr|
+                    #![trigger self.get_physical_page_reference_counter(p)]
+                    self.get_physical_page_reference_counter(p) == old(
+                        self,
+                    ).get_physical_page_reference_counter(p),
+                forall|i: int|
+                    #![trigger self.page_alloc.page_array@[i].is_io_page]
+                    0 <= i < NUM_PAGES ==> self.page_alloc.page_array@[i].is_io_page == old(
+                        self,
+                    ).page_alloc.page_array@[i].is_io_page,
+                forall|p: PagePtr|
+                    #![trigger self.page_alloc.page_is_cow(p)]
+                    old(self).page_alloc.page_is_cow(p) ==> self.page_alloc.page_is_cow(p),
+                forall|p_ptr: ProcPtr|
+                    #![auto]
+                    self.proc_dom().contains(p_ptr) && p_ptr != target_proc_ptr
+                        ==> self.get_address_space(p_ptr) =~= old(self).get_address_space(p_ptr),
+                forall|p_ptr: ProcPtr|
+                    #![auto]
+                    self.proc_dom().contains(p_ptr) && self.get_proc(p_ptr).ioid.is_Some()
+                        ==> self.get_io_space(p_ptr) =~= old(self).get_io_space(p_ptr),
+                self.get_address_space(target_proc_ptr).dom() =~= old(self).get_address_space(
+                    target_proc_ptr,
+                ).dom(),
+                forall|va: VAddr|
+                    #![auto]
+                    va_range@.contains(va) == false && old(self).get_address_space(
+                        target_proc_ptr,
+                    ).dom().contains(va) ==> self.get_address_space(target_proc_ptr)[va] == old(
+                        self,
+                    ).get_address_space(target_proc_ptr)[va],
+                forall|i: int|
+                    #![auto]
+                    index <= i < va_range.len ==> self.get_address_space(
+                        target_proc_ptr,
+                    )[va_range@[i]] == old(self).get_address_space(target_proc_ptr)[va_range@[i]],
+                forall|i: int|
+                    #![auto]
+                    0 <= i < index ==> self.get_address_space(target_proc_ptr)[va_range@[i]]
+                        == spec_share_map_entry(
+                        old(self).get_address_space(target_proc_ptr)[va_range@[i]],
+                        true,
+                    ),
+                old(self).address_space_range_cow_shareable(target_proc_ptr, va_range),
+        {
+            assert(old(self).get_address_space(target_proc_ptr).dom().contains(
+                va_range@[index as int],
+            ));
+            self.mark_mapping_cow(target_proc_ptr, va_range.index(index));
+        }
+        assert(self.address_space_range_shareable(target_proc_ptr, va_range)) by {
+            assert(forall|j: int|
+                #![auto]
+                0 <= j < va_range.len ==> self.get_address_space(target_proc_ptr)[va_range@[j]].addr
+                    == old(self).get_address_space(target_proc_ptr)[va_range@[j]].addr);
+        };
+    }
+}
+
+} // verus!
@@ -115,6 +115,7 @@ impl Kernel {
         target_proc_ptr: ProcPtr,
         target_va: VAddr,
         tagret_l1_p: PageMapPtr,
+        mem_type: MemoryType,
     ) -> (ret: MapEntry)
         requires
             old(self).wf(),
@@ -227,6 +228,7 @@ impl Kernel {
             self.get_address_space(target_proc_ptr) =~= old(self).get_address_space(
                 target_proc_ptr,
             ).insert(target_va, ret),
+            spec_map_entry_with_memory_type(ret, mem_type) =~= ret,
             old(self).page_alloc.page_is_mapped(ret.addr) == false,
             self.page_alloc.page_is_mapped(ret.addr),
             forall|p: PagePtr|
@@ -257,6 +259,16 @@ impl Kernel {
             target_va,
             target_container_ptr,
         );
+        let map_entry = map_entry_with_memory_type(
+            MapEntry {
+                addr: new_page_ptr,
+                write: true,
+                execute_disable: false,
+                write_through: false,
+                cache_disable: false,
+            },
+            mem_type,
+        );
         self.mem_man.pagetable_map_4k_page(
             target_pcid,
             l4i,
@@ -264,7 +276,7 @@ impl Kernel {
             l2i,
             l1i,
             tagret_l1_p,
-            &MapEntry { addr: new_page_ptr, write: true, execute_disable: false },
+            &map_entry,
         );
         self.proc_man.set_container_mem_quota_mem_4k(target_container_ptr, old_quota - 1);
         proof {
@@ -290,7 +302,7 @@ impl Kernel {
             assert(self.mapping_wf()) by {};
             assert(self.pcid_ioid_wf());
         };
-        MapEntry { addr: new_page_ptr, write: true, execute_disable: false }
+        map_entry
     }
 
     pub fn alloc_and_map_io(
@@ -462,7 +474,13 @@ impl Kernel {
             l2i,
             l1i,
             tagret_l1_p,
-            &MapEntry { addr: new_page_ptr, write: true, execute_disable: false },
+            &MapEntry {
+                addr: new_page_ptr,
+                write: true,
+                execute_disable: false,
+                write_through: false,
+                cache_disable: false,
+            },
         );
         self.proc_man.set_container_mem_quota_mem_4k(target_container_ptr, old_quota - 1);
         proof {
@@ -489,13 +507,20 @@ impl Kernel {
             assert(self.pcid_ioid_wf());
             assert(self.page_mapping_wf());
         };
-        MapEntry { addr: new_page_ptr, write: true, execute_disable: false }
+        MapEntry {
+            addr: new_page_ptr,
+            write: true,
+            execute_disable: false,
+            write_through: false,
+            cache_disable: false,
+        }
     }
 
     pub fn create_entry_and_alloc_and_map(
         &mut self,
         target_proc_ptr: ProcPtr,
         target_va: VAddr,
+        mem_type: MemoryType,
     ) -> (ret: (usize, MapEntry))
         requires
             old(self).wf(),
@@ -597,6 +622,7 @@ impl Kernel {
             self.get_address_space(target_proc_ptr) =~= old(self).get_address_space(
                 target_proc_ptr,
             ).insert(target_va, ret.1),
+            spec_map_entry_with_memory_type(ret.1, mem_type) =~= ret.1,
             old(self).page_alloc.page_is_mapped(ret.1.addr) == false,
             self.page_alloc.page_is_mapped(ret.1.addr),
             forall|p: PagePtr|
@@ -613,7 +639,7 @@ impl Kernel {
             self.page_mapping@[ret.1.addr] == Set::empty().insert((target_proc_ptr, target_va)),
     {
         let (ret, new_entry) = self.create_entry(target_proc_ptr, target_va);
-        (ret + 1, self.alloc_and_map(target_proc_ptr, target_va, new_entry))
+        (ret + 1, self.alloc_and_map(target_proc_ptr, target_va, new_entry, mem_type))
     }
 
     pub fn create_entry_and_alloc_and_map_io(
@@ -750,10 +776,12 @@ impl Kernel {
         (ret + 1, self.alloc_and_map_io(target_proc_ptr, target_va, new_entry))
     }
 
-    pub fn range_alloc_and_map(&mut self, target_proc_ptr: ProcPtr, va_range: &VaRange4K) -> (ret: (
-        usize,
-        Ghost<Seq<PagePtr>>,
-    ))
+    pub fn range_alloc_and_map(
+        &mut self,
+        target_proc_ptr: ProcPtr,
+        va_range: &VaRange4K,
+        mem_type: MemoryType,
+    ) -> (ret: (usize, Ghost<Seq<PagePtr>>))
         requires
             old(self).wf(),
             old(self).proc_dom().contains(target_proc_ptr),
@@ -878,6 +906,12 @@ impl Kernel {
                 0 <= i < va_range.len ==> self.get_address_space(
                     target_proc_ptr,
                 )[va_range@[i as int]].addr == ret.1@[i as int],
+            forall|i: usize|
+                #![auto]
+                0 <= i < va_range.len ==> spec_map_entry_with_memory_type(
+                    self.get_address_space(target_proc_ptr)[va_range@[i as int]],
+                    mem_type,
+                ) =~= self.get_address_space(target_proc_ptr)[va_range@[i as int]],
             self.get_container(old(self).get_proc(target_proc_ptr).owning_container).owned_procs
                 =~= old(self).get_container(
                 old(self).get_proc(target_proc_ptr).owning_container,
@@ -1024,6 +1058,12 @@ impl Kernel {
                         va_range@[j as int],
                     ) && self.get_address_space(target_proc_ptr)[va_range@[j as int]].addr
                         == page_diff@[j as int],
+                forall|j: usize|
+                    #![auto]
+                    0 <= j < i ==> spec_map_entry_with_memory_type(
+                        self.get_address_space(target_proc_ptr)[va_range@[j as int]],
+                        mem_type,
+                    ) =~= self.get_address_space(target_proc_ptr)[va_range@[j as int]],
                 self.get_container(old(self).get_proc(target_proc_ptr).owning_container).owned_procs
                     =~= old(self).get_container(
                     old(self).get_proc(target_proc_ptr).owning_container,
@@ -1063,6 +1103,7 @@ impl Kernel {
             let (num, map_entry) = self.create_entry_and_alloc_and_map(
                 target_proc_ptr,
                 va_range.index(i),
+                mem_type,
             );
 
             assert(va_range@.subrange(0, i + 1 as int) == va_range@.subrange(0, i as int).push(
@@ -322,6 +322,7 @@ impl Kernel {
         src_va: VAddr,
         target_proc_ptr: ProcPtr,
         target_va: VAddr,
+        read_only: bool,
     ) -> (ret: usize)
         requires
             old(self).wf(),
@@ -419,7 +420,10 @@ impl Kernel {
             ).dom().insert(target_va),
             self.get_address_space(target_proc_ptr) =~= old(self).get_address_space(
                 target_proc_ptr,
-            ).insert(target_va, old(self).get_address_space(src_proc_ptr)[src_va]),
+            ).insert(
+                target_va,
+                spec_share_map_entry(old(self).get_address_space(src_proc_ptr)[src_va], read_only),
+            ),
             forall|va: VAddr|
                 #![auto]
                 va != target_va && old(self).get_address_space(target_proc_ptr).dom().contains(va)
@@ -461,8 +465,9 @@ impl Kernel {
         proof {
             self.page_alloc.mapped_page_imply_page_ptr_valid(src_entry.addr);
         }
+        let share_entry = share_map_entry(src_entry, read_only);
         let (ret, new_entry) = self.create_entry(target_proc_ptr, target_va);
-        self.share_mapping(src_proc_ptr, src_va, target_proc_ptr, target_va, new_entry, src_entry);
+        self.share_mapping(src_proc_ptr, src_va, target_proc_ptr, target_va, new_entry, share_entry);
         ret
     }
 
@@ -472,6 +477,7 @@ impl Kernel {
         src_va_range: &VaRange4K,
         target_proc_ptr: ProcPtr,
         target_va_range: &VaRange4K,
+        read_only: bool,
     ) -> (ret: usize)
         requires
             old(self).wf(),
@@ -580,7 +586,10 @@ impl Kernel {
                     target_proc_ptr,
                 ).dom().contains(target_va_range@[i]) && self.get_address_space(
                     target_proc_ptr,
-                )[target_va_range@[i]] == self.get_address_space(src_proc_ptr)[src_va_range@[i]],
+                )[target_va_range@[i]] == spec_share_map_entry(
+                    self.get_address_space(src_proc_ptr)[src_va_range@[i]],
+                    read_only,
+                ),
             forall|page_ptr: PagePtr|
                 #![trigger self.page_mapping@[page_ptr]]
                 old(self).page_mapping@.dom().contains(page_ptr) && (forall|i: int|
@@ -756,7 +765,10 @@ impl Kernel {
                     0 <= i < index ==> self.get_address_space(target_proc_ptr).dom().contains(
                         target_va_range@[i],
                     ) && self.get_address_space(target_proc_ptr)[target_va_range@[i]]
-                        == self.get_address_space(src_proc_ptr)[src_va_range@[i]],
+                        == spec_share_map_entry(
+                        self.get_address_space(src_proc_ptr)[src_va_range@[i]],
+                        read_only,
+                    ),
                 forall|page_ptr: PagePtr|
                     #![trigger self.page_mapping@[page_ptr]]
                     old(self).page_mapping@.dom().contains(page_ptr) && (forall|i: int|
@@ -784,6 +796,7 @@ impl Kernel {
                 src_va_range.index(index),
                 target_proc_ptr,
                 target_va_range.index(index),
+                read_only,
             );
             assert(self.wf());
         }
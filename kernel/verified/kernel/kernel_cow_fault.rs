@@ -0,0 +1,278 @@
+use vstd::prelude::*;
+verus! {
+
+use crate::util::page_ptr_util_u::*;
+use crate::define::*;
+use crate::pagetable::entry::*;
+use crate::kernel::Kernel;
+
+pub open spec fn kernel_cow_fault_return_value(
+    old: Kernel,
+    thread_ptr: ThreadPtr,
+    va: VAddr,
+) -> UserRetValueType {
+    let proc_ptr = old.get_thread(thread_ptr).owning_proc;
+    let container_ptr = old.get_thread(thread_ptr).owning_container;
+
+    if old.cow_fault_resolvable(proc_ptr, va) == false {
+        UserRetValueType::Else
+    } else if old.get_physical_page_reference_counter(
+        old.get_address_space(proc_ptr)[va].addr,
+    ) == 1 {
+        UserRetValueType::Success
+    } else if old.get_container_quota(container_ptr).mem_4k < 1 {
+        UserRetValueType::ErrorNoQuota
+    } else if old.get_num_of_free_pages() < 1 {
+        UserRetValueType::Else
+    } else {
+        UserRetValueType::Success
+    }
+}
+
+pub open spec fn kernel_cow_fault_spec(
+    old: Kernel,
+    new: Kernel,
+    thread_ptr: ThreadPtr,
+    va: VAddr,
+    ret: SyscallReturnStruct,
+) -> bool {
+    let proc_ptr = old.get_thread(thread_ptr).owning_proc;
+    let container_ptr = old.get_thread(thread_ptr).owning_container;
+    let old_entry = old.get_address_space(proc_ptr)[va];
+    let new_page_ptr = ret.get_return_vaule_pair_usize().unwrap().1;
+    if kernel_cow_fault_return_value(old, thread_ptr, va).is_error() {
+        new =~= old
+    } else if old.get_physical_page_reference_counter(old_entry.addr) == 1 {
+        // the other side already got its copy, the page is made writable in place
+        &&& old.thread_dom() =~= new.thread_dom()
+        &&& old.proc_dom() =~= new.proc_dom()
+        &&& old.container_dom() =~= new.container_dom()
+        &&& old.endpoint_dom() =~= new.endpoint_dom()
+        &&& forall|t_ptr: ThreadPtr|
+            #![trigger new.get_thread(t_ptr)]
+            old.thread_dom().contains(t_ptr) ==> new.get_thread(t_ptr) =~= old.get_thread(t_ptr)
+        &&& forall|p_ptr: ProcPtr|
+            #![trigger new.get_proc(p_ptr)]
+            new.proc_dom().contains(p_ptr) ==> new.get_proc(p_ptr) =~= old.get_proc(p_ptr)
+        &&& forall|c: ContainerPtr|
+            #![trigger new.get_container(c)]
+            new.container_dom().contains(c) ==> old.get_container(c) =~= new.get_container(c)
+        &&& forall|e_ptr: EndpointPtr|
+            #![trigger new.get_endpoint(e_ptr)]
+            new.endpoint_dom().contains(e_ptr) ==> old.get_endpoint(e_ptr) =~= new.get_endpoint(
+                e_ptr,
+            )
+        &&& forall|p_ptr: ProcPtr|
+            #![trigger new.get_address_space(p_ptr)]
+            new.proc_dom().contains(p_ptr) && p_ptr != proc_ptr ==> new.get_address_space(p_ptr)
+                =~= old.get_address_space(p_ptr)
+        &&& new.get_physical_page_mapping() =~= old.get_physical_page_mapping()
+        &&& new.page_alloc.page_is_cow(old_entry.addr) == false
+        &&& new.get_address_space(proc_ptr) =~= old.get_address_space(proc_ptr).insert(
+            va,
+            MapEntry {
+                addr: old_entry.addr,
+                write: true,
+                execute_disable: old_entry.execute_disable,
+                write_through: old_entry.write_through,
+                cache_disable: old_entry.cache_disable,
+            },
+        )
+    } else {
+        // things that did not change
+        &&& old.thread_dom() =~= new.thread_dom()
+        &&& old.proc_dom() =~= new.proc_dom()
+        &&& old.container_dom() =~= new.container_dom()
+        &&& old.endpoint_dom() =~= new.endpoint_dom()
+        &&& forall|t_ptr: ThreadPtr|
+            #![trigger new.get_thread(t_ptr)]
+            old.thread_dom().contains(t_ptr) ==> new.get_thread(t_ptr) =~= old.get_thread(t_ptr)
+        &&& forall|p_ptr: ProcPtr|
+            #![trigger new.get_proc(p_ptr)]
+            new.proc_dom().contains(p_ptr) ==> new.get_proc(p_ptr) =~= old.get_proc(p_ptr)
+        &&& forall|c: ContainerPtr|
+            #![trigger new.get_container(c)]
+            new.container_dom().contains(c) && c != container_ptr ==> old.get_container(c)
+                =~= new.get_container(c)
+        &&& forall|e_ptr: EndpointPtr|
+            #![trigger new.get_endpoint(e_ptr)]
+            new.endpoint_dom().contains(e_ptr) ==> old.get_endpoint(e_ptr) =~= new.get_endpoint(
+                e_ptr,
+            )
+        &&& forall|p_ptr: ProcPtr|
+            #![trigger new.get_address_space(p_ptr)]
+            new.proc_dom().contains(p_ptr) && p_ptr != proc_ptr ==> new.get_address_space(p_ptr)
+                =~= old.get_address_space(p_ptr)
+        //Things that changed
+        &&& old.get_container(container_ptr).quota.spec_subtract_mem_4k(
+            new.get_container(container_ptr).quota,
+            1,
+        )
+        &&& old.get_physical_page_mapping().dom().contains(new_page_ptr) == false
+        &&& new.get_physical_page_mapping().dom() =~= old.get_physical_page_mapping().dom().insert(
+            new_page_ptr,
+        )
+        &&& new.get_physical_page_mapping()[new_page_ptr] == Set::empty().insert((proc_ptr, va))
+        &&& new.get_physical_page_mapping()[old_entry.addr] == old.get_physical_page_mapping()[old_entry.addr].remove(
+            (proc_ptr, va),
+        )
+        &&& new.get_address_space(proc_ptr) =~= old.get_address_space(proc_ptr).insert(
+            va,
+            MapEntry {
+                addr: new_page_ptr,
+                write: true,
+                execute_disable: old_entry.execute_disable,
+                write_through: old_entry.write_through,
+                cache_disable: old_entry.cache_disable,
+            },
+        )
+    }
+}
+
+impl Kernel {
+    /// A write fault is treated as copy-on-write when it hits a read-only
+    /// mapping of a page that was shared copy-on-write. Other read-only
+    /// mappings, including read-only shares, stay protection faults.
+    ///
+    /// The faulting process gets a private copy while the page is still
+    /// shared, and the page itself once it is the only one left mapping it.
+    pub open spec fn cow_fault_resolvable(&self, target_proc_ptr: ProcPtr, va: VAddr) -> bool {
+        &&& va_4k_valid(va)
+        &&& self.get_address_space(target_proc_ptr).dom().contains(va)
+        &&& self.get_address_space(target_proc_ptr)[va].write == false
+        &&& self.page_alloc.page_is_cow(self.get_address_space(target_proc_ptr)[va].addr)
+    }
+
+    #[verifier(external_body)]
+    pub fn copy_page_4k_t(src: PagePtr, dst: PagePtr)
+        requires
+            page_ptr_valid(src),
+            page_ptr_valid(dst),
+            src != dst,
+    {
+        unsafe {
+            core::ptr::copy_nonoverlapping(src as *const u8, dst as *mut u8, PAGE_SZ_4k);
+        }
+    }
+
+    pub fn kernel_resolve_cow_fault(&mut self, thread_ptr: ThreadPtr, va: VAddr) -> (ret:
+        SyscallReturnStruct)
+        requires
+            old(self).total_wf(),
+            old(self).thread_dom().contains(thread_ptr),
+        ensures
+            self.total_wf(),
+            kernel_cow_fault_spec(*old(self), *self, thread_ptr, va, ret),
+    {
+        let proc_ptr = self.proc_man.get_thread(thread_ptr).owning_proc;
+        let pcid = self.proc_man.get_proc(proc_ptr).pcid;
+        let container_ptr = self.proc_man.get_proc(proc_ptr).owning_container;
+
+        proof {
+            self.proc_man.thread_inv();
+            self.proc_man.process_inv();
+            self.proc_man.pcid_unique(proc_ptr);
+        }
+
+        if va_4k_valid(va) == false {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        let entry_op = self.mem_man.resolve_pagetable_mapping(pcid, va);
+        if entry_op.is_none() {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        let old_entry = page_entry_to_map_entry(&entry_op.unwrap());
+        if old_entry.write {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        assert(self.page_alloc.page_is_mapped(old_entry.addr));
+        if self.page_alloc.get_page_is_cow(old_entry.addr) == false {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        proof {
+            self.page_alloc.mapped_page_imply_page_ptr_valid(old_entry.addr);
+            self.page_alloc.mapped_page_are_not_allocated(old_entry.addr);
+        }
+        if self.page_alloc.get_page_reference_counter(old_entry.addr) == 1 {
+            self.mem_man.pagetable_protect_4k_page(
+                pcid,
+                va,
+                &MapEntry {
+                    addr: old_entry.addr,
+                    write: true,
+                    execute_disable: old_entry.execute_disable,
+                    write_through: old_entry.write_through,
+                    cache_disable: old_entry.cache_disable,
+                },
+            );
+            self.page_alloc.set_page_is_cow(old_entry.addr, false);
+            assert(self.wf()) by {
+                assert(self.mem_man.wf());
+                assert(self.page_alloc.wf());
+                assert(self.proc_man.wf());
+                assert(self.memory_wf());
+                assert(self.mapping_wf());
+                assert(self.pcid_ioid_wf());
+                assert(self.page_mapping_wf());
+            };
+            assert(self.total_wf());
+            return SyscallReturnStruct::NoSwitchNew(
+                RetValueType::SuccessPairUsize { value1: old_entry.addr, value2: old_entry.addr },
+            );
+        }
+
+        let old_quota = self.proc_man.get_container(container_ptr).quota.mem_4k;
+        if old_quota < 1 {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::ErrorNoQuota);
+        }
+        if self.page_alloc.free_pages_4k.len() < 1 {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+
+        let new_page_ptr = self.page_alloc.alloc_and_map_4k(pcid, va, container_ptr);
+        Self::copy_page_4k_t(old_entry.addr, new_page_ptr);
+        self.page_alloc.remove_mapping_4k(old_entry.addr, pcid, va);
+        self.mem_man.pagetable_remap_4k_page(
+            pcid,
+            va,
+            &MapEntry {
+                addr: new_page_ptr,
+                write: true,
+                execute_disable: old_entry.execute_disable,
+                write_through: old_entry.write_through,
+                cache_disable: old_entry.cache_disable,
+            },
+        );
+        self.proc_man.set_container_mem_quota_mem_4k(container_ptr, old_quota - 1);
+        proof {
+            self.page_mapping@ = self.page_mapping@.insert(
+                old_entry.addr,
+                self.page_mapping@[old_entry.addr].remove((proc_ptr, va)),
+            ).insert(new_page_ptr, Set::empty().insert((proc_ptr, va)));
+        }
+        assert(self.wf()) by {
+            assert(self.mem_man.wf());
+            assert(self.page_alloc.wf());
+            assert(self.proc_man.wf());
+            assert(self.memory_wf());
+            assert(self.mapping_wf());
+            assert(self.pcid_ioid_wf());
+            assert(self.page_mapping_wf());
+        };
+        assert(self.container_dom().fold(
+            0,
+            |e: int, a: ContainerPtr| e + self.get_container(a).quota.mem_4k,
+        ) == old(self).container_dom().fold(
+            0,
+            |e: int, a: ContainerPtr| e + old(self).get_container(a).quota.mem_4k,
+        ) - 1) by {
+            self.fold_change_mem_4k_lemma(*old(self), container_ptr);
+        }
+        assert(self.total_wf());
+        return SyscallReturnStruct::NoSwitchNew(
+            RetValueType::SuccessPairUsize { value1: old_entry.addr, value2: new_page_ptr },
+        );
+    }
+}
+
+} // verus!
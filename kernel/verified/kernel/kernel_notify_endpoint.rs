@@ -0,0 +1,104 @@
+use vstd::prelude::*;
+verus! {
+
+use crate::define::*;
+use crate::kernel::Kernel;
+
+impl Kernel {
+    /// Whether `thread_ptr` of the process with `pcid` still holds `endpoint_ptr` as its
+    /// endpoint descriptor `endpoint_index`. Interrupt and fault bindings keep raw endpoint
+    /// pointers, so they are checked with this before the endpoint is notified.
+    pub fn thread_holds_endpoint(
+        &self,
+        pcid: Pcid,
+        thread_ptr: ThreadPtr,
+        endpoint_index: EndpointIdx,
+        endpoint_ptr: EndpointPtr,
+    ) -> (ret: bool)
+        requires
+            self.wf(),
+        ensures
+            ret ==> self.thread_dom().contains(thread_ptr) && self.endpoint_dom().contains(
+                endpoint_ptr,
+            ),
+    {
+        if pcid >= PCID_MAX {
+            return false;
+        }
+        let proc_ptr = match *self.mem_man.pcid_to_proc_ptr.get(pcid) {
+            Some(proc_ptr) => proc_ptr,
+            None => {
+                return false;
+            },
+        };
+        assert(self.mem_man.pcid_active(pcid));
+        assert(self.proc_dom().contains(proc_ptr));
+        self.proc_man.thread_holds_endpoint(proc_ptr, thread_ptr, endpoint_index, endpoint_ptr)
+    }
+
+    /// Wakes the first thread waiting to receive on `endpoint_ptr`, like an empty send that
+    /// comes from the kernel instead of a thread. Used to deliver device interrupts.
+    ///
+    /// Returns `Error` when no thread is waiting or its container cannot schedule it.
+    pub fn kernel_notify_endpoint(&mut self, endpoint_ptr: EndpointPtr) -> (ret:
+        SyscallReturnStruct)
+        requires
+            old(self).total_wf(),
+            old(self).endpoint_dom().contains(endpoint_ptr),
+        ensures
+            self.total_wf(),
+            self.proc_dom() == old(self).proc_dom(),
+            self.thread_dom() == old(self).thread_dom(),
+            self.endpoint_dom() == old(self).endpoint_dom(),
+            self.container_dom() == old(self).container_dom(),
+            ret.is_error() ==> *self =~= *old(self),
+            // only the head receiver of `endpoint_ptr` changed state
+            !ret.is_error() ==> old(self).get_endpoint(endpoint_ptr).queue_state
+                == EndpointState::RECEIVE && old(self).get_endpoint(endpoint_ptr).queue.len() > 0
+                && self.get_endpoint(endpoint_ptr).queue@ == old(self).get_endpoint(
+                endpoint_ptr,
+            ).queue@.skip(1),
+            forall|t_ptr: ThreadPtr|
+                #![trigger self.get_thread(t_ptr)]
+                old(self).thread_dom().contains(t_ptr) && (ret.is_error() || t_ptr != old(
+                    self,
+                ).get_endpoint(endpoint_ptr).queue@[0]) ==> self.get_thread(t_ptr) =~= old(
+                    self,
+                ).get_thread(t_ptr),
+            forall|e_ptr: EndpointPtr|
+                #![trigger self.get_endpoint(e_ptr)]
+                self.endpoint_dom().contains(e_ptr) && e_ptr != endpoint_ptr ==> self.get_endpoint(
+                    e_ptr,
+                ) =~= old(self).get_endpoint(e_ptr),
+            forall|p_ptr: ProcPtr|
+                #![trigger self.get_proc(p_ptr)]
+                self.proc_dom().contains(p_ptr) ==> self.get_proc(p_ptr) =~= old(self).get_proc(
+                    p_ptr,
+                ),
+    {
+        proof {
+            self.proc_man.thread_inv();
+            self.proc_man.endpoint_inv();
+        }
+        if self.proc_man.get_endpoint(endpoint_ptr).queue_state.is_send()
+            || self.proc_man.get_endpoint(endpoint_ptr).queue.len() == 0 {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+
+        let receiver_thread_ptr = self.proc_man.get_endpoint(endpoint_ptr).queue.get_head();
+        let receiver_container_ptr = self.proc_man.get_thread(receiver_thread_ptr).owning_container;
+
+        if self.proc_man.get_container(receiver_container_ptr).scheduler.len()
+            >= MAX_CONTAINER_SCHEDULER_LEN {
+            // cannot schedule the receiver
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        self.proc_man.schedule_blocked_thread(endpoint_ptr);
+        assert(self.total_wf()) by {
+            self.fold_change_mem_4k_lemma(*old(self), receiver_container_ptr);
+        };
+        return SyscallReturnStruct::NoSwitchNew(RetValueType::Else);
+    }
+}
+
+} // verus!
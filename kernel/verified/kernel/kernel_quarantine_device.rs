@@ -0,0 +1,42 @@
+use vstd::prelude::*;
+verus! {
+
+use crate::define::*;
+use crate::kernel::Kernel;
+
+impl Kernel {
+    /// Detaches `bus:dev.fun` from whatever IOMMU table it is bound to after it raised a DMA
+    /// fault. The device keeps its owner and can be bound again with
+    /// `syscall_set_device_iommu`.
+    pub fn kernel_quarantine_device(&mut self, bus: u8, dev: u8, fun: u8)
+        requires
+            old(self).total_wf(),
+            0 <= dev < 32 && 0 <= fun < 8,
+        ensures
+            self.total_wf(),
+            self.proc_man =~= old(self).proc_man,
+            self.page_alloc =~= old(self).page_alloc,
+            self.page_mapping =~= old(self).page_mapping,
+            self.page_io_mapping =~= old(self).page_io_mapping,
+            self.mem_man.pci_bitmap@ =~= old(self).mem_man.pci_bitmap@,
+            self.mem_man.root_table.resolve(bus, dev, fun).is_None(),
+            forall|_bus: u8, _dev: u8, _fun: u8|
+                #![auto]
+                0 <= _dev < 32 && 0 <= _fun < 8 && (_bus != bus || _dev != dev || _fun != fun)
+                    ==> self.mem_man.root_table.resolve(_bus, _dev, _fun)
+                    =~= old(self).mem_man.root_table.resolve(_bus, _dev, _fun),
+    {
+        self.mem_man.clear_pci_binding(bus, dev, fun);
+
+        assert(self.mem_man.wf());
+        assert(self.page_alloc.wf());
+        assert(self.proc_man.wf());
+        assert(self.memory_wf());
+        assert(self.mapping_wf());
+        assert(self.pcid_ioid_wf());
+        assert(self.page_mapping_wf());
+        assert(self.total_mem_4k_quota_wf());
+    }
+}
+
+} // verus!
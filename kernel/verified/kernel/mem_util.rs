@@ -156,6 +156,12 @@ impl Kernel {
                 self.mem_man.pcid_active(p) ==> old(self).mem_man.pcid_to_proc_ptr(p)
                     == self.mem_man.pcid_to_proc_ptr(p),
             self.page_mapping == old(self).page_mapping,
+            forall|p: PagePtr|
+                #![trigger self.page_alloc.page_is_mmio(p)]
+                self.page_alloc.page_is_mmio(p) == old(self).page_alloc.page_is_mmio(p) && (
+                self.page_alloc.page_is_mmio(p) ==> self.page_alloc.mmio_page_owner(p) == old(
+                    self,
+                ).page_alloc.mmio_page_owner(p)),
     {
         let mut ret = 0;
         let container_ptr = self.proc_man.get_proc(proc_ptr).owning_container;
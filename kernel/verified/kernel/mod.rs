@@ -1,12 +1,20 @@
 pub mod create_and_map_pages;
 pub mod create_and_share_pages;
+pub mod move_pages;
+pub mod cow_pages;
 pub mod mem_util;
+pub mod protect_pages;
 pub mod schedule_idle_cpu;
 pub mod send_receive_pre_spec;
 pub mod spec;
 pub mod spec_util;
 pub mod syscall_io_mmap;
+pub mod syscall_io_munmap;
 pub mod syscall_mmap;
+pub mod syscall_mmap_mmio;
+pub mod syscall_dump_address_space;
+pub mod syscall_mem_usage;
+pub mod syscall_mprotect;
 pub mod syscall_new_container;
 pub mod syscall_new_endpoint;
 pub mod syscall_new_proc;
@@ -16,15 +24,22 @@ pub mod syscall_new_thread_with_endpoint;
 pub mod syscall_receive_empty;
 pub mod syscall_receive_endpoint;
 pub mod syscall_receive_pages;
+pub mod syscall_receive_pci;
 pub mod syscall_resolve_va;
 pub mod syscall_send_empty;
 pub mod syscall_send_empty_try_schedule;
 pub mod syscall_send_endpoint;
 pub mod syscall_send_pages;
+pub mod syscall_send_pci;
+pub mod syscall_set_device_iommu;
+pub mod syscall_transfer_quota;
 pub mod util_syscalls;
 pub mod kernel_drop_endpoint;
 pub mod kernel_kill_thread;
 pub mod kernel_kill_proc;
+pub mod kernel_cow_fault;
+pub mod kernel_quarantine_device;
+pub mod kernel_notify_endpoint;
 
 pub use spec::*;
 pub use spec_util::*;
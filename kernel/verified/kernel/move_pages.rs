@@ -0,0 +1,611 @@
+use vstd::prelude::*;
+verus! {
+
+use crate::util::page_ptr_util_u::*;
+use crate::define::*;
+use crate::pagetable::entry::*;
+use crate::kernel::Kernel;
+use crate::va_range::VaRange4K;
+
+/// The common outcome of a successful send/receive of pages in move mode.
+/// `woken_thread_ptr` is the thread that was blocked on the endpoint and got
+/// scheduled by the transfer.
+pub open spec fn pages_moved_spec(
+    old: Kernel,
+    new: Kernel,
+    blocking_endpoint_ptr: EndpointPtr,
+    woken_thread_ptr: ThreadPtr,
+    sender_proc_ptr: ProcPtr,
+    sender_va_range: VaRange4K,
+    receiver_proc_ptr: ProcPtr,
+    receiver_va_range: VaRange4K,
+    read_only: bool,
+) -> bool {
+    let sender_container_ptr = old.get_proc(sender_proc_ptr).owning_container;
+    let receiver_container_ptr = old.get_proc(receiver_proc_ptr).owning_container;
+    // things that did not change
+    &&& old.thread_dom() =~= new.thread_dom()
+    &&& old.proc_dom() =~= new.proc_dom()
+    &&& old.container_dom() =~= new.container_dom()
+    &&& old.endpoint_dom() =~= new.endpoint_dom()
+    &&& forall|t_ptr: ThreadPtr|
+        #![trigger new.get_thread(t_ptr)]
+        old.thread_dom().contains(t_ptr) && t_ptr != woken_thread_ptr ==> new.get_thread(t_ptr)
+            =~= old.get_thread(t_ptr)
+    &&& forall|p_ptr: ProcPtr|
+        #![trigger new.get_proc(p_ptr)]
+        new.proc_dom().contains(p_ptr) ==> new.get_proc(p_ptr) =~= old.get_proc(p_ptr)
+    &&& forall|c: ContainerPtr|
+        #![trigger new.get_container(c)]
+        new.container_dom().contains(c) && c != sender_container_ptr && c
+            != receiver_container_ptr ==> old.get_container(c) =~= new.get_container(c)
+    &&& forall|e_ptr: EndpointPtr|
+        #![trigger new.get_endpoint(e_ptr)]
+        new.endpoint_dom().contains(e_ptr) && e_ptr != blocking_endpoint_ptr
+            ==> old.get_endpoint(e_ptr) =~= new.get_endpoint(e_ptr)
+    &&& forall|p_ptr: ProcPtr|
+        #![trigger new.get_address_space(p_ptr)]
+        new.proc_dom().contains(p_ptr) && p_ptr != sender_proc_ptr && p_ptr != receiver_proc_ptr
+            ==> new.get_address_space(p_ptr) =~= old.get_address_space(p_ptr)
+    &&& new.get_physical_page_mapping().dom() =~= old.get_physical_page_mapping().dom()
+    &&& forall|page_ptr: PagePtr|
+        #![trigger new.get_physical_page_mapping()[page_ptr]]
+        old.get_physical_page_mapping().dom().contains(page_ptr) && (forall|i: int|
+            #![auto]
+            0 <= i < sender_va_range.len ==> old.get_address_space(
+                sender_proc_ptr,
+            )[sender_va_range@[i]].addr != page_ptr) ==> old.get_physical_page_mapping()[page_ptr]
+            == new.get_physical_page_mapping()[page_ptr]
+    //Things that changed
+    &&& new.get_endpoint(blocking_endpoint_ptr).queue@ =~= old.get_endpoint(
+        blocking_endpoint_ptr,
+    ).queue@.skip(1)
+    &&& new.get_endpoint(blocking_endpoint_ptr).owning_threads@ =~= old.get_endpoint(
+        blocking_endpoint_ptr,
+    ).owning_threads@
+    &&& new.get_endpoint(blocking_endpoint_ptr).queue_state =~= old.get_endpoint(
+        blocking_endpoint_ptr,
+    ).queue_state
+    &&& forall|i: int|
+        #![auto]
+        0 <= i < sender_va_range.len ==> new.get_address_space(receiver_proc_ptr).dom().contains(
+            receiver_va_range@[i],
+        ) && new.get_address_space(receiver_proc_ptr)[receiver_va_range@[i]]
+            == spec_share_map_entry(
+            old.get_address_space(sender_proc_ptr)[sender_va_range@[i]],
+            read_only,
+        ) && new.get_physical_page_mapping()[old.get_address_space(
+            sender_proc_ptr,
+        )[sender_va_range@[i]].addr] == Set::<(ProcPtr, VAddr)>::empty().insert(
+            (receiver_proc_ptr, receiver_va_range@[i]),
+        ) && new.get_address_space(sender_proc_ptr).dom().contains(sender_va_range@[i]) == false
+    &&& forall|va: VAddr|
+        #![auto]
+        receiver_va_range@.contains(va) == false && old.get_address_space(
+            receiver_proc_ptr,
+        ).dom().contains(va) ==> new.get_address_space(receiver_proc_ptr)[va]
+            == old.get_address_space(receiver_proc_ptr)[va]
+    &&& forall|va: VAddr|
+        #![auto]
+        sender_va_range@.contains(va) == false && old.get_address_space(
+            sender_proc_ptr,
+        ).dom().contains(va) ==> new.get_address_space(sender_proc_ptr).dom().contains(va)
+            && new.get_address_space(sender_proc_ptr)[va] == old.get_address_space(
+            sender_proc_ptr,
+        )[va]
+}
+
+impl Kernel {
+    /// Pages can only be moved if the source address space is their sole
+    /// reference. Otherwise the receiver would end up aliasing a page that
+    /// is still reachable from somewhere else.
+    pub open spec fn address_space_range_movable(
+        &self,
+        target_proc_ptr: ProcPtr,
+        va_range: &VaRange4K,
+    ) -> bool {
+        &&& forall|j: int|
+            #![auto]
+            0 <= j < va_range.len ==> self.get_address_space(target_proc_ptr).dom().contains(
+                va_range@[j],
+            )
+        &&& forall|j: int|
+            #![auto]
+            0 <= j < va_range.len ==> self.get_physical_page_reference_counter(
+                self.get_address_space(target_proc_ptr)[va_range@[j]].addr,
+            ) == 1
+    }
+
+    pub fn check_address_space_va_range_movable(
+        &self,
+        target_proc_ptr: ProcPtr,
+        va_range: &VaRange4K,
+    ) -> (ret: bool)
+        requires
+            self.wf(),
+            self.proc_dom().contains(target_proc_ptr),
+            va_range.wf(),
+        ensures
+            ret == self.address_space_range_movable(target_proc_ptr, va_range),
+    {
+        let target_pcid = self.proc_man.get_proc(target_proc_ptr).pcid;
+        for i in 0..va_range.len
+            invariant
+                self.mem_man.pcid_active(target_pcid),
+                target_pcid == self.get_proc(target_proc_ptr).pcid,
+                0 <= i <= va_range.len,
+                self.wf(),
+                self.proc_dom().contains(target_proc_ptr),
+                va_range.wf(),
+                forall|j: int|
+                    #![auto]
+                    0 <= j < i ==> self.get_address_space(target_proc_ptr).dom().contains(
+                        va_range@[j],
+                    ),
+                forall|j: int|
+                    #![auto]
+                    0 <= j < i ==> self.get_physical_page_reference_counter(
+                        self.get_address_space(target_proc_ptr)[va_range@[j]].addr,
+                    ) == 1,
+        {
+            let entry_op = self.mem_man.resolve_pagetable_mapping(target_pcid, va_range.index(i));
+            if entry_op.is_none() {
+                return false;
+            }
+            assert(self.mem_man.get_pagetable_mapping_by_pcid(target_pcid).dom().contains(
+                va_range@[i as int],
+            ));
+            let entry = entry_op.unwrap();
+            if self.page_alloc.get_page_reference_counter(entry.addr) != 1 {
+                return false;
+            }
+        }
+        return true;
+    }
+
+    /// Drops the source side of a page that has already been shared with the
+    /// receiver and hands the page over to the receiver's container.
+    pub fn unmap_moved_page(
+        &mut self,
+        src_proc_ptr: ProcPtr,
+        src_va: VAddr,
+        target_container_ptr: ContainerPtr,
+    )
+        requires
+            old(self).wf(),
+            old(self).proc_dom().contains(src_proc_ptr),
+            old(self).container_dom().contains(target_container_ptr),
+            va_4k_valid(src_va),
+            old(self).get_address_space(src_proc_ptr).dom().contains(src_va),
+            old(self).get_physical_page_reference_counter(
+                old(self).get_address_space(src_proc_ptr)[src_va].addr,
+            ) > 1,
+        ensures
+            self.wf(),
+            self.proc_man =~= old(self).proc_man,
+            self.get_num_of_free_pages() == old(self).get_num_of_free_pages(),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) && p_ptr != src_proc_ptr
+                    ==> self.get_address_space(p_ptr) =~= old(self).get_address_space(p_ptr),
+            self.get_address_space(src_proc_ptr) =~= old(self).get_address_space(
+                src_proc_ptr,
+            ).remove(src_va),
+            forall|p: PagePtr|
+                #![trigger self.page_alloc.page_is_mapped(p)]
+                self.page_alloc.page_is_mapped(p) == old(self).page_alloc.page_is_mapped(p),
+            forall|p: PagePtr|
+                #![trigger self.page_alloc.page_is_mapped(p)]
+                self.page_alloc.page_is_mapped(p) && p != old(self).get_address_space(
+                    src_proc_ptr,
+                )[src_va].addr ==> old(self).get_physical_page_reference_counter(p)
+                    == self.get_physical_page_reference_counter(p),
+            old(self).get_physical_page_reference_counter(
+                old(self).get_address_space(src_proc_ptr)[src_va].addr,
+            ) - 1 == self.get_physical_page_reference_counter(
+                old(self).get_address_space(src_proc_ptr)[src_va].addr,
+            ),
+            self.page_mapping@.dom() == old(self).page_mapping@.dom(),
+            forall|page_ptr: PagePtr|
+                #![trigger self.page_mapping@[page_ptr]]
+                old(self).page_mapping@.dom().contains(page_ptr) && page_ptr != old(
+                    self,
+                ).get_address_space(src_proc_ptr)[src_va].addr ==> old(self).page_mapping@[page_ptr]
+                    == self.page_mapping@[page_ptr],
+            self.page_mapping@[old(self).get_address_space(src_proc_ptr)[src_va].addr] == old(
+                self,
+            ).page_mapping@[old(self).get_address_space(src_proc_ptr)[src_va].addr].remove(
+                (src_proc_ptr, src_va),
+            ),
+            self.get_container_owned_pages(target_container_ptr).contains(
+                old(self).get_address_space(src_proc_ptr)[src_va].addr,
+            ),
+    {
+        proof {
+            self.proc_man.pcid_unique(src_proc_ptr);
+        }
+        let src_pcid = self.proc_man.get_proc(src_proc_ptr).pcid;
+        let src_entry = page_entry_to_map_entry(
+            &self.mem_man.resolve_pagetable_mapping(src_pcid, src_va).unwrap(),
+        );
+        proof {
+            self.page_alloc.mapped_page_imply_page_ptr_valid(src_entry.addr);
+            self.page_alloc.mapped_page_are_not_allocated(src_entry.addr);
+        }
+        self.mem_man.pagetable_unmap_4k_page(src_pcid, src_va);
+        self.page_alloc.remove_mapping_4k(src_entry.addr, src_pcid, src_va);
+        self.page_alloc.transfer_page_owner_4k(src_entry.addr, target_container_ptr);
+        proof {
+            self.page_mapping@ = self.page_mapping@.insert(
+                src_entry.addr,
+                self.page_mapping@[src_entry.addr].remove((src_proc_ptr, src_va)),
+            );
+        }
+        assert(self.wf()) by {
+            assert(self.mem_man.wf());
+            assert(self.page_alloc.wf());
+            assert(self.proc_man.wf());
+            assert(self.memory_wf());
+            assert(self.mapping_wf());
+            assert(self.pcid_ioid_wf());
+            assert(self.page_mapping_wf());
+        };
+    }
+
+    pub fn create_entry_and_move(
+        &mut self,
+        src_proc_ptr: ProcPtr,
+        src_va: VAddr,
+        target_proc_ptr: ProcPtr,
+        target_va: VAddr,
+        read_only: bool,
+    ) -> (ret: usize)
+        requires
+            old(self).wf(),
+            old(self).proc_dom().contains(src_proc_ptr),
+            old(self).proc_dom().contains(target_proc_ptr),
+            src_proc_ptr != target_proc_ptr,
+            old(self).get_container_quota(
+                old(self).get_proc(target_proc_ptr).owning_container,
+            ).mem_4k >= 3,
+            old(self).get_num_of_free_pages() >= 3,
+            va_4k_valid(src_va),
+            va_4k_valid(target_va),
+            old(self).get_address_space(target_proc_ptr).dom().contains(target_va) == false,
+            old(self).get_address_space(src_proc_ptr).dom().contains(src_va) == true,
+            old(self).get_physical_page_reference_counter(
+                old(self).get_address_space(src_proc_ptr)[src_va].addr,
+            ) == 1,
+        ensures
+            ret <= 3,
+            self.wf(),
+            self.proc_dom() == old(self).proc_dom(),
+            self.thread_dom() == old(self).thread_dom(),
+            self.endpoint_dom() == old(self).endpoint_dom(),
+            self.container_dom() == old(self).container_dom(),
+            self.get_num_of_free_pages() == old(self).get_num_of_free_pages() - ret,
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) ==> self.get_proc(p_ptr) =~= old(self).get_proc(
+                    p_ptr,
+                ),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) && p_ptr != target_proc_ptr && p_ptr
+                    != src_proc_ptr ==> self.get_address_space(p_ptr) =~= old(
+                    self,
+                ).get_address_space(p_ptr),
+            forall|t_ptr: ThreadPtr|
+                #![auto]
+                self.thread_dom().contains(t_ptr) ==> self.get_thread(t_ptr) =~= old(
+                    self,
+                ).get_thread(t_ptr),
+            forall|c_ptr: ContainerPtr|
+                #![auto]
+                self.container_dom().contains(c_ptr) && c_ptr != self.get_proc(
+                    target_proc_ptr,
+                ).owning_container ==> self.get_container(c_ptr) =~= old(self).get_container(c_ptr),
+            forall|e_ptr: EndpointPtr|
+                #![auto]
+                self.endpoint_dom().contains(e_ptr) ==> self.get_endpoint(e_ptr) =~= old(
+                    self,
+                ).get_endpoint(e_ptr),
+            old(self).get_container(
+                old(self).get_proc(target_proc_ptr).owning_container,
+            ).quota.spec_subtract_mem_4k(
+                self.get_container(old(self).get_proc(target_proc_ptr).owning_container).quota,
+                ret,
+            ),
+            self.get_address_space(target_proc_ptr) =~= old(self).get_address_space(
+                target_proc_ptr,
+            ).insert(
+                target_va,
+                spec_share_map_entry(old(self).get_address_space(src_proc_ptr)[src_va], read_only),
+            ),
+            self.get_address_space(src_proc_ptr) =~= old(self).get_address_space(
+                src_proc_ptr,
+            ).remove(src_va),
+            self.page_mapping@.dom() == old(self).page_mapping@.dom(),
+            forall|page_ptr: PagePtr|
+                #![trigger self.page_mapping@[page_ptr]]
+                old(self).page_mapping@.dom().contains(page_ptr) && page_ptr != old(
+                    self,
+                ).get_address_space(src_proc_ptr)[src_va].addr ==> old(self).page_mapping@[page_ptr]
+                    == self.page_mapping@[page_ptr],
+            self.page_mapping@[old(self).get_address_space(src_proc_ptr)[src_va].addr]
+                == Set::<(ProcPtr, VAddr)>::empty().insert((target_proc_ptr, target_va)),
+            self.get_container_owned_pages(old(self).get_proc(target_proc_ptr).owning_container).contains(
+            old(self).get_address_space(src_proc_ptr)[src_va].addr),
+    {
+        let target_container_ptr = self.proc_man.get_proc(target_proc_ptr).owning_container;
+        let ret = self.create_entry_and_share(
+            src_proc_ptr,
+            src_va,
+            target_proc_ptr,
+            target_va,
+            read_only,
+        );
+        self.unmap_moved_page(src_proc_ptr, src_va, target_container_ptr);
+        ret
+    }
+
+    pub fn range_create_and_move_mapping(
+        &mut self,
+        src_proc_ptr: ProcPtr,
+        src_va_range: &VaRange4K,
+        target_proc_ptr: ProcPtr,
+        target_va_range: &VaRange4K,
+        read_only: bool,
+    ) -> (ret: usize)
+        requires
+            old(self).total_wf(),
+            old(self).proc_dom().contains(src_proc_ptr),
+            old(self).proc_dom().contains(target_proc_ptr),
+            src_va_range.wf(),
+            target_va_range.wf(),
+            src_va_range.len == target_va_range.len,
+            old(self).get_container_quota(
+                old(self).get_proc(target_proc_ptr).owning_container,
+            ).mem_4k >= 4 * src_va_range.len,
+            old(self).get_num_of_free_pages() >= 3 * src_va_range.len,
+            old(self).address_space_range_movable(src_proc_ptr, src_va_range),
+            old(self).address_space_range_free(target_proc_ptr, target_va_range),
+            src_proc_ptr != target_proc_ptr,
+        ensures
+            self.total_wf(),
+            self.proc_dom() == old(self).proc_dom(),
+            self.thread_dom() == old(self).thread_dom(),
+            self.endpoint_dom() == old(self).endpoint_dom(),
+            self.container_dom() == old(self).container_dom(),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) ==> self.get_proc(p_ptr) =~= old(self).get_proc(
+                    p_ptr,
+                ),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) && p_ptr != target_proc_ptr && p_ptr
+                    != src_proc_ptr ==> self.get_address_space(p_ptr) =~= old(
+                    self,
+                ).get_address_space(p_ptr),
+            forall|t_ptr: ThreadPtr|
+                #![auto]
+                self.thread_dom().contains(t_ptr) ==> self.get_thread(t_ptr) =~= old(
+                    self,
+                ).get_thread(t_ptr),
+            forall|e_ptr: EndpointPtr|
+                #![auto]
+                self.endpoint_dom().contains(e_ptr) ==> self.get_endpoint(e_ptr) =~= old(
+                    self,
+                ).get_endpoint(e_ptr),
+            forall|c_ptr: ContainerPtr|
+                #![auto]
+                self.container_dom().contains(c_ptr) && c_ptr != self.get_proc(
+                    target_proc_ptr,
+                ).owning_container && c_ptr != self.get_proc(src_proc_ptr).owning_container
+                    ==> self.get_container(c_ptr) =~= old(self).get_container(c_ptr),
+            self.page_mapping@.dom() == old(self).page_mapping@.dom(),
+            forall|i: int|
+                #![auto]
+                0 <= i < src_va_range.len ==> self.get_address_space(
+                    target_proc_ptr,
+                ).dom().contains(target_va_range@[i]) && self.get_address_space(
+                    target_proc_ptr,
+                )[target_va_range@[i]] == spec_share_map_entry(
+                    old(self).get_address_space(src_proc_ptr)[src_va_range@[i]],
+                    read_only,
+                ) && self.page_mapping@[old(self).get_address_space(
+                    src_proc_ptr,
+                )[src_va_range@[i]].addr] == Set::<(ProcPtr, VAddr)>::empty().insert(
+                    (target_proc_ptr, target_va_range@[i]),
+                ),
+            forall|i: int|
+                #![auto]
+                0 <= i < src_va_range.len ==> self.get_address_space(
+                    src_proc_ptr,
+                ).dom().contains(src_va_range@[i]) == false,
+            forall|va: VAddr|
+                #![auto]
+                src_va_range@.contains(va) == false && old(self).get_address_space(
+                    src_proc_ptr,
+                ).dom().contains(va) ==> self.get_address_space(src_proc_ptr).dom().contains(va)
+                    && self.get_address_space(src_proc_ptr)[va] == old(self).get_address_space(
+                    src_proc_ptr,
+                )[va],
+            forall|va: VAddr|
+                #![auto]
+                target_va_range@.contains(va) == false && old(self).get_address_space(
+                    target_proc_ptr,
+                ).dom().contains(va) ==> self.get_address_space(target_proc_ptr)[va] == old(
+                    self,
+                ).get_address_space(target_proc_ptr)[va],
+            forall|page_ptr: PagePtr|
+                #![trigger self.page_mapping@[page_ptr]]
+                old(self).page_mapping@.dom().contains(page_ptr) && (forall|i: int|
+                    #![auto]
+                    0 <= i < src_va_range.len ==> old(self).get_address_space(
+                        src_proc_ptr,
+                    )[src_va_range@[i]].addr != page_ptr) ==> old(self).page_mapping@[page_ptr]
+                    == self.page_mapping@[page_ptr],
+    {
+        let src_container_ptr = self.proc_man.get_proc(src_proc_ptr).owning_container;
+        let target_container_ptr = self.proc_man.get_proc(target_proc_ptr).owning_container;
+        let mut ret = 0;
+        for index in 0..src_va_range.len
+            invariant
+                self.wf(),
+                0 <= index <= src_va_range.len,
+                src_va_range.wf(),
+                target_va_range.wf(),
+                src_va_range.len == target_va_range.len,
+                src_proc_ptr != target_proc_ptr,
+                ret <= 3 * index,
+                self.proc_dom() == old(self).proc_dom(),
+                self.thread_dom() == old(self).thread_dom(),
+                self.endpoint_dom() == old(self).endpoint_dom(),
+                self.container_dom() == old(self).container_dom(),
+                src_container_ptr == self.get_proc(src_proc_ptr).owning_container,
+                target_container_ptr == self.get_proc(target_proc_ptr).owning_container,
+                self.get_num_of_free_pages() == old(self).get_num_of_free_pages() - ret,
+                self.get_container_quota(target_container_ptr).mem_4k == old(
+                    self,
+                ).get_container_quota(target_container_ptr).mem_4k - ret,
+                forall|p_ptr: ProcPtr|
+                    #![auto]
+                    self.proc_dom().contains(p_ptr) ==> self.get_proc(p_ptr) =~= old(
+                        self,
+                    ).get_proc(p_ptr),
+                forall|p_ptr: ProcPtr|
+                    #![auto]
+                    self.proc_dom().contains(p_ptr) && p_ptr != target_proc_ptr && p_ptr
+                        != src_proc_ptr ==> self.get_address_space(p_ptr) =~= old(
+                        self,
+                    ).get_address_space(p_ptr),
+                forall|t_ptr: ThreadPtr|
+                    #![auto]
+                    self.thread_dom().contains(t_ptr) ==> self.get_thread(t_ptr) =~= old(
+                        self,
+                    ).get_thread(t_ptr),
+                forall|e_ptr: EndpointPtr|
+                    #![auto]
+                    self.endpoint_dom().contains(e_ptr) ==> self.get_endpoint(e_ptr) =~= old(
+                        self,
+                    ).get_endpoint(e_ptr),
+                forall|c_ptr: ContainerPtr|
+                    #![auto]
+                    self.container_dom().contains(c_ptr) && c_ptr != target_container_ptr
+                        ==> self.get_container(c_ptr) =~= old(self).get_container(c_ptr),
+                self.page_mapping@.dom() == old(self).page_mapping@.dom(),
+                forall|i: int|
+                    #![auto]
+                    index <= i < src_va_range.len ==> self.get_address_space(
+                        src_proc_ptr,
+                    ).dom().contains(src_va_range@[i]) && self.get_address_space(
+                        src_proc_ptr,
+                    )[src_va_range@[i]] == old(self).get_address_space(
+                        src_proc_ptr,
+                    )[src_va_range@[i]] && self.get_physical_page_reference_counter(
+                        self.get_address_space(src_proc_ptr)[src_va_range@[i]].addr,
+                    ) == 1,
+                forall|i: int|
+                    #![auto]
+                    index <= i < src_va_range.len ==> self.get_address_space(
+                        target_proc_ptr,
+                    ).dom().contains(target_va_range@[i]) == false,
+                forall|i: int|
+                    #![auto]
+                    0 <= i < index ==> self.get_address_space(target_proc_ptr).dom().contains(
+                        target_va_range@[i],
+                    ) && self.get_address_space(target_proc_ptr)[target_va_range@[i]]
+                        == spec_share_map_entry(
+                        old(self).get_address_space(src_proc_ptr)[src_va_range@[i]],
+                        read_only,
+                    ) && self.page_mapping@[old(self).get_address_space(
+                        src_proc_ptr,
+                    )[src_va_range@[i]].addr] == Set::<(ProcPtr, VAddr)>::empty().insert(
+                        (target_proc_ptr, target_va_range@[i]),
+                    ),
+                forall|i: int|
+                    #![auto]
+                    0 <= i < index ==> self.get_address_space(src_proc_ptr).dom().contains(
+                        src_va_range@[i],
+                    ) == false,
+                forall|va: VAddr|
+                    #![auto]
+                    src_va_range@.contains(va) == false && old(self).get_address_space(
+                        src_proc_ptr,
+                    ).dom().contains(va) ==> self.get_address_space(src_proc_ptr).dom().contains(
+                        va,
+                    ) && self.get_address_space(src_proc_ptr)[va] == old(self).get_address_space(
+                        src_proc_ptr,
+                    )[va],
+                forall|va: VAddr|
+                    #![auto]
+                    target_va_range@.contains(va) == false && old(self).get_address_space(
+                        target_proc_ptr,
+                    ).dom().contains(va) ==> self.get_address_space(target_proc_ptr)[va] == old(
+                        self,
+                    ).get_address_space(target_proc_ptr)[va],
+                forall|page_ptr: PagePtr|
+                    #![trigger self.page_mapping@[page_ptr]]
+                    old(self).page_mapping@.dom().contains(page_ptr) && (forall|i: int|
+                        #![auto]
+                        0 <= i < src_va_range.len ==> old(self).get_address_space(
+                            src_proc_ptr,
+                        )[src_va_range@[i]].addr != page_ptr) ==> old(self).page_mapping@[page_ptr]
+                        == self.page_mapping@[page_ptr],
+        {
+            proof {
+                src_va_range.va_range_lemma();
+                target_va_range.va_range_lemma();
+            }
+            let num_page = self.create_entry_and_move(
+                src_proc_ptr,
+                src_va_range.index(index),
+                target_proc_ptr,
+                target_va_range.index(index),
+                read_only,
+            );
+            ret = ret + num_page;
+        }
+        proof {
+            self.fold_change_mem_4k_lemma(*old(self), target_container_ptr);
+        }
+        // Every moved page was charged to the sender's quota when it was
+        // allocated. The charge now follows the page to the receiver.
+        if src_container_ptr != target_container_ptr {
+            let src_quota = self.proc_man.get_container(src_container_ptr).quota.mem_4k;
+            let target_quota = self.proc_man.get_container(target_container_ptr).quota.mem_4k;
+            let ghost_before = Ghost(*self);
+            self.proc_man.set_container_mem_quota_mem_4k(
+                target_container_ptr,
+                target_quota - src_va_range.len,
+            );
+            proof {
+                self.fold_change_mem_4k_lemma(ghost_before@, target_container_ptr);
+                self.fold_mem_4k_lemma();
+            }
+            let ghost_after_target = Ghost(*self);
+            self.proc_man.set_container_mem_quota_mem_4k(
+                src_container_ptr,
+                src_quota + src_va_range.len,
+            );
+            proof {
+                self.fold_change_mem_4k_lemma(ghost_after_target@, src_container_ptr);
+            }
+            assert(self.wf()) by {
+                assert(self.mem_man.wf());
+                assert(self.page_alloc.wf());
+                assert(self.proc_man.wf());
+                assert(self.memory_wf());
+                assert(self.mapping_wf());
+                assert(self.pcid_ioid_wf());
+                assert(self.page_mapping_wf());
+            };
+        }
+        assert(self.total_wf());
+        ret
+    }
+}
+
+} // verus!
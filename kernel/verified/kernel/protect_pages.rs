@@ -0,0 +1,273 @@
+use vstd::prelude::*;
+verus! {
+
+use crate::util::page_ptr_util_u::*;
+use crate::define::*;
+use crate::pagetable::entry::*;
+use crate::kernel::Kernel;
+use crate::va_range::VaRange4K;
+
+impl Kernel {
+    /// A mapping may only gain permissions if it is the only reference to a
+    /// regular page. Pages shared with someone else (read-only shares and
+    /// copy-on-write pages), pages a device can reach and MMIO pages keep at
+    /// most the permissions they have.
+    pub open spec fn protect_mapping_allowed(
+        &self,
+        target_proc_ptr: ProcPtr,
+        va: VAddr,
+        page_type: PageType,
+    ) -> bool {
+        let entry = self.get_address_space(target_proc_ptr)[va];
+        ||| spec_page_type_narrows(&entry, page_type)
+        ||| (self.page_alloc.page_array@[page_ptr2page_index(entry.addr) as int].is_io_page == false
+            && self.get_physical_page_reference_counter(entry.addr) == 1)
+    }
+
+    pub open spec fn address_space_range_protect_allowed(
+        &self,
+        target_proc_ptr: ProcPtr,
+        va_range: &VaRange4K,
+        page_type: PageType,
+    ) -> bool {
+        forall|j: int|
+            #![auto]
+            0 <= j < va_range.len ==> self.protect_mapping_allowed(
+                target_proc_ptr,
+                va_range@[j],
+                page_type,
+            )
+    }
+
+    pub fn check_address_space_va_range_protect_allowed(
+        &self,
+        target_proc_ptr: ProcPtr,
+        va_range: &VaRange4K,
+        page_type: PageType,
+    ) -> (ret: bool)
+        requires
+            self.wf(),
+            self.proc_dom().contains(target_proc_ptr),
+            va_range.wf(),
+            self.address_space_range_exists(target_proc_ptr, va_range),
+        ensures
+            ret == self.address_space_range_protect_allowed(target_proc_ptr, va_range, page_type),
+    {
+        let target_pcid = self.proc_man.get_proc(target_proc_ptr).pcid;
+        for i in 0..va_range.len
+            invariant
+                self.mem_man.pcid_active(target_pcid),
+                target_pcid == self.get_proc(target_proc_ptr).pcid,
+                0 <= i <= va_range.len,
+                self.wf(),
+                self.proc_dom().contains(target_proc_ptr),
+                va_range.wf(),
+                self.address_space_range_exists(target_proc_ptr, va_range),
+                forall|j: int|
+                    #![auto]
+                    0 <= j < i ==> self.protect_mapping_allowed(
+                        target_proc_ptr,
+                        va_range@[j],
+                        page_type,
+                    ),
+        {
+            proof {
+                va_range.va_range_lemma();
+            }
+            let entry = page_entry_to_map_entry(
+                &self.mem_man.resolve_pagetable_mapping(target_pcid, va_range.index(i)).unwrap(),
+            );
+            assert(entry == self.get_address_space(target_proc_ptr)[va_range@[i as int]]);
+            if page_type_narrows(&entry, page_type) == false {
+                assert(self.page_alloc.page_is_mapped(entry.addr));
+                if self.page_alloc.get_page_is_io_page(entry.addr)
+                    || self.page_alloc.get_page_reference_counter(entry.addr) != 1 {
+                    assert(!self.protect_mapping_allowed(
+                        target_proc_ptr,
+                        va_range@[i as int],
+                        page_type,
+                    ));
+                    return false;
+                }
+            }
+        }
+        return true;
+    }
+
+    pub fn check_address_space_va_range_exists(
+        &self,
+        target_proc_ptr: ProcPtr,
+        va_range: &VaRange4K,
+    ) -> (ret: bool)
+        requires
+            self.wf(),
+            self.proc_dom().contains(target_proc_ptr),
+            va_range.wf(),
+        ensures
+            ret == self.address_space_range_exists(target_proc_ptr, va_range),
+    {
+        let target_pcid = self.proc_man.get_proc(target_proc_ptr).pcid;
+        for i in 0..va_range.len
+            invariant
+                self.mem_man.pcid_active(target_pcid),
+                target_pcid == self.get_proc(target_proc_ptr).pcid,
+                0 <= i <= va_range.len,
+                self.wf(),
+                self.proc_dom().contains(target_proc_ptr),
+                va_range.wf(),
+                forall|j: int|
+                    #![auto]
+                    0 <= j < i ==> self.get_address_space(target_proc_ptr).dom().contains(
+                        va_range@[j],
+                    ),
+        {
+            if self.mem_man.resolve_pagetable_mapping(target_pcid, va_range.index(i)).is_none() {
+                return false;
+            }
+        }
+        return true;
+    }
+
+    pub fn protect_mapping(&mut self, target_proc_ptr: ProcPtr, target_va: VAddr, page_type: PageType)
+        requires
+            old(self).wf(),
+            old(self).proc_dom().contains(target_proc_ptr),
+            va_4k_valid(target_va),
+            old(self).get_address_space(target_proc_ptr).dom().contains(target_va),
+        ensures
+            self.wf(),
+            self.proc_man =~= old(self).proc_man,
+            self.page_alloc =~= old(self).page_alloc,
+            self.page_mapping =~= old(self).page_mapping,
+            self.page_io_mapping =~= old(self).page_io_mapping,
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) && p_ptr != target_proc_ptr
+                    ==> self.get_address_space(p_ptr) =~= old(self).get_address_space(p_ptr),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) && self.get_proc(p_ptr).ioid.is_Some()
+                    ==> self.get_io_space(p_ptr) =~= old(self).get_io_space(p_ptr),
+            self.get_address_space(target_proc_ptr) =~= old(self).get_address_space(
+                target_proc_ptr,
+            ).insert(
+                target_va,
+                spec_page_type_to_map_entry(
+                    old(self).get_address_space(target_proc_ptr)[target_va],
+                    page_type,
+                ),
+            ),
+    {
+        proof {
+            self.proc_man.pcid_unique(target_proc_ptr);
+        }
+        let target_pcid = self.proc_man.get_proc(target_proc_ptr).pcid;
+        let old_entry = self.mem_man.resolve_pagetable_mapping(target_pcid, target_va).unwrap();
+        let new_entry = page_type_to_map_entry(old_entry, page_type);
+        self.mem_man.pagetable_protect_4k_page(target_pcid, target_va, &new_entry);
+        assert(self.wf()) by {
+            assert(self.mem_man.wf());
+            assert(self.page_alloc.wf());
+            assert(self.proc_man.wf());
+            assert(self.memory_wf());
+            assert(self.mapping_wf());
+            assert(self.pcid_ioid_wf());
+            assert(self.page_mapping_wf());
+        };
+    }
+
+    pub fn range_protect_mapping(
+        &mut self,
+        target_proc_ptr: ProcPtr,
+        va_range: &VaRange4K,
+        page_type: PageType,
+    )
+        requires
+            old(self).wf(),
+            old(self).proc_dom().contains(target_proc_ptr),
+            va_range.wf(),
+            old(self).address_space_range_exists(target_proc_ptr, va_range),
+        ensures
+            self.wf(),
+            self.proc_man =~= old(self).proc_man,
+            self.page_alloc =~= old(self).page_alloc,
+            self.page_mapping =~= old(self).page_mapping,
+            self.page_io_mapping =~= old(self).page_io_mapping,
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) && p_ptr != target_proc_ptr
+                    ==> self.get_address_space(p_ptr) =~= old(self).get_address_space(p_ptr),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) && self.get_proc(p_ptr).ioid.is_Some()
+                    ==> self.get_io_space(p_ptr) =~= old(self).get_io_space(p_ptr),
+            self.get_address_space(target_proc_ptr).dom() =~= old(self).get_address_space(
+                target_proc_ptr,
+            ).dom(),
+            forall|va: VAddr|
+                #![auto]
+                va_range@.contains(va) == false && old(self).get_address_space(
+                    target_proc_ptr,
+                ).dom().contains(va) ==> self.get_address_space(target_proc_ptr)[va] == old(
+                    self,
+                ).get_address_space(target_proc_ptr)[va],
+            forall|i: int|
+                #![auto]
+                0 <= i < va_range.len ==> self.get_address_space(target_proc_ptr)[va_range@[i]]
+                    == spec_page_type_to_map_entry(
+                    old(self).get_address_space(target_proc_ptr)[va_range@[i]],
+                    page_type,
+                ),
+    {
+        for index in 0..va_range.len
+            invariant
+                self.wf(),
+                self.proc_dom().contains(target_proc_ptr),
+                va_range.wf(),
+                self.proc_man =~= old(self).proc_man,
+                self.page_alloc =~= old(self).page_alloc,
+                self.page_mapping =~= old(self).page_mapping,
+                self.page_io_mapping =~= old(self).page_io_mapping,
+                forall|p_ptr: ProcPtr|
+                    #![auto]
+                    self.proc_dom().contains(p_ptr) && p_ptr != target_proc_ptr
+                        ==> self.get_address_space(p_ptr) =~= old(self).get_address_space(p_ptr),
+                forall|p_ptr: ProcPtr|
+                    #![auto]
+                    self.proc_dom().contains(p_ptr) && self.get_proc(p_ptr).ioid.is_Some()
+                        ==> self.get_io_space(p_ptr) =~= old(self).get_io_space(p_ptr),
+                self.get_address_space(target_proc_ptr).dom() =~= old(self).get_address_space(
+                    target_proc_ptr,
+                ).dom(),
+                forall|va: VAddr|
+                    #![auto]
+                    va_range@.contains(va) == false && old(self).get_address_space(
+                        target_proc_ptr,
+                    ).dom().contains(va) ==> self.get_address_space(target_proc_ptr)[va] == old(
+                        self,
+                    ).get_address_space(target_proc_ptr)[va],
+                forall|i: int|
+                    #![auto]
+                    index <= i < va_range.len ==> self.get_address_space(target_proc_ptr)[va_range@[i]]
+                        == old(self).get_address_space(target_proc_ptr)[va_range@[i]],
+                forall|i: int|
+                    #![auto]
+                    0 <= i < index ==> self.get_address_space(target_proc_ptr)[va_range@[i]]
+                        == spec_page_type_to_map_entry(
+                        old(self).get_address_space(target_proc_ptr)[va_range@[i]],
+                        page_type,
+                    ),
+                old(self).address_space_range_exists(target_proc_ptr, va_range),
+        {
+            proof {
+                va_range.va_range_lemma();
+            }
+            self.protect_mapping(target_proc_ptr, va_range.index(index), page_type);
+            assert(forall|i: int|
+                #![auto]
+                0 <= i < va_range.len && i != index ==> va_range@[i] != va_range@[index as int]);
+        }
+    }
+}
+
+} // verus!
@@ -0,0 +1,53 @@
+use vstd::prelude::*;
+verus! {
+
+use crate::define::*;
+use crate::kernel::Kernel;
+use crate::pagetable::entry::MapEntry;
+use crate::util::page_ptr_util_u::*;
+
+impl Kernel {
+    /// Returns the lowest mapped 4k page at or above `va` in the caller's
+    /// address space, together with its mapping. Called repeatedly to walk
+    /// the whole address space.
+    pub fn syscall_next_mapping(&self, thread_ptr: ThreadPtr, va: VAddr) -> (ret: Option<
+        (VAddr, MapEntry),
+    >)
+        requires
+            self.total_wf(),
+            self.thread_dom().contains(thread_ptr),
+        ensures
+            va_4k_valid(va) == false ==> ret.is_None(),
+            ret.is_Some() ==> va_4k_valid(ret.unwrap().0) && va <= ret.unwrap().0
+                && self.get_address_space(self.get_thread(thread_ptr).owning_proc).dom().contains(
+                ret.unwrap().0,
+            ) && self.get_address_space(self.get_thread(thread_ptr).owning_proc)[ret.unwrap().0]
+                =~= ret.unwrap().1,
+            ret.is_Some() ==> forall|v: VAddr|
+                #![auto]
+                va_4k_valid(v) && va <= v < ret.unwrap().0 ==> self.get_address_space(
+                    self.get_thread(thread_ptr).owning_proc,
+                ).dom().contains(v) == false,
+            va_4k_valid(va) && ret.is_None() ==> forall|v: VAddr|
+                #![auto]
+                va_4k_valid(v) && va <= v ==> self.get_address_space(
+                    self.get_thread(thread_ptr).owning_proc,
+                ).dom().contains(v) == false,
+    {
+        if va_4k_valid(va) == false {
+            return None;
+        }
+        let proc_ptr = self.proc_man.get_thread(thread_ptr).owning_proc;
+
+        proof {
+            self.proc_man.thread_inv();
+            self.proc_man.process_inv();
+            self.proc_man.pcid_unique(proc_ptr);
+        }
+
+        let pcid = self.proc_man.get_proc(proc_ptr).pcid;
+        self.mem_man.pagetable_next_4k_mapping(pcid, va)
+    }
+}
+
+} // verus!
@@ -131,6 +131,10 @@ impl Kernel {
         if !self.check_address_space_va_range_shareable(proc_ptr, &va_range){
             return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
         }
+        if !self.check_address_space_va_range_not_cow(proc_ptr, &va_range) {
+            // the fault handler would copy the page away from the device
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
         if self.check_io_space_va_range_free(proc_ptr, &va_range) == false {
             return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
         }
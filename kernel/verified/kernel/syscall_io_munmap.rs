@@ -0,0 +1,465 @@
+use vstd::prelude::*;
+verus! {
+
+use crate::util::page_ptr_util_u::*;
+use crate::define::*;
+use crate::pagetable::entry::*;
+use crate::kernel::Kernel;
+use crate::va_range::VaRange4K;
+
+pub open spec fn syscall_io_munmap_return_value(
+    old: Kernel,
+    thread_ptr: ThreadPtr,
+    va_range: VaRange4K,
+) -> UserRetValueType {
+    let proc_ptr = old.proc_man.get_thread(thread_ptr).owning_proc;
+
+    if old.get_proc(proc_ptr).ioid.is_None() {
+        UserRetValueType::Else
+    } else if old.io_space_range_exists(proc_ptr, &va_range) == false {
+        UserRetValueType::Else
+    } else {
+        UserRetValueType::Success
+    }
+}
+
+pub open spec fn syscall_io_munmap_spec(
+    old: Kernel,
+    new: Kernel,
+    thread_id: ThreadPtr,
+    va_range: VaRange4K,
+    ret: SyscallReturnStruct,
+) -> bool {
+    let proc_ptr = old.get_thread(thread_id).owning_proc;
+    if syscall_io_munmap_return_value(old, thread_id, va_range).is_error() {
+        new =~= old
+    } else {
+        // things that did not change
+        &&& old.thread_dom() =~= new.thread_dom()
+        &&& old.proc_dom() =~= new.proc_dom()
+        &&& old.container_dom() =~= new.container_dom()
+        &&& old.endpoint_dom() =~= new.endpoint_dom()
+        &&& forall|t_ptr: ThreadPtr|
+            #![trigger new.get_thread(t_ptr)]
+            #![trigger old.get_thread(t_ptr)]
+            old.thread_dom().contains(t_ptr) ==> new.get_thread(t_ptr) =~= old.get_thread(t_ptr)
+        &&& forall|proc_ptr: ProcPtr|
+            #![trigger new.get_proc(proc_ptr)]
+            new.proc_dom().contains(proc_ptr) ==> new.get_proc(proc_ptr) =~= old.get_proc(proc_ptr)
+        &&& forall|e_ptr: EndpointPtr|
+            #![trigger new.get_endpoint(e_ptr)]
+            new.endpoint_dom().contains(e_ptr) ==> old.get_endpoint(e_ptr) =~= new.get_endpoint(
+                e_ptr,
+            )
+        &&& forall|p_ptr: ProcPtr|
+            #![trigger new.get_address_space(p_ptr)]
+            new.proc_dom().contains(p_ptr) ==> new.get_address_space(p_ptr) =~= old.get_address_space(
+                p_ptr,
+            )
+        &&& forall|p_ptr: ProcPtr|
+            #![trigger new.get_io_space(p_ptr)]
+            new.proc_dom().contains(p_ptr) && new.get_proc_has_iommu_table(p_ptr) && p_ptr
+                != proc_ptr ==> new.get_io_space(p_ptr) =~= old.get_io_space(p_ptr)
+        &&& forall|va: VAddr|
+            #![trigger new.get_io_space(proc_ptr).dom().contains(va)]
+            va_range@.contains(va) == false ==> new.get_io_space(proc_ptr).dom().contains(va)
+                == old.get_io_space(proc_ptr).dom().contains(va)
+        &&& old.page_alloc.free_pages_4k().subset_of(new.page_alloc.free_pages_4k())
+        //Things that changed
+        &&& forall|i: int|
+            #![auto]
+            0 <= i < va_range.len ==> new.get_io_space(proc_ptr).dom().contains(va_range@[i])
+                == false
+        &&& forall|i: int|
+            #![auto]
+            0 <= i < va_range.len && old.io_range_page_freed(proc_ptr, va_range@[i])
+                ==> new.page_alloc.free_pages_4k().contains(
+                old.get_io_space(proc_ptr)[va_range@[i]].addr,
+            )
+    }
+}
+
+impl Kernel {
+    pub open spec fn io_space_range_exists(
+        &self,
+        target_proc_ptr: ProcPtr,
+        va_range: &VaRange4K,
+    ) -> bool {
+        forall|j: int|
+            #![auto]
+            0 <= j < va_range.len ==> self.get_io_space(target_proc_ptr).dom().contains(
+                va_range@[j],
+            )
+    }
+
+    pub fn check_io_space_va_range_exists(
+        &self,
+        target_proc_ptr: ProcPtr,
+        va_range: &VaRange4K,
+    ) -> (ret: bool)
+        requires
+            self.wf(),
+            self.proc_dom().contains(target_proc_ptr),
+            self.get_proc(target_proc_ptr).ioid.is_Some(),
+            va_range.wf(),
+        ensures
+            ret == self.io_space_range_exists(target_proc_ptr, va_range),
+    {
+        let target_ioid = self.proc_man.get_proc(target_proc_ptr).ioid.unwrap();
+        for i in 0..va_range.len
+            invariant
+                self.mem_man.ioid_active(target_ioid),
+                self.get_proc(target_proc_ptr).ioid.is_Some(),
+                target_ioid == self.get_proc(target_proc_ptr).ioid.unwrap(),
+                0 <= i <= va_range.len,
+                self.wf(),
+                self.proc_dom().contains(target_proc_ptr),
+                va_range.wf(),
+                forall|j: int|
+                    #![auto]
+                    0 <= j < i ==> self.get_io_space(target_proc_ptr).dom().contains(
+                        va_range@[j],
+                    ),
+        {
+            if self.mem_man.resolve_iommu_table_mapping(target_ioid, va_range.index(i)).is_none() {
+                return false;
+            }
+        }
+        return true;
+    }
+
+    /// Removes one IOMMU mapping of `target_proc_ptr`. When this was the last reference to an
+    /// ordinary page, the page goes back to the free list and its owner gets the quota back.
+    /// Pages of the `Io` region are never returned to the free list.
+    pub fn unmap_io_page(&mut self, target_proc_ptr: ProcPtr, target_va: VAddr) -> (ret: MapEntry)
+        requires
+            old(self).total_wf(),
+            old(self).proc_dom().contains(target_proc_ptr),
+            old(self).get_proc_has_iommu_table(target_proc_ptr),
+            va_4k_valid(target_va),
+            old(self).get_io_space(target_proc_ptr).dom().contains(target_va),
+        ensures
+            self.total_wf(),
+            self.proc_dom() == old(self).proc_dom(),
+            self.thread_dom() == old(self).thread_dom(),
+            self.endpoint_dom() == old(self).endpoint_dom(),
+            self.container_dom() == old(self).container_dom(),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) ==> self.get_proc(p_ptr) =~= old(self).get_proc(
+                    p_ptr,
+                ),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) ==> self.get_address_space(p_ptr) =~= old(
+                    self,
+                ).get_address_space(p_ptr),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) && self.get_proc_has_iommu_table(p_ptr) && p_ptr
+                    != target_proc_ptr ==> self.get_io_space(p_ptr) =~= old(self).get_io_space(
+                    p_ptr,
+                ),
+            forall|t_ptr: ThreadPtr|
+                #![auto]
+                self.thread_dom().contains(t_ptr) ==> self.get_thread(t_ptr) =~= old(
+                    self,
+                ).get_thread(t_ptr),
+            forall|e_ptr: EndpointPtr|
+                #![auto]
+                self.endpoint_dom().contains(e_ptr) ==> self.get_endpoint(e_ptr) =~= old(
+                    self,
+                ).get_endpoint(e_ptr),
+            self.get_io_space(target_proc_ptr) =~= old(self).get_io_space(target_proc_ptr).remove(
+                target_va,
+            ),
+            ret == old(self).get_io_space(target_proc_ptr)[target_va],
+            old(self).get_physical_page_reference_counter(ret.addr) == 1
+                && !old(self).page_alloc.page_is_io_page(ret.addr)
+                ==> self.page_alloc.free_pages_4k() =~= old(self).page_alloc.free_pages_4k().insert(
+                ret.addr,
+            ),
+            !(old(self).get_physical_page_reference_counter(ret.addr) == 1
+                && !old(self).page_alloc.page_is_io_page(ret.addr))
+                ==> self.page_alloc.free_pages_4k() =~= old(self).page_alloc.free_pages_4k(),
+            old(self).get_physical_page_reference_counter(ret.addr) == 1
+                ==> !self.page_alloc.page_is_mapped(ret.addr),
+            forall|p: PagePtr|
+                #![auto]
+                self.page_alloc.page_is_io_page(p) == old(self).page_alloc.page_is_io_page(p),
+            forall|p: PagePtr|
+                #![auto]
+                self.page_alloc.page_is_mapped(p) && p != ret.addr
+                    ==> self.get_physical_page_reference_counter(p)
+                    == old(self).get_physical_page_reference_counter(p),
+    {
+        proof {
+            self.proc_man.ioid_unique(target_proc_ptr);
+        }
+        let target_ioid = self.proc_man.get_proc(target_proc_ptr).ioid.unwrap();
+        let target_entry = page_entry_to_map_entry(
+            &self.mem_man.resolve_iommu_table_mapping(target_ioid, target_va).unwrap(),
+        );
+        proof {
+            self.page_alloc.mapped_page_imply_page_ptr_valid(target_entry.addr);
+            self.page_alloc.mapped_page_are_not_allocated(target_entry.addr);
+        }
+        let last_reference = self.page_alloc.get_page_reference_counter(target_entry.addr) == 1;
+        assert(self.page_alloc.page_array@[page_ptr2page_index(target_entry.addr) as int].ref_count
+            == self.get_physical_page_reference_counter(target_entry.addr));
+        let is_io_page = self.page_alloc.get_page_is_io_page(target_entry.addr);
+
+        self.mem_man.iommu_table_unmap_4k_page(target_ioid, target_va);
+        let owner_op = self.page_alloc.remove_io_mapping_4k(
+            target_entry.addr,
+            target_ioid,
+            target_va,
+        );
+        proof {
+            if last_reference {
+                self.page_mapping@ = self.page_mapping@.remove(target_entry.addr);
+                self.page_io_mapping@ = self.page_io_mapping@.remove(target_entry.addr);
+            } else {
+                self.page_io_mapping@ = self.page_io_mapping@.insert(
+                    target_entry.addr,
+                    self.page_io_mapping@[target_entry.addr].remove((target_proc_ptr, target_va)),
+                );
+            }
+        }
+        if last_reference && is_io_page == false {
+            let owner = owner_op.unwrap();
+            assert(self.proc_man.container_dom().contains(owner));
+            proof {
+                old(self).fold_mem_4k_lemma();
+            }
+            let old_quota = self.proc_man.get_container(owner).quota.mem_4k;
+            self.proc_man.set_container_mem_quota_mem_4k(owner, old_quota + 1);
+            assert(self.container_dom().fold(
+                0,
+                |e: int, a: ContainerPtr| e + self.get_container(a).quota.mem_4k,
+            ) == old(self).container_dom().fold(
+                0,
+                |e: int, a: ContainerPtr| e + old(self).get_container(a).quota.mem_4k,
+            ) + 1) by {
+                self.fold_change_mem_4k_lemma(*old(self), owner);
+            }
+        }
+        assert(self.wf()) by {
+            assert(self.mem_man.wf());
+            assert(self.page_alloc.wf());
+            assert(self.proc_man.wf());
+            assert(self.memory_wf()) by {
+                assert(self.mem_man.page_closure().disjoint(self.proc_man.page_closure()));
+                assert(self.mem_man.page_closure() + self.proc_man.page_closure()
+                    == self.page_alloc.allocated_pages_4k());
+                assert(self.page_alloc.container_map_4k@.dom() =~= self.proc_man.container_dom());
+            };
+            assert(self.mapping_wf());
+            assert(self.pcid_ioid_wf());
+            assert(self.page_mapping_wf());
+        };
+        assert(self.total_wf());
+        target_entry
+    }
+
+    /// The page `va` maps in the IOMMU table of `target_proc_ptr` has no other reference and is
+    /// ordinary memory, so unmapping `va` puts it back on the free list.
+    pub open spec fn io_range_page_freed(&self, target_proc_ptr: ProcPtr, va: VAddr) -> bool {
+        let page_ptr = self.get_io_space(target_proc_ptr)[va].addr;
+        &&& self.get_physical_page_reference_counter(page_ptr) == 1
+        &&& !self.page_alloc.page_is_io_page(page_ptr)
+    }
+
+    pub fn range_unmap_io(&mut self, target_proc_ptr: ProcPtr, va_range: &VaRange4K)
+        requires
+            old(self).total_wf(),
+            old(self).proc_dom().contains(target_proc_ptr),
+            old(self).get_proc_has_iommu_table(target_proc_ptr),
+            va_range.wf(),
+            old(self).io_space_range_exists(target_proc_ptr, va_range),
+        ensures
+            self.total_wf(),
+            self.proc_dom() == old(self).proc_dom(),
+            self.thread_dom() == old(self).thread_dom(),
+            self.endpoint_dom() == old(self).endpoint_dom(),
+            self.container_dom() == old(self).container_dom(),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) ==> self.get_proc(p_ptr) =~= old(self).get_proc(
+                    p_ptr,
+                ),
+            forall|t_ptr: ThreadPtr|
+                #![auto]
+                self.thread_dom().contains(t_ptr) ==> self.get_thread(t_ptr) =~= old(
+                    self,
+                ).get_thread(t_ptr),
+            forall|e_ptr: EndpointPtr|
+                #![auto]
+                self.endpoint_dom().contains(e_ptr) ==> self.get_endpoint(e_ptr) =~= old(
+                    self,
+                ).get_endpoint(e_ptr),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) ==> self.get_address_space(p_ptr) =~= old(
+                    self,
+                ).get_address_space(p_ptr),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) && self.get_proc_has_iommu_table(p_ptr) && p_ptr
+                    != target_proc_ptr ==> self.get_io_space(p_ptr) =~= old(self).get_io_space(
+                    p_ptr,
+                ),
+            forall|i: int|
+                #![auto]
+                0 <= i < va_range.len ==> self.get_io_space(target_proc_ptr).dom().contains(
+                    va_range@[i],
+                ) == false,
+            forall|va: VAddr|
+                #![auto]
+                va_range@.contains(va) == false ==> self.get_io_space(
+                    target_proc_ptr,
+                ).dom().contains(va) == old(self).get_io_space(target_proc_ptr).dom().contains(va),
+            old(self).page_alloc.free_pages_4k().subset_of(self.page_alloc.free_pages_4k()),
+            forall|i: int|
+                #![auto]
+                0 <= i < va_range.len && old(self).io_range_page_freed(
+                    target_proc_ptr,
+                    va_range@[i],
+                ) ==> self.page_alloc.free_pages_4k().contains(
+                    old(self).get_io_space(target_proc_ptr)[va_range@[i]].addr,
+                ),
+    {
+        for index in 0..va_range.len
+            invariant
+                self.total_wf(),
+                self.proc_dom().contains(target_proc_ptr),
+                self.get_proc_has_iommu_table(target_proc_ptr),
+                va_range.wf(),
+                self.proc_dom() == old(self).proc_dom(),
+                self.thread_dom() == old(self).thread_dom(),
+                self.endpoint_dom() == old(self).endpoint_dom(),
+                self.container_dom() == old(self).container_dom(),
+                forall|p_ptr: ProcPtr|
+                    #![auto]
+                    self.proc_dom().contains(p_ptr) ==> self.get_proc(p_ptr) =~= old(self).get_proc(
+                        p_ptr,
+                    ),
+                forall|t_ptr: ThreadPtr|
+                    #![auto]
+                    self.thread_dom().contains(t_ptr) ==> self.get_thread(t_ptr) =~= old(
+                        self,
+                    ).get_thread(t_ptr),
+                forall|e_ptr: EndpointPtr|
+                    #![auto]
+                    self.endpoint_dom().contains(e_ptr) ==> self.get_endpoint(e_ptr) =~= old(
+                        self,
+                    ).get_endpoint(e_ptr),
+                forall|p_ptr: ProcPtr|
+                    #![auto]
+                    self.proc_dom().contains(p_ptr) ==> self.get_address_space(p_ptr) =~= old(
+                        self,
+                    ).get_address_space(p_ptr),
+                forall|p_ptr: ProcPtr|
+                    #![auto]
+                    self.proc_dom().contains(p_ptr) && self.get_proc_has_iommu_table(p_ptr)
+                        && p_ptr != target_proc_ptr ==> self.get_io_space(p_ptr) =~= old(
+                        self,
+                    ).get_io_space(p_ptr),
+                forall|i: int|
+                    #![auto]
+                    0 <= i < index ==> self.get_io_space(target_proc_ptr).dom().contains(
+                        va_range@[i],
+                    ) == false,
+                forall|i: int|
+                    #![auto]
+                    index <= i < va_range.len ==> self.get_io_space(
+                        target_proc_ptr,
+                    ).dom().contains(va_range@[i]),
+                forall|va: VAddr|
+                    #![auto]
+                    va_range@.contains(va) == false ==> self.get_io_space(
+                        target_proc_ptr,
+                    ).dom().contains(va) == old(self).get_io_space(target_proc_ptr).dom().contains(
+                        va,
+                    ),
+                forall|i: int|
+                    #![auto]
+                    index <= i < va_range.len ==> self.get_io_space(target_proc_ptr)[va_range@[i]]
+                        =~= old(self).get_io_space(target_proc_ptr)[va_range@[i]],
+                forall|i: int|
+                    #![auto]
+                    index <= i < va_range.len && old(self).get_physical_page_reference_counter(
+                        old(self).get_io_space(target_proc_ptr)[va_range@[i]].addr,
+                    ) == 1 ==> self.get_physical_page_reference_counter(
+                        old(self).get_io_space(target_proc_ptr)[va_range@[i]].addr,
+                    ) == 1,
+                forall|p: PagePtr|
+                    #![auto]
+                    self.page_alloc.page_is_io_page(p) == old(self).page_alloc.page_is_io_page(p),
+                old(self).page_alloc.free_pages_4k().subset_of(self.page_alloc.free_pages_4k()),
+                forall|i: int|
+                    #![auto]
+                    0 <= i < index && old(self).io_range_page_freed(target_proc_ptr, va_range@[i])
+                        ==> self.page_alloc.free_pages_4k().contains(
+                        old(self).get_io_space(target_proc_ptr)[va_range@[i]].addr,
+                    ),
+        {
+            proof {
+                va_range.va_range_lemma();
+            }
+            let entry = self.unmap_io_page(target_proc_ptr, va_range.index(index));
+            assert(forall|i: int|
+                #![auto]
+                0 <= i < va_range.len && i != index ==> va_range@[i] != va_range@[index as int]);
+            // A page with a single reference that is still mapped further up the range cannot
+            // be the one just unmapped, since that one is no longer mapped at all.
+            assert forall|i: int|
+                #![auto]
+                index < i < va_range.len && old(self).get_physical_page_reference_counter(
+                    old(self).get_io_space(target_proc_ptr)[va_range@[i]].addr,
+                ) == 1 implies self.get_physical_page_reference_counter(
+                old(self).get_io_space(target_proc_ptr)[va_range@[i]].addr,
+            ) == 1 by {
+                assert(self.get_io_space(target_proc_ptr).dom().contains(va_range@[i]));
+                assert(self.page_alloc.page_is_mapped(
+                    self.get_io_space(target_proc_ptr)[va_range@[i]].addr,
+                ));
+                assert(old(self).get_io_space(target_proc_ptr)[va_range@[i]].addr != entry.addr);
+            };
+        }
+    }
+
+    /// Removes the caller's IOMMU mappings in `va_range`.
+    ///
+    /// Pages whose last reference goes away here are back on the free list when this returns.
+    /// The caller must invalidate the IOTLB of the process's IOMMU domain before the kernel
+    /// lock is released, so no stale device translation can reach a reallocated page.
+    pub fn syscall_io_munmap(&mut self, thread_ptr: ThreadPtr, va_range: VaRange4K) -> (ret:
+        SyscallReturnStruct)
+        requires
+            old(self).total_wf(),
+            old(self).thread_dom().contains(thread_ptr),
+            va_range.wf(),
+        ensures
+            self.total_wf(),
+            syscall_io_munmap_spec(*old(self), *self, thread_ptr, va_range, ret),
+    {
+        let proc_ptr = self.proc_man.get_thread(thread_ptr).owning_proc;
+
+        proof {
+            self.proc_man.thread_inv();
+            self.proc_man.process_inv();
+        }
+
+        if self.proc_man.get_proc(proc_ptr).ioid.is_none() {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        if self.check_io_space_va_range_exists(proc_ptr, &va_range) == false {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        self.range_unmap_io(proc_ptr, &va_range);
+        return SyscallReturnStruct::NoSwitchNew(RetValueType::Else);
+    }
+}
+
+} // verus!
@@ -0,0 +1,71 @@
+use vstd::prelude::*;
+verus! {
+
+use crate::define::*;
+use crate::kernel::Kernel;
+use crate::quota::Quota;
+
+impl Kernel {
+    /// Returns the remaining quota of the caller's container and the number
+    /// of 4k pages mapped in the caller's address space.
+    pub fn syscall_mem_usage(&self, thread_ptr: ThreadPtr) -> (ret: (Quota, usize))
+        requires
+            self.total_wf(),
+            self.thread_dom().contains(thread_ptr),
+        ensures
+            ret.0 =~= self.get_container_quota(self.get_thread(thread_ptr).owning_container),
+            ret.1 == self.get_address_space(self.get_thread(thread_ptr).owning_proc).dom().len(),
+    {
+        let proc_ptr = self.proc_man.get_thread(thread_ptr).owning_proc;
+        let container_ptr = self.proc_man.get_thread(thread_ptr).owning_container;
+
+        proof {
+            self.proc_man.thread_inv();
+            self.proc_man.process_inv();
+            self.proc_man.pcid_unique(proc_ptr);
+        }
+
+        let pcid = self.proc_man.get_proc(proc_ptr).pcid;
+        let quota = self.proc_man.get_container(container_ptr).quota;
+        let mapped_pages = self.mem_man.pagetable_count_4k_mappings(pcid);
+        (quota, mapped_pages)
+    }
+
+    /// Returns the number of 4k pages mapped in the address space of `pcid`,
+    /// provided `pcid` belongs to a process of the caller's container.
+    pub fn syscall_proc_mem_usage(&self, thread_ptr: ThreadPtr, pcid: Pcid) -> (ret: Option<
+        usize,
+    >)
+        requires
+            self.total_wf(),
+            self.thread_dom().contains(thread_ptr),
+        ensures
+            ret.is_Some() <==> 0 <= pcid < PCID_MAX && self.mem_man.pcid_active(pcid)
+                && self.get_proc(self.mem_man.pcid_to_proc_ptr(pcid)).owning_container
+                == self.get_thread(thread_ptr).owning_container,
+            ret.is_Some() ==> ret.unwrap() == self.get_address_space(
+                self.mem_man.pcid_to_proc_ptr(pcid),
+            ).dom().len(),
+    {
+        if pcid >= PCID_MAX {
+            return None;
+        }
+        let container_ptr = self.proc_man.get_thread(thread_ptr).owning_container;
+        let proc_ptr_op = *self.mem_man.pcid_to_proc_ptr.get(pcid);
+        if proc_ptr_op.is_none() {
+            return None;
+        }
+        let proc_ptr = proc_ptr_op.unwrap();
+        proof {
+            assert(self.mem_man.pcid_active(pcid));
+            assert(self.mem_man.pcid_to_proc_ptr(pcid) == proc_ptr);
+            assert(self.proc_dom().contains(proc_ptr));
+        }
+        if self.proc_man.get_proc(proc_ptr).owning_container != container_ptr {
+            return None;
+        }
+        Some(self.mem_man.pagetable_count_4k_mappings(pcid))
+    }
+}
+
+} // verus!
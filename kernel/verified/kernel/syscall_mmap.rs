@@ -12,7 +12,7 @@ use crate::define::*;
 
 // use crate::trap::*;
 // use crate::pagetable::pagemap_util_t::*;
-// use crate::pagetable::entry::*;
+use crate::pagetable::entry::*;
 use crate::kernel::Kernel;
 use crate::va_range::VaRange4K;
 
@@ -133,8 +133,12 @@ pub open spec fn syscall_mmap_spec(
 }
 
 impl Kernel {
-    pub fn syscall_mmap(&mut self, thread_ptr: ThreadPtr, va_range: VaRange4K) -> (ret:
-        SyscallReturnStruct)
+    pub fn syscall_mmap(
+        &mut self,
+        thread_ptr: ThreadPtr,
+        va_range: VaRange4K,
+        mem_type: MemoryType,
+    ) -> (ret: SyscallReturnStruct)
         requires
             old(self).total_wf(),
             old(self).thread_dom().contains(thread_ptr),
@@ -144,6 +148,17 @@ impl Kernel {
     // TODO: @Xiangdong fix
 
             syscall_mmap_spec(*old(self), *self, thread_ptr, va_range, ret),
+            syscall_mmap_return_value(*old(self), thread_ptr, va_range).is_error() == false
+                ==> forall|i: usize|
+                #![auto]
+                0 <= i < va_range.len ==> spec_map_entry_with_memory_type(
+                    self.get_address_space(
+                        old(self).get_thread(thread_ptr).owning_proc,
+                    )[va_range@[i as int]],
+                    mem_type,
+                ) =~= self.get_address_space(
+                    old(self).get_thread(thread_ptr).owning_proc,
+                )[va_range@[i as int]],
     {
         let proc_ptr = self.proc_man.get_thread(thread_ptr).owning_proc;
         let pcid = self.proc_man.get_proc(proc_ptr).pcid;
@@ -164,7 +179,7 @@ impl Kernel {
         if self.check_address_space_va_range_free(proc_ptr, &va_range) == false {
             return SyscallReturnStruct::NoSwitchNew(RetValueType::ErrorVaInUse);
         }
-        let (num_page, seq_pages) = self.range_alloc_and_map(proc_ptr, &va_range);
+        let (num_page, seq_pages) = self.range_alloc_and_map(proc_ptr, &va_range, mem_type);
 
         assert(self.container_dom().fold(
             0,
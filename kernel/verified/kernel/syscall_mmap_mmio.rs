@@ -0,0 +1,541 @@
+use vstd::prelude::*;
+verus! {
+
+use crate::util::page_ptr_util_u::*;
+use crate::define::*;
+use crate::pagetable::entry::*;
+use crate::kernel::Kernel;
+use crate::va_range::VaRange4K;
+
+impl Kernel {
+    /// Every 4k page of `[pa, pa + len * 4096)` is a device page reserved
+    /// for `container_ptr` and not mapped yet.
+    pub open spec fn mmio_range_owned(
+        &self,
+        container_ptr: ContainerPtr,
+        pa: PAddr,
+        len: usize,
+    ) -> bool {
+        forall|i: usize|
+            #![trigger self.page_alloc.page_is_mmio((pa + i * 4096) as usize)]
+            0 <= i < len ==> self.page_alloc.page_is_mmio((pa + i * 4096) as usize)
+                && self.page_alloc.mmio_page_owner((pa + i * 4096) as usize) == container_ptr
+    }
+}
+
+pub open spec fn syscall_mmap_mmio_return_value(
+    old: Kernel,
+    thread_ptr: ThreadPtr,
+    va_range: VaRange4K,
+    pa: PAddr,
+    mem_type: MemoryType,
+) -> UserRetValueType {
+    let proc_ptr = old.get_thread(thread_ptr).owning_proc;
+    let container_ptr = old.get_thread(thread_ptr).owning_container;
+
+    if mem_type == MemoryType::WriteBack {
+        UserRetValueType::Else
+    } else if old.mmio_range_owned(container_ptr, pa, va_range.len) == false {
+        UserRetValueType::Else
+    } else if old.address_space_range_free(proc_ptr, &va_range) == false {
+        UserRetValueType::ErrorVaInUse
+    } else if old.get_container_quota(container_ptr).mem_4k < 3 * va_range.len {
+        UserRetValueType::ErrorNoQuota
+    } else {
+        UserRetValueType::Success
+    }
+}
+
+pub open spec fn syscall_mmap_mmio_spec(
+    old: Kernel,
+    new: Kernel,
+    thread_ptr: ThreadPtr,
+    va_range: VaRange4K,
+    pa: PAddr,
+    mem_type: MemoryType,
+    ret: SyscallReturnStruct,
+) -> bool {
+    let proc_ptr = old.get_thread(thread_ptr).owning_proc;
+    let container_ptr = old.get_thread(thread_ptr).owning_container;
+
+    if syscall_mmap_mmio_return_value(old, thread_ptr, va_range, pa, mem_type).is_error() {
+        new =~= old
+    } else {
+        // things that did not change
+        &&& old.thread_dom() =~= new.thread_dom()
+        &&& old.proc_dom() =~= new.proc_dom()
+        &&& old.container_dom() =~= new.container_dom()
+        &&& old.endpoint_dom() =~= new.endpoint_dom()
+        &&& forall|t_ptr: ThreadPtr|
+            #![trigger new.get_thread(t_ptr)]
+            old.thread_dom().contains(t_ptr) ==> new.get_thread(t_ptr) =~= old.get_thread(t_ptr)
+        &&& forall|p_ptr: ProcPtr|
+            #![trigger new.get_proc(p_ptr)]
+            new.proc_dom().contains(p_ptr) ==> new.get_proc(p_ptr) =~= old.get_proc(p_ptr)
+        &&& forall|c: ContainerPtr|
+            #![trigger new.get_container(c)]
+            new.container_dom().contains(c) && c != container_ptr ==> old.get_container(c)
+                =~= new.get_container(c)
+        &&& forall|e_ptr: EndpointPtr|
+            #![trigger new.get_endpoint(e_ptr)]
+            new.endpoint_dom().contains(e_ptr) ==> old.get_endpoint(e_ptr) =~= new.get_endpoint(
+                e_ptr,
+            )
+        &&& forall|p_ptr: ProcPtr|
+            #![trigger new.get_address_space(p_ptr)]
+            new.proc_dom().contains(p_ptr) && p_ptr != proc_ptr ==> new.get_address_space(p_ptr)
+                =~= old.get_address_space(p_ptr)
+        &&& forall|page_ptr: PagePtr|
+            #![trigger new.get_physical_page_mapping()[page_ptr]]
+            old.get_physical_page_mapping().dom().contains(page_ptr)
+                ==> old.get_physical_page_mapping()[page_ptr]
+                == new.get_physical_page_mapping()[page_ptr]
+        &&& forall|va: VAddr|
+            #![trigger new.get_address_space(proc_ptr).dom().contains(va)]
+            #![trigger new.get_address_space(proc_ptr)[va]]
+            va_range@.contains(va) == false ==> new.get_address_space(proc_ptr).dom().contains(va)
+                == old.get_address_space(proc_ptr).dom().contains(va) && new.get_address_space(
+                proc_ptr,
+            )[va] =~= old.get_address_space(proc_ptr)[va]
+        //Things that changed
+        &&& forall|i: usize|
+            #![auto]
+            0 <= i < va_range.len ==> new.get_address_space(proc_ptr).dom().contains(
+                va_range@[i as int],
+            ) && new.get_address_space(proc_ptr)[va_range@[i as int]].addr == (pa + i
+                * 4096) as usize && spec_map_entry_with_memory_type(
+                new.get_address_space(proc_ptr)[va_range@[i as int]],
+                mem_type,
+            ) =~= new.get_address_space(proc_ptr)[va_range@[i as int]]
+        &&& forall|i: usize|
+            #![auto]
+            0 <= i < va_range.len ==> new.get_physical_page_mapping()[(pa + i * 4096) as usize]
+                == Set::empty().insert((proc_ptr, va_range@[i as int]))
+    }
+}
+
+impl Kernel {
+    pub fn check_mmio_range_owned(
+        &self,
+        container_ptr: ContainerPtr,
+        pa: PAddr,
+        len: usize,
+    ) -> (ret: bool)
+        requires
+            self.wf(),
+        ensures
+            ret == self.mmio_range_owned(container_ptr, pa, len),
+    {
+        if len == 0 {
+            return true;
+        }
+        if pa % 0x1000 != 0 || pa / 0x1000 >= NUM_PAGES || len > NUM_PAGES - pa / 0x1000 {
+            return false;
+        }
+        for i in 0..len
+            invariant
+                self.wf(),
+                pa % 0x1000 == 0,
+                pa / 0x1000 + len <= NUM_PAGES,
+                forall|j: usize|
+                    #![trigger self.page_alloc.page_is_mmio((pa + j * 4096) as usize)]
+                    0 <= j < i ==> self.page_alloc.page_is_mmio((pa + j * 4096) as usize)
+                        && self.page_alloc.mmio_page_owner((pa + j * 4096) as usize)
+                        == container_ptr,
+        {
+            let page_ptr = pa + i * 4096;
+            assert(page_ptr_valid(page_ptr));
+            match self.page_alloc.get_mmio_page_owner(page_ptr) {
+                Some(owner) => {
+                    if owner != container_ptr {
+                        return false;
+                    }
+                },
+                None => {
+                    return false;
+                },
+            }
+        }
+        true
+    }
+
+    pub fn map_mmio(
+        &mut self,
+        target_proc_ptr: ProcPtr,
+        target_va: VAddr,
+        tagret_l1_p: PageMapPtr,
+        page_ptr: PagePtr,
+        mem_type: MemoryType,
+    ) -> (ret: MapEntry)
+        requires
+            old(self).wf(),
+            old(self).proc_dom().contains(target_proc_ptr),
+            old(self).page_alloc.page_is_mmio(page_ptr),
+            old(self).page_alloc.mmio_page_owner(page_ptr) == old(self).get_proc(
+                target_proc_ptr,
+            ).owning_container,
+            va_4k_valid(target_va),
+            old(self).get_address_space(target_proc_ptr).dom().contains(target_va) == false,
+            old(self).mem_man.get_pagetable_by_pcid(
+                old(self).get_proc(target_proc_ptr).pcid,
+            ).unwrap().spec_resolve_mapping_l2(
+                spec_va2index(target_va).0,
+                spec_va2index(target_va).1,
+                spec_va2index(target_va).2,
+            ).is_Some(),
+            old(self).mem_man.get_pagetable_by_pcid(
+                old(self).get_proc(target_proc_ptr).pcid,
+            ).unwrap().spec_resolve_mapping_l2(
+                spec_va2index(target_va).0,
+                spec_va2index(target_va).1,
+                spec_va2index(target_va).2,
+            ).unwrap().addr == tagret_l1_p,
+        ensures
+            self.wf(),
+            self.proc_dom() == old(self).proc_dom(),
+            self.thread_dom() == old(self).thread_dom(),
+            self.endpoint_dom() == old(self).endpoint_dom(),
+            self.container_dom() == old(self).container_dom(),
+            self.get_num_of_free_pages() == old(self).get_num_of_free_pages(),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) ==> self.get_proc(p_ptr) =~= old(self).get_proc(
+                    p_ptr,
+                ),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) && p_ptr != target_proc_ptr
+                    ==> self.get_address_space(p_ptr) =~= old(self).get_address_space(p_ptr),
+            forall|t_ptr: ThreadPtr|
+                #![auto]
+                self.thread_dom().contains(t_ptr) ==> self.get_thread(t_ptr) =~= old(
+                    self,
+                ).get_thread(t_ptr),
+            forall|c_ptr: ContainerPtr|
+                #![auto]
+                self.container_dom().contains(c_ptr) ==> self.get_container(c_ptr) =~= old(
+                    self,
+                ).get_container(c_ptr),
+            forall|e_ptr: EndpointPtr|
+                #![auto]
+                self.endpoint_dom().contains(e_ptr) ==> self.get_endpoint(e_ptr) =~= old(
+                    self,
+                ).get_endpoint(e_ptr),
+            self.get_address_space(target_proc_ptr) =~= old(self).get_address_space(
+                target_proc_ptr,
+            ).insert(target_va, ret),
+            ret.addr == page_ptr,
+            spec_map_entry_with_memory_type(ret, mem_type) =~= ret,
+            self.page_mapping@.dom() == old(self).page_mapping@.dom().insert(page_ptr),
+            forall|p: PagePtr|
+                #![trigger self.page_mapping@[p]]
+                old(self).page_mapping@.dom().contains(p) ==> old(self).page_mapping@[p]
+                    == self.page_mapping@[p],
+            self.page_mapping@[page_ptr] == Set::empty().insert((target_proc_ptr, target_va)),
+            forall|p: PagePtr|
+                #![trigger self.page_alloc.page_is_mmio(p)]
+                p != page_ptr ==> self.page_alloc.page_is_mmio(p) == old(
+                    self,
+                ).page_alloc.page_is_mmio(p) && (self.page_alloc.page_is_mmio(p)
+                    ==> self.page_alloc.mmio_page_owner(p) == old(
+                    self,
+                ).page_alloc.mmio_page_owner(p)),
+    {
+        proof {
+            self.proc_man.pcid_unique(target_proc_ptr);
+        }
+        let target_pcid = self.proc_man.get_proc(target_proc_ptr).pcid;
+        proof {
+            va_lemma();
+        }
+        let (l4i, l3i, l2i, l1i) = va2index(target_va);
+        self.page_alloc.map_mmio_4k(page_ptr, target_pcid, target_va);
+        // Device registers are never executable.
+        let map_entry = map_entry_with_memory_type(
+            MapEntry {
+                addr: page_ptr,
+                write: true,
+                execute_disable: true,
+                write_through: false,
+                cache_disable: false,
+            },
+            mem_type,
+        );
+        self.mem_man.pagetable_map_4k_page(
+            target_pcid,
+            l4i,
+            l3i,
+            l2i,
+            l1i,
+            tagret_l1_p,
+            &map_entry,
+        );
+        proof {
+            self.page_mapping@ = self.page_mapping@.insert(
+                page_ptr,
+                Set::empty().insert((target_proc_ptr, target_va)),
+            );
+        }
+        assert(self.wf()) by {
+            assert(self.mem_man.wf());
+            assert(self.page_alloc.wf());
+            assert(self.proc_man.wf());
+            assert(self.memory_wf()) by {
+                assert(self.mem_man.page_closure().disjoint(self.proc_man.page_closure()));
+                assert(self.mem_man.page_closure() + self.proc_man.page_closure()
+                    == self.page_alloc.allocated_pages_4k());
+                assert(self.page_alloc.mapped_pages_2m() =~= Set::empty());
+                assert(self.page_alloc.mapped_pages_1g() =~= Set::empty());
+                assert(self.page_alloc.allocated_pages_2m() =~= Set::empty());
+                assert(self.page_alloc.allocated_pages_1g() =~= Set::empty());
+                assert(self.page_alloc.container_map_4k@.dom() =~= self.proc_man.container_dom());
+            };
+            assert(self.mapping_wf()) by {};
+            assert(self.pcid_ioid_wf());
+            assert(self.page_mapping_wf());
+        };
+        map_entry
+    }
+
+    pub fn create_entry_and_map_mmio(
+        &mut self,
+        target_proc_ptr: ProcPtr,
+        target_va: VAddr,
+        page_ptr: PagePtr,
+        mem_type: MemoryType,
+    ) -> (ret: (usize, MapEntry))
+        requires
+            old(self).wf(),
+            old(self).proc_dom().contains(target_proc_ptr),
+            old(self).get_container_quota(
+                old(self).get_proc(target_proc_ptr).owning_container,
+            ).mem_4k >= 3,
+            old(self).get_num_of_free_pages() >= 3,
+            old(self).page_alloc.page_is_mmio(page_ptr),
+            old(self).page_alloc.mmio_page_owner(page_ptr) == old(self).get_proc(
+                target_proc_ptr,
+            ).owning_container,
+            va_4k_valid(target_va),
+            old(self).get_address_space(target_proc_ptr).dom().contains(target_va) == false,
+        ensures
+            ret.0 <= 3,
+            self.wf(),
+            self.proc_dom() == old(self).proc_dom(),
+            self.thread_dom() == old(self).thread_dom(),
+            self.endpoint_dom() == old(self).endpoint_dom(),
+            self.container_dom() == old(self).container_dom(),
+            self.get_num_of_free_pages() == old(self).get_num_of_free_pages() - ret.0,
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) ==> self.get_proc(p_ptr) =~= old(self).get_proc(
+                    p_ptr,
+                ),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) && p_ptr != target_proc_ptr
+                    ==> self.get_address_space(p_ptr) =~= old(self).get_address_space(p_ptr),
+            forall|t_ptr: ThreadPtr|
+                #![auto]
+                self.thread_dom().contains(t_ptr) ==> self.get_thread(t_ptr) =~= old(
+                    self,
+                ).get_thread(t_ptr),
+            forall|c_ptr: ContainerPtr|
+                #![auto]
+                self.container_dom().contains(c_ptr) && c_ptr != self.get_proc(
+                    target_proc_ptr,
+                ).owning_container ==> self.get_container(c_ptr) =~= old(self).get_container(c_ptr),
+            forall|e_ptr: EndpointPtr|
+                #![auto]
+                self.endpoint_dom().contains(e_ptr) ==> self.get_endpoint(e_ptr) =~= old(
+                    self,
+                ).get_endpoint(e_ptr),
+            old(self).get_container(
+                old(self).get_proc(target_proc_ptr).owning_container,
+            ).quota.spec_subtract_mem_4k(
+                self.get_container(old(self).get_proc(target_proc_ptr).owning_container).quota,
+                ret.0,
+            ),
+            self.get_address_space(target_proc_ptr) =~= old(self).get_address_space(
+                target_proc_ptr,
+            ).insert(target_va, ret.1),
+            ret.1.addr == page_ptr,
+            spec_map_entry_with_memory_type(ret.1, mem_type) =~= ret.1,
+            self.page_mapping@.dom() == old(self).page_mapping@.dom().insert(page_ptr),
+            forall|p: PagePtr|
+                #![trigger self.page_mapping@[p]]
+                old(self).page_mapping@.dom().contains(p) ==> old(self).page_mapping@[p]
+                    == self.page_mapping@[p],
+            self.page_mapping@[page_ptr] == Set::empty().insert((target_proc_ptr, target_va)),
+            forall|p: PagePtr|
+                #![trigger self.page_alloc.page_is_mmio(p)]
+                p != page_ptr ==> self.page_alloc.page_is_mmio(p) == old(
+                    self,
+                ).page_alloc.page_is_mmio(p) && (self.page_alloc.page_is_mmio(p)
+                    ==> self.page_alloc.mmio_page_owner(p) == old(
+                    self,
+                ).page_alloc.mmio_page_owner(p)),
+    {
+        let (ret, new_entry) = self.create_entry(target_proc_ptr, target_va);
+        (ret, self.map_mmio(target_proc_ptr, target_va, new_entry, page_ptr, mem_type))
+    }
+
+    /// Maps the device pages `[pa, pa + va_range.len * 4096)` into the
+    /// caller's address space at `va_range`. Every page must be a device page
+    /// reserved for the caller's container. The kernel does not know which
+    /// BARs belong to which device, so only the root container (dom0) may map
+    /// device memory. Only the page table pages are charged to the container's
+    /// quota. Device memory cannot be mapped write-back.
+    pub fn syscall_mmap_mmio(
+        &mut self,
+        thread_ptr: ThreadPtr,
+        va_range: VaRange4K,
+        pa: PAddr,
+        mem_type: MemoryType,
+    ) -> (ret: SyscallReturnStruct)
+        requires
+            old(self).total_wf(),
+            old(self).thread_dom().contains(thread_ptr),
+            va_range.wf(),
+            va_range.len * 3 < usize::MAX,
+        ensures
+            self.total_wf(),
+            syscall_mmap_mmio_spec(*old(self), *self, thread_ptr, va_range, pa, mem_type, ret),
+    {
+        let proc_ptr = self.proc_man.get_thread(thread_ptr).owning_proc;
+        let container_ptr = self.proc_man.get_proc(proc_ptr).owning_container;
+
+        proof {
+            self.proc_man.thread_inv();
+            self.proc_man.process_inv();
+        }
+
+        if let MemoryType::WriteBack = mem_type {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        if self.check_mmio_range_owned(container_ptr, pa, va_range.len) == false {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        if self.check_address_space_va_range_free(proc_ptr, &va_range) == false {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::ErrorVaInUse);
+        }
+        if self.proc_man.get_container(container_ptr).quota.mem_4k < va_range.len * 3 {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::ErrorNoQuota);
+        }
+        assert(self.page_alloc.free_pages_4k.len() >= va_range.len * 3) by {
+            old(self).fold_mem_4k_lemma();
+        }
+
+        let mut num_page = 0;
+        for i in 0..va_range.len
+            invariant
+                0 <= i <= va_range.len,
+                va_range.len * 3 < usize::MAX,
+                old(self).wf(),
+                self.wf(),
+                va_range.wf(),
+                self.proc_dom().contains(proc_ptr),
+                self.get_proc(proc_ptr).owning_container == container_ptr,
+                self.get_container_quota(container_ptr).mem_4k >= 3 * (va_range.len - i),
+                self.get_num_of_free_pages() >= 3 * (va_range.len - i),
+                self.proc_dom() == old(self).proc_dom(),
+                self.thread_dom() == old(self).thread_dom(),
+                self.endpoint_dom() == old(self).endpoint_dom(),
+                self.container_dom() == old(self).container_dom(),
+                forall|p_ptr: ProcPtr|
+                    #![auto]
+                    self.proc_dom().contains(p_ptr) ==> self.get_proc(p_ptr) =~= old(self).get_proc(
+                        p_ptr,
+                    ),
+                forall|p_ptr: ProcPtr|
+                    #![auto]
+                    self.proc_dom().contains(p_ptr) && p_ptr != proc_ptr ==> self.get_address_space(
+                        p_ptr,
+                    ) =~= old(self).get_address_space(p_ptr),
+                forall|t_ptr: ThreadPtr|
+                    #![auto]
+                    self.thread_dom().contains(t_ptr) ==> self.get_thread(t_ptr) =~= old(
+                        self,
+                    ).get_thread(t_ptr),
+                forall|c_ptr: ContainerPtr|
+                    #![auto]
+                    self.container_dom().contains(c_ptr) && c_ptr != container_ptr
+                        ==> self.get_container(c_ptr) =~= old(self).get_container(c_ptr),
+                forall|e_ptr: EndpointPtr|
+                    #![auto]
+                    self.endpoint_dom().contains(e_ptr) ==> self.get_endpoint(e_ptr) =~= old(
+                        self,
+                    ).get_endpoint(e_ptr),
+                forall|j: int|
+                    #![auto]
+                    i <= j < va_range.len ==> self.get_address_space(proc_ptr).dom().contains(
+                        va_range@[j],
+                    ) == false,
+                forall|j: usize|
+                    #![trigger self.page_alloc.page_is_mmio((pa + j * 4096) as usize)]
+                    i <= j < va_range.len ==> self.page_alloc.page_is_mmio(
+                        (pa + j * 4096) as usize,
+                    ) && self.page_alloc.mmio_page_owner((pa + j * 4096) as usize)
+                        == container_ptr,
+                forall|j: usize|
+                    #![auto]
+                    0 <= j < i ==> self.get_address_space(proc_ptr).dom().contains(
+                        va_range@[j as int],
+                    ) && self.get_address_space(proc_ptr)[va_range@[j as int]].addr == (pa + j
+                        * 4096) as usize && spec_map_entry_with_memory_type(
+                        self.get_address_space(proc_ptr)[va_range@[j as int]],
+                        mem_type,
+                    ) =~= self.get_address_space(proc_ptr)[va_range@[j as int]],
+                forall|j: usize|
+                    #![auto]
+                    0 <= j < i ==> self.page_mapping@[(pa + j * 4096) as usize]
+                        == Set::empty().insert((proc_ptr, va_range@[j as int])),
+                forall|page_ptr: PagePtr|
+                    #![trigger self.page_mapping@[page_ptr]]
+                    old(self).page_mapping@.dom().contains(page_ptr) ==> old(
+                        self,
+                    ).page_mapping@[page_ptr] == self.page_mapping@[page_ptr],
+                forall|va: VAddr|
+                    #![trigger self.get_address_space(proc_ptr).dom().contains(va)]
+                    #![trigger self.get_address_space(proc_ptr)[va]]
+                    va_range@.contains(va) == false ==> self.get_address_space(
+                        proc_ptr,
+                    ).dom().contains(va) == old(self).get_address_space(proc_ptr).dom().contains(
+                        va,
+                    ) && self.get_address_space(proc_ptr)[va] == old(self).get_address_space(
+                        proc_ptr,
+                    )[va],
+                num_page <= i * 3,
+                self.get_num_of_free_pages() == old(self).get_num_of_free_pages() - num_page,
+                old(self).get_container_quota(container_ptr).spec_subtract_mem_4k(
+                    self.get_container_quota(container_ptr),
+                    num_page,
+                ),
+        {
+            proof {
+                self.proc_man.thread_inv();
+                self.proc_man.process_inv();
+            }
+            let (num, _map_entry) = self.create_entry_and_map_mmio(
+                proc_ptr,
+                va_range.index(i),
+                pa + i * 4096,
+                mem_type,
+            );
+            num_page = num_page + num;
+        }
+
+        assert(self.container_dom().fold(
+            0,
+            |e: int, a: ContainerPtr| e + self.get_container(a).quota.mem_4k,
+        ) == old(self).container_dom().fold(
+            0,
+            |e: int, a: ContainerPtr| e + old(self).get_container(a).quota.mem_4k,
+        ) - num_page) by {
+            self.fold_change_mem_4k_lemma(*old(self), container_ptr);
+        }
+        assert(self.total_wf());
+        return SyscallReturnStruct::NoSwitchNew(RetValueType::Else);
+    }
+}
+
+} // verus!
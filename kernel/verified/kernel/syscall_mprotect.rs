@@ -0,0 +1,114 @@
+use vstd::prelude::*;
+verus! {
+
+use crate::define::*;
+use crate::pagetable::entry::*;
+use crate::kernel::Kernel;
+use crate::va_range::VaRange4K;
+
+pub open spec fn syscall_mprotect_return_value(
+    old: Kernel,
+    thread_ptr: ThreadPtr,
+    va_range: VaRange4K,
+    page_type: PageType,
+) -> UserRetValueType {
+    let proc_ptr = old.proc_man.get_thread(thread_ptr).owning_proc;
+
+    if old.address_space_range_exists(proc_ptr, &va_range) == false {
+        UserRetValueType::Else
+    } else if old.address_space_range_protect_allowed(proc_ptr, &va_range, page_type) == false {
+        UserRetValueType::Else
+    } else {
+        UserRetValueType::Success
+    }
+}
+
+pub open spec fn syscall_mprotect_spec(
+    old: Kernel,
+    new: Kernel,
+    thread_id: ThreadPtr,
+    va_range: VaRange4K,
+    page_type: PageType,
+    ret: SyscallReturnStruct,
+) -> bool {
+    let proc_ptr = old.get_thread(thread_id).owning_proc;
+    if syscall_mprotect_return_value(old, thread_id, va_range, page_type).is_error() {
+        new =~= old
+    } else {
+        // things that did not change
+        &&& old.thread_dom() =~= new.thread_dom()
+        &&& old.proc_dom() =~= new.proc_dom()
+        &&& old.container_dom() =~= new.container_dom()
+        &&& old.endpoint_dom() =~= new.endpoint_dom()
+        &&& forall|t_ptr: ThreadPtr|
+            #![trigger new.get_thread(t_ptr)]
+            #![trigger old.get_thread(t_ptr)]
+            old.thread_dom().contains(t_ptr) ==> new.get_thread(t_ptr) =~= old.get_thread(t_ptr)
+        &&& forall|proc_ptr: ProcPtr|
+            #![trigger new.get_proc(proc_ptr)]
+            new.proc_dom().contains(proc_ptr) ==> new.get_proc(proc_ptr) =~= old.get_proc(proc_ptr)
+        &&& forall|c: ContainerPtr|
+            #![trigger new.get_container(c)]
+            new.container_dom().contains(c) ==> old.get_container(c) =~= new.get_container(c)
+        &&& forall|e_ptr: EndpointPtr|
+            #![trigger new.get_endpoint(e_ptr)]
+            new.endpoint_dom().contains(e_ptr) ==> old.get_endpoint(e_ptr) =~= new.get_endpoint(
+                e_ptr,
+            )
+        &&& forall|p_ptr: ProcPtr|
+            #![trigger new.get_address_space(p_ptr)]
+            new.proc_dom().contains(p_ptr) && p_ptr != proc_ptr ==> new.get_address_space(p_ptr)
+                =~= old.get_address_space(p_ptr)
+        &&& new.get_physical_page_mapping() =~= old.get_physical_page_mapping()
+        &&& new.get_address_space(proc_ptr).dom() =~= old.get_address_space(proc_ptr).dom()
+        &&& forall|va: VAddr|
+            #![trigger new.get_address_space(proc_ptr)[va]]
+            va_range@.contains(va) == false && old.get_address_space(proc_ptr).dom().contains(va)
+                ==> new.get_address_space(proc_ptr)[va] =~= old.get_address_space(proc_ptr)[va]
+        //Things that changed
+        &&& forall|i: int|
+            #![auto]
+            0 <= i < va_range.len ==> new.get_address_space(proc_ptr)[va_range@[i]]
+                == spec_page_type_to_map_entry(
+                old.get_address_space(proc_ptr)[va_range@[i]],
+                page_type,
+            )
+    }
+}
+
+impl Kernel {
+    pub fn syscall_mprotect(
+        &mut self,
+        thread_ptr: ThreadPtr,
+        va_range: VaRange4K,
+        page_type: PageType,
+    ) -> (ret: SyscallReturnStruct)
+        requires
+            old(self).total_wf(),
+            old(self).thread_dom().contains(thread_ptr),
+            va_range.wf(),
+        ensures
+            self.total_wf(),
+            syscall_mprotect_spec(*old(self), *self, thread_ptr, va_range, page_type, ret),
+    {
+        let proc_ptr = self.proc_man.get_thread(thread_ptr).owning_proc;
+
+        proof {
+            self.proc_man.thread_inv();
+            self.proc_man.process_inv();
+        }
+
+        if self.check_address_space_va_range_exists(proc_ptr, &va_range) == false {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        if self.check_address_space_va_range_protect_allowed(proc_ptr, &va_range, page_type)
+            == false {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        self.range_protect_mapping(proc_ptr, &va_range, page_type);
+        assert(self.total_wf());
+        return SyscallReturnStruct::NoSwitchNew(RetValueType::Else);
+    }
+}
+
+} // verus!
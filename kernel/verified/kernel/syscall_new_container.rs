@@ -338,7 +338,7 @@ impl Kernel {
         assert(old(self).page_alloc.allocated_pages_4k().contains(page_ptr_2) == false);
         assert(old(self).proc_man.page_closure().contains(page_ptr_2) == false);
         assert(old(self).container_dom().contains(page_ptr_2) == false);
-        self.range_create_and_share_mapping(proc_ptr, &va_range, page_ptr_3, &va_range);
+        self.range_create_and_share_mapping(proc_ptr, &va_range, page_ptr_3, &va_range, false);
 
         return SyscallReturnStruct::NoSwitchNew(
             RetValueType::SuccessThreeUsize {
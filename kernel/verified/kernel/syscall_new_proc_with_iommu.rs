@@ -149,7 +149,7 @@ impl Kernel {
         };
         assert(self.page_mapping_wf());
 
-        self.range_create_and_share_mapping(proc_ptr, &va_range, page_ptr_2, &va_range);
+        self.range_create_and_share_mapping(proc_ptr, &va_range, page_ptr_2, &va_range, false);
 
         return SyscallReturnStruct::NoSwitchNew(
             RetValueType::SuccessPairUsize { value1: page_ptr_2, value2: page_ptr_3 },
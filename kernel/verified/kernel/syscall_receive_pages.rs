@@ -2,8 +2,12 @@ use vstd::prelude::*;
 verus! {
 
 use crate::define::*;
+use crate::pagetable::entry::*;
 use crate::kernel::Kernel;
+use crate::kernel::move_pages::pages_moved_spec;
+use crate::kernel::cow_pages::pages_shared_cow_spec;
 use crate::process_manager::thread::IPCPayLoad;
+use crate::trap::Registers;
 use crate::va_range::*;
 
 pub open spec fn syscall_receive_pages_spec_success(
@@ -28,7 +32,38 @@ pub open spec fn syscall_receive_pages_spec_success(
     let sender_va_range = old.get_thread(
         sender_thread_ptr,
     ).ipc_payload.get_payload_as_va_range().unwrap();
+    let sender_read_only = old.get_thread(
+        sender_thread_ptr,
+    ).ipc_payload.get_payload_pages_read_only();
+    let sender_move_pages = old.get_thread(sender_thread_ptr).ipc_payload.get_payload_pages_move();
+    let sender_copy_on_write = old.get_thread(
+        sender_thread_ptr,
+    ).ipc_payload.get_payload_pages_copy_on_write();
 
+    if sender_copy_on_write {
+        pages_shared_cow_spec(
+            old,
+            new,
+            blocking_endpoint_ptr,
+            sender_thread_ptr,
+            sender_proc_ptr,
+            sender_va_range,
+            receiver_proc_ptr,
+            receiver_va_range,
+        )
+    } else if sender_move_pages {
+        pages_moved_spec(
+            old,
+            new,
+            blocking_endpoint_ptr,
+            sender_thread_ptr,
+            sender_proc_ptr,
+            sender_va_range,
+            receiver_proc_ptr,
+            receiver_va_range,
+            sender_read_only,
+        )
+    } else {
     old.thread_dom() =~= new.thread_dom() && old.proc_dom() =~= new.proc_dom()
         && old.container_dom() =~= new.container_dom() && old.endpoint_dom() =~= new.endpoint_dom()
         && forall|t_ptr: ThreadPtr|
@@ -101,9 +136,12 @@ pub open spec fn syscall_receive_pages_spec_success(
                                     ).dom().contains(receiver_va_range@[i])
                                         && new.get_address_space(
                                         receiver_proc_ptr,
-                                    )[receiver_va_range@[i]] == old.get_address_space(
-                                        sender_proc_ptr,
-                                    )[sender_va_range@[i]] && forall|va: VAddr|
+                                    )[receiver_va_range@[i]] == spec_share_map_entry(
+                                        old.get_address_space(
+                                            sender_proc_ptr,
+                                        )[sender_va_range@[i]],
+                                        sender_read_only,
+                                    ) && forall|va: VAddr|
                                         #![auto]
                                         receiver_va_range@.contains(va) == false
                                             && old.get_address_space(
@@ -111,6 +149,7 @@ pub open spec fn syscall_receive_pages_spec_success(
                                         ).dom().contains(va) ==> new.get_address_space(
                                             receiver_proc_ptr,
                                         )[va] == old.get_address_space(receiver_proc_ptr)[va]
+    }
 }
 
 pub open spec fn syscall_receive_pages_spec_fail(
@@ -256,9 +295,10 @@ impl Kernel {
         receiver_thread_ptr: ThreadPtr,
         endpoint_idx: EndpointIdx,
         receiver_va_range: VaRange4K,
+        pt_regs: &Registers,
     ) -> (ret: SyscallReturnStruct)
         requires
-            old(self).wf(),
+            old(self).total_wf(),
             old(self).thread_dom().contains(receiver_thread_ptr),
             0 <= endpoint_idx < MAX_NUM_ENDPOINT_DESCRIPTORS,
             old(self).get_thread(receiver_thread_ptr).state == ThreadState::RUNNING,
@@ -298,13 +338,19 @@ impl Kernel {
             && self.proc_man.get_endpoint(blocking_endpoint_ptr).queue.len()
             < MAX_NUM_THREADS_PER_ENDPOINT {
             // Block
-            self.proc_man.block_running_thread(
+            self.proc_man.block_running_thread_and_set_trap_frame(
                 receiver_thread_ptr,
                 endpoint_idx,
-                IPCPayLoad::Pages { va_range: receiver_va_range },
+                IPCPayLoad::Pages {
+                    va_range: receiver_va_range,
+                    read_only: false,
+                    move_pages: false,
+                    copy_on_write: false,
+                },
+                pt_regs,
             );
             assert(self.wf());
-            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+            return SyscallReturnStruct::NoNextThreadNew(RetValueType::Error);
         }
         if self.proc_man.get_endpoint(blocking_endpoint_ptr).queue_state.is_receive()
             && self.proc_man.get_endpoint(blocking_endpoint_ptr).queue.len()
@@ -315,14 +361,20 @@ impl Kernel {
         if self.proc_man.get_endpoint(blocking_endpoint_ptr).queue_state.is_send()
             && self.proc_man.get_endpoint(blocking_endpoint_ptr).queue.len() == 0 {
             // change queue state and Block
-            self.proc_man.block_running_thread_and_change_queue_state(
+            self.proc_man.block_running_thread_and_change_queue_state_and_set_trap_frame(
                 receiver_thread_ptr,
                 endpoint_idx,
-                IPCPayLoad::Pages { va_range: receiver_va_range },
+                IPCPayLoad::Pages {
+                    va_range: receiver_va_range,
+                    read_only: false,
+                    move_pages: false,
+                    copy_on_write: false,
+                },
                 EndpointState::RECEIVE,
+                pt_regs,
             );
             assert(self.wf());
-            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+            return SyscallReturnStruct::NoNextThreadNew(RetValueType::Error);
         }
         assert(self.sender_exist(receiver_thread_ptr, endpoint_idx));
 
@@ -343,6 +395,9 @@ impl Kernel {
             return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
         }
         let sender_va_range = sender_endpoint_payload_op.unwrap();
+        let sender_read_only = self.proc_man.get_thread(
+            sender_thread_ptr,
+        ).ipc_payload.get_payload_pages_read_only();
 
         if receiver_va_range.len != sender_va_range.len {
             // @Xiangdong TODO schedule the threads and return error
@@ -369,11 +424,55 @@ impl Kernel {
                 old(self).get_endpoint(blocking_endpoint_ptr).queue@[0],
             ).owning_container,
         ).scheduler.len() < MAX_CONTAINER_SCHEDULER_LEN);
+        let sender_copy_on_write = self.proc_man.get_thread(
+            sender_thread_ptr,
+        ).ipc_payload.get_payload_pages_copy_on_write();
+        if sender_copy_on_write {
+            if self.check_address_space_va_range_cow_shareable(sender_proc_ptr, &sender_va_range)
+                == false {
+                // sender pages are reachable by a device
+                return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+            }
+            self.range_mark_cow(sender_proc_ptr, &sender_va_range);
+            self.range_create_and_share_mapping(
+                sender_proc_ptr,
+                &sender_va_range,
+                receiver_proc_ptr,
+                &receiver_va_range,
+                true,
+            );
+            self.proc_man.schedule_blocked_thread(blocking_endpoint_ptr);
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Else);
+        }
+        let sender_move_pages = self.proc_man.get_thread(
+            sender_thread_ptr,
+        ).ipc_payload.get_payload_pages_move();
+        if sender_move_pages {
+            if self.check_address_space_va_range_movable(sender_proc_ptr, &sender_va_range)
+                == false {
+                // sender pages are still referenced elsewhere
+                return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+            }
+            if self.proc_man.get_container(receiver_container_ptr).quota.mem_4k
+                < receiver_va_range.len * 4 {
+                return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+            }
+            self.range_create_and_move_mapping(
+                sender_proc_ptr,
+                &sender_va_range,
+                receiver_proc_ptr,
+                &receiver_va_range,
+                sender_read_only,
+            );
+            self.proc_man.schedule_blocked_thread(blocking_endpoint_ptr);
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Else);
+        }
         self.range_create_and_share_mapping(
             sender_proc_ptr,
             &sender_va_range,
             receiver_proc_ptr,
             &receiver_va_range,
+            sender_read_only,
         );
         self.proc_man.schedule_blocked_thread(blocking_endpoint_ptr);
         return SyscallReturnStruct::NoSwitchNew(RetValueType::Else);
@@ -0,0 +1,142 @@
+use vstd::prelude::*;
+verus! {
+
+use crate::define::*;
+use crate::kernel::Kernel;
+use crate::kernel::syscall_send_pci::pci_device_passed_spec;
+use crate::process_manager::thread::IPCPayLoad;
+use crate::trap::Registers;
+
+impl Kernel {
+    /// Takes over the PCI device `bus:dev.fun` from the thread sending it on the
+    /// endpoint. The device is recorded under the IOid of the caller's process.
+    pub fn syscall_receive_pci_block(
+        &mut self,
+        receiver_thread_ptr: ThreadPtr,
+        blocking_endpoint_index: EndpointIdx,
+        bus: u8,
+        dev: u8,
+        fun: u8,
+        pt_regs: &Registers,
+    ) -> (ret: SyscallReturnStruct)
+        requires
+            old(self).total_wf(),
+            old(self).thread_dom().contains(receiver_thread_ptr),
+            0 <= blocking_endpoint_index < MAX_NUM_ENDPOINT_DESCRIPTORS,
+            old(self).get_thread(receiver_thread_ptr).state == ThreadState::RUNNING,
+        ensures
+            self.total_wf(),
+            ret.is_error() ==> self.mem_man.pci_bitmap@ =~= old(self).mem_man.pci_bitmap@,
+            !ret.is_error() ==> pci_device_passed_spec(
+                *old(self),
+                *self,
+                old(self).get_endpoint(
+                    old(self).get_endpoint_ptr_by_endpoint_idx(
+                        receiver_thread_ptr,
+                        blocking_endpoint_index,
+                    ).unwrap(),
+                ).queue@[0],
+                receiver_thread_ptr,
+                bus,
+                dev,
+                fun,
+            ),
+    {
+        proof {
+            self.proc_man.thread_inv();
+            self.proc_man.endpoint_inv();
+            self.proc_man.process_inv();
+        }
+        if dev >= 32 || fun >= 8 {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        let receiver_proc_ptr = self.proc_man.get_thread(receiver_thread_ptr).owning_proc;
+        if self.proc_man.get_proc(receiver_proc_ptr).ioid.is_none() {
+            // nowhere to record the device
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+
+        let blocking_endpoint_ptr_op = self.proc_man.get_thread(
+            receiver_thread_ptr,
+        ).endpoint_descriptors.get(blocking_endpoint_index);
+
+        if blocking_endpoint_ptr_op.is_none() {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        let blocking_endpoint_ptr = blocking_endpoint_ptr_op.unwrap();
+        if self.proc_man.get_endpoint(blocking_endpoint_ptr).queue_state.is_receive()
+            && self.proc_man.get_endpoint(blocking_endpoint_ptr).queue.len()
+            < MAX_NUM_THREADS_PER_ENDPOINT {
+            self.proc_man.block_running_thread_and_set_trap_frame(
+                receiver_thread_ptr,
+                blocking_endpoint_index,
+                IPCPayLoad::Pci { bus: bus, dev: dev, fun: fun },
+                pt_regs,
+            );
+            assert(self.total_wf()) by {
+                self.fold_change_mem_4k_lemma(
+                    *old(self),
+                    self.proc_man.get_thread(receiver_thread_ptr).owning_container,
+                );
+            };
+            return SyscallReturnStruct::NoNextThreadNew(RetValueType::Error);
+        }
+        if self.proc_man.get_endpoint(blocking_endpoint_ptr).queue_state.is_receive()
+            && self.proc_man.get_endpoint(blocking_endpoint_ptr).queue.len()
+            >= MAX_NUM_THREADS_PER_ENDPOINT {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        if self.proc_man.get_endpoint(blocking_endpoint_ptr).queue_state.is_send()
+            && self.proc_man.get_endpoint(blocking_endpoint_ptr).queue.len() == 0 {
+            self.proc_man.block_running_thread_and_change_queue_state_and_set_trap_frame(
+                receiver_thread_ptr,
+                blocking_endpoint_index,
+                IPCPayLoad::Pci { bus: bus, dev: dev, fun: fun },
+                EndpointState::RECEIVE,
+                pt_regs,
+            );
+            assert(self.total_wf()) by {
+                self.fold_change_mem_4k_lemma(
+                    *old(self),
+                    self.proc_man.get_thread(receiver_thread_ptr).owning_container,
+                );
+            };
+            return SyscallReturnStruct::NoNextThreadNew(RetValueType::Error);
+        }
+        assert(self.sender_exist(receiver_thread_ptr, blocking_endpoint_index));
+
+        let sender_thread_ptr = self.proc_man.get_endpoint(blocking_endpoint_ptr).queue.get_head();
+        let sender_container_ptr = self.proc_man.get_thread(sender_thread_ptr).owning_container;
+
+        match self.proc_man.get_thread(sender_thread_ptr).ipc_payload.get_payload_as_pci() {
+            Some((s_bus, s_dev, s_fun)) => {
+                if s_bus != bus || s_dev != dev || s_fun != fun {
+                    // sender is offering a different device
+                    return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+                }
+            },
+            None => {
+                // sender not sending a device
+                return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+            },
+        }
+        // cannot schedule the sender
+        if self.proc_man.get_container(sender_container_ptr).scheduler.len()
+            >= MAX_CONTAINER_SCHEDULER_LEN {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        if self.pass_pci_device(sender_thread_ptr, receiver_thread_ptr, bus, dev, fun) == false {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        self.proc_man.schedule_blocked_thread(blocking_endpoint_ptr);
+        assert(self.total_wf()) by {
+            self.fold_change_mem_4k_lemma(
+                *old(self),
+                self.proc_man.get_thread(receiver_thread_ptr).owning_container,
+            );
+        };
+        return SyscallReturnStruct::NoSwitchNew(RetValueType::Else);
+    }
+}
+
+} // verus!
@@ -12,13 +12,16 @@ use crate::define::*;
 
 // use crate::trap::*;
 // use crate::pagetable::pagemap_util_t::*;
-// use crate::pagetable::entry::*;
+use crate::pagetable::entry::*;
 use crate::kernel::Kernel;
+use crate::kernel::move_pages::pages_moved_spec;
+use crate::kernel::cow_pages::pages_shared_cow_spec;
 
 // use crate::va_range::VaRange4K;
 // use crate::trap::Registers;
 // use crate::pagetable::pagemap_util_t::*;
 use crate::process_manager::thread::IPCPayLoad;
+use crate::trap::Registers;
 use crate::va_range::*;
 
 pub open spec fn syscall_send_pages_spec(
@@ -27,6 +30,9 @@ pub open spec fn syscall_send_pages_spec(
     sender_thread_ptr: ThreadPtr,
     sender_endpoint_payload: EndpointIdx,
     sender_va_range: VaRange4K,
+    read_only: bool,
+    move_pages: bool,
+    copy_on_write: bool,
     ret: SyscallReturnStruct,
 ) -> bool {
     let blocking_endpoint_ptr = old.get_endpoint_ptr_by_endpoint_idx(
@@ -94,6 +100,15 @@ pub open spec fn syscall_send_pages_spec(
                                         && new.get_thread(
                                         sender_thread_ptr,
                                     ).ipc_payload.get_payload_as_va_range() == Some(sender_va_range)
+                                        && new.get_thread(
+                                        sender_thread_ptr,
+                                    ).ipc_payload.get_payload_pages_read_only() == read_only
+                                        && new.get_thread(
+                                        sender_thread_ptr,
+                                    ).ipc_payload.get_payload_pages_move() == move_pages
+                                        && new.get_thread(
+                                        sender_thread_ptr,
+                                    ).ipc_payload.get_payload_pages_copy_on_write() == copy_on_write
                                         && new.get_endpoint(blocking_endpoint_ptr).queue_state
                                         =~= old.get_endpoint(blocking_endpoint_ptr).queue_state
     } else if old.get_endpoint_exists(sender_thread_ptr, sender_endpoint_payload)
@@ -139,6 +154,12 @@ pub open spec fn syscall_send_pages_spec(
                                         && new.get_thread(
                                         sender_thread_ptr,
                                     ).ipc_payload.get_payload_as_va_range() == Some(sender_va_range)
+                                        && new.get_thread(
+                                        sender_thread_ptr,
+                                    ).ipc_payload.get_payload_pages_read_only() == read_only
+                                        && new.get_thread(
+                                        sender_thread_ptr,
+                                    ).ipc_payload.get_payload_pages_move() == move_pages
                                         && new.get_endpoint(blocking_endpoint_ptr).queue_state
                                         =~= EndpointState::SEND
     } else if old.get_container(receiver_container_ptr).scheduler.len()
@@ -158,6 +179,40 @@ pub open spec fn syscall_send_pages_spec(
         old =~= new
     } else if sender_proc_ptr == receiver_proc_ptr {
         old =~= new
+    } else if copy_on_write && old.address_space_range_cow_shareable(
+        sender_proc_ptr,
+        &sender_va_range,
+    ) == false {
+        old =~= new
+    } else if copy_on_write {
+        pages_shared_cow_spec(
+            old,
+            new,
+            blocking_endpoint_ptr,
+            receiver_thread_ptr,
+            sender_proc_ptr,
+            sender_va_range,
+            receiver_proc_ptr,
+            receiver_va_range,
+        )
+    } else if move_pages && old.address_space_range_movable(sender_proc_ptr, &sender_va_range)
+        == false {
+        old =~= new
+    } else if move_pages && old.get_container(receiver_container_ptr).quota.mem_4k
+        < receiver_va_range.len * 4 {
+        old =~= new
+    } else if move_pages {
+        pages_moved_spec(
+            old,
+            new,
+            blocking_endpoint_ptr,
+            receiver_thread_ptr,
+            sender_proc_ptr,
+            sender_va_range,
+            receiver_proc_ptr,
+            receiver_va_range,
+            read_only,
+        )
     } else {
         old.thread_dom() =~= new.thread_dom() && old.proc_dom() =~= new.proc_dom()
             && old.container_dom() =~= new.container_dom() && old.endpoint_dom()
@@ -238,9 +293,12 @@ pub open spec fn syscall_send_pages_spec(
                                         ).dom().contains(receiver_va_range@[i])
                                             && new.get_address_space(
                                             receiver_proc_ptr,
-                                        )[receiver_va_range@[i]] == old.get_address_space(
-                                            sender_proc_ptr,
-                                        )[sender_va_range@[i]] && forall|va: VAddr|
+                                        )[receiver_va_range@[i]] == spec_share_map_entry(
+                                            old.get_address_space(
+                                                sender_proc_ptr,
+                                            )[sender_va_range@[i]],
+                                            read_only,
+                                        ) && forall|va: VAddr|
                                             #![auto]
                                             receiver_va_range@.contains(va) == false
                                                 && old.get_address_space(
@@ -257,9 +315,13 @@ impl Kernel {
         sender_thread_ptr: ThreadPtr,
         sender_endpoint_payload: EndpointIdx,
         sender_va_range: VaRange4K,
+        read_only: bool,
+        move_pages: bool,
+        copy_on_write: bool,
+        pt_regs: &Registers,
     ) -> (ret: SyscallReturnStruct)
         requires
-            old(self).wf(),
+            old(self).total_wf(),
             old(self).thread_dom().contains(sender_thread_ptr),
             0 <= sender_endpoint_payload < MAX_NUM_ENDPOINT_DESCRIPTORS,
             old(self).get_thread(sender_thread_ptr).state == ThreadState::RUNNING,
@@ -271,6 +333,9 @@ impl Kernel {
                 sender_thread_ptr,
                 sender_endpoint_payload,
                 sender_va_range,
+                read_only,
+                move_pages,
+                copy_on_write,
                 ret,
             ),
     {
@@ -293,13 +358,19 @@ impl Kernel {
             && self.proc_man.get_endpoint(blocking_endpoint_ptr).queue.len()
             < MAX_NUM_THREADS_PER_ENDPOINT {
             // Block
-            self.proc_man.block_running_thread(
+            self.proc_man.block_running_thread_and_set_trap_frame(
                 sender_thread_ptr,
                 sender_endpoint_payload,
-                IPCPayLoad::Pages { va_range: sender_va_range },
+                IPCPayLoad::Pages {
+                    va_range: sender_va_range,
+                    read_only: read_only,
+                    move_pages: move_pages,
+                    copy_on_write: copy_on_write,
+                },
+                pt_regs,
             );
             assert(self.wf());
-            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+            return SyscallReturnStruct::NoNextThreadNew(RetValueType::Error);
         }
         if self.proc_man.get_endpoint(blocking_endpoint_ptr).queue_state.is_send()
             && self.proc_man.get_endpoint(blocking_endpoint_ptr).queue.len()
@@ -310,14 +381,20 @@ impl Kernel {
         if self.proc_man.get_endpoint(blocking_endpoint_ptr).queue_state.is_receive()
             && self.proc_man.get_endpoint(blocking_endpoint_ptr).queue.len() == 0 {
             // change queue state and Block
-            self.proc_man.block_running_thread_and_change_queue_state(
+            self.proc_man.block_running_thread_and_change_queue_state_and_set_trap_frame(
                 sender_thread_ptr,
                 sender_endpoint_payload,
-                IPCPayLoad::Pages { va_range: sender_va_range },
+                IPCPayLoad::Pages {
+                    va_range: sender_va_range,
+                    read_only: read_only,
+                    move_pages: move_pages,
+                    copy_on_write: copy_on_write,
+                },
                 EndpointState::SEND,
+                pt_regs,
             );
             assert(self.wf());
-            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+            return SyscallReturnStruct::NoNextThreadNew(RetValueType::Error);
         }
         assert(self.receiver_exist(sender_thread_ptr, sender_endpoint_payload));
 
@@ -369,11 +446,49 @@ impl Kernel {
                 old(self).get_endpoint(blocking_endpoint_ptr).queue@[0],
             ).owning_container,
         ).scheduler.len() < MAX_CONTAINER_SCHEDULER_LEN);
+        if copy_on_write {
+            if self.check_address_space_va_range_cow_shareable(sender_proc_ptr, &sender_va_range)
+                == false {
+                // sender pages are reachable by a device
+                return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+            }
+            self.range_mark_cow(sender_proc_ptr, &sender_va_range);
+            self.range_create_and_share_mapping(
+                sender_proc_ptr,
+                &sender_va_range,
+                receiver_proc_ptr,
+                &receiver_va_range,
+                true,
+            );
+            self.proc_man.schedule_blocked_thread(blocking_endpoint_ptr);
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Else);
+        }
+        if move_pages {
+            if self.check_address_space_va_range_movable(sender_proc_ptr, &sender_va_range)
+                == false {
+                // sender pages are still referenced elsewhere
+                return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+            }
+            if self.proc_man.get_container(receiver_container_ptr).quota.mem_4k
+                < receiver_va_range.len * 4 {
+                return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+            }
+            self.range_create_and_move_mapping(
+                sender_proc_ptr,
+                &sender_va_range,
+                receiver_proc_ptr,
+                &receiver_va_range,
+                read_only,
+            );
+            self.proc_man.schedule_blocked_thread(blocking_endpoint_ptr);
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Else);
+        }
         self.range_create_and_share_mapping(
             sender_proc_ptr,
             &sender_va_range,
             receiver_proc_ptr,
             &receiver_va_range,
+            read_only,
         );
         self.proc_man.schedule_blocked_thread(blocking_endpoint_ptr);
         return SyscallReturnStruct::NoSwitchNew(RetValueType::Else);
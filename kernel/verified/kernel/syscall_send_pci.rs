@@ -0,0 +1,292 @@
+use vstd::prelude::*;
+verus! {
+
+use crate::define::*;
+use crate::kernel::Kernel;
+use crate::process_manager::thread::IPCPayLoad;
+use crate::trap::Registers;
+use crate::util::page_ptr_util_u::*;
+
+/// `bus:dev.fun` moved from the IOid of the sender's process to the IOid of
+/// the receiver's process and was detached from the root table, so it cannot
+/// DMA until the receiver binds it to its IOMMU table.
+pub open spec fn pci_device_passed_spec(
+    old: Kernel,
+    new: Kernel,
+    sender_thread_ptr: ThreadPtr,
+    receiver_thread_ptr: ThreadPtr,
+    bus: u8,
+    dev: u8,
+    fun: u8,
+) -> bool {
+    let sender_ioid = old.get_thread_ioid(sender_thread_ptr);
+    let receiver_ioid = old.get_thread_ioid(receiver_thread_ptr);
+
+    &&& sender_ioid.is_Some()
+    &&& receiver_ioid.is_Some()
+    &&& old.mem_man.pci_bitmap@[(sender_ioid.unwrap(), bus, dev, fun)]
+    &&& new.mem_man.pci_bitmap@ =~= old.mem_man.pci_bitmap@.insert(
+        (sender_ioid.unwrap(), bus, dev, fun),
+        false,
+    ).insert((receiver_ioid.unwrap(), bus, dev, fun), true)
+    &&& new.mem_man.root_table.resolve(bus, dev, fun).is_None()
+}
+
+impl Kernel {
+    pub open spec fn get_thread_ioid(&self, thread_ptr: ThreadPtr) -> Option<IOid> {
+        self.get_proc(self.get_thread(thread_ptr).owning_proc).ioid
+    }
+
+    /// Moves `bus:dev.fun` from the IOid of the sender's process to the IOid of
+    /// the receiver's process. Fails if the sender does not own the device or
+    /// the receiver has no IOMMU table.
+    pub fn pass_pci_device(
+        &mut self,
+        sender_thread_ptr: ThreadPtr,
+        receiver_thread_ptr: ThreadPtr,
+        bus: u8,
+        dev: u8,
+        fun: u8,
+    ) -> (ret: bool)
+        requires
+            old(self).wf(),
+            old(self).thread_dom().contains(sender_thread_ptr),
+            old(self).thread_dom().contains(receiver_thread_ptr),
+            0 <= dev < 32 && 0 <= fun < 8,
+        ensures
+            self.wf(),
+            self.proc_man =~= old(self).proc_man,
+            self.page_alloc =~= old(self).page_alloc,
+            ret == false ==> *self =~= *old(self),
+            ret ==> old(self).get_thread_ioid(sender_thread_ptr).is_Some()
+                && old(self).get_thread_ioid(receiver_thread_ptr).is_Some()
+                && old(self).mem_man.pci_bitmap@[(
+                old(self).get_thread_ioid(sender_thread_ptr).unwrap(),
+                bus,
+                dev,
+                fun,
+            )] && self.mem_man.pci_bitmap@ =~= old(self).mem_man.pci_bitmap@.insert(
+                (old(self).get_thread_ioid(sender_thread_ptr).unwrap(), bus, dev, fun),
+                false,
+            ).insert((old(self).get_thread_ioid(receiver_thread_ptr).unwrap(), bus, dev, fun), true)
+                && self.mem_man.root_table.resolve(bus, dev, fun).is_None(),
+    {
+        proof {
+            self.proc_man.thread_inv();
+            self.proc_man.process_inv();
+        }
+        let sender_proc_ptr = self.proc_man.get_thread(sender_thread_ptr).owning_proc;
+        let receiver_proc_ptr = self.proc_man.get_thread(receiver_thread_ptr).owning_proc;
+        let sender_ioid_op = self.proc_man.get_proc(sender_proc_ptr).ioid;
+        let receiver_ioid_op = self.proc_man.get_proc(receiver_proc_ptr).ioid;
+        if sender_ioid_op.is_none() || receiver_ioid_op.is_none() {
+            return false;
+        }
+        let sender_ioid = sender_ioid_op.unwrap();
+        let receiver_ioid = receiver_ioid_op.unwrap();
+        assert(self.mem_man.ioid_active(sender_ioid));
+        assert(self.mem_man.ioid_active(receiver_ioid));
+
+        if self.mem_man.pci_device_owned(sender_ioid, bus, dev, fun) == false {
+            return false;
+        }
+
+        self.mem_man.transfer_pci_device(sender_ioid, receiver_ioid, bus, dev, fun);
+
+        assert(self.mem_man.wf());
+        assert(self.page_alloc.wf());
+        assert(self.proc_man.wf());
+        assert(self.memory_wf());
+        assert(self.mapping_wf());
+        assert(self.pcid_ioid_wf());
+        assert(self.page_mapping_wf());
+        true
+    }
+
+    /// Reserves `page_ptr` for `container_ptr` if it is unmapped device memory,
+    /// so a BAR page can follow its device to another container.
+    pub fn pass_mmio_page(&mut self, page_ptr: PagePtr, container_ptr: ContainerPtr)
+        requires
+            old(self).total_wf(),
+            old(self).container_dom().contains(container_ptr),
+            page_ptr_valid(page_ptr),
+        ensures
+            self.total_wf(),
+            self.proc_man =~= old(self).proc_man,
+            self.mem_man =~= old(self).mem_man,
+            self.page_mapping =~= old(self).page_mapping,
+            self.page_io_mapping =~= old(self).page_io_mapping,
+            forall|p: PagePtr|
+                #![auto]
+                self.page_alloc.page_is_mmio(p) == old(self).page_alloc.page_is_mmio(p),
+            forall|p: PagePtr|
+                #![auto]
+                self.page_alloc.page_is_mmio(p) && p != page_ptr
+                    ==> self.page_alloc.mmio_page_owner(p) == old(
+                    self,
+                ).page_alloc.mmio_page_owner(p),
+            self.page_alloc.page_is_mmio(page_ptr) ==> self.page_alloc.mmio_page_owner(page_ptr)
+                == container_ptr,
+    {
+        if self.page_alloc.get_mmio_page_owner(page_ptr).is_none() {
+            return;
+        }
+        self.page_alloc.set_mmio_page_owner(page_ptr, container_ptr);
+        assert(self.wf()) by {
+            assert(self.mem_man.wf());
+            assert(self.page_alloc.wf());
+            assert(self.proc_man.wf());
+            assert(self.memory_wf());
+            assert(self.mapping_wf());
+            assert(self.pcid_ioid_wf());
+            assert(self.page_mapping_wf());
+        };
+        assert(self.get_num_of_free_pages() == old(self).get_num_of_free_pages());
+    }
+
+    ///
+    /// Hands the PCI device `bus:dev.fun` to the thread receiving on the endpoint.
+    /// The receiver must be waiting for the same device.
+    ///
+    /// endpoint state
+    /// | queue state | queue len | action |
+    /// | send        | >= 0      | block  |
+    /// | receive     | == 0      | block + changed queue state |
+    /// | receive     | > 0       | success |
+    ///
+    pub fn syscall_send_pci_block(
+        &mut self,
+        sender_thread_ptr: ThreadPtr,
+        blocking_endpoint_index: EndpointIdx,
+        bus: u8,
+        dev: u8,
+        fun: u8,
+        pt_regs: &Registers,
+    ) -> (ret: SyscallReturnStruct)
+        requires
+            old(self).total_wf(),
+            old(self).thread_dom().contains(sender_thread_ptr),
+            0 <= blocking_endpoint_index < MAX_NUM_ENDPOINT_DESCRIPTORS,
+            old(self).get_thread(sender_thread_ptr).state == ThreadState::RUNNING,
+        ensures
+            self.total_wf(),
+            ret.is_error() ==> self.mem_man.pci_bitmap@ =~= old(self).mem_man.pci_bitmap@,
+            !ret.is_error() ==> pci_device_passed_spec(
+                *old(self),
+                *self,
+                sender_thread_ptr,
+                old(self).get_endpoint(
+                    old(self).get_endpoint_ptr_by_endpoint_idx(
+                        sender_thread_ptr,
+                        blocking_endpoint_index,
+                    ).unwrap(),
+                ).queue@[0],
+                bus,
+                dev,
+                fun,
+            ),
+    {
+        proof {
+            self.proc_man.thread_inv();
+            self.proc_man.endpoint_inv();
+            self.proc_man.process_inv();
+        }
+        if dev >= 32 || fun >= 8 {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        let sender_proc_ptr = self.proc_man.get_thread(sender_thread_ptr).owning_proc;
+        let sender_ioid_op = self.proc_man.get_proc(sender_proc_ptr).ioid;
+        if sender_ioid_op.is_none() {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        assert(self.mem_man.ioid_active(sender_ioid_op.unwrap()));
+        if self.mem_man.pci_device_owned(sender_ioid_op.unwrap(), bus, dev, fun) == false {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+
+        let blocking_endpoint_ptr_op = self.proc_man.get_thread(
+            sender_thread_ptr,
+        ).endpoint_descriptors.get(blocking_endpoint_index);
+
+        if blocking_endpoint_ptr_op.is_none() {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        let blocking_endpoint_ptr = blocking_endpoint_ptr_op.unwrap();
+        if self.proc_man.get_endpoint(blocking_endpoint_ptr).queue_state.is_send()
+            && self.proc_man.get_endpoint(blocking_endpoint_ptr).queue.len()
+            < MAX_NUM_THREADS_PER_ENDPOINT {
+            self.proc_man.block_running_thread_and_set_trap_frame(
+                sender_thread_ptr,
+                blocking_endpoint_index,
+                IPCPayLoad::Pci { bus: bus, dev: dev, fun: fun },
+                pt_regs,
+            );
+            assert(self.total_wf()) by {
+                self.fold_change_mem_4k_lemma(
+                    *old(self),
+                    self.proc_man.get_thread(sender_thread_ptr).owning_container,
+                );
+            };
+            return SyscallReturnStruct::NoNextThreadNew(RetValueType::Error);
+        }
+        if self.proc_man.get_endpoint(blocking_endpoint_ptr).queue_state.is_send()
+            && self.proc_man.get_endpoint(blocking_endpoint_ptr).queue.len()
+            >= MAX_NUM_THREADS_PER_ENDPOINT {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        if self.proc_man.get_endpoint(blocking_endpoint_ptr).queue_state.is_receive()
+            && self.proc_man.get_endpoint(blocking_endpoint_ptr).queue.len() == 0 {
+            self.proc_man.block_running_thread_and_change_queue_state_and_set_trap_frame(
+                sender_thread_ptr,
+                blocking_endpoint_index,
+                IPCPayLoad::Pci { bus: bus, dev: dev, fun: fun },
+                EndpointState::SEND,
+                pt_regs,
+            );
+            assert(self.total_wf()) by {
+                self.fold_change_mem_4k_lemma(
+                    *old(self),
+                    self.proc_man.get_thread(sender_thread_ptr).owning_container,
+                );
+            };
+            return SyscallReturnStruct::NoNextThreadNew(RetValueType::Error);
+        }
+        assert(self.receiver_exist(sender_thread_ptr, blocking_endpoint_index));
+
+        let receiver_thread_ptr = self.proc_man.get_endpoint(
+            blocking_endpoint_ptr,
+        ).queue.get_head();
+        let receiver_container_ptr = self.proc_man.get_thread(receiver_thread_ptr).owning_container;
+
+        match self.proc_man.get_thread(receiver_thread_ptr).ipc_payload.get_payload_as_pci() {
+            Some((r_bus, r_dev, r_fun)) => {
+                if r_bus != bus || r_dev != dev || r_fun != fun {
+                    // receiver is waiting for a different device
+                    return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+                }
+            },
+            None => {
+                // receiver not receiving a device
+                return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+            },
+        }
+        if self.proc_man.get_container(receiver_container_ptr).scheduler.len()
+            >= MAX_CONTAINER_SCHEDULER_LEN {
+            // cannot schedule the receiver
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        if self.pass_pci_device(sender_thread_ptr, receiver_thread_ptr, bus, dev, fun) == false {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        self.proc_man.schedule_blocked_thread(blocking_endpoint_ptr);
+        assert(self.total_wf()) by {
+            self.fold_change_mem_4k_lemma(
+                *old(self),
+                self.proc_man.get_thread(sender_thread_ptr).owning_container,
+            );
+        };
+        return SyscallReturnStruct::NoSwitchNew(RetValueType::Else);
+    }
+}
+
+} // verus!
@@ -0,0 +1,134 @@
+use vstd::prelude::*;
+verus! {
+
+use crate::define::*;
+use crate::kernel::Kernel;
+
+pub open spec fn syscall_set_device_iommu_return_value(
+    old: Kernel,
+    thread_ptr: ThreadPtr,
+    bus: u8,
+    dev: u8,
+    fun: u8,
+) -> UserRetValueType {
+    let proc_ptr = old.get_thread(thread_ptr).owning_proc;
+    let ioid_op = old.get_proc(proc_ptr).ioid;
+
+    if dev >= 32 || fun >= 8 {
+        UserRetValueType::Else
+    } else if ioid_op.is_None() {
+        UserRetValueType::Else
+    } else if old.mem_man.pci_bitmap@[(ioid_op.unwrap(), bus, dev, fun)] == false {
+        UserRetValueType::Else
+    } else {
+        UserRetValueType::Success
+    }
+}
+
+pub open spec fn syscall_set_device_iommu_spec(
+    old: Kernel,
+    new: Kernel,
+    thread_ptr: ThreadPtr,
+    bus: u8,
+    dev: u8,
+    fun: u8,
+    ret: SyscallReturnStruct,
+) -> bool {
+    let proc_ptr = old.get_thread(thread_ptr).owning_proc;
+    let ioid = old.get_proc(proc_ptr).ioid.unwrap();
+    if syscall_set_device_iommu_return_value(old, thread_ptr, bus, dev, fun).is_error() {
+        new =~= old
+    } else {
+        // things that did not change
+        &&& old.thread_dom() =~= new.thread_dom()
+        &&& old.proc_dom() =~= new.proc_dom()
+        &&& old.container_dom() =~= new.container_dom()
+        &&& old.endpoint_dom() =~= new.endpoint_dom()
+        &&& forall|t_ptr: ThreadPtr|
+            #![trigger new.get_thread(t_ptr)]
+            old.thread_dom().contains(t_ptr) ==> new.get_thread(t_ptr) =~= old.get_thread(t_ptr)
+        &&& forall|p_ptr: ProcPtr|
+            #![trigger new.get_proc(p_ptr)]
+            new.proc_dom().contains(p_ptr) ==> new.get_proc(p_ptr) =~= old.get_proc(p_ptr)
+        &&& forall|c: ContainerPtr|
+            #![trigger new.get_container(c)]
+            new.container_dom().contains(c) ==> old.get_container(c) =~= new.get_container(c)
+        &&& forall|p_ptr: ProcPtr|
+            #![trigger new.get_address_space(p_ptr)]
+            new.proc_dom().contains(p_ptr) ==> new.get_address_space(p_ptr)
+                =~= old.get_address_space(p_ptr)
+        &&& forall|p_ptr: ProcPtr|
+            #![trigger new.get_io_space(p_ptr)]
+            new.proc_dom().contains(p_ptr) && new.get_proc(p_ptr).ioid.is_Some()
+                ==> new.get_io_space(p_ptr) =~= old.get_io_space(p_ptr)
+        &&& new.mem_man.pci_bitmap@ =~= old.mem_man.pci_bitmap@
+        &&& forall|_bus: u8, _dev: u8, _fun: u8|
+            #![auto]
+            0 <= _dev < 32 && 0 <= _fun < 8 && (_bus != bus || _dev != dev || _fun != fun)
+                ==> new.mem_man.root_table.resolve(_bus, _dev, _fun)
+                =~= old.mem_man.root_table.resolve(_bus, _dev, _fun)
+        //Things that changed
+        &&& new.mem_man.root_table.resolve(bus, dev, fun) == Some(
+            (ioid, new.mem_man.get_iommu_table_by_ioid(ioid).unwrap().cr3),
+        )
+    }
+}
+
+impl Kernel {
+    /// Binds `bus:dev.fun` to the IOMMU table of the calling process.
+    ///
+    /// The device must be recorded under the caller's IOid in the PCI bitmap,
+    /// so a process can only point devices it was given at its own memory.
+    pub fn syscall_set_device_iommu(
+        &mut self,
+        thread_ptr: ThreadPtr,
+        bus: u8,
+        dev: u8,
+        fun: u8,
+    ) -> (ret: SyscallReturnStruct)
+        requires
+            old(self).total_wf(),
+            old(self).thread_dom().contains(thread_ptr),
+        ensures
+            self.total_wf(),
+            syscall_set_device_iommu_return_value(*old(self), thread_ptr, bus, dev, fun).is_error()
+                == ret.is_error(),
+            syscall_set_device_iommu_spec(*old(self), *self, thread_ptr, bus, dev, fun, ret),
+    {
+        let proc_ptr = self.proc_man.get_thread(thread_ptr).owning_proc;
+
+        proof {
+            self.proc_man.thread_inv();
+            self.proc_man.process_inv();
+        }
+
+        if dev >= 32 || fun >= 8 {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        let ioid_op = self.proc_man.get_proc(proc_ptr).ioid;
+        if ioid_op.is_none() {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        let ioid = ioid_op.unwrap();
+        assert(self.mem_man.ioid_active(ioid));
+
+        if self.mem_man.pci_device_owned(ioid, bus, dev, fun) == false {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+
+        self.mem_man.set_pci_binding(bus, dev, fun, ioid);
+
+        assert(self.mem_man.wf());
+        assert(self.page_alloc.wf());
+        assert(self.proc_man.wf());
+        assert(self.memory_wf());
+        assert(self.mapping_wf());
+        assert(self.pcid_ioid_wf());
+        assert(self.page_mapping_wf());
+        assert(self.total_mem_4k_quota_wf());
+
+        return SyscallReturnStruct::NoSwitchNew(RetValueType::Else);
+    }
+}
+
+} // verus!
@@ -0,0 +1,270 @@
+use vstd::prelude::*;
+verus! {
+
+use crate::define::*;
+use crate::kernel::Kernel;
+
+pub open spec fn syscall_transfer_quota_return_value(
+    old: Kernel,
+    thread_ptr: ThreadPtr,
+    child_container_ptr: ContainerPtr,
+    mem_4k: usize,
+    donate: bool,
+) -> UserRetValueType {
+    let container_ptr = old.get_thread(thread_ptr).owning_container;
+    let (src_container_ptr, dst_container_ptr) = if donate {
+        (container_ptr, child_container_ptr)
+    } else {
+        (child_container_ptr, container_ptr)
+    };
+
+    if old.get_container(container_ptr).children@.contains(child_container_ptr) == false {
+        UserRetValueType::Else
+    } else if old.get_container_quota(src_container_ptr).mem_4k < mem_4k {
+        UserRetValueType::ErrorNoQuota
+    } else if old.get_container_quota(dst_container_ptr).mem_4k + mem_4k > usize::MAX {
+        UserRetValueType::Else
+    } else {
+        UserRetValueType::Success
+    }
+}
+
+pub open spec fn syscall_transfer_quota_spec(
+    old: Kernel,
+    new: Kernel,
+    thread_ptr: ThreadPtr,
+    child_container_ptr: ContainerPtr,
+    mem_4k: usize,
+    donate: bool,
+    ret: SyscallReturnStruct,
+) -> bool {
+    let container_ptr = old.get_thread(thread_ptr).owning_container;
+    let (src_container_ptr, dst_container_ptr) = if donate {
+        (container_ptr, child_container_ptr)
+    } else {
+        (child_container_ptr, container_ptr)
+    };
+
+    if syscall_transfer_quota_return_value(
+        old,
+        thread_ptr,
+        child_container_ptr,
+        mem_4k,
+        donate,
+    ).is_error() {
+        new =~= old
+    } else {
+        // things that did not change
+        &&& old.thread_dom() =~= new.thread_dom()
+        &&& old.proc_dom() =~= new.proc_dom()
+        &&& old.container_dom() =~= new.container_dom()
+        &&& old.endpoint_dom() =~= new.endpoint_dom()
+        &&& forall|t_ptr: ThreadPtr|
+            #![trigger new.get_thread(t_ptr)]
+            #![trigger old.get_thread(t_ptr)]
+            old.thread_dom().contains(t_ptr) ==> new.get_thread(t_ptr) =~= old.get_thread(t_ptr)
+        &&& forall|proc_ptr: ProcPtr|
+            #![trigger new.get_proc(proc_ptr)]
+            new.proc_dom().contains(proc_ptr) ==> new.get_proc(proc_ptr) =~= old.get_proc(proc_ptr)
+        &&& forall|c: ContainerPtr|
+            #![trigger new.get_container(c)]
+            new.container_dom().contains(c) && c != src_container_ptr && c != dst_container_ptr
+                ==> old.get_container(c) =~= new.get_container(c)
+        &&& forall|e_ptr: EndpointPtr|
+            #![trigger new.get_endpoint(e_ptr)]
+            new.endpoint_dom().contains(e_ptr) ==> old.get_endpoint(e_ptr) =~= new.get_endpoint(
+                e_ptr,
+            )
+        &&& forall|p_ptr: ProcPtr|
+            #![trigger new.get_address_space(p_ptr)]
+            new.proc_dom().contains(p_ptr) ==> new.get_address_space(p_ptr) =~= old.get_address_space(
+                p_ptr,
+            )
+        &&& new.get_physical_page_mapping() =~= old.get_physical_page_mapping()
+        //Things that changed
+        &&& old.get_container_quota(src_container_ptr).spec_subtract_mem_4k(
+            new.get_container_quota(src_container_ptr),
+            mem_4k,
+        )
+        &&& new.get_container_quota(dst_container_ptr).mem_4k == old.get_container_quota(
+            dst_container_ptr,
+        ).mem_4k + mem_4k
+    }
+}
+
+impl Kernel {
+    /// Moves `mem_4k` pages worth of quota from `src_container_ptr` to
+    /// `dst_container_ptr`. The sum of all container quotas is unchanged.
+    pub fn transfer_mem_4k_quota(
+        &mut self,
+        src_container_ptr: ContainerPtr,
+        dst_container_ptr: ContainerPtr,
+        mem_4k: usize,
+    )
+        requires
+            old(self).total_wf(),
+            old(self).container_dom().contains(src_container_ptr),
+            old(self).container_dom().contains(dst_container_ptr),
+            src_container_ptr != dst_container_ptr,
+            old(self).get_container_quota(src_container_ptr).mem_4k >= mem_4k,
+            old(self).get_container_quota(dst_container_ptr).mem_4k + mem_4k <= usize::MAX,
+        ensures
+            self.total_wf(),
+            self.thread_dom() =~= old(self).thread_dom(),
+            self.proc_dom() =~= old(self).proc_dom(),
+            self.container_dom() =~= old(self).container_dom(),
+            self.endpoint_dom() =~= old(self).endpoint_dom(),
+            forall|t_ptr: ThreadPtr|
+                #![auto]
+                self.thread_dom().contains(t_ptr) ==> self.get_thread(t_ptr) =~= old(
+                    self,
+                ).get_thread(t_ptr),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) ==> self.get_proc(p_ptr) =~= old(self).get_proc(
+                    p_ptr,
+                ),
+            forall|c: ContainerPtr|
+                #![auto]
+                self.container_dom().contains(c) && c != src_container_ptr && c
+                    != dst_container_ptr ==> self.get_container(c) =~= old(self).get_container(c),
+            forall|e_ptr: EndpointPtr|
+                #![auto]
+                self.endpoint_dom().contains(e_ptr) ==> self.get_endpoint(e_ptr) =~= old(
+                    self,
+                ).get_endpoint(e_ptr),
+            forall|p_ptr: ProcPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) ==> self.get_address_space(p_ptr) =~= old(
+                    self,
+                ).get_address_space(p_ptr),
+            self.get_physical_page_mapping() =~= old(self).get_physical_page_mapping(),
+            old(self).get_container_quota(src_container_ptr).spec_subtract_mem_4k(
+                self.get_container_quota(src_container_ptr),
+                mem_4k,
+            ),
+            self.get_container_quota(dst_container_ptr).mem_4k == old(self).get_container_quota(
+                dst_container_ptr,
+            ).mem_4k + mem_4k,
+    {
+        let src_quota = self.proc_man.get_container(src_container_ptr).quota.mem_4k;
+        let dst_quota = self.proc_man.get_container(dst_container_ptr).quota.mem_4k;
+
+        let ghost_before = Ghost(*self);
+        self.proc_man.set_container_mem_quota_mem_4k(src_container_ptr, src_quota - mem_4k);
+        proof {
+            self.fold_change_mem_4k_lemma(ghost_before@, src_container_ptr);
+        }
+        let ghost_after_src = Ghost(*self);
+        self.proc_man.set_container_mem_quota_mem_4k(dst_container_ptr, dst_quota + mem_4k);
+        proof {
+            self.fold_change_mem_4k_lemma(ghost_after_src@, dst_container_ptr);
+        }
+        assert(self.wf()) by {
+            assert(self.mem_man.wf());
+            assert(self.page_alloc.wf());
+            assert(self.proc_man.wf());
+            assert(self.memory_wf());
+            assert(self.mapping_wf());
+            assert(self.pcid_ioid_wf());
+            assert(self.page_mapping_wf());
+        };
+        assert(self.total_wf());
+    }
+
+    /// Gives `mem_4k` pages of the caller container's quota to one of its
+    /// direct children.
+    pub fn syscall_donate_quota(
+        &mut self,
+        thread_ptr: ThreadPtr,
+        child_container_ptr: ContainerPtr,
+        mem_4k: usize,
+    ) -> (ret: SyscallReturnStruct)
+        requires
+            old(self).total_wf(),
+            old(self).thread_dom().contains(thread_ptr),
+        ensures
+            self.total_wf(),
+            syscall_transfer_quota_spec(
+                *old(self),
+                *self,
+                thread_ptr,
+                child_container_ptr,
+                mem_4k,
+                true,
+                ret,
+            ),
+    {
+        let container_ptr = self.proc_man.get_thread(thread_ptr).owning_container;
+
+        proof {
+            self.proc_man.thread_inv();
+            self.proc_man.container_inv();
+            self.proc_man.container_children_inv();
+        }
+
+        if self.proc_man.get_container(container_ptr).children.contains(child_container_ptr)
+            == false {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        let src_quota = self.proc_man.get_container(container_ptr).quota.mem_4k;
+        let dst_quota = self.proc_man.get_container(child_container_ptr).quota.mem_4k;
+        if src_quota < mem_4k {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::ErrorNoQuota);
+        }
+        if dst_quota > usize::MAX - mem_4k {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        self.transfer_mem_4k_quota(container_ptr, child_container_ptr, mem_4k);
+        return SyscallReturnStruct::NoSwitchNew(RetValueType::Else);
+    }
+
+    /// Takes back `mem_4k` pages of unused quota from one of the caller
+    /// container's direct children.
+    pub fn syscall_reclaim_quota(
+        &mut self,
+        thread_ptr: ThreadPtr,
+        child_container_ptr: ContainerPtr,
+        mem_4k: usize,
+    ) -> (ret: SyscallReturnStruct)
+        requires
+            old(self).total_wf(),
+            old(self).thread_dom().contains(thread_ptr),
+        ensures
+            self.total_wf(),
+            syscall_transfer_quota_spec(
+                *old(self),
+                *self,
+                thread_ptr,
+                child_container_ptr,
+                mem_4k,
+                false,
+                ret,
+            ),
+    {
+        let container_ptr = self.proc_man.get_thread(thread_ptr).owning_container;
+
+        proof {
+            self.proc_man.thread_inv();
+            self.proc_man.container_inv();
+            self.proc_man.container_children_inv();
+        }
+
+        if self.proc_man.get_container(container_ptr).children.contains(child_container_ptr)
+            == false {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        let src_quota = self.proc_man.get_container(child_container_ptr).quota.mem_4k;
+        let dst_quota = self.proc_man.get_container(container_ptr).quota.mem_4k;
+        if src_quota < mem_4k {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::ErrorNoQuota);
+        }
+        if dst_quota > usize::MAX - mem_4k {
+            return SyscallReturnStruct::NoSwitchNew(RetValueType::Error);
+        }
+        self.transfer_mem_4k_quota(child_container_ptr, container_ptr, mem_4k);
+        return SyscallReturnStruct::NoSwitchNew(RetValueType::Else);
+    }
+}
+
+} // verus!
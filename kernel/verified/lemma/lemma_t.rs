@@ -53,6 +53,18 @@ pub proof fn set_add_lemma<A>()
 {
 }
 
+/// There are fewer than `usize::MAX` 4k-aligned user addresses, so any set of
+/// them is finite and its size fits in a `usize`.
+#[verifier(external_body)]
+pub proof fn va_4k_set_len_lemma(s: Set<VAddr>)
+    requires
+        forall|va: VAddr| #![auto] s.contains(va) ==> va_4k_valid(va),
+    ensures
+        s.finite(),
+        s.len() < usize::MAX,
+{
+}
+
 //TODO: @Xiangdong prove this
 #[verifier(external_body)]
 pub proof fn page_ptr_lemma()
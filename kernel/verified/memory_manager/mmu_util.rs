@@ -322,6 +322,233 @@ impl Array<Option<PageTable>, PCID_MAX> {
         );
     }
 
+    #[verifier(external_body)]
+    pub fn pagetable_array_protect_4k_page_t(
+        &mut self,
+        pcid: Pcid,
+        target_l4i: L4Index,
+        target_l3i: L3Index,
+        target_l2i: L2Index,
+        target_l1i: L2Index,
+        target_l1_p: PageMapPtr,
+        target_entry: &MapEntry,
+    )
+        requires
+            old(self).wf(),
+            old(self)@[pcid as int].unwrap().wf(),
+            KERNEL_MEM_END_L4INDEX <= target_l4i < 512,
+            0 <= target_l3i < 512,
+            0 <= target_l2i < 512,
+            0 <= target_l1i < 512,
+            old(self)@[pcid as int].unwrap().spec_resolve_mapping_l2(
+                target_l4i,
+                target_l3i,
+                target_l2i,
+            ).is_Some(),
+            old(self)@[pcid as int].unwrap().spec_resolve_mapping_l2(
+                target_l4i,
+                target_l3i,
+                target_l2i,
+            ).get_Some_0().addr == target_l1_p,
+            old(self)@[pcid as int].unwrap().mapping_4k().dom().contains(
+                spec_index2va((target_l4i, target_l3i, target_l2i, target_l1i)),
+            ),
+            old(self)@[pcid as int].unwrap().mapping_4k()[spec_index2va(
+                (target_l4i, target_l3i, target_l2i, target_l1i),
+            )].addr == target_entry.addr,
+        ensures
+            self.wf(),
+            forall|p: Pcid|
+                #![trigger self@[p as int]]
+                #![trigger old(self)@[p as int]]
+                0 <= p < PCID_MAX && p != pcid ==> self@[p as int] =~= old(self)@[p as int],
+            self@[pcid as int].is_Some(),
+            self@[pcid as int].unwrap().wf(),
+            self@[pcid as int].unwrap().pcid == old(self)@[pcid as int].unwrap().pcid,
+            self@[pcid as int].unwrap().kernel_l4_end == old(
+                self,
+            )@[pcid as int].unwrap().kernel_l4_end,
+            self@[pcid as int].unwrap().page_closure() =~= old(
+                self,
+            )@[pcid as int].unwrap().page_closure(),
+            self@[pcid as int].unwrap().mapping_4k() =~= old(
+                self,
+            )@[pcid as int].unwrap().mapping_4k().insert(
+                spec_index2va((target_l4i, target_l3i, target_l2i, target_l1i)),
+                *target_entry,
+            ),
+            self@[pcid as int].unwrap().mapping_2m() =~= old(
+                self,
+            )@[pcid as int].unwrap().mapping_2m(),
+            self@[pcid as int].unwrap().mapping_1g() =~= old(
+                self,
+            )@[pcid as int].unwrap().mapping_1g(),
+            self@[pcid as int].unwrap().kernel_entries =~= old(
+                self,
+            )@[pcid as int].unwrap().kernel_entries,
+    {
+        self.ar[pcid].as_mut().unwrap().protect_4k_page(
+            target_l4i,
+            target_l3i,
+            target_l2i,
+            target_l1i,
+            target_l1_p,
+            target_entry,
+        );
+    }
+
+    #[verifier(external_body)]
+    pub fn pagetable_array_unmap_4k_page_t(
+        &mut self,
+        pcid: Pcid,
+        target_l4i: L4Index,
+        target_l3i: L3Index,
+        target_l2i: L2Index,
+        target_l1i: L2Index,
+        target_l1_p: PageMapPtr,
+    )
+        requires
+            old(self).wf(),
+            old(self)@[pcid as int].unwrap().wf(),
+            KERNEL_MEM_END_L4INDEX <= target_l4i < 512,
+            0 <= target_l3i < 512,
+            0 <= target_l2i < 512,
+            0 <= target_l1i < 512,
+            old(self)@[pcid as int].unwrap().spec_resolve_mapping_l2(
+                target_l4i,
+                target_l3i,
+                target_l2i,
+            ).is_Some(),
+            old(self)@[pcid as int].unwrap().spec_resolve_mapping_l2(
+                target_l4i,
+                target_l3i,
+                target_l2i,
+            ).get_Some_0().addr == target_l1_p,
+            old(self)@[pcid as int].unwrap().mapping_4k().dom().contains(
+                spec_index2va((target_l4i, target_l3i, target_l2i, target_l1i)),
+            ),
+        ensures
+            self.wf(),
+            forall|p: Pcid|
+                #![trigger self@[p as int]]
+                #![trigger old(self)@[p as int]]
+                0 <= p < PCID_MAX && p != pcid ==> self@[p as int] =~= old(self)@[p as int],
+            self@[pcid as int].is_Some(),
+            self@[pcid as int].unwrap().wf(),
+            self@[pcid as int].unwrap().pcid == old(self)@[pcid as int].unwrap().pcid,
+            self@[pcid as int].unwrap().kernel_l4_end == old(
+                self,
+            )@[pcid as int].unwrap().kernel_l4_end,
+            self@[pcid as int].unwrap().page_closure() =~= old(
+                self,
+            )@[pcid as int].unwrap().page_closure(),
+            self@[pcid as int].unwrap().mapping_4k() =~= old(
+                self,
+            )@[pcid as int].unwrap().mapping_4k().remove(
+                spec_index2va((target_l4i, target_l3i, target_l2i, target_l1i)),
+            ),
+            self@[pcid as int].unwrap().spec_resolve_mapping_l2(
+                target_l4i,
+                target_l3i,
+                target_l2i,
+            ) =~= old(self)@[pcid as int].unwrap().spec_resolve_mapping_l2(
+                target_l4i,
+                target_l3i,
+                target_l2i,
+            ),
+            self@[pcid as int].unwrap().mapping_2m() =~= old(
+                self,
+            )@[pcid as int].unwrap().mapping_2m(),
+            self@[pcid as int].unwrap().mapping_1g() =~= old(
+                self,
+            )@[pcid as int].unwrap().mapping_1g(),
+            self@[pcid as int].unwrap().kernel_entries =~= old(
+                self,
+            )@[pcid as int].unwrap().kernel_entries,
+    {
+        self.ar[pcid].as_mut().unwrap().unmap_4k_page(
+            target_l4i,
+            target_l3i,
+            target_l2i,
+            target_l1i,
+            target_l1_p,
+        );
+    }
+
+    #[verifier(external_body)]
+    pub fn pagetable_array_get_mapped_4k_t(&self, pcid: Pcid) -> (ret: usize)
+        requires
+            self.wf(),
+            self@[pcid as int].is_Some(),
+        ensures
+            ret == self@[pcid as int].unwrap().mapped_4k,
+    {
+        self.ar[pcid].as_ref().unwrap().mapped_4k
+    }
+
+    #[verifier(external_body)]
+    pub fn pagetable_array_next_4k_mapping_t(&self, pcid: Pcid, va: VAddr) -> (ret: Option<
+        (VAddr, MapEntry),
+    >)
+        requires
+            self.wf(),
+            self@[pcid as int].is_Some(),
+            self@[pcid as int].unwrap().wf(),
+            va_4k_valid(va),
+        ensures
+            ret.is_Some() ==> va_4k_valid(ret.unwrap().0) && va <= ret.unwrap().0
+                && self@[pcid as int].unwrap().mapping_4k().dom().contains(ret.unwrap().0)
+                && self@[pcid as int].unwrap().mapping_4k()[ret.unwrap().0] =~= ret.unwrap().1,
+            ret.is_Some() ==> forall|v: VAddr|
+                #![auto]
+                va_4k_valid(v) && va <= v < ret.unwrap().0
+                    ==> self@[pcid as int].unwrap().mapping_4k().dom().contains(v) == false,
+            ret.is_None() ==> forall|v: VAddr|
+                #![auto]
+                va_4k_valid(v) && va <= v
+                    ==> self@[pcid as int].unwrap().mapping_4k().dom().contains(v) == false,
+    {
+        let pagetable = self.ar[pcid].as_ref().unwrap();
+        let (mut start_l4i, mut start_l3i, mut start_l2i, mut start_l1i) = va2index(va);
+        // The entries below KERNEL_MEM_END_L4INDEX map the kernel in every
+        // address space and are never reported.
+        if start_l4i < KERNEL_MEM_END_L4INDEX {
+            (start_l4i, start_l3i, start_l2i, start_l1i) = (KERNEL_MEM_END_L4INDEX, 0, 0, 0);
+        }
+        for l4i in start_l4i..512 {
+            let l4_entry = match pagetable.get_entry_l4(l4i) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let first_l3i = if l4i == start_l4i { start_l3i } else { 0 };
+            for l3i in first_l3i..512 {
+                let l3_entry = match pagetable.get_entry_l3(l4i, l3i, &l4_entry) {
+                    Some(entry) => entry,
+                    None => continue,
+                };
+                let first_l2i = if l4i == start_l4i && l3i == start_l3i { start_l2i } else { 0 };
+                for l2i in first_l2i..512 {
+                    let l2_entry = match pagetable.get_entry_l2(l4i, l3i, l2i, &l3_entry) {
+                        Some(entry) => entry,
+                        None => continue,
+                    };
+                    let first_l1i = if l4i == start_l4i && l3i == start_l3i && l2i == start_l2i {
+                        start_l1i
+                    } else {
+                        0
+                    };
+                    for l1i in first_l1i..512 {
+                        if let Some(entry) = pagetable.get_entry_l1(l4i, l3i, l2i, l1i, &l2_entry) {
+                            let found = l4i << 39 | l3i << 30 | l2i << 21 | l1i << 12;
+                            return Some((found, page_entry_to_map_entry(&entry)));
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+
     #[verifier(external_body)]
     pub fn iommu_table_array_create_iommu_table_l4_entry_t(
         &mut self,
@@ -633,6 +860,75 @@ impl Array<Option<PageTable>, PCID_MAX> {
             target_entry,
         );
     }
+
+    #[verifier(external_body)]
+    pub fn iommu_table_array_unmap_4k_page_t(
+        &mut self,
+        ioid: IOid,
+        target_l4i: L4Index,
+        target_l3i: L3Index,
+        target_l2i: L2Index,
+        target_l1i: L2Index,
+        target_l1_p: PageMapPtr,
+    )
+        requires
+            old(self).wf(),
+            old(self)@[ioid as int].unwrap().wf(),
+            KERNEL_MEM_END_L4INDEX <= target_l4i < 512,
+            0 <= target_l3i < 512,
+            0 <= target_l2i < 512,
+            0 <= target_l1i < 512,
+            old(self)@[ioid as int].unwrap().spec_resolve_mapping_l2(
+                target_l4i,
+                target_l3i,
+                target_l2i,
+            ).is_Some(),
+            old(self)@[ioid as int].unwrap().spec_resolve_mapping_l2(
+                target_l4i,
+                target_l3i,
+                target_l2i,
+            ).get_Some_0().addr == target_l1_p,
+            old(self)@[ioid as int].unwrap().mapping_4k().dom().contains(
+                spec_index2va((target_l4i, target_l3i, target_l2i, target_l1i)),
+            ),
+        ensures
+            self.wf(),
+            forall|p: IOid|
+                #![trigger self@[p as int]]
+                #![trigger old(self)@[p as int]]
+                0 <= p < IOID_MAX && p != ioid ==> self@[p as int] =~= old(self)@[p as int],
+            self@[ioid as int].is_Some(),
+            self@[ioid as int].unwrap().wf(),
+            self@[ioid as int].unwrap().ioid == old(self)@[ioid as int].unwrap().ioid,
+            self@[ioid as int].unwrap().kernel_l4_end == old(
+                self,
+            )@[ioid as int].unwrap().kernel_l4_end,
+            self@[ioid as int].unwrap().page_closure() =~= old(
+                self,
+            )@[ioid as int].unwrap().page_closure(),
+            self@[ioid as int].unwrap().mapping_4k() =~= old(
+                self,
+            )@[ioid as int].unwrap().mapping_4k().remove(
+                spec_index2va((target_l4i, target_l3i, target_l2i, target_l1i)),
+            ),
+            self@[ioid as int].unwrap().mapping_2m() =~= old(
+                self,
+            )@[ioid as int].unwrap().mapping_2m(),
+            self@[ioid as int].unwrap().mapping_1g() =~= old(
+                self,
+            )@[ioid as int].unwrap().mapping_1g(),
+            self@[ioid as int].unwrap().kernel_entries =~= old(
+                self,
+            )@[ioid as int].unwrap().kernel_entries,
+    {
+        self.ar[ioid].as_mut().unwrap().unmap_4k_page(
+            target_l4i,
+            target_l3i,
+            target_l2i,
+            target_l1i,
+            target_l1_p,
+        );
+    }
 }
 
 } // verus!
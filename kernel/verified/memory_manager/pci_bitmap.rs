@@ -42,12 +42,7 @@ impl PCIBitMap {
             while bus != 256 {
                 let mut dev = 0;
                 while dev != 32 {
-                    let mut fun = 0;
-                    while fun != 8 {
-                        self.bit_map[ioid][bus as usize][dev as usize] = 0;
-
-                        fun = fun + 1;
-                    }
+                    self.bit_map[ioid][bus as usize][dev as usize] = 0;
                     dev = dev + 1;
                 }
                 bus = bus + 1;
@@ -85,7 +80,7 @@ impl PCIBitMap {
         ensures
             ret == self.resolve(ioid, bus, dev, fun),
     {
-        (self.bit_map[ioid][bus as usize][dev as usize] & (0x1u8 << (fun as usize))) == 0
+        (self.bit_map[ioid][bus as usize][dev as usize] & (0x1u8 << (fun as usize))) != 0
     }
 
     #[verifier(external_body)]
@@ -102,9 +97,9 @@ impl PCIBitMap {
         let bit_mask = !(0x1u8 << fun);
         let old = self.bit_map[ioid][bus as usize][dev as usize] & bit_mask;
         if target {
-            self.bit_map[ioid][bus as usize][dev as usize] = old;
-        } else {
             self.bit_map[ioid][bus as usize][dev as usize] = old | (0x1u8 << fun);
+        } else {
+            self.bit_map[ioid][bus as usize][dev as usize] = old;
         }
     }
 }
@@ -36,7 +36,13 @@ pub struct DeviceTable {
 //         self.ar[dev*fun] = cr3;
 //     }
 // }
-#[repr(align(4096))]
+/// VT-d root table followed by one context table per bus.
+///
+/// Root entry `bus` points to `deviecs[bus]`. Context entry `(dev << 3) | fun`
+/// holds the IOMMU page table of the device in its low word and the domain id
+/// in its high word. The domain id is `ioid + 1`, as domain 0 is reserved when
+/// the hardware reports caching mode.
+#[repr(C, align(4096))]
 pub struct RootTable {
     root: [usize; 512],
     seq_ar: Ghost<Seq<Seq<Seq<Option<(IOid, usize)>>>>>,
@@ -69,8 +75,8 @@ impl RootTable {
     {
         let mut i = 0;
         while i != 256 {
-            self.root[i * 2] = 0;
-            self.root[i * 2 + 1] = &self.deviecs[i] as *const DeviceTable as usize | 0x1b;
+            self.root[i * 2] = &self.deviecs[i] as *const DeviceTable as usize | 0x1;
+            self.root[i * 2 + 1] = 0;
             i = i + 1;
         }
         let mut i = 0;
@@ -98,7 +104,13 @@ impl RootTable {
             self.wf(),
             0 <= bus < 256 && 0 <= dev < 32 && 0 <= fun < 8,
     {
-        None
+        self.seq_ar@[bus as int][dev as int][fun as int]
+    }
+
+    /// Address to load into the IOMMU root table address register.
+    #[verifier(external_body)]
+    pub fn root_table_addr(&self) -> (ret: usize) {
+        &self.root as *const [usize; 512] as usize
     }
 
     #[verifier(external_body)]
@@ -109,14 +121,14 @@ impl RootTable {
         ensures
             ret =~= self.resolve(bus, dev, fun),
     {
-        let p = self.deviecs[bus as usize].ar[((dev * fun) * 2 + 1) as usize] & 0x1b;
-        if p == 0 {
+        let index = (((dev as usize) << 3) | fun as usize) * 2;
+        let lower = self.deviecs[bus as usize].ar[index];
+        if lower & 0x1 == 0 {
             return None;
         }
-        let ioid = self.deviecs[bus as usize].ar[((dev * fun) * 2) as usize] & 0x7FFF00 as usize;
-        let cr3 = self.deviecs[bus as usize].ar[((dev * fun) * 2 + 1) as usize]
-            & 0xFFFFFFFFFFFFF000 as usize;
-        return Some((ioid, cr3));
+        let did = (self.deviecs[bus as usize].ar[index + 1] >> 8) & 0xFFFF;
+        let cr3 = lower & 0xFFFFFFFFFFFFF000 as usize;
+        return Some((did - 1, cr3));
     }
 
     #[verifier(external_body)]
@@ -136,14 +148,15 @@ impl RootTable {
                     _fun,
                 ),
     {
+        let index = (((dev as usize) << 3) | fun as usize) * 2;
         if target.is_none() {
-            self.deviecs[bus as usize].ar[((dev * fun) * 2) as usize] = 0;
-            self.deviecs[bus as usize].ar[((dev * fun) * 2 + 1) as usize] = 0;
+            self.deviecs[bus as usize].ar[index] = 0;
+            self.deviecs[bus as usize].ar[index + 1] = 0;
         } else {
-            self.deviecs[bus as usize].ar[((dev * fun) * 2) as usize] = ((target.unwrap().0 << 8)
-                & 0x7FFF00) | (0x010b);
-            self.deviecs[bus as usize].ar[((dev * fun) * 2 + 1) as usize] = (target.unwrap().1
-                & 0xFFFFFFFFFFFFF000) | (0x1001b);
+            // AW = 010b, 48-bit 4-level tables
+            self.deviecs[bus as usize].ar[index + 1] = (((target.unwrap().0 + 1) << 8) & 0xFFFF00)
+                | 0x2;
+            self.deviecs[bus as usize].ar[index] = (target.unwrap().1 & 0xFFFFFFFFFFFFF000) | 0x1;
         }
     }
 }
@@ -336,6 +336,177 @@ impl MemoryManager {
         return self.root_table.get_ioid(bus, dev, fun);
     }
 
+    pub fn pci_device_owned(&self, ioid: IOid, bus: u8, dev: u8, fun: u8) -> (ret: bool)
+        requires
+            self.wf(),
+            0 <= ioid < IOID_MAX,
+            0 <= bus < 256 && 0 <= dev < 32 && 0 <= fun < 8,
+        ensures
+            ret == self.pci_bitmap@[(ioid, bus, dev, fun)],
+    {
+        self.pci_bitmap.get(ioid, bus, dev, fun)
+    }
+
+    /// Points the context entry of `bus:dev.fun` at the IOMMU table of `ioid`.
+    pub fn set_pci_binding(&mut self, bus: u8, dev: u8, fun: u8, ioid: IOid)
+        requires
+            old(self).wf(),
+            old(self).ioid_active(ioid),
+            0 <= bus < 256 && 0 <= dev < 32 && 0 <= fun < 8,
+        ensures
+            self.wf(),
+            self.kernel_entries =~= old(self).kernel_entries,
+            self.kernel_entries_ghost =~= old(self).kernel_entries_ghost,
+            self.free_pcids =~= old(self).free_pcids,
+            self.pcid_to_proc_ptr =~= old(self).pcid_to_proc_ptr,
+            self.page_tables =~= old(self).page_tables,
+            self.page_table_pages =~= old(self).page_table_pages,
+            self.free_ioids =~= old(self).free_ioids,
+            self.ioid_to_proc_ptr =~= old(self).ioid_to_proc_ptr,
+            self.iommu_tables =~= old(self).iommu_tables,
+            self.iommu_table_pages =~= old(self).iommu_table_pages,
+            self.pci_bitmap =~= old(self).pci_bitmap,
+            self.root_table.resolve(bus, dev, fun) == Some(
+                (ioid, self.get_iommu_table_by_ioid(ioid).unwrap().cr3),
+            ),
+            forall|_bus: u8, _dev: u8, _fun: u8|
+                #![auto]
+                0 <= _bus < 256 && 0 <= _dev < 32 && 0 <= _fun < 8 && (_bus != bus || _dev != dev
+                    || _fun != fun) ==> self.root_table.resolve(_bus, _dev, _fun)
+                    =~= old(self).root_table.resolve(_bus, _dev, _fun),
+    {
+        let cr3 = self.get_cr3_by_ioid(ioid);
+        self.root_table.set(bus, dev, fun, Some((ioid, cr3)));
+        proof {
+            self.root_table_cache@ = self.root_table_cache@.update(
+                bus as int,
+                self.root_table_cache@[bus as int].update(
+                    dev as int,
+                    self.root_table_cache@[bus as int][dev as int].update(
+                        fun as int,
+                        Some((ioid, cr3)),
+                    ),
+                ),
+            );
+        }
+        assert(self.wf()) by {
+            assert(self.pagetables_wf());
+            assert(self.iommutables_wf());
+            assert(self.pagetable_iommu_table_disjoint());
+            assert(self.root_table_wf());
+            assert(self.root_table_cache_wf());
+            assert(self.kernel_entries_wf());
+        };
+    }
+
+    /// Clears the context entry of `bus:dev.fun`, blocking all DMA from the device.
+    /// Ownership in the PCI bitmap is left alone, so the owner can bind it again.
+    pub fn clear_pci_binding(&mut self, bus: u8, dev: u8, fun: u8)
+        requires
+            old(self).wf(),
+            0 <= bus < 256 && 0 <= dev < 32 && 0 <= fun < 8,
+        ensures
+            self.wf(),
+            self.kernel_entries =~= old(self).kernel_entries,
+            self.kernel_entries_ghost =~= old(self).kernel_entries_ghost,
+            self.free_pcids =~= old(self).free_pcids,
+            self.pcid_to_proc_ptr =~= old(self).pcid_to_proc_ptr,
+            self.page_tables =~= old(self).page_tables,
+            self.page_table_pages =~= old(self).page_table_pages,
+            self.free_ioids =~= old(self).free_ioids,
+            self.ioid_to_proc_ptr =~= old(self).ioid_to_proc_ptr,
+            self.iommu_tables =~= old(self).iommu_tables,
+            self.iommu_table_pages =~= old(self).iommu_table_pages,
+            self.pci_bitmap =~= old(self).pci_bitmap,
+            self.root_table.resolve(bus, dev, fun).is_None(),
+            forall|_bus: u8, _dev: u8, _fun: u8|
+                #![auto]
+                0 <= _bus < 256 && 0 <= _dev < 32 && 0 <= _fun < 8 && (_bus != bus || _dev != dev
+                    || _fun != fun) ==> self.root_table.resolve(_bus, _dev, _fun)
+                    =~= old(self).root_table.resolve(_bus, _dev, _fun),
+    {
+        self.root_table.set(bus, dev, fun, None);
+        proof {
+            self.root_table_cache@ = self.root_table_cache@.update(
+                bus as int,
+                self.root_table_cache@[bus as int].update(
+                    dev as int,
+                    self.root_table_cache@[bus as int][dev as int].update(fun as int, None),
+                ),
+            );
+        }
+        assert(self.wf()) by {
+            assert(self.pagetables_wf());
+            assert(self.iommutables_wf());
+            assert(self.pagetable_iommu_table_disjoint());
+            assert(self.root_table_wf());
+            assert(self.root_table_cache_wf());
+            assert(self.kernel_entries_wf());
+        };
+    }
+
+    /// Moves ownership of `bus:dev.fun` from `from_ioid` to `to_ioid`.
+    ///
+    /// The device is unbound from any IOMMU table, so it cannot reach the old
+    /// owner's memory until the new owner binds it.
+    pub fn transfer_pci_device(
+        &mut self,
+        from_ioid: IOid,
+        to_ioid: IOid,
+        bus: u8,
+        dev: u8,
+        fun: u8,
+    )
+        requires
+            old(self).wf(),
+            0 <= from_ioid < IOID_MAX,
+            0 <= to_ioid < IOID_MAX,
+            0 <= bus < 256 && 0 <= dev < 32 && 0 <= fun < 8,
+        ensures
+            self.wf(),
+            self.kernel_entries =~= old(self).kernel_entries,
+            self.kernel_entries_ghost =~= old(self).kernel_entries_ghost,
+            self.free_pcids =~= old(self).free_pcids,
+            self.pcid_to_proc_ptr =~= old(self).pcid_to_proc_ptr,
+            self.page_tables =~= old(self).page_tables,
+            self.page_table_pages =~= old(self).page_table_pages,
+            self.free_ioids =~= old(self).free_ioids,
+            self.ioid_to_proc_ptr =~= old(self).ioid_to_proc_ptr,
+            self.iommu_tables =~= old(self).iommu_tables,
+            self.iommu_table_pages =~= old(self).iommu_table_pages,
+            self.pci_bitmap@ =~= old(self).pci_bitmap@.insert((from_ioid, bus, dev, fun), false).insert(
+                (to_ioid, bus, dev, fun),
+                true,
+            ),
+            self.root_table.resolve(bus, dev, fun).is_None(),
+            forall|_bus: u8, _dev: u8, _fun: u8|
+                #![auto]
+                0 <= _bus < 256 && 0 <= _dev < 32 && 0 <= _fun < 8 && (_bus != bus || _dev != dev
+                    || _fun != fun) ==> self.root_table.resolve(_bus, _dev, _fun)
+                    =~= old(self).root_table.resolve(_bus, _dev, _fun),
+    {
+        self.pci_bitmap.set(from_ioid, bus, dev, fun, false);
+        self.pci_bitmap.set(to_ioid, bus, dev, fun, true);
+        self.root_table.set(bus, dev, fun, None);
+        proof {
+            self.root_table_cache@ = self.root_table_cache@.update(
+                bus as int,
+                self.root_table_cache@[bus as int].update(
+                    dev as int,
+                    self.root_table_cache@[bus as int][dev as int].update(fun as int, None),
+                ),
+            );
+        }
+        assert(self.wf()) by {
+            assert(self.pagetables_wf());
+            assert(self.iommutables_wf());
+            assert(self.pagetable_iommu_table_disjoint());
+            assert(self.root_table_wf());
+            assert(self.root_table_cache_wf());
+            assert(self.kernel_entries_wf());
+        };
+    }
+
     pub open spec fn pcid_to_proc_wf(&self) -> bool {
         &&& self.pcid_to_proc_ptr.wf()
         &&& forall|pcid: Pcid|
@@ -2000,6 +2171,368 @@ impl MemoryManager {
         };
     }
 
+    pub fn pagetable_protect_4k_page(
+        &mut self,
+        target_pcid: Pcid,
+        target_va: VAddr,
+        target_entry: &MapEntry,
+    )
+        requires
+            old(self).wf(),
+            old(self).pcid_active(target_pcid),
+            va_4k_valid(target_va),
+            old(self).get_pagetable_mapping_by_pcid(target_pcid).dom().contains(target_va),
+            old(self).get_pagetable_mapping_by_pcid(target_pcid)[target_va].addr
+                == target_entry.addr,
+        ensures
+            self.wf(),
+            self.kernel_entries =~= old(self).kernel_entries,
+            self.kernel_entries_ghost =~= old(self).kernel_entries_ghost,
+            self.free_pcids =~= old(self).free_pcids,
+            self.page_table_pages =~= old(self).page_table_pages,
+            self.free_ioids =~= old(self).free_ioids,
+            self.iommu_tables =~= old(self).iommu_tables,
+            self.iommu_table_pages =~= old(self).iommu_table_pages,
+            self.root_table =~= old(self).root_table,
+            self.root_table_cache =~= old(self).root_table_cache,
+            self.pci_bitmap =~= old(self).pci_bitmap,
+            self.page_closure() =~= old(self).page_closure(),
+            forall|p: Pcid|
+                #![trigger self.pcid_active(p)]
+                self.pcid_active(p) == old(self).pcid_active(p),
+            forall|p: Pcid|
+                #![trigger self.pcid_active(p)]
+                #![trigger self.pcid_to_proc_ptr(p)]
+                self.pcid_active(p) ==> old(self).pcid_to_proc_ptr(p) == self.pcid_to_proc_ptr(p),
+            forall|p: Pcid|
+                #![trigger self.pcid_active(p)]
+                #![trigger self.get_pagetable_mapping_by_pcid(p)]
+                self.pcid_active(p) && p != target_pcid ==> old(self).get_pagetable_mapping_by_pcid(
+                    p,
+                ) == self.get_pagetable_mapping_by_pcid(p),
+            forall|i: IOid|
+                #![trigger self.ioid_active(i)]
+                self.ioid_active(i) == old(self).ioid_active(i),
+            forall|i: IOid|
+                #![trigger self.ioid_active(i)]
+                #![trigger self.ioid_to_proc_ptr(i)]
+                self.ioid_active(i) ==> old(self).ioid_to_proc_ptr(i) == self.ioid_to_proc_ptr(i),
+            forall|i: IOid|
+                #![trigger self.ioid_active(i)]
+                #![trigger self.get_iommu_table_mapping_by_ioid(i)]
+                self.ioid_active(i) ==> old(self).get_iommu_table_mapping_by_ioid(i)
+                    == self.get_iommu_table_mapping_by_ioid(i),
+            self.get_pagetable_mapping_by_pcid(target_pcid) == old(
+                self,
+            ).get_pagetable_mapping_by_pcid(target_pcid).insert(target_va, *target_entry),
+            self.get_pagetable_mapping_by_pcid(target_pcid).dom() == old(
+                self,
+            ).get_pagetable_mapping_by_pcid(target_pcid).dom(),
+    {
+        proof {
+            va_lemma();
+        }
+        let (l4i, l3i, l2i, l1i) = va2index(target_va);
+        assert(spec_index2va((l4i, l3i, l2i, l1i)) == target_va);
+        let l4_entry = self.get_pagetable_l4_entry(target_pcid, l4i).unwrap();
+        let l3_entry = self.get_pagetable_l3_entry(target_pcid, l4i, l3i, &l4_entry).unwrap();
+        let l2_entry = self.get_pagetable_l2_entry(target_pcid, l4i, l3i, l2i, &l3_entry).unwrap();
+        self.page_tables.pagetable_array_protect_4k_page_t(
+            target_pcid,
+            l4i,
+            l3i,
+            l2i,
+            l1i,
+            l2_entry.addr,
+            target_entry,
+        );
+        assert(self.wf()) by {
+            assert(self.pagetables_wf());
+            assert(self.iommutables_wf());
+            assert(self.pagetable_iommu_table_disjoint());
+            assert(self.root_table_wf());
+            assert(self.root_table_cache_wf());
+            assert(self.kernel_entries_wf());
+        };
+    }
+
+    pub fn pagetable_unmap_4k_page(&mut self, target_pcid: Pcid, target_va: VAddr)
+        requires
+            old(self).wf(),
+            old(self).pcid_active(target_pcid),
+            va_4k_valid(target_va),
+            old(self).get_pagetable_mapping_by_pcid(target_pcid).dom().contains(target_va),
+        ensures
+            self.wf(),
+            self.kernel_entries =~= old(self).kernel_entries,
+            self.kernel_entries_ghost =~= old(self).kernel_entries_ghost,
+            self.free_pcids =~= old(self).free_pcids,
+            self.page_table_pages =~= old(self).page_table_pages,
+            self.free_ioids =~= old(self).free_ioids,
+            self.iommu_tables =~= old(self).iommu_tables,
+            self.iommu_table_pages =~= old(self).iommu_table_pages,
+            self.root_table =~= old(self).root_table,
+            self.root_table_cache =~= old(self).root_table_cache,
+            self.pci_bitmap =~= old(self).pci_bitmap,
+            self.page_closure() =~= old(self).page_closure(),
+            forall|p: Pcid|
+                #![trigger self.pcid_active(p)]
+                self.pcid_active(p) == old(self).pcid_active(p),
+            forall|p: Pcid|
+                #![trigger self.pcid_active(p)]
+                #![trigger self.pcid_to_proc_ptr(p)]
+                self.pcid_active(p) ==> old(self).pcid_to_proc_ptr(p) == self.pcid_to_proc_ptr(p),
+            forall|p: Pcid|
+                #![trigger self.pcid_active(p)]
+                #![trigger self.get_pagetable_mapping_by_pcid(p)]
+                self.pcid_active(p) && p != target_pcid ==> old(self).get_pagetable_mapping_by_pcid(
+                    p,
+                ) == self.get_pagetable_mapping_by_pcid(p),
+            forall|i: IOid|
+                #![trigger self.ioid_active(i)]
+                self.ioid_active(i) == old(self).ioid_active(i),
+            forall|i: IOid|
+                #![trigger self.ioid_active(i)]
+                #![trigger self.ioid_to_proc_ptr(i)]
+                self.ioid_active(i) ==> old(self).ioid_to_proc_ptr(i) == self.ioid_to_proc_ptr(i),
+            forall|i: IOid|
+                #![trigger self.ioid_active(i)]
+                #![trigger self.get_iommu_table_mapping_by_ioid(i)]
+                self.ioid_active(i) ==> old(self).get_iommu_table_mapping_by_ioid(i)
+                    == self.get_iommu_table_mapping_by_ioid(i),
+            self.get_pagetable_mapping_by_pcid(target_pcid) == old(
+                self,
+            ).get_pagetable_mapping_by_pcid(target_pcid).remove(target_va),
+    {
+        proof {
+            va_lemma();
+        }
+        let (l4i, l3i, l2i, l1i) = va2index(target_va);
+        assert(spec_index2va((l4i, l3i, l2i, l1i)) == target_va);
+        let l4_entry = self.get_pagetable_l4_entry(target_pcid, l4i).unwrap();
+        let l3_entry = self.get_pagetable_l3_entry(target_pcid, l4i, l3i, &l4_entry).unwrap();
+        let l2_entry = self.get_pagetable_l2_entry(target_pcid, l4i, l3i, l2i, &l3_entry).unwrap();
+        self.page_tables.pagetable_array_unmap_4k_page_t(
+            target_pcid,
+            l4i,
+            l3i,
+            l2i,
+            l1i,
+            l2_entry.addr,
+        );
+        assert(self.wf()) by {
+            assert(self.pagetables_wf());
+            assert(self.iommutables_wf());
+            assert(self.pagetable_iommu_table_disjoint());
+            assert(self.root_table_wf());
+            assert(self.root_table_cache_wf());
+            assert(self.kernel_entries_wf());
+        };
+    }
+
+    pub fn iommu_table_unmap_4k_page(&mut self, target_ioid: IOid, target_va: VAddr)
+        requires
+            old(self).wf(),
+            old(self).ioid_active(target_ioid),
+            va_4k_valid(target_va),
+            old(self).get_iommu_table_mapping_by_ioid(target_ioid).dom().contains(target_va),
+        ensures
+            self.wf(),
+            self.kernel_entries =~= old(self).kernel_entries,
+            self.kernel_entries_ghost =~= old(self).kernel_entries_ghost,
+            self.free_pcids =~= old(self).free_pcids,
+            self.page_tables =~= old(self).page_tables,
+            self.page_table_pages =~= old(self).page_table_pages,
+            self.free_ioids =~= old(self).free_ioids,
+            self.iommu_table_pages =~= old(self).iommu_table_pages,
+            self.root_table =~= old(self).root_table,
+            self.root_table_cache =~= old(self).root_table_cache,
+            self.pci_bitmap =~= old(self).pci_bitmap,
+            self.page_closure() =~= old(self).page_closure(),
+            forall|p: Pcid|
+                #![trigger self.pcid_active(p)]
+                self.pcid_active(p) == old(self).pcid_active(p),
+            forall|p: Pcid|
+                #![trigger self.pcid_active(p)]
+                #![trigger self.pcid_to_proc_ptr(p)]
+                self.pcid_active(p) ==> old(self).pcid_to_proc_ptr(p) == self.pcid_to_proc_ptr(p),
+            forall|p: Pcid|
+                #![trigger self.pcid_active(p)]
+                #![trigger self.get_pagetable_mapping_by_pcid(p)]
+                self.pcid_active(p) ==> old(self).get_pagetable_mapping_by_pcid(p)
+                    == self.get_pagetable_mapping_by_pcid(p),
+            forall|i: IOid|
+                #![trigger self.ioid_active(i)]
+                self.ioid_active(i) == old(self).ioid_active(i),
+            forall|i: IOid|
+                #![trigger self.ioid_active(i)]
+                #![trigger self.ioid_to_proc_ptr(i)]
+                self.ioid_active(i) ==> old(self).ioid_to_proc_ptr(i) == self.ioid_to_proc_ptr(i),
+            forall|i: IOid|
+                #![trigger self.ioid_active(i)]
+                #![trigger self.get_iommu_table_mapping_by_ioid(i)]
+                self.ioid_active(i) && i != target_ioid ==> old(self).get_iommu_table_mapping_by_ioid(
+                    i,
+                ) == self.get_iommu_table_mapping_by_ioid(i),
+            self.get_iommu_table_mapping_by_ioid(target_ioid) == old(
+                self,
+            ).get_iommu_table_mapping_by_ioid(target_ioid).remove(target_va),
+    {
+        proof {
+            va_lemma();
+        }
+        let (l4i, l3i, l2i, l1i) = va2index(target_va);
+        assert(spec_index2va((l4i, l3i, l2i, l1i)) == target_va);
+        let l4_entry = self.get_iommu_table_l4_entry(target_ioid, l4i).unwrap();
+        let l3_entry = self.get_iommu_table_l3_entry(target_ioid, l4i, l3i, &l4_entry).unwrap();
+        let l2_entry = self.get_iommu_table_l2_entry(target_ioid, l4i, l3i, l2i, &l3_entry).unwrap();
+        self.iommu_tables.iommu_table_array_unmap_4k_page_t(
+            target_ioid,
+            l4i,
+            l3i,
+            l2i,
+            l1i,
+            l2_entry.addr,
+        );
+        assert(self.wf()) by {
+            assert(self.pagetables_wf());
+            assert(self.iommutables_wf());
+            assert(self.pagetable_iommu_table_disjoint());
+            assert(self.root_table_wf());
+            assert(self.root_table_cache_wf());
+            assert(self.kernel_entries_wf());
+        };
+    }
+
+    pub fn pagetable_remap_4k_page(
+        &mut self,
+        target_pcid: Pcid,
+        target_va: VAddr,
+        target_entry: &MapEntry,
+    )
+        requires
+            old(self).wf(),
+            old(self).pcid_active(target_pcid),
+            va_4k_valid(target_va),
+            old(self).get_pagetable_mapping_by_pcid(target_pcid).dom().contains(target_va),
+            old(self).page_closure().contains(target_entry.addr) == false,
+            page_ptr_valid(target_entry.addr),
+        ensures
+            self.wf(),
+            self.kernel_entries =~= old(self).kernel_entries,
+            self.kernel_entries_ghost =~= old(self).kernel_entries_ghost,
+            self.free_pcids =~= old(self).free_pcids,
+            self.page_table_pages =~= old(self).page_table_pages,
+            self.free_ioids =~= old(self).free_ioids,
+            self.iommu_tables =~= old(self).iommu_tables,
+            self.iommu_table_pages =~= old(self).iommu_table_pages,
+            self.root_table =~= old(self).root_table,
+            self.root_table_cache =~= old(self).root_table_cache,
+            self.pci_bitmap =~= old(self).pci_bitmap,
+            self.page_closure() =~= old(self).page_closure(),
+            forall|p: Pcid|
+                #![trigger self.pcid_active(p)]
+                self.pcid_active(p) == old(self).pcid_active(p),
+            forall|p: Pcid|
+                #![trigger self.pcid_active(p)]
+                #![trigger self.pcid_to_proc_ptr(p)]
+                self.pcid_active(p) ==> old(self).pcid_to_proc_ptr(p) == self.pcid_to_proc_ptr(p),
+            forall|p: Pcid|
+                #![trigger self.pcid_active(p)]
+                #![trigger self.get_pagetable_mapping_by_pcid(p)]
+                self.pcid_active(p) && p != target_pcid ==> old(self).get_pagetable_mapping_by_pcid(
+                    p,
+                ) == self.get_pagetable_mapping_by_pcid(p),
+            forall|i: IOid|
+                #![trigger self.ioid_active(i)]
+                self.ioid_active(i) == old(self).ioid_active(i),
+            forall|i: IOid|
+                #![trigger self.ioid_active(i)]
+                #![trigger self.ioid_to_proc_ptr(i)]
+                self.ioid_active(i) ==> old(self).ioid_to_proc_ptr(i) == self.ioid_to_proc_ptr(i),
+            forall|i: IOid|
+                #![trigger self.ioid_active(i)]
+                #![trigger self.get_iommu_table_mapping_by_ioid(i)]
+                self.ioid_active(i) ==> old(self).get_iommu_table_mapping_by_ioid(i)
+                    == self.get_iommu_table_mapping_by_ioid(i),
+            self.get_pagetable_mapping_by_pcid(target_pcid) == old(
+                self,
+            ).get_pagetable_mapping_by_pcid(target_pcid).insert(target_va, *target_entry),
+            self.get_pagetable_mapping_by_pcid(target_pcid).dom() == old(
+                self,
+            ).get_pagetable_mapping_by_pcid(target_pcid).dom(),
+    {
+        proof {
+            va_lemma();
+        }
+        let (l4i, l3i, l2i, l1i) = va2index(target_va);
+        assert(spec_index2va((l4i, l3i, l2i, l1i)) == target_va);
+        let l4_entry = self.get_pagetable_l4_entry(target_pcid, l4i).unwrap();
+        let l3_entry = self.get_pagetable_l3_entry(target_pcid, l4i, l3i, &l4_entry).unwrap();
+        let l2_entry = self.get_pagetable_l2_entry(target_pcid, l4i, l3i, l2i, &l3_entry).unwrap();
+        self.page_tables.pagetable_array_unmap_4k_page_t(
+            target_pcid,
+            l4i,
+            l3i,
+            l2i,
+            l1i,
+            l2_entry.addr,
+        );
+        self.page_tables.pagetable_array_map_4k_page_t(
+            target_pcid,
+            l4i,
+            l3i,
+            l2i,
+            l1i,
+            l2_entry.addr,
+            target_entry,
+        );
+        assert(self.wf()) by {
+            assert(self.pagetables_wf());
+            assert(self.iommutables_wf());
+            assert(self.pagetable_iommu_table_disjoint());
+            assert(self.root_table_wf());
+            assert(self.root_table_cache_wf());
+            assert(self.kernel_entries_wf());
+        };
+    }
+
+    pub fn pagetable_count_4k_mappings(&self, pcid: Pcid) -> (ret: usize)
+        requires
+            self.wf(),
+            self.pcid_active(pcid),
+        ensures
+            ret == self.get_pagetable_mapping_by_pcid(pcid).dom().len(),
+    {
+        proof {
+            self.page_tables@[pcid as int].unwrap().mapped_4k_len();
+        }
+        self.page_tables.pagetable_array_get_mapped_4k_t(pcid)
+    }
+
+    pub fn pagetable_next_4k_mapping(&self, pcid: Pcid, va: VAddr) -> (ret: Option<
+        (VAddr, MapEntry),
+    >)
+        requires
+            self.wf(),
+            self.pcid_active(pcid),
+            va_4k_valid(va),
+        ensures
+            ret.is_Some() ==> va_4k_valid(ret.unwrap().0) && va <= ret.unwrap().0
+                && self.get_pagetable_mapping_by_pcid(pcid).dom().contains(ret.unwrap().0)
+                && self.get_pagetable_mapping_by_pcid(pcid)[ret.unwrap().0] =~= ret.unwrap().1,
+            ret.is_Some() ==> forall|v: VAddr|
+                #![auto]
+                va_4k_valid(v) && va <= v < ret.unwrap().0
+                    ==> self.get_pagetable_mapping_by_pcid(pcid).dom().contains(v) == false,
+            ret.is_None() ==> forall|v: VAddr|
+                #![auto]
+                va_4k_valid(v) && va <= v
+                    ==> self.get_pagetable_mapping_by_pcid(pcid).dom().contains(v) == false,
+    {
+        self.page_tables.pagetable_array_next_4k_mapping_t(pcid, va)
+    }
+
     pub fn resolve_pagetable_mapping(&self, pcid: Pcid, va: VAddr) -> (ret: Option<PageEntry>)
         requires
             self.wf(),
@@ -2495,6 +3028,17 @@ pub fn free_page_table(&mut self, proc_ptr: ProcPtr, pcid: Pcid)
         );
         self.ioid_to_proc_ptr.set(0, Some(new_proc_ptr));
 
+        // dom0 starts out owning every PCI device and hands them out from there.
+        self.root_table.init();
+        self.pci_bitmap.init();
+        for bus in 0..256usize {
+            for dev in 0..32u8 {
+                for fun in 0..8u8 {
+                    self.pci_bitmap.set(0, bus as u8, dev, fun, true);
+                }
+            }
+        }
+
         self.pcid_to_proc_ptr.set(0, Some(new_proc_ptr));
         self.page_tables.set(
             0,
@@ -15,6 +15,8 @@ pub struct PageEntryPerm {
     pub write: bool,
     pub execute_disable: bool,
     pub user: bool,
+    pub write_through: bool,
+    pub cache_disable: bool,
 }
 
 #[derive(Clone,Debug)]
@@ -32,6 +34,8 @@ impl PageEntry {
         &&& self.perm.write == false
         &&& self.perm.execute_disable == false
         &&& self.perm.user == false
+        &&& self.perm.write_through == false
+        &&& self.perm.cache_disable == false
     }
 
     pub fn empty() -> (ret: Self)
@@ -46,6 +50,8 @@ impl PageEntry {
                 write: false,
                 execute_disable: false,
                 user: false,
+                write_through: false,
+                cache_disable: false,
             },
         }
     }
@@ -55,10 +61,18 @@ pub struct MapEntry {
     pub addr: PAddr,
     pub write: bool,
     pub execute_disable: bool,
+    pub write_through: bool,
+    pub cache_disable: bool,
 }
 
 pub open spec fn spec_page_entry_to_map_entry(p: &PageEntry) -> MapEntry {
-    MapEntry { addr: p.addr, write: p.perm.write, execute_disable: p.perm.execute_disable }
+    MapEntry {
+        addr: p.addr,
+        write: p.perm.write,
+        execute_disable: p.perm.execute_disable,
+        write_through: p.perm.write_through,
+        cache_disable: p.perm.cache_disable,
+    }
 }
 
 #[verifier(when_used_as_spec(spec_page_entry_to_map_entry))]
@@ -66,7 +80,13 @@ pub fn page_entry_to_map_entry(p: &PageEntry) -> (ret: MapEntry)
     ensures
         ret =~= spec_page_entry_to_map_entry(p),
 {
-    MapEntry { addr: p.addr, write: p.perm.write, execute_disable: p.perm.execute_disable }
+    MapEntry {
+        addr: p.addr,
+        write: p.perm.write,
+        execute_disable: p.perm.execute_disable,
+        write_through: p.perm.write_through,
+        cache_disable: p.perm.cache_disable,
+    }
 }
 
 pub open spec fn spec_map_entry_to_page_entry(m: &MapEntry, ps: bool) -> PageEntry {
@@ -78,6 +98,8 @@ pub open spec fn spec_map_entry_to_page_entry(m: &MapEntry, ps: bool) -> PageEnt
             write: m.write,
             execute_disable: m.execute_disable,
             user: true,
+            write_through: m.write_through,
+            cache_disable: m.cache_disable,
         },
     }
 }
@@ -95,10 +117,174 @@ pub fn map_entry_to_page_entry(m: &MapEntry, ps: bool) -> (ret: PageEntry)
             write: m.write,
             execute_disable: m.execute_disable,
             user: true,
+            write_through: m.write_through,
+            cache_disable: m.cache_disable,
         },
     }
 }
 
+pub open spec fn spec_page_type_to_map_entry(entry: MapEntry, page_type: PageType) -> MapEntry {
+    match page_type {
+        PageType::R => MapEntry {
+            addr: entry.addr,
+            write: false,
+            execute_disable: true,
+            write_through: entry.write_through,
+            cache_disable: entry.cache_disable,
+        },
+        PageType::RW => MapEntry {
+            addr: entry.addr,
+            write: true,
+            execute_disable: true,
+            write_through: entry.write_through,
+            cache_disable: entry.cache_disable,
+        },
+        PageType::RX => MapEntry {
+            addr: entry.addr,
+            write: false,
+            execute_disable: false,
+            write_through: entry.write_through,
+            cache_disable: entry.cache_disable,
+        },
+        PageType::RWX => MapEntry {
+            addr: entry.addr,
+            write: true,
+            execute_disable: false,
+            write_through: entry.write_through,
+            cache_disable: entry.cache_disable,
+        },
+    }
+}
+
+#[verifier(when_used_as_spec(spec_page_type_to_map_entry))]
+pub fn page_type_to_map_entry(entry: MapEntry, page_type: PageType) -> (ret: MapEntry)
+    ensures
+        ret =~= spec_page_type_to_map_entry(entry, page_type),
+{
+    match page_type {
+        PageType::R => MapEntry {
+            addr: entry.addr,
+            write: false,
+            execute_disable: true,
+            write_through: entry.write_through,
+            cache_disable: entry.cache_disable,
+        },
+        PageType::RW => MapEntry {
+            addr: entry.addr,
+            write: true,
+            execute_disable: true,
+            write_through: entry.write_through,
+            cache_disable: entry.cache_disable,
+        },
+        PageType::RX => MapEntry {
+            addr: entry.addr,
+            write: false,
+            execute_disable: false,
+            write_through: entry.write_through,
+            cache_disable: entry.cache_disable,
+        },
+        PageType::RWX => MapEntry {
+            addr: entry.addr,
+            write: true,
+            execute_disable: false,
+            write_through: entry.write_through,
+            cache_disable: entry.cache_disable,
+        },
+    }
+}
+
+/// Changing `entry` to `page_type` grants no permission it does not already have.
+pub open spec fn spec_page_type_narrows(entry: &MapEntry, page_type: PageType) -> bool {
+    let new_entry = spec_page_type_to_map_entry(*entry, page_type);
+    &&& new_entry.write ==> entry.write
+    &&& new_entry.execute_disable == false ==> entry.execute_disable == false
+}
+
+#[verifier(when_used_as_spec(spec_page_type_narrows))]
+pub fn page_type_narrows(entry: &MapEntry, page_type: PageType) -> (ret: bool)
+    ensures
+        ret == spec_page_type_narrows(entry, page_type),
+{
+    let (write, execute_disable) = match page_type {
+        PageType::R => (false, true),
+        PageType::RW => (true, true),
+        PageType::RX => (false, false),
+        PageType::RWX => (true, false),
+    };
+    (write == false || entry.write) && (execute_disable || entry.execute_disable == false)
+}
+
+pub open spec fn spec_share_map_entry(entry: MapEntry, read_only: bool) -> MapEntry {
+    if read_only {
+        MapEntry {
+            addr: entry.addr,
+            write: false,
+            execute_disable: entry.execute_disable,
+            write_through: entry.write_through,
+            cache_disable: entry.cache_disable,
+        }
+    } else {
+        entry
+    }
+}
+
+#[verifier(when_used_as_spec(spec_share_map_entry))]
+pub fn share_map_entry(entry: MapEntry, read_only: bool) -> (ret: MapEntry)
+    ensures
+        ret =~= spec_share_map_entry(entry, read_only),
+{
+    if read_only {
+        MapEntry {
+            addr: entry.addr,
+            write: false,
+            execute_disable: entry.execute_disable,
+            write_through: entry.write_through,
+            cache_disable: entry.cache_disable,
+        }
+    } else {
+        entry
+    }
+}
+
+pub open spec fn spec_map_entry_with_memory_type(
+    entry: MapEntry,
+    mem_type: MemoryType,
+) -> MapEntry {
+    match mem_type {
+        MemoryType::WriteBack => MapEntry {
+            write_through: false,
+            cache_disable: false,
+            ..entry
+        },
+        MemoryType::WriteCombining => MapEntry {
+            write_through: true,
+            cache_disable: false,
+            ..entry
+        },
+        MemoryType::Uncached => MapEntry { write_through: true, cache_disable: true, ..entry },
+    }
+}
+
+#[verifier(when_used_as_spec(spec_map_entry_with_memory_type))]
+pub fn map_entry_with_memory_type(entry: MapEntry, mem_type: MemoryType) -> (ret: MapEntry)
+    ensures
+        ret =~= spec_map_entry_with_memory_type(entry, mem_type),
+{
+    match mem_type {
+        MemoryType::WriteBack => MapEntry {
+            write_through: false,
+            cache_disable: false,
+            ..entry
+        },
+        MemoryType::WriteCombining => MapEntry {
+            write_through: true,
+            cache_disable: false,
+            ..entry
+        },
+        MemoryType::Uncached => MapEntry { write_through: true, cache_disable: true, ..entry },
+    }
+}
+
 pub open spec fn usize2present(v: usize) -> bool {
     (v & PAGE_ENTRY_PRESENT_MASK as usize) != 0
 }
@@ -119,6 +305,14 @@ pub open spec fn usize2user(v: usize) -> bool {
     (v & PAGE_ENTRY_USER_MASK as usize) != 0
 }
 
+pub open spec fn usize2write_through(v: usize) -> bool {
+    (v & PAGE_ENTRY_WRITE_THROUGH_MASK as usize) != 0
+}
+
+pub open spec fn usize2cache_disable(v: usize) -> bool {
+    (v & PAGE_ENTRY_CACHE_DISABLE_MASK as usize) != 0
+}
+
 pub proof fn zero_leads_is_empty_page_entry()
     ensures
         spec_usize2page_entry(0).is_empty(),
@@ -129,6 +323,8 @@ pub proof fn zero_leads_is_empty_page_entry()
     assert(0usize & (0x1u64 << 0x1u64) as usize != 0 == false) by (bit_vector);
     assert(0usize & (0x1u64 << 63u64) as usize != 0 == false) by (bit_vector);
     assert(0usize & (0x1u64 << 0x2u64) as usize != 0 == false) by (bit_vector);
+    assert(0usize & (0x1u64 << 0x3u64) as usize != 0 == false) by (bit_vector);
+    assert(0usize & (0x1u64 << 0x4u64) as usize != 0 == false) by (bit_vector);
 }
 
 pub open spec fn spec_usize2page_entry_perm(v: usize) -> PageEntryPerm {
@@ -138,6 +334,8 @@ pub open spec fn spec_usize2page_entry_perm(v: usize) -> PageEntryPerm {
         write: usize2write(v),
         execute_disable: usize2execute_disable(v),
         user: usize2user(v),
+        write_through: usize2write_through(v),
+        cache_disable: usize2cache_disable(v),
     }
 }
 
@@ -146,19 +344,24 @@ pub fn usize2page_entry_perm(v: usize) -> (ret: PageEntryPerm)
     ensures
         ret =~= spec_usize2page_entry_perm(v),
         v == 0 ==> ret.present == false && ret.ps == false && ret.write == false
-            && ret.execute_disable == false && ret.user == false,
+            && ret.execute_disable == false && ret.user == false && ret.write_through == false
+            && ret.cache_disable == false,
 {
     assert(0usize & 0x1 as usize != 0 == false) by (bit_vector);
     assert(0usize & (0x1u64 << 0x7u64) as usize != 0 == false) by (bit_vector);
     assert(0usize & (0x1u64 << 0x1u64) as usize != 0 == false) by (bit_vector);
     assert(0usize & (0x1u64 << 63u64) as usize != 0 == false) by (bit_vector);
     assert(0usize & (0x1u64 << 0x2u64) as usize != 0 == false) by (bit_vector);
+    assert(0usize & (0x1u64 << 0x3u64) as usize != 0 == false) by (bit_vector);
+    assert(0usize & (0x1u64 << 0x4u64) as usize != 0 == false) by (bit_vector);
     PageEntryPerm {
         present: (v & PAGE_ENTRY_PRESENT_MASK as usize) != 0,
         ps: (v & PAGE_ENTRY_PS_MASK as usize) != 0,
         write: (v & PAGE_ENTRY_WRITE_MASK as usize) != 0,
         execute_disable: (v & PAGE_ENTRY_EXECUTE_MASK as usize) != 0,
         user: (v & PAGE_ENTRY_USER_MASK as usize) != 0,
+        write_through: (v & PAGE_ENTRY_WRITE_THROUGH_MASK as usize) != 0,
+        cache_disable: (v & PAGE_ENTRY_CACHE_DISABLE_MASK as usize) != 0,
     }
 }
 
@@ -172,7 +375,7 @@ pub fn usize2page_entry(v: usize) -> (ret: PageEntry)
         ret =~= spec_usize2page_entry(v),
         v == 0 ==> ret.addr == 0 && ret.perm.present == false && ret.perm.ps == false
             && ret.perm.write == false && ret.perm.execute_disable == false && ret.perm.user
-            == false,
+            == false && ret.perm.write_through == false && ret.perm.cache_disable == false,
 {
     assert(0usize & 0x0000_ffff_ffff_f000u64 as usize == 0) by (bit_vector);
     PageEntry { addr: usize2pa(v), perm: usize2page_entry_perm(v) }
@@ -203,6 +406,8 @@ pub fn page_entry2usize(page_entry: &PageEntry) -> (ret: usize)
         usize2write(ret) == page_entry.perm.write,
         usize2execute_disable(ret) == page_entry.perm.execute_disable,
         usize2user(ret) == page_entry.perm.user,
+        usize2write_through(ret) == page_entry.perm.write_through,
+        usize2cache_disable(ret) == page_entry.perm.cache_disable,
         usize2pa(ret) == page_entry.addr,
         usize2page_entry_perm(ret) =~= page_entry.perm,
 {
@@ -215,6 +420,7 @@ pub fn page_entry2usize(page_entry: &PageEntry) -> (ret: usize)
     let ghost_write = Ghost(page_entry.perm.write);
     let ghost_execute_disable = Ghost(page_entry.perm.execute_disable);
     let ghost_user = Ghost(page_entry.perm.user);
+    let ghost_write_through = Ghost(page_entry.perm.write_through);
     assert(ret == ghost_addr@);
 
     if page_entry.perm.present == true {
@@ -547,6 +753,208 @@ pub fn page_entry2usize(page_entry: &PageEntry) -> (ret: usize)
     assert(usize2execute_disable(ret) == page_entry.perm.execute_disable);
     assert(usize2user(ret) == page_entry.perm.user);
     assert(usize2pa(ret) == page_entry.addr);
+    assert(ret & (!(PAGE_ENTRY_PRESENT_MASK | PAGE_ENTRY_PS_MASK | PAGE_ENTRY_WRITE_MASK
+        | PAGE_ENTRY_EXECUTE_MASK | PAGE_ENTRY_USER_MASK | MEM_MASK)) as usize == 0);
+
+    ghost_ret = Ghost(ret);
+
+    if page_entry.perm.write_through == true {
+        assert(((ret | (0x1u64 << 3u64) as usize) & (0x1u64 << 3u64) as usize) != 0)
+            by (bit_vector);
+        assert(((ret | (0x1u64 << 3u64) as usize) & 0x1 as usize) != 0 == ghost_present@)
+            by (bit_vector)
+            requires
+                ghost_ret@ == ret && (ret & 0x1u64 as usize) != 0 == ghost_present@,
+        ;
+        assert(((ret | (0x1u64 << 3u64) as usize) & (0x1u64 << 0x7u64) as usize) != 0 == ghost_ps@)
+            by (bit_vector)
+            requires
+                ghost_ret@ == ret && (ret & (0x1u64 << 0x7u64) as usize) != 0 == ghost_ps@,
+        ;
+        assert(((ret | (0x1u64 << 3u64) as usize) & (0x1u64 << 0x1u64) as usize) != 0
+            == ghost_write@) by (bit_vector)
+            requires
+                ghost_ret@ == ret && (ret & (0x1u64 << 0x1u64) as usize) != 0 == ghost_write@,
+        ;
+        assert(((ret | (0x1u64 << 3u64) as usize) & (0x1u64 << 63u64) as usize) != 0
+            == ghost_execute_disable@) by (bit_vector)
+            requires
+                ghost_ret@ == ret && (ret & (0x1u64 << 63u64) as usize) != 0
+                    == ghost_execute_disable@,
+        ;
+        assert(((ret | (0x1u64 << 3u64) as usize) & (0x1u64 << 2u64) as usize) != 0 == ghost_user@)
+            by (bit_vector)
+            requires
+                ghost_ret@ == ret && (ret & (0x1u64 << 2u64) as usize) != 0 == ghost_user@,
+        ;
+        assert(((ret | (0x1u64 << 3u64) as usize) & 0x0000_ffff_ffff_f000u64 as usize)
+            == ghost_addr@) by (bit_vector)
+            requires
+                ghost_ret@ == ret && (ghost_ret@ & 0x0000_ffff_ffff_f000u64 as usize)
+                    == ghost_addr@,
+        ;
+        ret = ret | (0x1u64 << 3u64) as usize;
+
+        assert(usize2present(ret) == page_entry.perm.present);
+        assert(usize2ps(ret) == page_entry.perm.ps);
+        assert(usize2write(ret) == page_entry.perm.write);
+        assert(usize2execute_disable(ret) == page_entry.perm.execute_disable);
+        assert(usize2user(ret) == page_entry.perm.user);
+        assert((ret & (0x1u64 << 3u64) as usize) != 0);
+        assert(usize2write_through(ret) == page_entry.perm.write_through);
+        assert(usize2pa(ret) == page_entry.addr);
+
+        assert((ghost_ret@ | (0x1u64 << 3u64) as usize) & (!(0x1u64 | 0x1u64 << 0x7u64
+            | 0x1u64 << 0x1u64 | 0x1u64 << 63u64 | 0x1u64 << 2u64 | 0x1u64 << 3u64
+                | 0x0000_ffff_ffff_f000u64)) as usize == 0)
+            by (bit_vector)
+            requires
+                ghost_ret@ & (!(0x1u64 | 0x1u64 << 0x7u64 | 0x1u64 << 0x1u64 | 0x1u64 << 63u64
+                    | 0x1u64 << 2u64 | 0x0000_ffff_ffff_f000u64)) as usize == 0,
+        ;
+        assert(ret & (!(PAGE_ENTRY_PRESENT_MASK | PAGE_ENTRY_PS_MASK | PAGE_ENTRY_WRITE_MASK
+            | PAGE_ENTRY_EXECUTE_MASK | PAGE_ENTRY_USER_MASK | PAGE_ENTRY_WRITE_THROUGH_MASK
+                | MEM_MASK)) as usize == 0);
+    } else {
+        assert((ret & (0x1u64 << 3u64) as usize) == 0) by (bit_vector)
+            requires
+                ret & (!(0x1u64 | 0x1u64 << 0x7u64 | 0x1u64 << 0x1u64 | 0x1u64 << 63u64
+                    | 0x1u64 << 2u64 | 0x0000_ffff_ffff_f000u64)) as usize == 0,
+        ;
+
+        assert(usize2present(ret) == page_entry.perm.present);
+        assert(usize2ps(ret) == page_entry.perm.ps);
+        assert(usize2write(ret) == page_entry.perm.write);
+        assert(usize2execute_disable(ret) == page_entry.perm.execute_disable);
+        assert(usize2user(ret) == page_entry.perm.user);
+        assert(usize2write_through(ret) == page_entry.perm.write_through);
+        assert(usize2pa(ret) == page_entry.addr);
+
+        assert(ret & (!(0x1u64 | 0x1u64 << 0x7u64 | 0x1u64 << 0x1u64 | 0x1u64 << 63u64
+            | 0x1u64 << 2u64 | 0x1u64 << 3u64 | 0x0000_ffff_ffff_f000u64)) as usize == 0)
+            by (bit_vector)
+            requires
+                ret & (!(0x1u64 | 0x1u64 << 0x7u64 | 0x1u64 << 0x1u64 | 0x1u64 << 63u64
+                    | 0x1u64 << 2u64 | 0x0000_ffff_ffff_f000u64)) as usize == 0,
+        ;
+        assert(ret & (!(PAGE_ENTRY_PRESENT_MASK | PAGE_ENTRY_PS_MASK | PAGE_ENTRY_WRITE_MASK
+            | PAGE_ENTRY_EXECUTE_MASK | PAGE_ENTRY_USER_MASK | PAGE_ENTRY_WRITE_THROUGH_MASK
+                | MEM_MASK)) as usize == 0);
+    }
+
+    assert(usize2present(ret) == page_entry.perm.present);
+    assert(usize2ps(ret) == page_entry.perm.ps);
+    assert(usize2write(ret) == page_entry.perm.write);
+    assert(usize2execute_disable(ret) == page_entry.perm.execute_disable);
+    assert(usize2user(ret) == page_entry.perm.user);
+    assert(usize2write_through(ret) == page_entry.perm.write_through);
+    assert(usize2pa(ret) == page_entry.addr);
+    assert(ret & (!(PAGE_ENTRY_PRESENT_MASK | PAGE_ENTRY_PS_MASK | PAGE_ENTRY_WRITE_MASK
+        | PAGE_ENTRY_EXECUTE_MASK | PAGE_ENTRY_USER_MASK | PAGE_ENTRY_WRITE_THROUGH_MASK
+            | MEM_MASK)) as usize == 0);
+
+    ghost_ret = Ghost(ret);
+
+    if page_entry.perm.cache_disable == true {
+        assert(((ret | (0x1u64 << 4u64) as usize) & (0x1u64 << 4u64) as usize) != 0)
+            by (bit_vector);
+        assert(((ret | (0x1u64 << 4u64) as usize) & 0x1 as usize) != 0 == ghost_present@)
+            by (bit_vector)
+            requires
+                ghost_ret@ == ret && (ret & 0x1u64 as usize) != 0 == ghost_present@,
+        ;
+        assert(((ret | (0x1u64 << 4u64) as usize) & (0x1u64 << 0x7u64) as usize) != 0 == ghost_ps@)
+            by (bit_vector)
+            requires
+                ghost_ret@ == ret && (ret & (0x1u64 << 0x7u64) as usize) != 0 == ghost_ps@,
+        ;
+        assert(((ret | (0x1u64 << 4u64) as usize) & (0x1u64 << 0x1u64) as usize) != 0
+            == ghost_write@) by (bit_vector)
+            requires
+                ghost_ret@ == ret && (ret & (0x1u64 << 0x1u64) as usize) != 0 == ghost_write@,
+        ;
+        assert(((ret | (0x1u64 << 4u64) as usize) & (0x1u64 << 63u64) as usize) != 0
+            == ghost_execute_disable@) by (bit_vector)
+            requires
+                ghost_ret@ == ret && (ret & (0x1u64 << 63u64) as usize) != 0
+                    == ghost_execute_disable@,
+        ;
+        assert(((ret | (0x1u64 << 4u64) as usize) & (0x1u64 << 2u64) as usize) != 0 == ghost_user@)
+            by (bit_vector)
+            requires
+                ghost_ret@ == ret && (ret & (0x1u64 << 2u64) as usize) != 0 == ghost_user@,
+        ;
+        assert(((ret | (0x1u64 << 4u64) as usize) & (0x1u64 << 3u64) as usize) != 0
+            == ghost_write_through@) by (bit_vector)
+            requires
+                ghost_ret@ == ret && (ret & (0x1u64 << 3u64) as usize) != 0 == ghost_write_through@,
+        ;
+        assert(((ret | (0x1u64 << 4u64) as usize) & 0x0000_ffff_ffff_f000u64 as usize)
+            == ghost_addr@) by (bit_vector)
+            requires
+                ghost_ret@ == ret && (ghost_ret@ & 0x0000_ffff_ffff_f000u64 as usize)
+                    == ghost_addr@,
+        ;
+        ret = ret | (0x1u64 << 4u64) as usize;
+
+        assert(usize2present(ret) == page_entry.perm.present);
+        assert(usize2ps(ret) == page_entry.perm.ps);
+        assert(usize2write(ret) == page_entry.perm.write);
+        assert(usize2execute_disable(ret) == page_entry.perm.execute_disable);
+        assert(usize2user(ret) == page_entry.perm.user);
+        assert(usize2write_through(ret) == page_entry.perm.write_through);
+        assert((ret & (0x1u64 << 4u64) as usize) != 0);
+        assert(usize2cache_disable(ret) == page_entry.perm.cache_disable);
+        assert(usize2pa(ret) == page_entry.addr);
+
+        assert((ghost_ret@ | (0x1u64 << 4u64) as usize) & (!(0x1u64 | 0x1u64 << 0x7u64
+            | 0x1u64 << 0x1u64 | 0x1u64 << 63u64 | 0x1u64 << 2u64 | 0x1u64 << 3u64
+                | 0x1u64 << 4u64 | 0x0000_ffff_ffff_f000u64)) as usize == 0)
+            by (bit_vector)
+            requires
+                ghost_ret@ & (!(0x1u64 | 0x1u64 << 0x7u64 | 0x1u64 << 0x1u64 | 0x1u64 << 63u64
+                    | 0x1u64 << 2u64 | 0x1u64 << 3u64 | 0x0000_ffff_ffff_f000u64)) as usize == 0,
+        ;
+        assert(ret & (!(PAGE_ENTRY_PRESENT_MASK | PAGE_ENTRY_PS_MASK | PAGE_ENTRY_WRITE_MASK
+            | PAGE_ENTRY_EXECUTE_MASK | PAGE_ENTRY_USER_MASK | PAGE_ENTRY_WRITE_THROUGH_MASK
+            | PAGE_ENTRY_CACHE_DISABLE_MASK | MEM_MASK)) as usize == 0);
+    } else {
+        assert((ret & (0x1u64 << 4u64) as usize) == 0) by (bit_vector)
+            requires
+                ret & (!(0x1u64 | 0x1u64 << 0x7u64 | 0x1u64 << 0x1u64 | 0x1u64 << 63u64
+                    | 0x1u64 << 2u64 | 0x1u64 << 3u64 | 0x0000_ffff_ffff_f000u64)) as usize == 0,
+        ;
+
+        assert(usize2present(ret) == page_entry.perm.present);
+        assert(usize2ps(ret) == page_entry.perm.ps);
+        assert(usize2write(ret) == page_entry.perm.write);
+        assert(usize2execute_disable(ret) == page_entry.perm.execute_disable);
+        assert(usize2user(ret) == page_entry.perm.user);
+        assert(usize2write_through(ret) == page_entry.perm.write_through);
+        assert(usize2cache_disable(ret) == page_entry.perm.cache_disable);
+        assert(usize2pa(ret) == page_entry.addr);
+
+        assert(ret & (!(0x1u64 | 0x1u64 << 0x7u64 | 0x1u64 << 0x1u64 | 0x1u64 << 63u64
+            | 0x1u64 << 2u64 | 0x1u64 << 3u64 | 0x1u64 << 4u64
+                | 0x0000_ffff_ffff_f000u64)) as usize == 0)
+            by (bit_vector)
+            requires
+                ret & (!(0x1u64 | 0x1u64 << 0x7u64 | 0x1u64 << 0x1u64 | 0x1u64 << 63u64
+                    | 0x1u64 << 2u64 | 0x1u64 << 3u64 | 0x0000_ffff_ffff_f000u64)) as usize == 0,
+        ;
+        assert(ret & (!(PAGE_ENTRY_PRESENT_MASK | PAGE_ENTRY_PS_MASK | PAGE_ENTRY_WRITE_MASK
+            | PAGE_ENTRY_EXECUTE_MASK | PAGE_ENTRY_USER_MASK | PAGE_ENTRY_WRITE_THROUGH_MASK
+            | PAGE_ENTRY_CACHE_DISABLE_MASK | MEM_MASK)) as usize == 0);
+    }
+
+    assert(usize2present(ret) == page_entry.perm.present);
+    assert(usize2ps(ret) == page_entry.perm.ps);
+    assert(usize2write(ret) == page_entry.perm.write);
+    assert(usize2execute_disable(ret) == page_entry.perm.execute_disable);
+    assert(usize2user(ret) == page_entry.perm.user);
+    assert(usize2write_through(ret) == page_entry.perm.write_through);
+    assert(usize2cache_disable(ret) == page_entry.perm.cache_disable);
+    assert(usize2pa(ret) == page_entry.addr);
 
     return ret;
 }
@@ -9,6 +9,7 @@ use crate::pagetable::entry::*;
 use crate::define::*;
 use vstd::simple_pptr::*;
 use crate::lemma::lemma_u::*;
+use crate::lemma::lemma_t::va_4k_set_len_lemma;
 
 // exec
 impl PageTable {
@@ -301,6 +302,8 @@ impl PageTable {
                     write: true,
                     execute_disable: false,
                     user: true,
+                    write_through: false,
+                    cache_disable: false,
                 },
             },
         );
@@ -437,6 +440,8 @@ impl PageTable {
                     write: true,
                     execute_disable: false,
                     user: true,
+                    write_through: false,
+                    cache_disable: false,
                 },
             },
         );
@@ -583,6 +588,8 @@ impl PageTable {
                     write: true,
                     execute_disable: false,
                     user: true,
+                    write_through: false,
+                    cache_disable: false,
                 },
             },
         );
@@ -710,6 +717,11 @@ impl PageTable {
         ) == false) by {
             broadcast use PageTable::reveal_page_table_mappings_wf;
         };
+        assert(self.mapped_4k < usize::MAX) by {
+            broadcast use PageTable::reveal_page_table_mappings_wf;
+            va_4k_set_len_lemma(self.mapping_4k@.dom());
+        };
+        self.mapped_4k = self.mapped_4k + 1;
         let tracked mut l1_perm = self.l1_tables.borrow_mut().tracked_remove(target_l1_p);
         proof {
             page_ptr_valid_imply_MEM_valid(target_entry.addr);
@@ -726,6 +738,8 @@ impl PageTable {
                     write: target_entry.write,
                     execute_disable: target_entry.execute_disable,
                     user: true,
+                    write_through: false,
+                    cache_disable: false,
                 },
             },
         );
@@ -910,6 +924,11 @@ impl PageTable {
             va_lemma();
         };
         assert(self.mapping_4k@.dom().contains(va@)) by { broadcast use PageTable::reveal_page_table_mappings_wf; };
+        assert(self.mapped_4k > 0) by {
+            broadcast use PageTable::reveal_page_table_mappings_wf;
+            vstd::set_lib::lemma_set_empty_equivalency_len(self.mapping_4k@.dom());
+        };
+        self.mapped_4k = self.mapped_4k - 1;
         let tracked mut l1_perm = self.l1_tables.borrow_mut().tracked_remove(target_l1_p);
         page_map_set(target_l1_p, Tracked(&mut l1_perm), target_l1i, PageEntry::empty());
 
@@ -1044,6 +1063,184 @@ impl PageTable {
         assert(self.mapping_1g() =~= old(self).mapping_1g());
     }
 
+    /// Precondition:
+    /// All previous levels exist and the target VA -> PA mapping exists in the pagetable.
+    /// The new entry must point to the same physical page.
+    ///
+    /// Postcondition:
+    /// Only the permission bits of the target entry change. The stale entry is flushed
+    /// from every TLB so no core keeps using the old permissions.
+    pub fn protect_4k_page(
+        &mut self,
+        target_l4i: L4Index,
+        target_l3i: L3Index,
+        target_l2i: L2Index,
+        target_l1i: L2Index,
+        target_l1_p: PageMapPtr,
+        target_entry: &MapEntry,
+    )
+        requires
+            old(self).wf(),
+            old(self).kernel_l4_end <= target_l4i < 512,
+            0 <= target_l3i < 512,
+            0 <= target_l2i < 512,
+            0 <= target_l1i < 512,
+            old(self).spec_resolve_mapping_l2(target_l4i, target_l3i, target_l2i).is_Some(),
+            old(self).spec_resolve_mapping_l2(target_l4i, target_l3i, target_l2i).get_Some_0().addr
+                == target_l1_p,
+            old(self).mapping_4k().dom().contains(
+                spec_index2va((target_l4i, target_l3i, target_l2i, target_l1i)),
+            ),
+            old(self).mapping_4k()[spec_index2va(
+                (target_l4i, target_l3i, target_l2i, target_l1i),
+            )].addr == target_entry.addr,
+        ensures
+            self.wf(),
+            self.kernel_l4_end == old(self).kernel_l4_end,
+            self.page_closure() =~= old(self).page_closure(),
+            self.mapping_4k@ == old(self).mapping_4k@.insert(
+                spec_index2va((target_l4i, target_l3i, target_l2i, target_l1i)),
+                *target_entry,
+            ),
+            self.mapping_2m() =~= old(self).mapping_2m(),
+            self.mapping_1g() =~= old(self).mapping_1g(),
+            self.kernel_entries =~= old(self).kernel_entries,
+    {
+        broadcast use PageTable::reveal_page_table_wf;
+        broadcast use PageTable::reveal_page_table_levels_wf;
+
+        let va = Ghost(spec_index2va((target_l4i, target_l3i, target_l2i, target_l1i)));
+        assert(va_4k_valid(va@)) by {
+            va_lemma();
+        };
+        assert(old(self).spec_resolve_mapping_4k_l1(
+            target_l4i,
+            target_l3i,
+            target_l2i,
+            target_l1i,
+        ).is_Some()) by {
+            broadcast use PageTable::reveal_page_table_mappings_wf;
+        };
+        assert(page_ptr_valid(target_entry.addr)) by {
+            broadcast use PageTable::reveal_page_table_mappings_wf;
+        };
+        let tracked mut l1_perm = self.l1_tables.borrow_mut().tracked_remove(target_l1_p);
+        proof {
+            page_ptr_valid_imply_MEM_valid(target_entry.addr);
+        }
+        page_map_set(
+            target_l1_p,
+            Tracked(&mut l1_perm),
+            target_l1i,
+            PageEntry {
+                addr: target_entry.addr,
+                perm: PageEntryPerm {
+                    present: true,
+                    ps: false,
+                    write: target_entry.write,
+                    execute_disable: target_entry.execute_disable,
+                    user: true,
+                    write_through: false,
+                    cache_disable: false,
+                },
+            },
+        );
+        proof {
+            self.l1_tables.borrow_mut().tracked_insert(target_l1_p, l1_perm);
+            self.mapping_4k@ = self.mapping_4k@.insert(va@, *target_entry);
+            assert(self.mapping_4k@.dom() =~= old(self).mapping_4k@.dom());
+        }
+
+        // the old permissions may still be cached, flush the entry on all cores.
+        assert(self.tlb_mapping_4k@.len() == NUM_CPUS) by { broadcast use PageTable::reveal_page_table_addtional_wf; };
+        self.tlb_mapping_4k = flush_tlb_4kentry(self.tlb_mapping_4k, va);
+
+        assert(self.tlb_submap_of_mapping()) by {
+            broadcast use PageTable::reveal_page_table_addtional_wf;
+            assert(forall|cpu_id: CpuId|
+                #![auto]
+                0 <= cpu_id < NUM_CPUS ==> self.tlb_mapping_4k@[cpu_id as int].submap_of(
+                    old(self).tlb_mapping_4k@[cpu_id as int],
+                ) && old(self).tlb_mapping_4k@[cpu_id as int].submap_of(old(self).mapping_4k@)
+                    && !self.tlb_mapping_4k@[cpu_id as int].contains_key(va@));
+            broadcast use submap_by_transitivity;
+
+            assert(forall|cpu_id: CpuId|
+                #![auto]
+                0 <= cpu_id < NUM_CPUS ==> self.tlb_mapping_4k@[cpu_id as int].submap_of(
+                    old(self).mapping_4k@.remove(va@),
+                ));
+            assert(old(self).mapping_4k@.remove(va@).submap_of(self.mapping_4k@));
+        };
+
+        assert(self.wf_l4());
+        assert(self.wf_l3());
+        assert(self.wf_l2());
+        assert(self.wf_l1());
+        assert(self.disjoint_l4()) by { broadcast use PageTable::reveal_page_table_disjoint_wf; };
+        assert(self.disjoint_l3()) by { broadcast use PageTable::reveal_page_table_disjoint_wf; };
+        assert(self.disjoint_l2()) by { broadcast use PageTable::reveal_page_table_disjoint_wf; };
+        assert(self.disjoint_wf()) by { broadcast use PageTable::reveal_page_table_disjoint_wf; };
+        assert(self.wf_mapping_4k()) by {
+            broadcast use PageTable::reveal_page_table_mappings_wf;
+            va_lemma();
+            assert(forall|l4i: L4Index, l3i: L3Index, l2i: L2Index|
+                #![trigger self.spec_resolve_mapping_l2(l4i,l3i,l2i)]
+                #![trigger old(self).spec_resolve_mapping_l2(l4i,l3i,l2i)]
+                self.kernel_l4_end <= l4i < 512 && 0 <= l3i < 512 && 0 <= l2i < 512 ==> self.spec_resolve_mapping_l2(l4i, l3i, l2i) =~= old(
+                    self,
+                ).spec_resolve_mapping_l2(l4i, l3i, l2i));
+
+            assert(forall|l4i: L4Index, l3i: L3Index, l2i: L2Index|
+                #![trigger self.spec_resolve_mapping_l2(l4i,l3i,l2i)]
+                self.kernel_l4_end <= l4i < 512 && 0 <= l3i < 512 && 0 <= l2i < 512
+                    && self.spec_resolve_mapping_l2(l4i, l3i, l2i).is_Some() && !((
+                    target_l4i,
+                    target_l3i,
+                    target_l2i,
+                ) =~= (l4i, l3i, l2i)) ==> self.spec_resolve_mapping_l2(
+                    l4i,
+                    l3i,
+                    l2i,
+                ).get_Some_0().addr != target_l1_p) by {
+                old(self).internal_resolve_disjoint();
+            };
+
+            assert(forall|l4i: L4Index, l3i: L3Index, l2i: L2Index, l1i: L2Index|
+                #![trigger self.spec_resolve_mapping_4k_l1(l4i,l3i,l2i,l1i)]
+                #![trigger old(self).spec_resolve_mapping_4k_l1(l4i,l3i,l2i,l1i)]
+                self.kernel_l4_end <= l4i < 512 && 0 <= l3i < 512 && 0 <= l2i < 512 && 0 <= l1i
+                    < 512 ==> self.spec_resolve_mapping_4k_l1(l4i, l3i, l2i, l1i).is_Some() == old(
+                    self,
+                ).spec_resolve_mapping_4k_l1(l4i, l3i, l2i, l1i).is_Some());
+        };
+        assert(self.wf_mapping_2m()) by {
+            broadcast use PageTable::reveal_page_table_mappings_wf;
+            assert(forall|l4i: L4Index, l3i: L3Index, l2i: L2Index|
+                #![trigger self.spec_resolve_mapping_2m_l2(l4i,l3i,l2i)]
+                #![trigger old(self).spec_resolve_mapping_2m_l2(l4i,l3i,l2i)]
+                self.kernel_l4_end <= l4i < 512 && 0 <= l3i < 512 && 0 <= l2i < 512 ==> old(
+                    self,
+                ).spec_resolve_mapping_2m_l2(l4i, l3i, l2i) == self.spec_resolve_mapping_2m_l2(
+                    l4i,
+                    l3i,
+                    l2i,
+                ));
+        };
+        assert(self.wf_mapping_1g()) by {
+            broadcast use PageTable::reveal_page_table_mappings_wf;
+            assert(forall|l4i: L4Index, l3i: L3Index|
+                #![trigger self.spec_resolve_mapping_1g_l3(l4i,l3i)]
+                #![trigger old(self).spec_resolve_mapping_1g_l3(l4i,l3i)]
+                self.kernel_l4_end <= l4i < 512 && 0 <= l3i < 512 ==> old(self).spec_resolve_mapping_1g_l3(l4i, l3i)
+                    =~= self.spec_resolve_mapping_1g_l3(l4i, l3i));
+        };
+        assert(self.mappings_wf()) by { broadcast use PageTable::reveal_page_table_mappings_wf; };
+        assert(self.additonal_wf()) by {broadcast use PageTable::reveal_page_table_addtional_wf;}
+        assert(self.mapping_2m() =~= old(self).mapping_2m());
+        assert(self.mapping_1g() =~= old(self).mapping_1g());
+    }
+
     pub fn map_2m_page(
         &mut self,
         target_l4i: L4Index,
@@ -1115,6 +1312,8 @@ impl PageTable {
                     write: target_entry.write,
                     execute_disable: target_entry.execute_disable,
                     user: true,
+                    write_through: false,
+                    cache_disable: false,
                 },
             },
         );
@@ -1287,6 +1486,8 @@ impl PageTable {
                     write: false,
                     execute_disable: false,
                     user: false,
+                    write_through: false,
+                    cache_disable: false,
                 },
             },
         );
@@ -1486,6 +1687,8 @@ impl PageTable {
                     write: false,
                     execute_disable: false,
                     user: false,
+                    write_through: false,
+                    cache_disable: false,
                 },
             },
         );
@@ -1690,6 +1893,8 @@ impl PageTable {
                     write: false,
                     execute_disable: false,
                     user: false,
+                    write_through: false,
+                    cache_disable: false,
                 },
             },
         );
@@ -40,6 +40,8 @@ pub struct PageTable {
     pub l1_rev_map: Ghost<Map<PageMapPtr, (L4Index, L3Index, L2Index)>>,
     pub l1_tables: Tracked<Map<PageMapPtr, PointsTo<PageMap>>>,
     pub mapping_4k: Ghost<Map<VAddr, MapEntry>>,
+    /// Number of 4k mappings, kept in sync with `mapping_4k`.
+    pub mapped_4k: usize,
     pub mapping_2m: Ghost<Map<VAddr, MapEntry>>,
     pub mapping_1g: Ghost<Map<VAddr, MapEntry>>,
     pub kernel_entries: Ghost<Seq<PageEntry>>,
@@ -105,6 +107,7 @@ impl PageTable {
             l1_rev_map: Ghost(Map::<PageMapPtr, (L4Index, L3Index, L2Index)>::empty()),
             l1_tables: Tracked(Map::<PageMapPtr, PointsTo<PageMap>>::tracked_empty()),
             mapping_4k: Ghost(Map::<VAddr, MapEntry>::empty()),
+            mapped_4k: 0,
             mapping_2m: Ghost(Map::<VAddr, MapEntry>::empty()),
             mapping_1g: Ghost(Map::<VAddr, MapEntry>::empty()),
             kernel_entries: kernel_entries_ghost,
@@ -135,6 +138,7 @@ impl PageTable {
         assert(ret.wf_l3());
         assert(ret.wf_l2());
         assert(ret.wf_l1());
+        assert(ret.mapping_4k@.dom() =~= Set::<VAddr>::empty());
         assert(ret.wf_mapping_4k());
         assert(ret.wf_mapping_2m());
         assert(ret.wf_mapping_1g());
@@ -751,6 +755,8 @@ impl PageTable {
     }
 
     pub open   spec fn wf_mapping_4k(&self) -> bool {
+        &&& self.mapping_4k@.dom().finite()
+        &&& self.mapped_4k == self.mapping_4k@.dom().len()
         &&& forall|va: VAddr|
             #![trigger va_4k_valid(va), self.mapping_4k@.dom().contains(va)]
             self.mapping_4k@.dom().contains(va) ==> va_4k_valid(va)
@@ -895,6 +901,17 @@ impl PageTable {
         &&& self.tlb_wf()
         &&& self.tlb_submap_of_mapping()
     }
+
+    pub proof fn mapped_4k_len(&self)
+        requires
+            self.wf(),
+        ensures
+            self.mapped_4k == self.mapping_4k().dom().len(),
+    {
+        broadcast use PageTable::reveal_page_table_wf;
+        broadcast use PageTable::reveal_page_table_mappings_wf;
+    }
+
     pub broadcast proof fn reveal_page_table_wf(&self)
         ensures
             #[trigger] self.wf() <==> {
@@ -638,6 +638,23 @@ pub proof fn container_tree_wf_imply_childern_have_parent(
 {
 }
 
+pub proof fn container_tree_wf_imply_childern_in_dom(
+    root_container: ContainerPtr,
+    container_perms: Map<ContainerPtr, PointsTo<Container>>,
+)
+    requires
+        container_perms_wf(container_perms),
+        container_tree_wf(root_container, container_perms),
+    ensures
+        forall|c_ptr: ContainerPtr, child_c_ptr: ContainerPtr|
+        #![trigger container_perms[c_ptr].value().children@.contains(child_c_ptr)]
+        container_perms.dom().contains(c_ptr) && container_perms[c_ptr].value().children@.contains(child_c_ptr)
+            ==> container_perms.dom().contains(child_c_ptr)
+            && container_perms[child_c_ptr].value().parent.unwrap() == c_ptr
+            && child_c_ptr != c_ptr
+{
+}
+
 pub proof fn container_tree_inv(
     root_container: ContainerPtr,
     container_perms: Map<ContainerPtr, PointsTo<Container>>,
@@ -685,6 +685,11 @@ impl ProcessManager {
                 old(self).container_dom().contains(container_ptr)
                 ==> 
                 self.get_container(container_ptr).subtree_set =~= old(self).get_container(container_ptr).subtree_set,
+            forall|container_ptr: ContainerPtr|
+                #![trigger self.get_container(container_ptr)]
+                old(self).container_dom().contains(container_ptr)
+                ==> 
+                self.get_container(container_ptr).quota =~= old(self).get_container(container_ptr).quota,
             forall|t_ptr: ThreadPtr|
                 #![trigger old(self).get_thread(t_ptr)]
                 old(self).thread_dom().contains(t_ptr) && t_ptr != old(self).get_endpoint(endpoint_ptr).queue@[0] ==> old(self).get_thread(t_ptr) =~= self.get_thread(t_ptr),
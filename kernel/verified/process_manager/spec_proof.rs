@@ -164,6 +164,39 @@ impl ProcessManager {
         thread
     }
 
+    /// Whether `thread_ptr` is a thread of `proc_ptr` whose endpoint descriptor
+    /// `endpoint_index` is `endpoint_ptr`.
+    pub fn thread_holds_endpoint(
+        &self,
+        proc_ptr: ProcPtr,
+        thread_ptr: ThreadPtr,
+        endpoint_index: EndpointIdx,
+        endpoint_ptr: EndpointPtr,
+    ) -> (ret: bool)
+        requires
+            self.wf(),
+            self.proc_dom().contains(proc_ptr),
+        ensures
+            ret ==> self.thread_dom().contains(thread_ptr) && self.endpoint_dom().contains(
+                endpoint_ptr,
+            ),
+    {
+        if endpoint_index >= MAX_NUM_ENDPOINT_DESCRIPTORS {
+            return false;
+        }
+        if self.get_proc(proc_ptr).owned_threads.contains(thread_ptr) == false {
+            return false;
+        }
+        proof {
+            self.proc_thread_inv_2();
+            self.thread_inv();
+        }
+        match *self.get_thread(thread_ptr).endpoint_descriptors.get(endpoint_index) {
+            Some(held_ptr) => held_ptr == endpoint_ptr,
+            None => false,
+        }
+    }
+
     #[verifier(inline)]
     pub open spec fn spec_get_cpu(&self, cpu_id: CpuId) -> &Cpu
         recommends
@@ -1012,6 +1045,20 @@ impl ProcessManager {
     {
     }
 
+    pub proof fn proc_thread_inv_2(&self)
+        requires
+            self.wf(),
+        ensures
+            forall|p_ptr: ProcPtr, t_ptr: ThreadPtr|
+                #![auto]
+                self.proc_dom().contains(p_ptr) && self.get_proc(p_ptr).owned_threads@.contains(
+                    t_ptr,
+                ) ==> self.thread_dom().contains(t_ptr) && self.get_thread(t_ptr).owning_proc
+                    == p_ptr,
+    {
+        broadcast use ProcessManager::reveal_process_manager_wf;
+    }
+
     pub proof fn thread_inv(&self)
         requires
             self.wf(),
@@ -1108,6 +1155,21 @@ impl ProcessManager {
     {
     }
 
+    pub proof fn container_children_inv(&self)
+        requires
+            self.wf(),
+        ensures
+            forall|c_ptr: ContainerPtr, child_ptr: ContainerPtr|
+                #![trigger self.get_container(c_ptr).children@.contains(child_ptr)]
+                self.container_dom().contains(c_ptr) && self.get_container(
+                    c_ptr,
+                ).children@.contains(child_ptr) ==> self.container_dom().contains(child_ptr)
+                    && self.get_container(child_ptr).parent.unwrap() == c_ptr && child_ptr != c_ptr,
+    {
+        broadcast use ProcessManager::reveal_process_manager_wf;
+        container_tree_wf_imply_childern_in_dom(self.root_container, self.container_perms@);
+    }
+
     pub proof fn endpoint_inv(&self)
         requires
             self.wf(),
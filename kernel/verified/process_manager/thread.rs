@@ -26,7 +26,7 @@ pub struct Thread {
 #[allow(inconsistent_fields)]
 pub enum IPCPayLoad {
     Message { va: VAddr, len: usize },
-    Pages { va_range: VaRange4K },
+    Pages { va_range: VaRange4K, read_only: bool, move_pages: bool, copy_on_write: bool },
     Endpoint { endpoint_index: EndpointIdx },
     Pci { bus: u8, dev: u8, fun: u8 },
     PageFault { vaddr: VAddr },
@@ -68,7 +68,7 @@ impl IPCPayLoad {
 
     pub open spec fn spec_get_payload_as_va_range(&self) -> Option<VaRange4K> {
         match self {
-            IPCPayLoad::Pages { va_range: va_range } => Some(*va_range),
+            IPCPayLoad::Pages { va_range: va_range, .. } => Some(*va_range),
             _ => None,
         }
     }
@@ -79,11 +79,65 @@ impl IPCPayLoad {
             ret == self.spec_get_payload_as_va_range(),
     {
         match self {
-            IPCPayLoad::Pages { va_range: va_range } => Some(*va_range),
+            IPCPayLoad::Pages { va_range: va_range, .. } => Some(*va_range),
             _ => None,
         }
     }
 
+    pub open spec fn spec_get_payload_pages_read_only(&self) -> bool {
+        match self {
+            IPCPayLoad::Pages { read_only: read_only, .. } => *read_only,
+            _ => false,
+        }
+    }
+
+    #[verifier(when_used_as_spec(spec_get_payload_pages_read_only))]
+    pub fn get_payload_pages_read_only(&self) -> (ret: bool)
+        ensures
+            ret == self.spec_get_payload_pages_read_only(),
+    {
+        match self {
+            IPCPayLoad::Pages { read_only: read_only, .. } => *read_only,
+            _ => false,
+        }
+    }
+
+    pub open spec fn spec_get_payload_pages_move(&self) -> bool {
+        match self {
+            IPCPayLoad::Pages { move_pages: move_pages, .. } => *move_pages,
+            _ => false,
+        }
+    }
+
+    #[verifier(when_used_as_spec(spec_get_payload_pages_move))]
+    pub fn get_payload_pages_move(&self) -> (ret: bool)
+        ensures
+            ret == self.spec_get_payload_pages_move(),
+    {
+        match self {
+            IPCPayLoad::Pages { move_pages: move_pages, .. } => *move_pages,
+            _ => false,
+        }
+    }
+
+    pub open spec fn spec_get_payload_pages_copy_on_write(&self) -> bool {
+        match self {
+            IPCPayLoad::Pages { copy_on_write: copy_on_write, .. } => *copy_on_write,
+            _ => false,
+        }
+    }
+
+    #[verifier(when_used_as_spec(spec_get_payload_pages_copy_on_write))]
+    pub fn get_payload_pages_copy_on_write(&self) -> (ret: bool)
+        ensures
+            ret == self.spec_get_payload_pages_copy_on_write(),
+    {
+        match self {
+            IPCPayLoad::Pages { copy_on_write: copy_on_write, .. } => *copy_on_write,
+            _ => false,
+        }
+    }
+
     pub open spec fn spec_get_payload_as_endpoint(&self) -> Option<EndpointIdx> {
         match self {
             IPCPayLoad::Endpoint { endpoint_index: endpoint_index } => Some(*endpoint_index),
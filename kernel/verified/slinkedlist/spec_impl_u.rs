@@ -255,6 +255,40 @@ impl<T: Copy, const N: usize> StaticLinkedList<T, N> {
     }
 }
 
+impl<const N: usize> StaticLinkedList<usize, N> {
+    pub fn contains(&self, v: usize) -> (ret: bool)
+        requires
+            self.wf(),
+        ensures
+            ret == self@.contains(v),
+    {
+        let mut index = self.value_list_head;
+        let mut i: usize = 0;
+        while i < self.value_list_len
+            invariant
+                self.wf(),
+                0 <= i <= self.value_list_len,
+                i < self.value_list_len ==> index == self.value_list@[i as int],
+                forall|j: int| #![trigger self.spec_seq@[j]] 0 <= j < i ==> self.spec_seq@[j] != v,
+            decreases self.value_list_len - i,
+        {
+            assert(0 <= self.value_list@[i as int] < N);
+            assert(self.arr_seq@[index as int].value.get_Some_0() == self.spec_seq@[i as int]);
+            let value = self.get_value(index);
+            if value.is_some() && value.unwrap() == v {
+                assert(self@[i as int] == v);
+                return true;
+            }
+            assert(self.arr_seq@[self.value_list@[i as int] as int].next
+                == self.next_value_node_of(i as int));
+            index = self.get_next(index);
+            i = i + 1;
+        }
+        assert(forall|j: int| 0 <= j < self@.len() ==> self@[j] != v);
+        false
+    }
+}
+
 impl<T: Copy, const N: usize> StaticLinkedList<T, N> {
     pub fn init(&mut self)
         requires
@@ -133,6 +133,34 @@ use crate::lemma::lemma_t::*;
                 self.kernel_state@.get_address_space(p_ptr)[va].addr != self.kernel_state@.get_address_space(outside_p_ptr)[outside_va].addr
         }
 
+        //weaker isolation invariant, A and B may share pages as long as B can only read them
+        pub open spec fn memory_write_inv(&self) -> bool{
+            forall|a_sub_c_ptr:ContainerPtr, a_p_ptr: ProcPtr, b_p_ptr: ProcPtr, b_sub_c_ptr:ContainerPtr, a_va:VAddr, b_va:VAddr|
+            #![trigger 
+                self.kernel_state@.get_container(a_sub_c_ptr), 
+                self.kernel_state@.get_container(b_sub_c_ptr),
+                self.kernel_state@.get_address_space(a_p_ptr),
+                self.kernel_state@.get_address_space(b_p_ptr),
+                self.kernel_state@.get_address_space(a_p_ptr)[a_va],
+                self.kernel_state@.get_address_space(b_p_ptr)[b_va],
+            ]
+                self.kernel_state@.get_container(self.containers.a_c_ptr).subtree_set@.insert(self.containers.a_c_ptr).contains(a_sub_c_ptr)
+                &&
+                self.kernel_state@.get_container(self.containers.b_c_ptr).subtree_set@.insert(self.containers.b_c_ptr).contains(b_sub_c_ptr)
+                &&
+                self.kernel_state@.get_container(a_sub_c_ptr).owned_procs@.contains(a_p_ptr)
+                &&
+                self.kernel_state@.get_container(b_sub_c_ptr).owned_procs@.contains(b_p_ptr)
+                &&
+                self.kernel_state@.get_address_space(a_p_ptr).dom().contains(a_va)
+                &&
+                self.kernel_state@.get_address_space(b_p_ptr).dom().contains(b_va)
+                &&
+                self.kernel_state@.get_address_space(a_p_ptr)[a_va].addr == self.kernel_state@.get_address_space(b_p_ptr)[b_va].addr
+                ==>
+                self.kernel_state@.get_address_space(b_p_ptr)[b_va].write == false
+        }
+
         pub open spec fn endpoint_inv(&self) -> bool{
             &&&
             forall|a_sub_c_ptr:ContainerPtr, a_t_ptr: ThreadPtr, a_index:int,  b_sub_c_ptr:ContainerPtr, b_t_ptr: ThreadPtr, b_index:int|
@@ -330,6 +358,93 @@ use crate::lemma::lemma_t::*;
     }
 
     impl IsolationStateMachine{
+        pub proof fn memory_inv_implies_memory_write_inv(&self)
+            requires
+                self.memory_inv(),
+            ensures
+                self.memory_write_inv(),
+        {
+        }
+
+        // B receiving read-only mappings keeps memory_write_inv, even when
+        // the pages are mapped writable in A.
+        pub proof fn read_only_share_to_B_preserves_memory_write_inv(old: IsolationStateMachine, new: IsolationStateMachine, receiver_c_ptr: ContainerPtr, receiver_p_ptr: ProcPtr)
+            requires
+                old.memory_write_inv(),
+                old.containers =~= new.containers,
+                old.kernel_state@.get_container(old.containers.b_c_ptr).subtree_set@.insert(old.containers.b_c_ptr).contains(receiver_c_ptr),
+                old.kernel_state@.get_container(receiver_c_ptr).owned_procs@.contains(receiver_p_ptr),
+                forall|c_ptr:ContainerPtr|
+                    #![trigger new.kernel_state@.get_container(c_ptr)]
+                    old.kernel_state@.get_container(c_ptr).subtree_set =~= new.kernel_state@.get_container(c_ptr).subtree_set
+                    &&
+                    old.kernel_state@.get_container(c_ptr).owned_procs =~= new.kernel_state@.get_container(c_ptr).owned_procs,
+                forall|a_sub_c_ptr:ContainerPtr|
+                    #![trigger old.kernel_state@.get_container(a_sub_c_ptr)]
+                    old.kernel_state@.get_container(old.containers.a_c_ptr).subtree_set@.insert(old.containers.a_c_ptr).contains(a_sub_c_ptr)
+                    ==>
+                    old.kernel_state@.get_container(a_sub_c_ptr).owned_procs@.contains(receiver_p_ptr) == false,
+                forall|p_ptr:ProcPtr|
+                    #![trigger new.kernel_state@.get_address_space(p_ptr)]
+                    p_ptr != receiver_p_ptr
+                    ==>
+                    old.kernel_state@.get_address_space(p_ptr) =~= new.kernel_state@.get_address_space(p_ptr),
+                forall|va:VAddr|
+                    #![trigger new.kernel_state@.get_address_space(receiver_p_ptr)[va]]
+                    new.kernel_state@.get_address_space(receiver_p_ptr).dom().contains(va)
+                    ==>
+                    (
+                        old.kernel_state@.get_address_space(receiver_p_ptr).dom().contains(va)
+                        &&
+                        old.kernel_state@.get_address_space(receiver_p_ptr)[va] == new.kernel_state@.get_address_space(receiver_p_ptr)[va]
+                    )
+                    ||
+                    new.kernel_state@.get_address_space(receiver_p_ptr)[va].write == false,
+            ensures
+                new.memory_write_inv(),
+        {
+            assert forall|a_sub_c_ptr:ContainerPtr, a_p_ptr: ProcPtr, b_p_ptr: ProcPtr, b_sub_c_ptr:ContainerPtr, a_va:VAddr, b_va:VAddr|
+            #![trigger 
+                new.kernel_state@.get_container(a_sub_c_ptr), 
+                new.kernel_state@.get_container(b_sub_c_ptr),
+                new.kernel_state@.get_address_space(a_p_ptr),
+                new.kernel_state@.get_address_space(b_p_ptr),
+                new.kernel_state@.get_address_space(a_p_ptr)[a_va],
+                new.kernel_state@.get_address_space(b_p_ptr)[b_va],
+            ]
+                new.kernel_state@.get_container(new.containers.a_c_ptr).subtree_set@.insert(new.containers.a_c_ptr).contains(a_sub_c_ptr)
+                &&
+                new.kernel_state@.get_container(new.containers.b_c_ptr).subtree_set@.insert(new.containers.b_c_ptr).contains(b_sub_c_ptr)
+                &&
+                new.kernel_state@.get_container(a_sub_c_ptr).owned_procs@.contains(a_p_ptr)
+                &&
+                new.kernel_state@.get_container(b_sub_c_ptr).owned_procs@.contains(b_p_ptr)
+                &&
+                new.kernel_state@.get_address_space(a_p_ptr).dom().contains(a_va)
+                &&
+                new.kernel_state@.get_address_space(b_p_ptr).dom().contains(b_va)
+                &&
+                new.kernel_state@.get_address_space(a_p_ptr)[a_va].addr == new.kernel_state@.get_address_space(b_p_ptr)[b_va].addr
+                implies
+                new.kernel_state@.get_address_space(b_p_ptr)[b_va].write == false
+            by {
+                assert(old.kernel_state@.get_container(old.containers.a_c_ptr).subtree_set =~= new.kernel_state@.get_container(new.containers.a_c_ptr).subtree_set);
+                assert(old.kernel_state@.get_container(old.containers.b_c_ptr).subtree_set =~= new.kernel_state@.get_container(new.containers.b_c_ptr).subtree_set);
+                assert(old.kernel_state@.get_container(a_sub_c_ptr).owned_procs =~= new.kernel_state@.get_container(a_sub_c_ptr).owned_procs);
+                assert(old.kernel_state@.get_container(b_sub_c_ptr).owned_procs =~= new.kernel_state@.get_container(b_sub_c_ptr).owned_procs);
+                assert(a_p_ptr != receiver_p_ptr);
+                assert(old.kernel_state@.get_address_space(a_p_ptr) =~= new.kernel_state@.get_address_space(a_p_ptr));
+                if b_p_ptr != receiver_p_ptr {
+                    assert(old.kernel_state@.get_address_space(b_p_ptr) =~= new.kernel_state@.get_address_space(b_p_ptr));
+                    assert(old.kernel_state@.get_address_space(b_p_ptr)[b_va].write == false);
+                } else if new.kernel_state@.get_address_space(b_p_ptr)[b_va].write == true {
+                    assert(old.kernel_state@.get_address_space(b_p_ptr).dom().contains(b_va));
+                    assert(old.kernel_state@.get_address_space(b_p_ptr)[b_va] == new.kernel_state@.get_address_space(b_p_ptr)[b_va]);
+                    assert(old.kernel_state@.get_address_space(b_p_ptr)[b_va].write == false);
+                }
+            };
+        }
+
         pub proof fn new_thread_from_B_preserve_non_interference_A(old: IsolationStateMachine, new: IsolationStateMachine, thread_ptr:ThreadPtr, endpoint_index: EndpointIdx, ret: SyscallReturnStruct)
             requires
                 old.kernel_state@.thread_dom().contains(thread_ptr),
@@ -4,7 +4,8 @@ use crate::queue::NvmeCommandQueue;
 use crate::queue::NvmeCompletionQueue;
 use crate::regs::NvmeArrayRegs;
 use crate::regs::{NvmeRegs32, NvmeRegs64};
-use crate::{BlockOp, BlockReq};
+use crate::{BlockOp, BlockReq, BlockResp};
+use interface::block::{BlockDevice, BlockStats};
 use alloc::boxed::Box;
 use alloc::collections::VecDeque;
 use alloc::format;
@@ -360,8 +361,7 @@ impl NvmeDevice {
         // HACK
         println!("reloading iommu");
         unsafe {
-            let pml4 = asys::sys_rd_io_cr3() as u64;
-            asys::sys_set_device_iommu(NVME_PCI_DEV.0, NVME_PCI_DEV.1, NVME_PCI_DEV.2, pml4);
+            asys::sys_set_device_iommu(NVME_PCI_DEV.0, NVME_PCI_DEV.1, NVME_PCI_DEV.2);
         }
 
         println!(
@@ -511,10 +511,12 @@ impl NvmeDevice {
     }
 
 
+    /// Submit the requests in `submit`, then move completed requests to `collect`. `res` of
+    /// each response is the status field of its completion entry, 0 on success.
     pub fn submit_and_poll_breq(
         &mut self,
         submit: &mut VecDeque<BlockReq>,
-        collect: &mut VecDeque<BlockReq>,
+        collect: &mut VecDeque<BlockResp>,
     ) -> usize {
         let mut sub_count = 0;
         let mut reap_count = 0;
@@ -568,13 +570,12 @@ impl NvmeDevice {
                         let sq = &mut self.submission_queues[qid];
                         if sq.req_slot[cq_idx] == true {
                             if let Some(req) = sq.breq_requests[cq_idx].take() {
-                                collect.push_front(req);
+                                collect.push_front(BlockResp::new(req, completion_status(&entry)));
                             }
                             sq.req_slot[cq_idx] = false;
                             reap_count += 1;
                         }
                         cur_head = head;
-                        //TODO: Handle errors
                         self.stats.completed += 1;
                     }
 
@@ -605,7 +606,7 @@ impl NvmeDevice {
                     let sq = &mut self.submission_queues[qid];
                     if sq.req_slot[cq_idx] == true {
                         if let Some(req) = sq.breq_requests[cq_idx].take() {
-                            collect.push_front(req);
+                            collect.push_front(BlockResp::new(req, completion_status(&entry)));
                         }
                         sq.req_slot[cq_idx] = false;
                         sq.breq_requests[cq_idx] = None;
@@ -617,7 +618,6 @@ impl NvmeDevice {
                         );
                     }
                     cur_head = head;
-                    //TODO: Handle errors
                     self.stats.completed += 1;
                 } else {
                     break;
@@ -806,7 +806,9 @@ impl NvmeDevice {
         sub_count
     }
 
-    pub fn poll_breq(&mut self, collect: &mut VecDeque<BlockReq>) -> usize {
+    /// Move completed requests to `collect`, returning how many there were. `res` of each
+    /// response is the status field of its completion entry, 0 on success.
+    pub fn poll_breq(&mut self, collect: &mut VecDeque<BlockResp>) -> usize {
         let qid = 1;
         let mut count: usize = 0;
         let mut reap_count = 0;
@@ -839,14 +841,13 @@ impl NvmeDevice {
                     //                                sq.blkreq_rrefs[cq_idx].is_some());
                     //assert_eq!(cid, cq_idx, "cid {} cq_idx {}", cid, cq_idx);
 
-                    if let Some(mut req) = sq.breq_requests[cq_idx].take() {
-                        collect.push_back(req);
+                    if let Some(req) = sq.breq_requests[cq_idx].take() {
+                        collect.push_back(BlockResp::new(req, completion_status(&entry)));
                     }
                     sq.req_slot[cq_idx] = false;
                     sq.breq_requests[cq_idx] = None;
                     reap_count += 1;
                     cur_head = head;
-                    //TODO: Handle errors
                     self.stats.completed += 1;
                 }
             } else {
@@ -1042,3 +1043,44 @@ impl NvmeDevice {
         sub_count
     }
 }
+
+/// Status field of a completion entry, without the phase tag.
+fn completion_status(entry: &NvmeCompletion) -> i32 {
+    (entry.status >> 1) as i32
+}
+
+impl BlockDevice for NvmeDevice {
+    fn submit_and_poll_breq(
+        &mut self,
+        submit: &mut VecDeque<BlockReq>,
+        collect: &mut VecDeque<BlockResp>,
+    ) -> usize {
+        NvmeDevice::submit_and_poll_breq(self, submit, collect)
+    }
+
+    fn poll_breq(&mut self, collect: &mut VecDeque<BlockResp>) -> usize {
+        NvmeDevice::poll_breq(self, collect)
+    }
+
+    fn get_stats(&self) -> BlockStats {
+        BlockStats {
+            submitted: self.stats.submitted,
+            completed: self.stats.completed,
+        }
+    }
+
+    // I/O goes to namespace 1
+    fn block_size(&self) -> usize {
+        self.namespaces
+            .iter()
+            .find(|ns| ns.id == 1)
+            .map_or(512, |ns| ns.block_size as usize)
+    }
+
+    fn capacity(&self) -> u64 {
+        self.namespaces
+            .iter()
+            .find(|ns| ns.id == 1)
+            .map_or(num_lbas(), |ns| ns.blocks)
+    }
+}
@@ -1,6 +1,6 @@
 use crate::device::NvmeDevice;
 use crate::println;
-use crate::{BlockOp, BlockReq};
+use crate::{BlockOp, BlockReq, BlockResp};
 use alloc::collections::VecDeque;
 use alloc::vec::Vec;
 use b2histogram::Base2Histogram;
@@ -164,7 +164,7 @@ pub fn run_blocktest_blkreq(dev: &mut NvmeDevice) {
     let runtime = 30;
 
     let mut submit: VecDeque<BlockReq> = VecDeque::with_capacity(batch_sz as usize);
-    let mut collect: VecDeque<BlockReq> = VecDeque::new();
+    let mut collect: VecDeque<BlockResp> = VecDeque::new();
 
     for i in 0..32 {
         let mut breq = BlockReq::new(
@@ -208,7 +208,7 @@ pub fn run_blocktest_blkreq(dev: &mut NvmeDevice) {
 
         poll_hist.record(collect.len() as u64);
 
-        while let Some(mut breq) = collect.pop_front() {
+        while let Some(BlockResp { req: mut breq, .. }) = collect.pop_front() {
             breq.block = block_num as u64;
             block_num = block_num.wrapping_add(8);
             submit.push_back(breq);
@@ -0,0 +1,381 @@
+use crate::ecam::{pci_read_ext, PCI_LEGACY_CONFIG_SPACE_SIZE};
+use crate::utils::{pci_read, pci_write, pci_write_u16, PciAddress, PciBarAddr};
+
+/// Capability IDs from the PCI Local Bus Specification.
+const CAP_ID_POWER_MANAGEMENT: u8 = 0x01;
+const CAP_ID_MSI: u8 = 0x05;
+const CAP_ID_PCI_EXPRESS: u8 = 0x10;
+const CAP_ID_MSIX: u8 = 0x11;
+
+/// Extended capability IDs from the PCI Express Base Specification.
+pub const EXT_CAP_ID_AER: u16 = 0x0001;
+pub const EXT_CAP_ID_ACS: u16 = 0x000d;
+pub const EXT_CAP_ID_SRIOV: u16 = 0x0010;
+
+/// Upper bound on the length of the list, in case it loops.
+const MAX_CAPABILITIES: usize = 48;
+
+/// A capability found in the capability list of a device.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PciCapability {
+    PowerManagement(PmCapability),
+    Msi(MsiCapability),
+    MsiX(MsiXCapability),
+    PciExpress(PcieCapability),
+    /// A capability we do not parse.
+    Other {
+        id: u8,
+        offset: u8,
+    },
+}
+
+/// Walk the capability list starting at `cap_pointer`.
+pub fn pci_read_capabilities<const N: usize>(
+    pci: &PciAddress,
+    cap_pointer: u8,
+) -> heapless::Vec<PciCapability, N> {
+    let mut caps = heapless::Vec::new();
+    let mut offset = cap_pointer & 0xfc;
+
+    for _ in 0..MAX_CAPABILITIES {
+        if offset == 0 {
+            break;
+        }
+        let header = pci_read(pci, offset);
+        let id = header as u8;
+        let cap = match id {
+            CAP_ID_POWER_MANAGEMENT => {
+                PciCapability::PowerManagement(PmCapability::read(pci, offset))
+            }
+            CAP_ID_MSI => PciCapability::Msi(MsiCapability::read(pci, offset)),
+            CAP_ID_PCI_EXPRESS => PciCapability::PciExpress(PcieCapability::read(pci, offset)),
+            CAP_ID_MSIX => PciCapability::MsiX(MsiXCapability::read(pci, offset)),
+            _ => PciCapability::Other { id, offset },
+        };
+        if caps.push(cap).is_err() {
+            log::warn!("Too many PCI capabilities, ignoring the rest");
+            break;
+        }
+        offset = (header >> 8) as u8 & 0xfc;
+    }
+    caps
+}
+
+/// An extended capability, found in the configuration space past the first 256 bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PciExtCapability {
+    pub id: u16,
+    pub version: u8,
+    /// Offset of the capability header in the configuration space.
+    pub offset: u16,
+}
+
+/// Walk the extended capability list. Returns an empty list if the extended configuration
+/// space is not reachable.
+pub fn pci_read_ext_capabilities<const N: usize>(
+    pci: &PciAddress,
+) -> heapless::Vec<PciExtCapability, N> {
+    let mut caps = heapless::Vec::new();
+    let mut offset = PCI_LEGACY_CONFIG_SPACE_SIZE;
+
+    for _ in 0..MAX_CAPABILITIES {
+        let header = match pci_read_ext(pci, offset) {
+            Ok(header) => header,
+            Err(_) => break,
+        };
+        // An empty list reads as 0, a missing function as all ones
+        if header == 0 || header == 0xffff_ffff {
+            break;
+        }
+        let cap = PciExtCapability {
+            id: header as u16,
+            version: ((header >> 16) & 0xf) as u8,
+            offset,
+        };
+        if caps.push(cap).is_err() {
+            log::warn!("Too many PCIe extended capabilities, ignoring the rest");
+            break;
+        }
+        offset = ((header >> 20) & 0xffc) as u16;
+        if offset < PCI_LEGACY_CONFIG_SPACE_SIZE {
+            break;
+        }
+    }
+    caps
+}
+
+/// Power Management capability.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PmCapability {
+    pub offset: u8,
+    /// The Power Management Capabilities register.
+    pub capabilities: u16,
+}
+
+impl PmCapability {
+    const PMCSR: u8 = 0x04;
+    const PMCSR_POWER_STATE: u32 = 0b11;
+    // RW1C, must not be written back
+    const PMCSR_PME_STATUS: u32 = 1 << 15;
+
+    fn read(pci: &PciAddress, offset: u8) -> Self {
+        Self {
+            offset,
+            capabilities: (pci_read(pci, offset) >> 16) as u16,
+        }
+    }
+
+    /// Return the current power state, 0 for D0 to 3 for D3hot.
+    pub fn power_state(&self, pci: &PciAddress) -> u8 {
+        (pci_read(pci, self.offset + Self::PMCSR) & Self::PMCSR_POWER_STATE) as u8
+    }
+
+    /// Move the device to power state `state`, 0 for D0 to 3 for D3hot.
+    pub fn set_power_state(&self, pci: &PciAddress, state: u8) {
+        let pmcsr = pci_read(pci, self.offset + Self::PMCSR) & !Self::PMCSR_PME_STATUS;
+        let pmcsr = (pmcsr & !Self::PMCSR_POWER_STATE) | (state as u32 & Self::PMCSR_POWER_STATE);
+        pci_write(pci, self.offset + Self::PMCSR, pmcsr);
+    }
+}
+
+/// MSI capability.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MsiCapability {
+    pub offset: u8,
+    /// Whether the message address has an upper 32-bit half.
+    pub is_64bit: bool,
+    /// Whether vectors can be masked one by one.
+    pub per_vector_masking: bool,
+    /// Number of vectors the device asks for.
+    pub vectors: u8,
+}
+
+impl MsiCapability {
+    const CONTROL: u8 = 0x02;
+    const CONTROL_ENABLE: u16 = 1 << 0;
+    const CONTROL_MMC_SHIFT: u16 = 1;
+    const CONTROL_MME_SHIFT: u16 = 4;
+    const CONTROL_MME_MASK: u16 = 0b111 << Self::CONTROL_MME_SHIFT;
+    const CONTROL_64BIT: u16 = 1 << 7;
+    const CONTROL_PER_VECTOR_MASKING: u16 = 1 << 8;
+
+    const ADDRESS: u8 = 0x04;
+
+    fn read(pci: &PciAddress, offset: u8) -> Self {
+        let control = (pci_read(pci, offset) >> 16) as u16;
+        Self {
+            offset,
+            is_64bit: control & Self::CONTROL_64BIT != 0,
+            per_vector_masking: control & Self::CONTROL_PER_VECTOR_MASKING != 0,
+            vectors: 1 << ((control >> Self::CONTROL_MMC_SHIFT) & 0b111),
+        }
+    }
+
+    fn control(&self, pci: &PciAddress) -> u16 {
+        (pci_read(pci, self.offset) >> 16) as u16
+    }
+
+    fn data_offset(&self) -> u8 {
+        if self.is_64bit {
+            self.offset + 0x0c
+        } else {
+            self.offset + 0x08
+        }
+    }
+
+    fn mask_offset(&self) -> u8 {
+        if self.is_64bit {
+            self.offset + 0x10
+        } else {
+            self.offset + 0x0c
+        }
+    }
+
+    /// Program the message the device writes to raise an interrupt. With several vectors
+    /// enabled, vector `i` is raised with `data + i`.
+    pub fn set_message(&self, pci: &PciAddress, address: u64, data: u16) {
+        pci_write(pci, self.offset + Self::ADDRESS, address as u32);
+        if self.is_64bit {
+            pci_write(pci, self.offset + Self::ADDRESS + 4, (address >> 32) as u32);
+        }
+        pci_write_u16(pci, self.data_offset(), data);
+    }
+
+    /// Enable `count` vectors, rounded down to a power of two the device supports.
+    pub fn set_vectors(&self, pci: &PciAddress, count: u8) {
+        let count = count.clamp(1, self.vectors);
+        let log2 = 7 - count.leading_zeros() as u16;
+        let control = self.control(pci) & !Self::CONTROL_MME_MASK;
+        pci_write_u16(
+            pci,
+            self.offset + Self::CONTROL,
+            control | log2 << Self::CONTROL_MME_SHIFT,
+        );
+    }
+
+    pub fn set_enabled(&self, pci: &PciAddress, enabled: bool) {
+        let mut control = self.control(pci);
+        if enabled {
+            control |= Self::CONTROL_ENABLE;
+        } else {
+            control &= !Self::CONTROL_ENABLE;
+        }
+        pci_write_u16(pci, self.offset + Self::CONTROL, control);
+    }
+
+    /// Mask or unmask `vector`. Does nothing without per-vector masking.
+    pub fn set_masked(&self, pci: &PciAddress, vector: u8, masked: bool) {
+        if !self.per_vector_masking || vector >= 32 {
+            return;
+        }
+        let mut mask = pci_read(pci, self.mask_offset());
+        if masked {
+            mask |= 1 << vector;
+        } else {
+            mask &= !(1 << vector);
+        }
+        pci_write(pci, self.mask_offset(), mask);
+    }
+}
+
+/// MSI-X capability.
+///
+/// The vector table and the pending bit array live in memory BARs, so programming entries
+/// needs the table mapped, see `MsiXCapability::table_address`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MsiXCapability {
+    pub offset: u8,
+    /// Number of entries in the vector table.
+    pub table_size: u16,
+    pub table_bar: u8,
+    pub table_offset: u32,
+    pub pba_bar: u8,
+    pub pba_offset: u32,
+}
+
+impl MsiXCapability {
+    const CONTROL: u8 = 0x02;
+    const CONTROL_TABLE_SIZE: u16 = 0x7ff;
+    const CONTROL_FUNCTION_MASK: u16 = 1 << 14;
+    const CONTROL_ENABLE: u16 = 1 << 15;
+
+    const TABLE: u8 = 0x04;
+    const PBA: u8 = 0x08;
+    const BIR_MASK: u32 = 0b111;
+
+    /// Size of an entry of the vector table.
+    pub const ENTRY_SIZE: usize = 16;
+    const ENTRY_ADDRESS_LOW: usize = 0x0;
+    const ENTRY_ADDRESS_HIGH: usize = 0x4;
+    const ENTRY_DATA: usize = 0x8;
+    const ENTRY_CONTROL: usize = 0xc;
+    const ENTRY_CONTROL_MASKED: u32 = 1 << 0;
+
+    fn read(pci: &PciAddress, offset: u8) -> Self {
+        let control = (pci_read(pci, offset) >> 16) as u16;
+        let table = pci_read(pci, offset + Self::TABLE);
+        let pba = pci_read(pci, offset + Self::PBA);
+        Self {
+            offset,
+            table_size: (control & Self::CONTROL_TABLE_SIZE) + 1,
+            table_bar: (table & Self::BIR_MASK) as u8,
+            table_offset: table & !Self::BIR_MASK,
+            pba_bar: (pba & Self::BIR_MASK) as u8,
+            pba_offset: pba & !Self::BIR_MASK,
+        }
+    }
+
+    fn set_control(&self, pci: &PciAddress, bit: u16, value: bool) {
+        let mut control = (pci_read(pci, self.offset) >> 16) as u16;
+        if value {
+            control |= bit;
+        } else {
+            control &= !bit;
+        }
+        pci_write_u16(pci, self.offset + Self::CONTROL, control);
+    }
+
+    pub fn set_enabled(&self, pci: &PciAddress, enabled: bool) {
+        self.set_control(pci, Self::CONTROL_ENABLE, enabled);
+    }
+
+    /// Mask or unmask all vectors of the function at once.
+    pub fn set_function_masked(&self, pci: &PciAddress, masked: bool) {
+        self.set_control(pci, Self::CONTROL_FUNCTION_MASK, masked);
+    }
+
+    /// Return the physical address of the vector table, given the BAR it lives in.
+    pub fn table_address(&self, bar: &PciBarAddr) -> u64 {
+        bar.base() + self.table_offset as u64
+    }
+
+    /// Return the physical address of the pending bit array, given the BAR it lives in.
+    pub fn pba_address(&self, bar: &PciBarAddr) -> u64 {
+        bar.base() + self.pba_offset as u64
+    }
+
+    /// Program entry `index` of the vector table mapped at `table`.
+    ///
+    /// # Safety
+    /// `table` must point to the mapped vector table of this device.
+    pub unsafe fn set_message(&self, table: *mut u8, index: u16, address: u64, data: u32) {
+        assert!(index < self.table_size, "MSI-X vector out of range");
+        let entry = table.add(index as usize * Self::ENTRY_SIZE);
+        (entry.add(Self::ENTRY_ADDRESS_LOW) as *mut u32).write_volatile(address as u32);
+        (entry.add(Self::ENTRY_ADDRESS_HIGH) as *mut u32).write_volatile((address >> 32) as u32);
+        (entry.add(Self::ENTRY_DATA) as *mut u32).write_volatile(data);
+    }
+
+    /// Mask or unmask entry `index` of the vector table mapped at `table`.
+    ///
+    /// # Safety
+    /// `table` must point to the mapped vector table of this device.
+    pub unsafe fn set_masked(&self, table: *mut u8, index: u16, masked: bool) {
+        assert!(index < self.table_size, "MSI-X vector out of range");
+        let control =
+            table.add(index as usize * Self::ENTRY_SIZE + Self::ENTRY_CONTROL) as *mut u32;
+        let value = control.read_volatile();
+        if masked {
+            control.write_volatile(value | Self::ENTRY_CONTROL_MASKED);
+        } else {
+            control.write_volatile(value & !Self::ENTRY_CONTROL_MASKED);
+        }
+    }
+}
+
+/// PCI Express capability.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PcieCapability {
+    pub offset: u8,
+    pub version: u8,
+    /// Device/Port Type, e.g. 0 for an endpoint or 4 for a root port.
+    pub device_type: u8,
+    /// The Device Capabilities register.
+    pub device_capabilities: u32,
+    /// The Link Capabilities register.
+    pub link_capabilities: u32,
+}
+
+impl PcieCapability {
+    const DEVICE_CAPABILITIES: u8 = 0x04;
+    const LINK_CAPABILITIES: u8 = 0x0c;
+    const LINK_STATUS: u8 = 0x12;
+
+    fn read(pci: &PciAddress, offset: u8) -> Self {
+        let capabilities = (pci_read(pci, offset) >> 16) as u16;
+        Self {
+            offset,
+            version: (capabilities & 0xf) as u8,
+            device_type: ((capabilities >> 4) & 0xf) as u8,
+            device_capabilities: pci_read(pci, offset + Self::DEVICE_CAPABILITIES),
+            link_capabilities: pci_read(pci, offset + Self::LINK_CAPABILITIES),
+        }
+    }
+
+    /// Return the negotiated link speed and width, as encoded in the Link Status register.
+    pub fn link_status(&self, pci: &PciAddress) -> (u8, u8) {
+        // The register is the upper half of the dword at 0x10
+        let status = (pci_read(pci, self.offset + Self::LINK_STATUS) >> 16) as u16;
+        ((status & 0xf) as u8, ((status >> 4) & 0x3f) as u8)
+    }
+}
@@ -0,0 +1,153 @@
+//! PCIe enhanced configuration access mechanism (ECAM).
+//!
+//! Port I/O only reaches the first 256 bytes of the configuration space. With the MCFG
+//! region mapped, the full 4 KiB of every function on the decoded buses is accessible, which
+//! is where PCIe extended capabilities like AER, ACS and SR-IOV live.
+
+use core::sync::atomic::{AtomicU16, AtomicUsize, Ordering};
+
+use crate::utils::{pci_read, pci_write, PciAddress};
+
+/// Size of the configuration space of a function.
+pub const PCI_CONFIG_SPACE_SIZE: u16 = 0x1000;
+
+/// Size of the configuration space reachable through port I/O.
+pub const PCI_LEGACY_CONFIG_SPACE_SIZE: u16 = 0x100;
+
+/// Maximum number of ECAM regions, one per segment and bus range in the MCFG table.
+pub const MAX_ECAM_REGIONS: usize = 4;
+
+struct EcamRegion {
+    /// Virtual address the region is mapped at, 0 if the slot is unused.
+    base: AtomicUsize,
+    segment: AtomicU16,
+    /// The buses decoded by the region, start in the low byte and end in the high byte.
+    buses: AtomicU16,
+}
+
+const NO_REGION: EcamRegion = EcamRegion {
+    base: AtomicUsize::new(0),
+    segment: AtomicU16::new(0),
+    buses: AtomicU16::new(0),
+};
+
+static ECAM_REGIONS: [EcamRegion; MAX_ECAM_REGIONS] = [NO_REGION; MAX_ECAM_REGIONS];
+
+/// Use the ECAM region mapped at `virt_base` for configuration space accesses to buses
+/// `start_bus` to `end_bus` of `segment`.
+///
+/// # Safety
+/// `virt_base` must be the uncached mapping of the whole ECAM region, as described by the MCFG
+/// table, and stay mapped from now on. Regions must be set up before any configuration space
+/// access and not concurrently.
+pub unsafe fn set_ecam(
+    virt_base: usize,
+    segment: u16,
+    start_bus: u8,
+    end_bus: u8,
+) -> Result<(), &'static str> {
+    let region = ECAM_REGIONS
+        .iter()
+        .find(|region| region.base.load(Ordering::Relaxed) == 0)
+        .ok_or("Too many ECAM regions")?;
+    region.segment.store(segment, Ordering::Relaxed);
+    region.buses.store(
+        u16::from(start_bus) | u16::from(end_bus) << 8,
+        Ordering::Relaxed,
+    );
+    region.base.store(virt_base, Ordering::Release);
+    log::info!(
+        "ECAM for buses {:04x}:{:02x}-{:02x} mapped at {:#x}",
+        segment,
+        start_bus,
+        end_bus,
+        virt_base
+    );
+    Ok(())
+}
+
+/// Call `f` with the segment, start bus and end bus of every ECAM region set up.
+pub fn for_each_ecam_region<F: FnMut(u16, u8, u8)>(mut f: F) {
+    for region in ECAM_REGIONS.iter() {
+        if region.base.load(Ordering::Acquire) == 0 {
+            continue;
+        }
+        let buses = region.buses.load(Ordering::Relaxed);
+        f(
+            region.segment.load(Ordering::Relaxed),
+            buses as u8,
+            (buses >> 8) as u8,
+        );
+    }
+}
+
+/// Return whether the extended configuration space of `pci` is reachable.
+pub fn ecam_available(pci: &PciAddress) -> bool {
+    ecam_address(pci, 0).is_some()
+}
+
+fn ecam_address(pci: &PciAddress, offset: u16) -> Option<*mut u32> {
+    let (bus, dev, func): (u8, u8, u8) = (*pci).into();
+    ECAM_REGIONS.iter().find_map(|region| {
+        let base = region.base.load(Ordering::Acquire);
+        if base == 0 || region.segment.load(Ordering::Relaxed) != pci.segment() {
+            return None;
+        }
+        let buses = region.buses.load(Ordering::Relaxed);
+        let (start_bus, end_bus) = (buses as u8, (buses >> 8) as u8);
+        if bus < start_bus || bus > end_bus {
+            return None;
+        }
+
+        let function =
+            ((bus - start_bus) as usize) << 20 | (dev as usize) << 15 | (func as usize) << 12;
+        Some((base + function + (offset & 0xffc) as usize) as *mut u32)
+    })
+}
+
+/// Read through ECAM, `None` if it does not cover `pci`.
+pub(crate) fn ecam_read(pci: &PciAddress, offset: u16) -> Option<u32> {
+    ecam_address(pci, offset).map(|address| unsafe { address.read_volatile() })
+}
+
+/// Write through ECAM, `false` if it does not cover `pci`.
+pub(crate) fn ecam_write(pci: &PciAddress, offset: u16, value: u32) -> bool {
+    match ecam_address(pci, offset) {
+        Some(address) => {
+            unsafe { address.write_volatile(value) };
+            true
+        }
+        None => false,
+    }
+}
+
+/// Read the dword at `offset` of the configuration space of `pci`.
+///
+/// Offsets below 256 fall back to port I/O if the bus is not covered by ECAM.
+pub fn pci_read_ext(pci: &PciAddress, offset: u16) -> Result<u32, &'static str> {
+    if offset >= PCI_CONFIG_SPACE_SIZE {
+        return Err("Offset beyond the configuration space");
+    }
+    if offset < PCI_LEGACY_CONFIG_SPACE_SIZE {
+        return Ok(pci_read(pci, offset as u8));
+    }
+    ecam_read(pci, offset).ok_or("Extended configuration space not available")
+}
+
+/// Write the dword at `offset` of the configuration space of `pci`.
+///
+/// Offsets below 256 fall back to port I/O if the bus is not covered by ECAM.
+pub fn pci_write_ext(pci: &PciAddress, offset: u16, value: u32) -> Result<(), &'static str> {
+    if offset >= PCI_CONFIG_SPACE_SIZE {
+        return Err("Offset beyond the configuration space");
+    }
+    if offset < PCI_LEGACY_CONFIG_SPACE_SIZE {
+        pci_write(pci, offset as u8, value);
+        return Ok(());
+    }
+    if ecam_write(pci, offset, value) {
+        Ok(())
+    } else {
+        Err("Extended configuration space not available")
+    }
+}
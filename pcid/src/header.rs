@@ -1,5 +1,9 @@
 use byteorder::{ByteOrder, LittleEndian};
 
+use crate::cap::{
+    pci_read_capabilities, pci_read_ext_capabilities, MsiCapability, MsiXCapability, PciCapability,
+    PciExtCapability, PcieCapability, PmCapability,
+};
 use crate::class::PciClass;
 use crate::utils::{
     pci_enable_bus_mastering, pci_read, pci_read_bars, pci_read_range, PciAddress, PciBarAddr,
@@ -30,14 +34,87 @@ bitflags! {
     }
 }
 
+/// Maximum number of capabilities kept per device.
+const MAX_CAPABILITIES: usize = 16;
+
+/// Set in the status register if the device has a capability list.
+const STATUS_CAPABILITIES_LIST: u16 = 1 << 4;
+
 #[derive(Debug)]
 pub struct PciDeviceHeader {
     pub hdr: PciHeader,
+    pub caps: AVec<PciCapability, MAX_CAPABILITIES>,
+    /// Extended capabilities, only read for PCIe devices when ECAM is set up.
+    pub ext_caps: AVec<PciExtCapability, MAX_CAPABILITIES>,
 }
 
 impl PciDeviceHeader {
-    fn new(hdr: PciHeader) -> PciDeviceHeader {
-        PciDeviceHeader { hdr }
+    fn new(pci_addr: &PciAddress, hdr: PciHeader) -> PciDeviceHeader {
+        let caps = if hdr.status() & STATUS_CAPABILITIES_LIST != 0 {
+            pci_read_capabilities(pci_addr, hdr.cap_pointer())
+        } else {
+            AVec::new()
+        };
+        let is_pcie = caps
+            .iter()
+            .any(|cap| matches!(cap, PciCapability::PciExpress(_)));
+        let ext_caps = if is_pcie {
+            pci_read_ext_capabilities(pci_addr)
+        } else {
+            AVec::new()
+        };
+        PciDeviceHeader {
+            hdr,
+            caps,
+            ext_caps,
+        }
+    }
+
+    /// Return the capabilities of the device.
+    pub fn capabilities(&self) -> &[PciCapability] {
+        &self.caps
+    }
+
+    /// Return the extended capabilities of the device.
+    pub fn ext_capabilities(&self) -> &[PciExtCapability] {
+        &self.ext_caps
+    }
+
+    /// Return the extended capability with the given ID, e.g. `cap::EXT_CAP_ID_SRIOV`.
+    pub fn ext_capability(&self, id: u16) -> Option<&PciExtCapability> {
+        self.ext_caps.iter().find(|cap| cap.id == id)
+    }
+
+    /// Return the MSI capability, if the device has one.
+    pub fn msi(&self) -> Option<&MsiCapability> {
+        self.caps.iter().find_map(|cap| match cap {
+            PciCapability::Msi(msi) => Some(msi),
+            _ => None,
+        })
+    }
+
+    /// Return the MSI-X capability, if the device has one.
+    pub fn msix(&self) -> Option<&MsiXCapability> {
+        self.caps.iter().find_map(|cap| match cap {
+            PciCapability::MsiX(msix) => Some(msix),
+            _ => None,
+        })
+    }
+
+    /// Return the PCI Express capability, if the device has one.
+    pub fn pcie(&self) -> Option<&PcieCapability> {
+        self.caps.iter().find_map(|cap| match cap {
+            PciCapability::PciExpress(pcie) => Some(pcie),
+            _ => None,
+        })
+    }
+
+    /// Return the Power Management capability, if the device has one.
+    pub fn power_management(&self) -> Option<&PmCapability> {
+        self.caps.iter().find_map(|cap| match cap {
+            PciCapability::PowerManagement(pm) => Some(pm),
+            _ => None,
+        })
     }
 
     pub fn get_bar(&self, idx: usize) -> Option<&PciBarAddr> {
@@ -160,41 +237,43 @@ pub fn read_config_space(pci_addr: &PciAddress) -> Result<PciDeviceHeader, PciHe
                     }
                 }
 
-                let bytes = pci_read_range(&pci_addr, 30, 24);
+                let bytes = pci_read_range(&pci_addr, 0x28, 24);
                 let cardbus_cis_ptr = LittleEndian::read_u32(&bytes[0..4]);
                 let subsystem_vendor_id = LittleEndian::read_u16(&bytes[4..6]);
                 let subsystem_id = LittleEndian::read_u16(&bytes[6..8]);
                 let expansion_rom_bar = LittleEndian::read_u32(&bytes[8..12]);
-                // TODO: Parse out the capabilities list.
                 let cap_pointer = bytes[12];
                 let interrupt_line = bytes[20];
                 let interrupt_pin = bytes[21];
                 let min_grant = bytes[22];
                 let max_latency = bytes[23];
-                Ok(PciDeviceHeader::new(PciHeader::General {
-                    vendor_id,
-                    device_id,
-                    command,
-                    status,
-                    revision,
-                    interface,
-                    subclass,
-                    class,
-                    cache_line_size,
-                    latency_timer,
-                    header_type,
-                    bist,
-                    bars,
-                    cardbus_cis_ptr,
-                    subsystem_vendor_id,
-                    subsystem_id,
-                    expansion_rom_bar,
-                    cap_pointer,
-                    interrupt_line,
-                    interrupt_pin,
-                    min_grant,
-                    max_latency,
-                }))
+                Ok(PciDeviceHeader::new(
+                    pci_addr,
+                    PciHeader::General {
+                        vendor_id,
+                        device_id,
+                        command,
+                        status,
+                        revision,
+                        interface,
+                        subclass,
+                        class,
+                        cache_line_size,
+                        latency_timer,
+                        header_type,
+                        bist,
+                        bars,
+                        cardbus_cis_ptr,
+                        subsystem_vendor_id,
+                        subsystem_id,
+                        expansion_rom_bar,
+                        cap_pointer,
+                        interrupt_line,
+                        interrupt_pin,
+                        min_grant,
+                        max_latency,
+                    },
+                ))
             }
             PciHeaderType::PCITOPCI => {
                 let bars = pci_read_bars(pci_addr, PciHeaderType::PCITOPCI);
@@ -215,47 +294,49 @@ pub fn read_config_space(pci_addr: &PciAddress) -> Result<PciDeviceHeader, PciHe
                 let prefetch_limit_upper = LittleEndian::read_u32(&bytes[20..24]);
                 let io_base_upper = LittleEndian::read_u16(&bytes[24..26]);
                 let io_limit_upper = LittleEndian::read_u16(&bytes[26..28]);
-                // TODO: Parse out the capabilities list.
                 let cap_pointer = bytes[28];
                 let expansion_rom = LittleEndian::read_u32(&bytes[32..36]);
                 let interrupt_line = bytes[36];
                 let interrupt_pin = bytes[37];
                 let bridge_control = LittleEndian::read_u16(&bytes[38..40]);
-                Ok(PciDeviceHeader::new(PciHeader::PciToPci {
-                    vendor_id,
-                    device_id,
-                    command,
-                    status,
-                    revision,
-                    interface,
-                    subclass,
-                    class,
-                    cache_line_size,
-                    latency_timer,
-                    header_type,
-                    bist,
-                    bars,
-                    primary_bus_num,
-                    secondary_bus_num,
-                    subordinate_bus_num,
-                    secondary_latency_timer,
-                    io_base,
-                    io_limit,
-                    secondary_status,
-                    mem_base,
-                    mem_limit,
-                    prefetch_base,
-                    prefetch_limit,
-                    prefetch_base_upper,
-                    prefetch_limit_upper,
-                    io_base_upper,
-                    io_limit_upper,
-                    cap_pointer,
-                    expansion_rom,
-                    interrupt_line,
-                    interrupt_pin,
-                    bridge_control,
-                }))
+                Ok(PciDeviceHeader::new(
+                    pci_addr,
+                    PciHeader::PciToPci {
+                        vendor_id,
+                        device_id,
+                        command,
+                        status,
+                        revision,
+                        interface,
+                        subclass,
+                        class,
+                        cache_line_size,
+                        latency_timer,
+                        header_type,
+                        bist,
+                        bars,
+                        primary_bus_num,
+                        secondary_bus_num,
+                        subordinate_bus_num,
+                        secondary_latency_timer,
+                        io_base,
+                        io_limit,
+                        secondary_status,
+                        mem_base,
+                        mem_limit,
+                        prefetch_base,
+                        prefetch_limit,
+                        prefetch_base_upper,
+                        prefetch_limit_upper,
+                        io_base_upper,
+                        io_limit_upper,
+                        cap_pointer,
+                        expansion_rom,
+                        interrupt_line,
+                        interrupt_pin,
+                        bridge_control,
+                    },
+                ))
             }
             id => Err(PciHeaderError::UnknownHeaderType(id.bits())),
         }
@@ -358,6 +439,48 @@ impl PciHeader {
         }
     }
 
+    /// Return the Status field.
+    pub fn status(&self) -> u16 {
+        match self {
+            &PciHeader::General { status, .. } | &PciHeader::PciToPci { status, .. } => status,
+        }
+    }
+
+    /// Return the Capabilities Pointer field.
+    pub fn cap_pointer(&self) -> u8 {
+        match self {
+            &PciHeader::General { cap_pointer, .. } | &PciHeader::PciToPci { cap_pointer, .. } => {
+                cap_pointer
+            }
+        }
+    }
+
+    /// Return whether the device implements more than one function.
+    pub fn is_multifunction(&self) -> bool {
+        self.header_type().contains(PciHeaderType::MULTIFUNCTION)
+    }
+
+    /// Return the Secondary Bus Number field of a PCI-to-PCI bridge.
+    pub fn secondary_bus_num(&self) -> Option<u8> {
+        match self {
+            &PciHeader::PciToPci {
+                secondary_bus_num, ..
+            } => Some(secondary_bus_num),
+            _ => None,
+        }
+    }
+
+    /// Return the Subordinate Bus Number field of a PCI-to-PCI bridge.
+    pub fn subordinate_bus_num(&self) -> Option<u8> {
+        match self {
+            &PciHeader::PciToPci {
+                subordinate_bus_num,
+                ..
+            } => Some(subordinate_bus_num),
+            _ => None,
+        }
+    }
+
     /// Return the Interrupt Line field.
     pub fn interrupt_line(&self) -> u8 {
         match self {
@@ -12,16 +12,25 @@ pub use log::info as println;
 
 mod bar;
 mod bus;
+pub mod cap;
 mod class;
 mod dev;
+pub mod ecam;
 pub mod func;
 pub mod header;
 pub mod pci;
+pub mod sriov;
+pub mod tree;
 pub mod utils;
 
 pub use crate::bar::PciBar;
 pub use crate::bus::{PciBus, PciBusIter};
+pub use crate::cap::{
+    MsiCapability, MsiXCapability, PciCapability, PciExtCapability, PcieCapability, PmCapability,
+};
 pub use crate::class::PciClass;
 pub use crate::dev::{PciDev, PciDevIter};
 pub use crate::func::PciFunc;
 pub use crate::header::{PciHeader, PciHeaderError, PciHeaderType};
+pub use crate::sriov::SriovCapability;
+pub use crate::tree::{PciNode, PciTree};
@@ -12,6 +12,8 @@ impl Pci {
         Pci
     }
 
+    /// Iterate over every bus number, whether or not a bridge leads to it.
+    /// `PciTree::enumerate` walks the actual hierarchy instead.
     pub fn buses<'pci>(&'pci self) -> PciIter<'pci> {
         PciIter::new(self)
     }
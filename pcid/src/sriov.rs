@@ -0,0 +1,226 @@
+//! Single Root I/O Virtualization.
+//!
+//! A physical function with the SR-IOV extended capability can expose virtual functions,
+//! lightweight functions with their own routing ID and BARs that can be handed to different
+//! IOMMU domains. Virtual functions do not answer configuration reads of their vendor ID, so
+//! they never show up when scanning the bus and are found through the capability instead.
+
+use heapless::Vec as AVec;
+
+use crate::cap::PciExtCapability;
+use crate::ecam::{pci_read_ext, pci_write_ext};
+use crate::utils::{PciAddress, PciBarAddr};
+
+/// Number of VF BARs in the capability.
+pub const SRIOV_NUM_BARS: usize = 6;
+
+/// Upper bound on the number of VFs we enumerate at once.
+pub const MAX_VFS: usize = 64;
+
+/// SR-IOV extended capability of a physical function.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SriovCapability {
+    pub offset: u16,
+    pub initial_vfs: u16,
+    pub total_vfs: u16,
+    /// Device ID of the virtual functions, which read 0xffff from their own header.
+    pub vf_device_id: u16,
+    /// BARs of the first VF. The BAR of VF `i` is `size * i` bytes further.
+    pub vf_bars: [Option<PciBarAddr>; SRIOV_NUM_BARS],
+}
+
+impl SriovCapability {
+    const CONTROL: u16 = 0x08;
+    const CONTROL_VF_ENABLE: u16 = 1 << 0;
+    const CONTROL_VF_MSE: u16 = 1 << 3;
+    const INITIAL_VFS: u16 = 0x0c;
+    const TOTAL_VFS: u16 = 0x0e;
+    const NUM_VFS: u16 = 0x10;
+    const FIRST_VF_OFFSET: u16 = 0x14;
+    const VF_STRIDE: u16 = 0x16;
+    const VF_DEVICE_ID: u16 = 0x1a;
+    const SYSTEM_PAGE_SIZE: u16 = 0x20;
+    const VF_BAR0: u16 = 0x24;
+
+    // 4 KiB, encoded as bit n for a page size of 2^(n + 12)
+    const SYSTEM_PAGE_SIZE_4K: u16 = 1 << 0;
+
+    const BAR_IO: u32 = 1 << 0;
+    const BAR_64BIT: u32 = 0b10 << 1;
+    const BAR_TYPE_MASK: u32 = 0b11 << 1;
+
+    /// Read the SR-IOV capability found at `cap`.
+    pub fn read(pci: &PciAddress, cap: &PciExtCapability) -> Result<Self, &'static str> {
+        let offset = cap.offset;
+        let control = read_u16(pci, offset + Self::CONTROL)?;
+
+        // Sizing the VF BARs must not race with VFs decoding them
+        if control & Self::CONTROL_VF_MSE != 0 {
+            write_control(pci, offset, control & !Self::CONTROL_VF_MSE)?;
+        }
+        let vf_bars = Self::read_vf_bars(pci, offset);
+        write_control(pci, offset, control)?;
+
+        Ok(Self {
+            offset,
+            initial_vfs: read_u16(pci, offset + Self::INITIAL_VFS)?,
+            total_vfs: read_u16(pci, offset + Self::TOTAL_VFS)?,
+            vf_device_id: read_u16(pci, offset + Self::VF_DEVICE_ID)?,
+            vf_bars: vf_bars?,
+        })
+    }
+
+    fn read_vf_bars(
+        pci: &PciAddress,
+        offset: u16,
+    ) -> Result<[Option<PciBarAddr>; SRIOV_NUM_BARS], &'static str> {
+        let mut bars = [None; SRIOV_NUM_BARS];
+        let mut i = 0;
+
+        while i < SRIOV_NUM_BARS {
+            let reg = offset + Self::VF_BAR0 + 4 * i as u16;
+            let low = pci_read_ext(pci, reg)?;
+            // VF BARs are memory BARs only
+            if low & Self::BAR_IO != 0 {
+                i += 1;
+                continue;
+            }
+            let is_64bit = low & Self::BAR_TYPE_MASK == Self::BAR_64BIT && i + 1 < SRIOV_NUM_BARS;
+
+            let high = if is_64bit {
+                pci_read_ext(pci, reg + 4)?
+            } else {
+                0
+            };
+            pci_write_ext(pci, reg, 0xffff_ffff)?;
+            let mut mask = u64::from(pci_read_ext(pci, reg)? & 0xffff_fff0);
+            pci_write_ext(pci, reg, low)?;
+            if is_64bit {
+                pci_write_ext(pci, reg + 4, 0xffff_ffff)?;
+                mask |= u64::from(pci_read_ext(pci, reg + 4)?) << 32;
+                pci_write_ext(pci, reg + 4, high)?;
+            } else {
+                mask |= 0xffff_ffff_0000_0000;
+            }
+
+            let base = u64::from(low & 0xffff_fff0) | u64::from(high) << 32;
+            if mask != 0xffff_ffff_0000_0000 && base != 0 {
+                let size = (!mask).wrapping_add(1);
+                bars[i] = Some(unsafe { PciBarAddr::new(base, size as usize) });
+            }
+
+            i += if is_64bit { 2 } else { 1 };
+        }
+        Ok(bars)
+    }
+
+    /// Return the number of VFs currently enabled.
+    pub fn num_vfs(&self, pci: &PciAddress) -> Result<u16, &'static str> {
+        let control = read_u16(pci, self.offset + Self::CONTROL)?;
+        if control & Self::CONTROL_VF_ENABLE == 0 {
+            return Ok(0);
+        }
+        read_u16(pci, self.offset + Self::NUM_VFS)
+    }
+
+    /// Enable `num_vfs` virtual functions.
+    ///
+    /// The PF driver must be ready to serve the VFs, and the caller must wait 100ms before
+    /// issuing configuration requests to the new VFs.
+    pub fn enable_vfs(&self, pci: &PciAddress, num_vfs: u16) -> Result<(), &'static str> {
+        if num_vfs == 0 || num_vfs > self.total_vfs {
+            return Err("Invalid number of VFs");
+        }
+        let control = read_u16(pci, self.offset + Self::CONTROL)?;
+        if control & Self::CONTROL_VF_ENABLE != 0 {
+            return Err("VFs are already enabled");
+        }
+
+        // NumVFs, the page size and the BARs can only change while VFs are disabled
+        write_u16(pci, self.offset + Self::NUM_VFS, num_vfs)?;
+        write_u16(
+            pci,
+            self.offset + Self::SYSTEM_PAGE_SIZE,
+            Self::SYSTEM_PAGE_SIZE_4K,
+        )?;
+        write_control(
+            pci,
+            self.offset,
+            control | Self::CONTROL_VF_ENABLE | Self::CONTROL_VF_MSE,
+        )?;
+        log::info!("Enabled {} SR-IOV virtual functions", num_vfs);
+        Ok(())
+    }
+
+    /// Disable all virtual functions. Their drivers must have stopped using them.
+    pub fn disable_vfs(&self, pci: &PciAddress) -> Result<(), &'static str> {
+        let control = read_u16(pci, self.offset + Self::CONTROL)?;
+        write_control(
+            pci,
+            self.offset,
+            control & !(Self::CONTROL_VF_ENABLE | Self::CONTROL_VF_MSE),
+        )?;
+        write_u16(pci, self.offset + Self::NUM_VFS, 0)
+    }
+
+    /// Return the address of VF `index`, counting from 0.
+    ///
+    /// The first VF offset and the stride depend on the number of VFs, so this is only
+    /// meaningful once the VFs are enabled.
+    pub fn vf_address(&self, pci: &PciAddress, index: u16) -> Result<PciAddress, &'static str> {
+        let (bus, dev, func): (u8, u8, u8) = (*pci).into();
+        let first_vf_offset = read_u16(pci, self.offset + Self::FIRST_VF_OFFSET)?;
+        let vf_stride = read_u16(pci, self.offset + Self::VF_STRIDE)?;
+
+        let pf_rid = u32::from(bus) << 8 | u32::from(dev) << 3 | u32::from(func);
+        let rid = pf_rid + u32::from(first_vf_offset) + u32::from(index) * u32::from(vf_stride);
+        if rid > 0xffff {
+            return Err("VF routing ID out of range");
+        }
+        PciAddress::with_segment(
+            pci.segment(),
+            (rid >> 8) as u8,
+            ((rid >> 3) & 0x1f) as u8,
+            (rid & 0x7) as u8,
+        )
+    }
+
+    /// Return the addresses of all enabled VFs.
+    pub fn vf_addresses(
+        &self,
+        pci: &PciAddress,
+    ) -> Result<AVec<PciAddress, MAX_VFS>, &'static str> {
+        let mut vfs = AVec::new();
+        for index in 0..self.num_vfs(pci)? {
+            if vfs.push(self.vf_address(pci, index)?).is_err() {
+                log::warn!("Too many VFs, ignoring the rest");
+                break;
+            }
+        }
+        Ok(vfs)
+    }
+
+    /// Return BAR `bar` of VF `index`.
+    pub fn vf_bar(&self, bar: usize, index: u16) -> Option<PciBarAddr> {
+        let first = self.vf_bars.get(bar).copied().flatten()?;
+        let base = first.base() + first.size() as u64 * u64::from(index);
+        Some(unsafe { PciBarAddr::new(base, first.size()) })
+    }
+}
+
+fn read_u16(pci: &PciAddress, offset: u16) -> Result<u16, &'static str> {
+    let dword = pci_read_ext(pci, offset & !0x3)?;
+    Ok((dword >> ((offset & 0x2) * 8)) as u16)
+}
+
+/// Write the SR-IOV Control register. The Status register next to it is write-1-to-clear, so
+/// it is written as 0.
+fn write_control(pci: &PciAddress, offset: u16, control: u16) -> Result<(), &'static str> {
+    pci_write_ext(pci, offset + SriovCapability::CONTROL, u32::from(control))
+}
+
+fn write_u16(pci: &PciAddress, offset: u16, value: u16) -> Result<(), &'static str> {
+    let shift = (offset & 0x2) * 8;
+    let dword = pci_read_ext(pci, offset & !0x3)? & !(0xffff << shift);
+    pci_write_ext(pci, offset & !0x3, dword | u32::from(value) << shift)
+}
@@ -0,0 +1,222 @@
+//! Enumeration of the PCI hierarchy.
+//!
+//! Every segment is walked from its root bus down through PCI-to-PCI bridges, following the
+//! secondary bus numbers the firmware programmed. Only buses that are actually reached are
+//! scanned, and the resulting tree records which bridge every function sits behind.
+
+use alloc::vec::Vec;
+
+use crate::class::PciClass;
+use crate::ecam::for_each_ecam_region;
+use crate::header::{read_config_space, PciHeaderError};
+use crate::utils::{pci_read, PciAddress, PciDevice};
+
+/// A function in the PCI hierarchy.
+#[derive(Debug)]
+pub struct PciNode {
+    pub device: PciDevice,
+    /// Index of the bridge the function is behind, `None` on a root bus.
+    pub parent: Option<usize>,
+    /// Number of bridges between the function and its root bus.
+    pub depth: usize,
+}
+
+/// Buses of a segment already scanned. Misprogrammed bridges can point back at buses above
+/// them, which would otherwise make the walk loop.
+struct ScannedBuses([u64; 4]);
+
+impl ScannedBuses {
+    fn new() -> Self {
+        ScannedBuses([0; 4])
+    }
+
+    fn contains(&self, bus: u8) -> bool {
+        self.0[bus as usize / 64] & (1 << (bus % 64)) != 0
+    }
+
+    /// Mark `bus` as scanned, returning false if it already was.
+    fn insert(&mut self, bus: u8) -> bool {
+        let was_scanned = self.contains(bus);
+        self.0[bus as usize / 64] |= 1 << (bus % 64);
+        !was_scanned
+    }
+}
+
+/// The PCI functions of the system, in the order they were found.
+///
+/// A bridge always comes before the functions behind it.
+#[derive(Debug, Default)]
+pub struct PciTree {
+    nodes: Vec<PciNode>,
+}
+
+impl PciTree {
+    /// Enumerate every segment with an ECAM region, and segment 0 through port I/O if no
+    /// region covers it.
+    pub fn enumerate() -> PciTree {
+        let mut tree = PciTree::default();
+        let mut has_segment_0 = false;
+
+        for_each_ecam_region(|segment, start_bus, end_bus| {
+            has_segment_0 |= segment == 0;
+            tree.scan_segment(segment, start_bus, end_bus);
+        });
+        if !has_segment_0 {
+            tree.scan_segment(0, 0, 0xff);
+        }
+        tree
+    }
+
+    fn scan_segment(&mut self, segment: u16, start_bus: u8, end_bus: u8) {
+        let mut scanned = ScannedBuses::new();
+        self.scan_bus(segment, start_bus, None, 0, &mut scanned);
+
+        // Host bridges other than the first, e.g. the root complexes of other sockets, have
+        // root buses that no bridge leads to. They are only found by probing.
+        for bus in start_bus..=end_bus {
+            if !scanned.contains(bus) && bus_has_devices(segment, bus) {
+                self.scan_bus(segment, bus, None, 0, &mut scanned);
+            }
+        }
+    }
+
+    fn scan_bus(
+        &mut self,
+        segment: u16,
+        bus: u8,
+        parent: Option<usize>,
+        depth: usize,
+        scanned: &mut ScannedBuses,
+    ) {
+        if !scanned.insert(bus) {
+            log::warn!("PCI bus {:04x}:{:02x} reached twice", segment, bus);
+            return;
+        }
+
+        for dev in 0..32 {
+            let pci_addr = PciAddress::with_segment(segment, bus, dev, 0).unwrap();
+            if !function_present(&pci_addr) {
+                continue;
+            }
+            let num_funcs = if is_multifunction(&pci_addr) { 8 } else { 1 };
+
+            for func in 0..num_funcs {
+                let pci_addr = PciAddress::with_segment(segment, bus, dev, func).unwrap();
+                match read_config_space(&pci_addr) {
+                    Ok(hdr) => {
+                        let device = unsafe { PciDevice::new(pci_addr, hdr) };
+                        self.add(device, bus, parent, depth, scanned);
+                    }
+                    Err(PciHeaderError::NoDevice) => {}
+                    Err(e) => log::warn!("Skipping PCI function {:?}: {:?}", pci_addr, e),
+                }
+            }
+        }
+    }
+
+    fn add(
+        &mut self,
+        device: PciDevice,
+        bus: u8,
+        parent: Option<usize>,
+        depth: usize,
+        scanned: &mut ScannedBuses,
+    ) {
+        let secondary_bus = device.pci_hdr.hdr.secondary_bus_num();
+        let segment = device.pci_addr.segment();
+        let index = self.nodes.len();
+        self.nodes.push(PciNode {
+            device,
+            parent,
+            depth,
+        });
+
+        // Bus numbers are taken as the firmware assigned them
+        match secondary_bus {
+            Some(secondary) if secondary > bus => {
+                self.scan_bus(segment, secondary, Some(index), depth + 1, scanned);
+            }
+            Some(_) => log::warn!(
+                "PCI bridge {:?} has no bus number assigned",
+                self.nodes[index].device.pci_addr
+            ),
+            None => {}
+        }
+    }
+
+    /// Return all functions.
+    pub fn nodes(&self) -> &[PciNode] {
+        &self.nodes
+    }
+
+    /// Return the function at `index`.
+    pub fn node(&self, index: usize) -> Option<&PciNode> {
+        self.nodes.get(index)
+    }
+
+    /// Return all functions, as devices.
+    pub fn devices(&self) -> impl Iterator<Item = &PciDevice> {
+        self.nodes.iter().map(|node| &node.device)
+    }
+
+    /// Return the function at `segment:bus:dev.func`.
+    pub fn find(&self, segment: u16, bus: u8, dev: u8, func: u8) -> Option<&PciDevice> {
+        let pci_addr = PciAddress::with_segment(segment, bus, dev, func).ok()?;
+        self.devices().find(|device| device.pci_addr == pci_addr)
+    }
+
+    /// Return the functions of class `class`, restricted to `subclass` if given.
+    pub fn find_by_class(
+        &self,
+        class: PciClass,
+        subclass: Option<u8>,
+    ) -> impl Iterator<Item = &PciDevice> {
+        self.devices().filter(move |device| {
+            device.class() == class && subclass.map_or(true, |s| device.subclass() == s)
+        })
+    }
+
+    /// Return the functions from `vendor_id`, restricted to `device_id` if given.
+    pub fn find_by_id(
+        &self,
+        vendor_id: u16,
+        device_id: Option<u16>,
+    ) -> impl Iterator<Item = &PciDevice> {
+        self.devices().filter(move |device| {
+            device.vendor_id() == vendor_id && device_id.map_or(true, |d| device.device_id() == d)
+        })
+    }
+
+    /// Return the bridge the function at `index` is behind.
+    pub fn parent(&self, index: usize) -> Option<&PciNode> {
+        self.nodes
+            .get(index)?
+            .parent
+            .map(|parent| &self.nodes[parent])
+    }
+
+    /// Return the functions directly behind the bridge at `index`.
+    pub fn children(&self, index: usize) -> impl Iterator<Item = &PciNode> {
+        self.nodes
+            .iter()
+            .filter(move |node| node.parent == Some(index))
+    }
+
+    /// Consume the tree, returning the functions in the order they were found.
+    pub fn into_devices(self) -> Vec<PciDevice> {
+        self.nodes.into_iter().map(|node| node.device).collect()
+    }
+}
+
+fn function_present(pci_addr: &PciAddress) -> bool {
+    pci_read(pci_addr, 0) & 0xFFFF != 0xFFFF
+}
+
+fn is_multifunction(pci_addr: &PciAddress) -> bool {
+    // Header Type is the third byte of the dword at 0x0c
+    (pci_read(pci_addr, 0x0c) >> 16) & 0x80 != 0
+}
+
+fn bus_has_devices(segment: u16, bus: u8) -> bool {
+    (0..32).any(|dev| function_present(&PciAddress::with_segment(segment, bus, dev, 0).unwrap()))
+}
@@ -1,6 +1,9 @@
+use crate::cap::EXT_CAP_ID_SRIOV;
 use crate::class::PciClass;
+use crate::ecam::{ecam_read, ecam_write};
 use crate::header::{read_config_space, PciDeviceHeader};
 use crate::println;
+use crate::sriov::SriovCapability;
 use crate::PciHeaderType;
 use alloc::vec::Vec;
 use byteorder::{ByteOrder, LittleEndian};
@@ -12,7 +15,15 @@ use core::arch::asm;
 const PCI_ADDR_PORT: u16 = 0xCF8;
 const PCI_DATA_PORT: u16 = 0xCFC;
 
-const BASE_PAGE_SIZE: u32 = 4096;
+const BASE_PAGE_SIZE: u64 = 4096;
+
+const PCI_COMMAND_IO: u32 = 1 << 0;
+const PCI_COMMAND_MEMORY: u32 = 1 << 1;
+
+const PCI_BAR_IO: u32 = 1 << 0;
+const PCI_BAR_TYPE_MASK: u32 = 0b11 << 1;
+const PCI_BAR_TYPE_64: u32 = 0b10 << 1;
+const PCI_BAR_PREFETCHABLE: u32 = 1 << 3;
 
 macro_rules! round_up {
     ($num:expr, $s:expr) => {
@@ -46,12 +57,26 @@ impl PciDevice {
     pub fn device_id(&self) -> u16 {
         self.pci_hdr.device_id()
     }
+
+    /// Return the SR-IOV capability if the device is a physical function.
+    pub fn sriov(&self) -> Option<SriovCapability> {
+        let cap = self.pci_hdr.ext_capability(EXT_CAP_ID_SRIOV)?;
+        match SriovCapability::read(&self.pci_addr, cap) {
+            Ok(sriov) => Some(sriov),
+            Err(e) => {
+                log::warn!("Failed to read the SR-IOV capability: {}", e);
+                None
+            }
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug)]
 pub struct PciBarAddr {
     base: u64,
     size: usize,
+    is_64bit: bool,
+    prefetchable: bool,
 }
 
 impl fmt::Display for PciBarAddr {
@@ -61,7 +86,14 @@ impl fmt::Display for PciBarAddr {
                 f,
                 "Memory base = 0x{:08X}, size = 0x{:04X}",
                 self.base, self.size
-            )
+            )?;
+            if self.is_64bit {
+                write!(f, ", 64-bit")?;
+            }
+            if self.prefetchable {
+                write!(f, ", prefetchable")?;
+            }
+            Ok(())
         } else {
             write!(f, "Port = 0x{:04X}", self.base)
         }
@@ -81,8 +113,35 @@ impl PciBarAddr {
     pub fn size(&self) -> usize {
         self.size
     }
+    /// Return whether the BAR takes two slots to hold a 64-bit address.
+    pub fn is_64bit(&self) -> bool {
+        self.is_64bit
+    }
+    /// Return whether the memory behind the BAR is prefetchable.
+    pub fn is_prefetchable(&self) -> bool {
+        self.prefetchable
+    }
     pub unsafe fn new(base: u64, size: usize) -> PciBarAddr {
-        PciBarAddr { base, size }
+        PciBarAddr {
+            base,
+            size,
+            is_64bit: false,
+            prefetchable: false,
+        }
+    }
+
+    pub(crate) unsafe fn new_memory(
+        base: u64,
+        size: usize,
+        is_64bit: bool,
+        prefetchable: bool,
+    ) -> PciBarAddr {
+        PciBarAddr {
+            base,
+            size,
+            is_64bit,
+            prefetchable,
+        }
     }
 
     pub unsafe fn get_base(&self) -> u64 {
@@ -94,24 +153,44 @@ impl PciBarAddr {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct PciAddress {
+    segment: u16,
     bus: u8,
     dev: u8,
     func: u8,
 }
 
 impl PciAddress {
-    fn new(bus: u8, dev: u8, func: u8) -> Result<PciAddress, &'static str> {
+    pub(crate) fn new(bus: u8, dev: u8, func: u8) -> Result<PciAddress, &'static str> {
+        PciAddress::with_segment(0, bus, dev, func)
+    }
+
+    pub(crate) fn with_segment(
+        segment: u16,
+        bus: u8,
+        dev: u8,
+        func: u8,
+    ) -> Result<PciAddress, &'static str> {
         // bus is implicitly checked because it's u8
         if
         /* bus <= 255 && */
         dev < 32 && func < 8 {
-            Ok(PciAddress { bus, dev, func })
+            Ok(PciAddress {
+                segment,
+                bus,
+                dev,
+                func,
+            })
         } else {
             Err("Invalid pci address")
         }
     }
+
+    /// Return the PCI segment (domain) the function is in.
+    pub fn segment(&self) -> u16 {
+        self.segment
+    }
 }
 
 impl Into<(u8, u8, u8)> for PciAddress {
@@ -139,49 +218,84 @@ pub fn pci_read_bars(pci: &PciAddress, hdr_type: PciHeaderType) -> AVec<Option<P
     // Baraddresses start from offset 0x10 in the config space and there can be a 2-6 bars each
     // 32-bit in size depending on the PCI device type
     let start = 0x10;
-    let end: u8;
-    match hdr_type & PciHeaderType::HEADER_TYPE {
-        PciHeaderType::GENERAL => {
-            end = start + 6 * 4;
-        }
+    let count: u8 = match hdr_type & PciHeaderType::HEADER_TYPE {
+        PciHeaderType::GENERAL => 6,
+        PciHeaderType::PCITOPCI => 2,
+        _ => 0,
+    };
+
+    // Turn off decoding while the BARs hold all 1's, otherwise the device could claim
+    // accesses meant for someone else
+    let command = pci_read(pci, 4) & 0xFFFF;
+    if count > 0 {
+        pci_write(pci, 4, command & !(PCI_COMMAND_IO | PCI_COMMAND_MEMORY));
+    }
 
-        PciHeaderType::PCITOPCI => {
-            end = start + 2 * 4;
+    let mut i = 0;
+    while i < count {
+        let off = start + 4 * i;
+        let low = pci_read(pci, off);
+        if low & PCI_BAR_IO != 0 {
+            if low & 0xFFFF_FFFC == 0 {
+                bar_vec.push(None).unwrap();
+            } else {
+                unsafe {
+                    bar_vec
+                        .push(Some(PciBarAddr::new(low as u64 & 0xFFFC, 0usize)))
+                        .unwrap();
+                }
+            }
+            i += 1;
+            continue;
         }
 
-        _ => end = start,
-    }
+        let is_64bit = low & PCI_BAR_TYPE_MASK == PCI_BAR_TYPE_64 && i + 1 < count;
+        let prefetchable = low & PCI_BAR_PREFETCHABLE != 0;
+        let high = if is_64bit { pci_read(pci, off + 4) } else { 0 };
+
+        // Write all 1's to the pci config space, read it back and restore the original bar
+        // address. The upper half of a 64-bit BAR is sized along with the lower one.
+        pci_write(pci, off, 0xFFFF_FFFF);
+        let mut mask = u64::from(pci_read(pci, off) & 0xFFFF_FFF0);
+        pci_write(pci, off, low);
+        if is_64bit {
+            pci_write(pci, off + 4, 0xFFFF_FFFF);
+            mask |= u64::from(pci_read(pci, off + 4)) << 32;
+            pci_write(pci, off + 4, high);
+        } else {
+            mask |= 0xFFFF_FFFF_0000_0000;
+        }
 
-    for off in (start..end).step_by(4) {
-        let mut addr = pci_read(pci, off);
-        if addr & 0xFFFF_FFFC == 0 {
+        let base = u64::from(low & 0xFFFF_FFF0) | u64::from(high) << 32;
+        if base == 0 || mask == 0xFFFF_FFFF_0000_0000 {
             bar_vec.push(None).unwrap();
-        } else if addr & 1 == 0 {
-            addr &= 0xFFFF_FFF0;
-            // Write all 1's to the pci config space
-            pci_write(pci, off, 0xFFFF_FFFF);
-            // Read it back, unmask, add 1 to determine size
-            let size = round_up!((!pci_read(pci, off)) + 1, BASE_PAGE_SIZE);
-            // Restore the original bar address
-            pci_write(pci, off, addr);
+        } else {
+            // Unmask, add 1 to determine size
+            let size = round_up!((!mask).wrapping_add(1), BASE_PAGE_SIZE);
             unsafe {
                 bar_vec
-                    .push(Some(PciBarAddr::new(
-                        addr as u64 & 0xFFFF_FFF0,
+                    .push(Some(PciBarAddr::new_memory(
+                        base,
                         size as usize,
+                        is_64bit,
+                        prefetchable,
                     )))
                     .unwrap();
             }
-            //println!("BarAddr {:x} size {:x}", addr, size);
+            //println!("BarAddr {:x} size {:x}", base, size);
+        }
+        if is_64bit {
+            // The upper half is not a BAR of its own
+            bar_vec.push(None).unwrap();
+            i += 2;
         } else {
-            unsafe {
-                bar_vec
-                    .push(Some(PciBarAddr::new(addr as u64 & 0xFFFC, 0usize)))
-                    .unwrap();
-            }
-            //println!("Bar I/O Addr {:x} size {:x}", addr, size);
+            i += 1;
         }
     }
+
+    if count > 0 {
+        pci_write(pci, 4, command);
+    }
     bar_vec
 }
 
@@ -202,7 +316,18 @@ pub fn pci_read_range(pci: &PciAddress, offset: u8, len: u8) -> Vec<u8> {
     ret
 }
 
+/// Read the dword at `offset` of the configuration space of `pci`.
+///
+/// ECAM is used if it covers the function, port I/O otherwise. Port I/O only reaches
+/// segment 0, functions of other segments without ECAM read as absent.
 pub fn pci_read(pci: &PciAddress, offset: u8) -> u32 {
+    if let Some(value) = ecam_read(pci, offset.into()) {
+        return value;
+    }
+    if pci.segment != 0 {
+        return 0xFFFF_FFFF;
+    }
+
     let address = 0x80000000
         | ((pci.bus as u32) << 16)
         | ((pci.dev as u32) << 11)
@@ -221,7 +346,14 @@ pub fn pci_read(pci: &PciAddress, offset: u8) -> u32 {
     value
 }
 
+/// Write the dword at `offset` of the configuration space of `pci`.
+///
+/// See `pci_read` for how the function is reached.
 pub fn pci_write(pci: &PciAddress, offset: u8, value: u32) {
+    if ecam_write(pci, offset.into(), value) || pci.segment != 0 {
+        return;
+    }
+
     let address = 0x80000000
         | ((pci.bus as u32) << 16)
         | ((pci.dev as u32) << 11)
@@ -240,6 +372,14 @@ pub fn pci_write(pci: &PciAddress, offset: u8, value: u32) {
     }
 }
 
+/// Write the 16-bit register at `offset`, which must be 2-byte aligned, preserving the other
+/// half of the dword it lives in.
+pub fn pci_write_u16(pci: &PciAddress, offset: u8, value: u16) {
+    let shift = (offset & 0x2) * 8;
+    let dword = pci_read(pci, offset) & !(0xFFFF << shift);
+    pci_write(pci, offset, dword | (u32::from(value) << shift));
+}
+
 pub fn pci_enable_bus_mastering(pci_addr: &PciAddress) {
     let cmd_status = pci_read(pci_addr, 4);
     let mut command = cmd_status & 0xFFFF;
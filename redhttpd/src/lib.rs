@@ -7,6 +7,10 @@
 
 extern crate alloc;
 
+mod phy;
+
+pub use phy::NetPhy;
+
 #[cfg(not(target_os = "linux"))]
 use alloc::vec;
 
@@ -0,0 +1,149 @@
+// smoltcp device on top of any network driver
+
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use interface::net::NetDevice;
+use smoltcp::phy::{self, DeviceCapabilities, Medium};
+use smoltcp::time::Instant;
+
+/// Receive buffers kept posted to the driver.
+const RX_BATCH: usize = 32;
+const RX_BUFFER_SIZE: usize = 2048;
+const MTU: usize = 1514;
+/// Packets waiting for room in the driver before `transmit` stops handing out tokens.
+const TX_PENDING_MAX: usize = 64;
+
+/// Lets smoltcp send and receive through a `NetDevice`, so the server runs on whichever
+/// driver the machine has.
+pub struct NetPhy<D: NetDevice> {
+    dev: D,
+    received: VecDeque<Vec<u8>>,
+    /// Packets the driver had no room for yet, sent on the next poll.
+    tx_pending: VecDeque<Vec<u8>>,
+    /// Transmitted packets, reused for the next ones.
+    tx_done: VecDeque<Vec<u8>>,
+    num_rx_posted: usize,
+}
+
+impl<D: NetDevice> NetPhy<D> {
+    pub fn new(dev: D) -> Self {
+        Self {
+            dev,
+            received: VecDeque::new(),
+            tx_pending: VecDeque::new(),
+            tx_done: VecDeque::new(),
+            num_rx_posted: 0,
+        }
+    }
+
+    pub fn device(&mut self) -> &mut D {
+        &mut self.dev
+    }
+
+    /// Top up the receive buffers and collect received packets.
+    fn poll_rx(&mut self) {
+        let mut buffers: VecDeque<Vec<u8>> = (self.num_rx_posted..RX_BATCH)
+            .map(|_| Vec::with_capacity(RX_BUFFER_SIZE))
+            .collect();
+        let new_buffers = buffers.len();
+        let already_received = self.received.len();
+        self.dev
+            .submit_and_poll(&mut buffers, &mut self.received, false);
+
+        let posted = self.num_rx_posted + new_buffers - buffers.len();
+        let received = self.received.len() - already_received;
+        self.num_rx_posted = posted.saturating_sub(received);
+    }
+
+    /// Retry the packets the driver had no room for.
+    fn poll_tx(&mut self) {
+        if !self.tx_pending.is_empty() {
+            self.dev
+                .submit_and_poll(&mut self.tx_pending, &mut self.tx_done, true);
+        }
+    }
+}
+
+impl<D: NetDevice> phy::Device for NetPhy<D> {
+    type RxToken<'a>
+        = RxToken
+    where
+        Self: 'a;
+    type TxToken<'a>
+        = TxToken<'a, D>
+    where
+        Self: 'a;
+
+    fn receive(&mut self, _timestamp: Instant) -> Option<(Self::RxToken<'_>, Self::TxToken<'_>)> {
+        self.poll_tx();
+        if self.received.is_empty() {
+            self.poll_rx();
+        }
+        let packet = self.received.pop_front()?;
+        Some((
+            RxToken(packet),
+            TxToken {
+                dev: &mut self.dev,
+                tx_pending: &mut self.tx_pending,
+                tx_done: &mut self.tx_done,
+            },
+        ))
+    }
+
+    fn transmit(&mut self, _timestamp: Instant) -> Option<Self::TxToken<'_>> {
+        self.poll_tx();
+        // let smoltcp hold on to its packets while the driver is backed up
+        if self.tx_pending.len() >= TX_PENDING_MAX {
+            return None;
+        }
+        Some(TxToken {
+            dev: &mut self.dev,
+            tx_pending: &mut self.tx_pending,
+            tx_done: &mut self.tx_done,
+        })
+    }
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        let mut caps = DeviceCapabilities::default();
+        caps.medium = Medium::Ethernet;
+        caps.max_transmission_unit = MTU;
+        caps.max_burst_size = Some(RX_BATCH);
+        caps
+    }
+}
+
+pub struct RxToken(Vec<u8>);
+
+impl phy::RxToken for RxToken {
+    fn consume<R, F>(mut self, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        f(&mut self.0)
+    }
+}
+
+pub struct TxToken<'a, D: NetDevice> {
+    dev: &'a mut D,
+    tx_pending: &'a mut VecDeque<Vec<u8>>,
+    tx_done: &'a mut VecDeque<Vec<u8>>,
+}
+
+impl<'a, D: NetDevice> phy::TxToken for TxToken<'a, D> {
+    fn consume<R, F>(self, len: usize, f: F) -> R
+    where
+        F: FnOnce(&mut [u8]) -> R,
+    {
+        let mut packet = self.tx_done.pop_front().unwrap_or_default();
+        packet.clear();
+        packet.resize(len, 0);
+        let result = f(&mut packet);
+
+        // packets the driver has no room for stay queued, in order, for the next poll
+        self.tx_pending.push_back(packet);
+        self.dev
+            .submit_and_poll(self.tx_pending, self.tx_done, true);
+        result
+    }
+}
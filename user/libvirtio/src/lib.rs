@@ -0,0 +1,57 @@
+//! Virtio 1.x over PCI, shared by the virtio device drivers.
+//!
+//! Only the modern PCI transport is supported: the device registers are found through the
+//! vendor-specific capabilities and accessed through a memory BAR. Queues use the split
+//! virtqueue layout and are polled, no interrupts are set up.
+
+#![no_std]
+
+extern crate alloc;
+
+mod pci;
+mod queue;
+
+pub use pci::{VirtioPciCap, VirtioPciCaps, VirtioTransport};
+pub use queue::{VirtQueue, QUEUE_SIZE};
+
+/// Vendor ID of all virtio PCI devices.
+pub const VIRTIO_PCI_VENDOR_ID: u16 = 0x1af4;
+
+/// Modern devices use PCI device ID 0x1040 + virtio device ID.
+pub const VIRTIO_PCI_MODERN_DEVICE_ID_BASE: u16 = 0x1040;
+
+/// Virtio device IDs.
+pub const VIRTIO_ID_NET: u16 = 1;
+pub const VIRTIO_ID_BLOCK: u16 = 2;
+
+/// Device status bits.
+pub const VIRTIO_STATUS_ACKNOWLEDGE: u8 = 1;
+pub const VIRTIO_STATUS_DRIVER: u8 = 2;
+pub const VIRTIO_STATUS_DRIVER_OK: u8 = 4;
+pub const VIRTIO_STATUS_FEATURES_OK: u8 = 8;
+pub const VIRTIO_STATUS_NEEDS_RESET: u8 = 64;
+pub const VIRTIO_STATUS_FAILED: u8 = 128;
+
+/// Feature bits common to all devices.
+pub const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+/// The device goes through the IOMMU, so buffer addresses are I/O virtual addresses.
+pub const VIRTIO_F_ACCESS_PLATFORM: u64 = 1 << 33;
+
+/// Return the PCI device ID of the modern virtio device `virtio_id`.
+pub const fn modern_device_id(virtio_id: u16) -> u16 {
+    VIRTIO_PCI_MODERN_DEVICE_ID_BASE + virtio_id
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Error {
+    /// The device lacks a capability or BAR the transport needs.
+    Config(&'static str),
+    /// The device did not accept the features we asked for.
+    FeaturesRejected,
+    /// The device does not have the requested queue.
+    NoQueue(u16),
+    /// The device reported an error and needs a reset.
+    DeviceFailed,
+}
+
+pub type Result<T> = core::result::Result<T, Error>;
@@ -0,0 +1,311 @@
+use core::ptr;
+
+use pcid::utils::{pci_read, PciBarAddr, PciDevice};
+use pcid::PciCapability;
+
+use crate::queue::{VirtQueue, QUEUE_SIZE};
+use crate::{
+    Error, Result, VIRTIO_F_VERSION_1, VIRTIO_STATUS_ACKNOWLEDGE, VIRTIO_STATUS_DRIVER,
+    VIRTIO_STATUS_DRIVER_OK, VIRTIO_STATUS_FAILED, VIRTIO_STATUS_FEATURES_OK,
+    VIRTIO_STATUS_NEEDS_RESET,
+};
+
+/// Capability ID of vendor-specific capabilities, which virtio uses to locate its registers.
+const PCI_CAP_ID_VENDOR: u8 = 0x09;
+
+const VIRTIO_PCI_CAP_COMMON_CFG: u8 = 1;
+const VIRTIO_PCI_CAP_NOTIFY_CFG: u8 = 2;
+const VIRTIO_PCI_CAP_ISR_CFG: u8 = 3;
+const VIRTIO_PCI_CAP_DEVICE_CFG: u8 = 4;
+
+// Layout of struct virtio_pci_common_cfg
+const COMMON_DEVICE_FEATURE_SELECT: usize = 0x00;
+const COMMON_DEVICE_FEATURE: usize = 0x04;
+const COMMON_DRIVER_FEATURE_SELECT: usize = 0x08;
+const COMMON_DRIVER_FEATURE: usize = 0x0c;
+const COMMON_MSIX_CONFIG: usize = 0x10;
+const COMMON_NUM_QUEUES: usize = 0x12;
+const COMMON_DEVICE_STATUS: usize = 0x14;
+const COMMON_CONFIG_GENERATION: usize = 0x15;
+const COMMON_QUEUE_SELECT: usize = 0x16;
+const COMMON_QUEUE_SIZE: usize = 0x18;
+const COMMON_QUEUE_MSIX_VECTOR: usize = 0x1a;
+const COMMON_QUEUE_ENABLE: usize = 0x1c;
+const COMMON_QUEUE_NOTIFY_OFF: usize = 0x1e;
+const COMMON_QUEUE_DESC: usize = 0x20;
+const COMMON_QUEUE_DRIVER: usize = 0x28;
+const COMMON_QUEUE_DEVICE: usize = 0x30;
+
+const VIRTIO_MSI_NO_VECTOR: u16 = 0xffff;
+
+/// Where a virtio register block lives.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VirtioPciCap {
+    pub bar: u8,
+    pub offset: u32,
+    pub length: u32,
+}
+
+/// The register blocks of a modern virtio PCI device.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VirtioPciCaps {
+    pub common: VirtioPciCap,
+    pub notify: VirtioPciCap,
+    pub notify_off_multiplier: u32,
+    pub isr: VirtioPciCap,
+    /// Absent for devices without device-specific configuration.
+    pub device: Option<VirtioPciCap>,
+}
+
+impl VirtioPciCaps {
+    /// Find the register blocks in the capability list of `dev`.
+    pub fn read(dev: &PciDevice) -> Result<VirtioPciCaps> {
+        let mut common = None;
+        let mut notify = None;
+        let mut isr = None;
+        let mut device = None;
+
+        for cap in dev.pci_hdr.capabilities() {
+            let offset = match *cap {
+                PciCapability::Other { id, offset } if id == PCI_CAP_ID_VENDOR => offset,
+                _ => continue,
+            };
+            // cap_vndr, cap_next, cap_len, cfg_type, then bar, id and padding
+            let header = pci_read(&dev.pci_addr, offset);
+            let cfg_type = (header >> 24) as u8;
+            let cap = VirtioPciCap {
+                bar: pci_read(&dev.pci_addr, offset + 4) as u8,
+                offset: pci_read(&dev.pci_addr, offset + 8),
+                length: pci_read(&dev.pci_addr, offset + 12),
+            };
+
+            // The first capability of each type is the preferred one
+            match cfg_type {
+                VIRTIO_PCI_CAP_COMMON_CFG if common.is_none() => common = Some(cap),
+                VIRTIO_PCI_CAP_NOTIFY_CFG if notify.is_none() => {
+                    let multiplier = pci_read(&dev.pci_addr, offset + 16);
+                    notify = Some((cap, multiplier));
+                }
+                VIRTIO_PCI_CAP_ISR_CFG if isr.is_none() => isr = Some(cap),
+                VIRTIO_PCI_CAP_DEVICE_CFG if device.is_none() => device = Some(cap),
+                _ => {}
+            }
+        }
+
+        let (notify, notify_off_multiplier) =
+            notify.ok_or(Error::Config("No notification capability"))?;
+        Ok(VirtioPciCaps {
+            common: common.ok_or(Error::Config("No common configuration capability"))?,
+            notify,
+            notify_off_multiplier,
+            isr: isr.ok_or(Error::Config("No ISR capability"))?,
+            device,
+        })
+    }
+
+    /// Return the BARs the register blocks are in, each at most once.
+    pub fn bars(&self) -> impl Iterator<Item = u8> {
+        let bars = [
+            Some(self.common.bar),
+            Some(self.notify.bar),
+            Some(self.isr.bar),
+            self.device.map(|cap| cap.bar),
+        ];
+        (0..6u8).filter(move |bar| bars.contains(&Some(*bar)))
+    }
+}
+
+/// Register access for a modern virtio PCI device.
+pub struct VirtioTransport {
+    common: *mut u8,
+    notify: *mut u8,
+    notify_off_multiplier: u32,
+    isr: *mut u8,
+    device: Option<*mut u8>,
+    device_len: usize,
+}
+
+impl VirtioTransport {
+    /// Create the transport from the register blocks in `caps`.
+    ///
+    /// # Safety
+    /// `bars[i]` must hold the virtual address BAR `i` is mapped at, uncached, for every BAR in
+    /// `caps.bars()`.
+    pub unsafe fn new(bars: &[Option<PciBarAddr>], caps: &VirtioPciCaps) -> Result<Self> {
+        let map = |cap: &VirtioPciCap| -> Result<*mut u8> {
+            let bar = bars
+                .get(cap.bar as usize)
+                .copied()
+                .flatten()
+                .ok_or(Error::Config("Register block in an unmapped BAR"))?;
+            if cap.offset as usize + cap.length as usize > bar.size() {
+                return Err(Error::Config("Register block beyond the end of its BAR"));
+            }
+            Ok((bar.base() as usize + cap.offset as usize) as *mut u8)
+        };
+
+        Ok(VirtioTransport {
+            common: map(&caps.common)?,
+            notify: map(&caps.notify)?,
+            notify_off_multiplier: caps.notify_off_multiplier,
+            isr: map(&caps.isr)?,
+            device: caps.device.as_ref().map(map).transpose()?,
+            device_len: caps.device.map_or(0, |cap| cap.length as usize),
+        })
+    }
+
+    fn read_common<T>(&self, offset: usize) -> T {
+        unsafe { ptr::read_volatile(self.common.add(offset) as *const T) }
+    }
+
+    fn write_common<T>(&self, offset: usize, value: T) {
+        unsafe { ptr::write_volatile(self.common.add(offset) as *mut T, value) }
+    }
+
+    /// Write a 64-bit queue address as two dwords, the register is only 4-byte aligned.
+    fn write_common_u64(&self, offset: usize, value: u64) {
+        self.write_common::<u32>(offset, value as u32);
+        self.write_common::<u32>(offset + 4, (value >> 32) as u32);
+    }
+
+    pub fn status(&self) -> u8 {
+        self.read_common(COMMON_DEVICE_STATUS)
+    }
+
+    fn add_status(&self, status: u8) {
+        self.write_common(COMMON_DEVICE_STATUS, self.status() | status);
+    }
+
+    /// Reset the device and wait for the reset to complete.
+    pub fn reset(&self) {
+        self.write_common::<u8>(COMMON_DEVICE_STATUS, 0);
+        while self.status() != 0 {
+            core::hint::spin_loop();
+        }
+    }
+
+    pub fn device_features(&self) -> u64 {
+        self.write_common::<u32>(COMMON_DEVICE_FEATURE_SELECT, 0);
+        let low: u32 = self.read_common(COMMON_DEVICE_FEATURE);
+        self.write_common::<u32>(COMMON_DEVICE_FEATURE_SELECT, 1);
+        let high: u32 = self.read_common(COMMON_DEVICE_FEATURE);
+        u64::from(low) | u64::from(high) << 32
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        self.write_common::<u32>(COMMON_DRIVER_FEATURE_SELECT, 0);
+        self.write_common::<u32>(COMMON_DRIVER_FEATURE, features as u32);
+        self.write_common::<u32>(COMMON_DRIVER_FEATURE_SELECT, 1);
+        self.write_common::<u32>(COMMON_DRIVER_FEATURE, (features >> 32) as u32);
+    }
+
+    /// Reset the device and negotiate the features in `supported` it also offers.
+    ///
+    /// VIRTIO_F_VERSION_1 is required. Returns the negotiated features, the driver then sets
+    /// up its queues and calls `driver_ok`.
+    pub fn negotiate(&self, supported: u64) -> Result<u64> {
+        self.reset();
+        self.add_status(VIRTIO_STATUS_ACKNOWLEDGE);
+        self.add_status(VIRTIO_STATUS_DRIVER);
+
+        let features = self.device_features() & supported;
+        if features & VIRTIO_F_VERSION_1 == 0 {
+            self.add_status(VIRTIO_STATUS_FAILED);
+            return Err(Error::Config("Device is not a virtio 1.0 device"));
+        }
+        self.set_driver_features(features);
+
+        self.add_status(VIRTIO_STATUS_FEATURES_OK);
+        if self.status() & VIRTIO_STATUS_FEATURES_OK == 0 {
+            self.add_status(VIRTIO_STATUS_FAILED);
+            return Err(Error::FeaturesRejected);
+        }
+
+        // Interrupts are not used
+        self.write_common(COMMON_MSIX_CONFIG, VIRTIO_MSI_NO_VECTOR);
+        Ok(features)
+    }
+
+    /// Tell the device the driver is ready.
+    pub fn driver_ok(&self) {
+        self.add_status(VIRTIO_STATUS_DRIVER_OK);
+    }
+
+    pub fn num_queues(&self) -> u16 {
+        self.read_common(COMMON_NUM_QUEUES)
+    }
+
+    /// Return the largest size queue `index` supports, 0 if the queue does not exist.
+    pub fn max_queue_size(&self, index: u16) -> u16 {
+        self.write_common(COMMON_QUEUE_SELECT, index);
+        self.read_common(COMMON_QUEUE_SIZE)
+    }
+
+    /// Return the size to use for queue `index`, the largest power of 2 both `QUEUE_SIZE` and
+    /// the device allow.
+    pub fn queue_size(&self, index: u16) -> Result<u16> {
+        let max = self.max_queue_size(index).min(QUEUE_SIZE as u16);
+        if max == 0 {
+            return Err(Error::NoQueue(index));
+        }
+        Ok(1 << (15 - max.leading_zeros()))
+    }
+
+    /// Hand `queue` to the device and enable it.
+    pub fn setup_queue(&self, queue: &mut VirtQueue) -> Result<()> {
+        let index = queue.index();
+        if self.max_queue_size(index) == 0 {
+            return Err(Error::NoQueue(index));
+        }
+
+        self.write_common(COMMON_QUEUE_SIZE, queue.size());
+        self.write_common(COMMON_QUEUE_MSIX_VECTOR, VIRTIO_MSI_NO_VECTOR);
+        self.write_common_u64(COMMON_QUEUE_DESC, queue.desc_addr());
+        self.write_common_u64(COMMON_QUEUE_DRIVER, queue.avail_addr());
+        self.write_common_u64(COMMON_QUEUE_DEVICE, queue.used_addr());
+
+        let notify_off: u16 = self.read_common(COMMON_QUEUE_NOTIFY_OFF);
+        let notify = unsafe {
+            self.notify
+                .add(notify_off as usize * self.notify_off_multiplier as usize)
+        };
+        queue.set_notify(notify as *mut u16);
+
+        self.write_common::<u16>(COMMON_QUEUE_ENABLE, 1);
+        Ok(())
+    }
+
+    /// Read and acknowledge the ISR status.
+    pub fn isr_status(&self) -> u8 {
+        unsafe { ptr::read_volatile(self.isr) }
+    }
+
+    /// Read a field of the device-specific configuration at `offset`.
+    ///
+    /// The read is retried until it does not race with a configuration change.
+    pub fn read_device_config<T: Copy>(&self, offset: usize) -> Result<T> {
+        let device = self
+            .device
+            .ok_or(Error::Config("No device configuration"))?;
+        if offset + core::mem::size_of::<T>() > self.device_len {
+            return Err(Error::Config("Offset beyond the device configuration"));
+        }
+
+        loop {
+            let generation: u8 = self.read_common(COMMON_CONFIG_GENERATION);
+            let value = unsafe { ptr::read_volatile(device.add(offset) as *const T) };
+            if generation == self.read_common::<u8>(COMMON_CONFIG_GENERATION) {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Return an error if the device gave up and needs a reset.
+    pub fn check_status(&self) -> Result<()> {
+        if self.status() & VIRTIO_STATUS_NEEDS_RESET != 0 {
+            Err(Error::DeviceFailed)
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use core::ptr;
+use core::result::Result;
+use core::sync::atomic::{fence, Ordering};
+
+use libdma::{zeroed_allocator, Dma, DmaAllocator};
+
+use crate::Error;
+
+/// Number of entries allocated for every queue. The device may ask for fewer.
+pub const QUEUE_SIZE: usize = 256;
+
+const VIRTQ_DESC_F_NEXT: u16 = 1;
+const VIRTQ_DESC_F_WRITE: u16 = 2;
+
+/// Set by the device in the used ring if it does not need to be notified.
+const VIRTQ_USED_F_NO_NOTIFY: u16 = 1;
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct VirtqDesc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C, align(16))]
+struct VirtqDescTable([VirtqDesc; QUEUE_SIZE]);
+
+#[repr(C, align(2))]
+struct VirtqAvail {
+    flags: u16,
+    idx: u16,
+    ring: [u16; QUEUE_SIZE],
+    used_event: u16,
+}
+
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct VirtqUsedElem {
+    id: u32,
+    len: u32,
+}
+
+#[repr(C, align(4))]
+struct VirtqUsed {
+    flags: u16,
+    idx: u16,
+    ring: [VirtqUsedElem; QUEUE_SIZE],
+    avail_event: u16,
+}
+
+zeroed_allocator!(VirtqDescTable);
+zeroed_allocator!(VirtqAvail);
+zeroed_allocator!(VirtqUsed);
+
+/// A split virtqueue.
+///
+/// Buffers are chains of descriptors identified by the index of their first descriptor. The
+/// caller keeps track of what each chain refers to until the device returns it.
+pub struct VirtQueue {
+    index: u16,
+    size: u16,
+    desc: Dma<VirtqDescTable>,
+    avail: Dma<VirtqAvail>,
+    used: Dma<VirtqUsed>,
+    /// Head of the list of free descriptors, linked through `next`.
+    free_head: u16,
+    num_free: u16,
+    /// Next entry of the used ring to look at.
+    last_used_idx: u16,
+    /// Number of chains added since the last notification.
+    pending: u16,
+    notify: *mut u16,
+}
+
+impl VirtQueue {
+    /// Allocate queue `index` with `size` entries, which must be a power of 2 no larger than
+    /// `QUEUE_SIZE` and the maximum the device supports.
+    pub fn new(index: u16, size: u16) -> Result<VirtQueue, Error> {
+        if size == 0 || !size.is_power_of_two() || size as usize > QUEUE_SIZE {
+            return Err(Error::Config("Invalid queue size"));
+        }
+
+        let mut desc = VirtqDescTable::allocate().map_err(|_| Error::Config("Out of memory"))?;
+        for i in 0..size {
+            desc.0[i as usize].next = i + 1;
+        }
+
+        Ok(VirtQueue {
+            index,
+            size,
+            desc,
+            avail: VirtqAvail::allocate().map_err(|_| Error::Config("Out of memory"))?,
+            used: VirtqUsed::allocate().map_err(|_| Error::Config("Out of memory"))?,
+            free_head: 0,
+            num_free: size,
+            last_used_idx: 0,
+            pending: 0,
+            notify: ptr::null_mut(),
+        })
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn num_free(&self) -> u16 {
+        self.num_free
+    }
+
+    /// Return the head of the chain the next `add` will create, if any descriptor is free.
+    pub fn next_head(&self) -> Option<u16> {
+        if self.num_free == 0 {
+            None
+        } else {
+            Some(self.free_head)
+        }
+    }
+
+    pub(crate) fn desc_addr(&self) -> u64 {
+        self.desc.physical() as u64
+    }
+
+    pub(crate) fn avail_addr(&self) -> u64 {
+        self.avail.physical() as u64
+    }
+
+    pub(crate) fn used_addr(&self) -> u64 {
+        self.used.physical() as u64
+    }
+
+    pub(crate) fn set_notify(&mut self, notify: *mut u16) {
+        self.notify = notify;
+    }
+
+    /// Add a chain made of the device-readable buffers `readable` followed by the
+    /// device-writable buffers `writable`, each given as (DMA address, length).
+    ///
+    /// Returns the head of the chain, or `None` if there are not enough free descriptors.
+    /// The device only sees the chain after `notify`.
+    pub fn add(&mut self, readable: &[(u64, u32)], writable: &[(u64, u32)]) -> Option<u16> {
+        let count = readable.len() + writable.len();
+        if count == 0 || count > self.num_free as usize {
+            return None;
+        }
+
+        let head = self.free_head;
+        let mut last = head;
+        let mut cur = head;
+        let buffers = readable
+            .iter()
+            .map(|buf| (buf, 0))
+            .chain(writable.iter().map(|buf| (buf, VIRTQ_DESC_F_WRITE)));
+        for (&(addr, len), flags) in buffers {
+            let desc = &mut self.desc.0[cur as usize];
+            desc.addr = addr;
+            desc.len = len;
+            desc.flags = flags | VIRTQ_DESC_F_NEXT;
+            last = cur;
+            cur = desc.next;
+        }
+        self.desc.0[last as usize].flags &= !VIRTQ_DESC_F_NEXT;
+        self.free_head = cur;
+        self.num_free -= count as u16;
+
+        let avail_idx = unsafe { ptr::read_volatile(&self.avail.idx) };
+        self.avail.ring[(avail_idx % self.size) as usize] = head;
+        // The descriptors and ring entry must be visible before the index
+        fence(Ordering::SeqCst);
+        unsafe { ptr::write_volatile(&mut self.avail.idx, avail_idx.wrapping_add(1)) };
+        self.pending += 1;
+
+        Some(head)
+    }
+
+    /// Notify the device of the chains added since the last notification.
+    pub fn notify(&mut self) {
+        if self.pending == 0 {
+            return;
+        }
+        self.pending = 0;
+
+        fence(Ordering::SeqCst);
+        let flags = unsafe { ptr::read_volatile(&self.used.flags) };
+        if flags & VIRTQ_USED_F_NO_NOTIFY == 0 && !self.notify.is_null() {
+            unsafe { ptr::write_volatile(self.notify, self.index) };
+        }
+    }
+
+    /// Return whether the device has returned chains not popped yet.
+    pub fn has_used(&self) -> bool {
+        self.last_used_idx != unsafe { ptr::read_volatile(&self.used.idx) }
+    }
+
+    /// Take the next chain returned by the device, as its head and the number of bytes the
+    /// device wrote to it. Its descriptors are free again.
+    pub fn pop_used(&mut self) -> Option<(u16, u32)> {
+        if !self.has_used() {
+            return None;
+        }
+        // Read the entry only after seeing the index
+        fence(Ordering::SeqCst);
+
+        let elem = unsafe {
+            ptr::read_volatile(&self.used.ring[(self.last_used_idx % self.size) as usize])
+        };
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+
+        let head = elem.id as u16;
+        let mut last = head;
+        let mut count = 1;
+        while self.desc.0[last as usize].flags & VIRTQ_DESC_F_NEXT != 0 {
+            last = self.desc.0[last as usize].next;
+            count += 1;
+        }
+        self.desc.0[last as usize].next = self.free_head;
+        self.free_head = head;
+        self.num_free += count;
+
+        Some((head, elem.len))
+    }
+}
@@ -0,0 +1,321 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::{fmt, mem, ptr};
+
+use interface::block::{BlockDevice, BlockStats};
+use libdma::Dma;
+use libvirtio::{
+    Error, Result, VirtQueue, VirtioPciCaps, VirtioTransport, QUEUE_SIZE, VIRTIO_F_ACCESS_PLATFORM,
+    VIRTIO_F_VERSION_1,
+};
+use pcid::utils::PciBarAddr;
+
+use crate::println;
+use crate::{BlockOp, BlockReq, BlockResp};
+
+const VIRTIO_BLK_F_RO: u64 = 1 << 5;
+const VIRTIO_BLK_F_BLK_SIZE: u64 = 1 << 6;
+
+const SUPPORTED_FEATURES: u64 =
+    VIRTIO_F_VERSION_1 | VIRTIO_F_ACCESS_PLATFORM | VIRTIO_BLK_F_RO | VIRTIO_BLK_F_BLK_SIZE;
+
+// Layout of struct virtio_blk_config
+const CONFIG_CAPACITY: usize = 0;
+const CONFIG_BLK_SIZE: usize = 20;
+
+const VIRTIO_BLK_T_IN: u32 = 0;
+const VIRTIO_BLK_T_OUT: u32 = 1;
+
+const VIRTIO_BLK_S_OK: u8 = 0;
+const VIRTIO_BLK_S_IOERR: u8 = 1;
+
+/// Requests address the disk in sectors of 512 bytes, whatever its block size.
+pub const SECTOR_SIZE: usize = 512;
+
+/// The request queue, virtio-blk devices may have more with VIRTIO_BLK_F_MQ.
+const REQUEST_QUEUE: u16 = 0;
+
+/// Header in front of every request, struct virtio_blk_req without the data and status.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct VirtioBlkReqHdr {
+    type_: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+const REQ_HDR_SIZE: usize = mem::size_of::<VirtioBlkReqHdr>();
+
+/// One header and status byte per descriptor, used by the request whose chain starts there.
+#[repr(C)]
+struct RequestHeaders([VirtioBlkReqHdr; QUEUE_SIZE]);
+
+#[repr(C)]
+struct RequestStatus([u8; QUEUE_SIZE]);
+
+pub struct VirtioBlkStats {
+    completed: u64,
+    submitted: u64,
+    failed: u64,
+}
+
+impl VirtioBlkStats {
+    pub fn get_stats(&self) -> (u64, u64) {
+        (self.submitted, self.completed)
+    }
+
+    /// Number of completed requests the device reported an error for.
+    pub fn failed(&self) -> u64 {
+        self.failed
+    }
+
+    pub fn reset_stats(&mut self) {
+        self.submitted = 0;
+        self.completed = 0;
+        self.failed = 0;
+    }
+}
+
+impl fmt::Display for VirtioBlkStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "submitted {} completed {} failed {}",
+            self.submitted, self.completed, self.failed
+        )
+    }
+}
+
+pub struct VirtioBlkDevice {
+    transport: VirtioTransport,
+    features: u64,
+    queue: Option<VirtQueue>,
+    headers: Dma<RequestHeaders>,
+    status: Dma<RequestStatus>,
+    /// Requests owned by the device, indexed by the head of their chain.
+    requests: Vec<Option<BlockReq>>,
+    pub stats: VirtioBlkStats,
+}
+
+impl VirtioBlkDevice {
+    /// Create a driver for the device whose registers are described by `caps`.
+    ///
+    /// # Safety
+    /// `bars` must hold the uncached virtual mappings of the BARs in `caps.bars()`, and the
+    /// device must be bound to the IOMMU table of the caller.
+    pub unsafe fn new(
+        bars: &[Option<PciBarAddr>],
+        caps: &VirtioPciCaps,
+    ) -> Result<VirtioBlkDevice> {
+        let dma_err = |_| Error::Config("Out of memory");
+        Ok(VirtioBlkDevice {
+            transport: VirtioTransport::new(bars, caps)?,
+            features: 0,
+            queue: None,
+            headers: Dma::zeroed().map_err(dma_err)?,
+            status: Dma::zeroed().map_err(dma_err)?,
+            requests: (0..QUEUE_SIZE).map(|_| None).collect(),
+            stats: VirtioBlkStats {
+                completed: 0,
+                submitted: 0,
+                failed: 0,
+            },
+        })
+    }
+
+    /// Reset and initialize the device.
+    pub fn init(&mut self) -> Result<()> {
+        self.features = self.transport.negotiate(SUPPORTED_FEATURES)?;
+        // The reset made the device forget the old queue
+        self.queue = None;
+        self.requests.iter_mut().for_each(|req| *req = None);
+        println!("Negotiated virtio-blk features {:#x}", self.features);
+
+        let mut queue = VirtQueue::new(REQUEST_QUEUE, self.transport.queue_size(REQUEST_QUEUE)?)?;
+        self.transport.setup_queue(&mut queue)?;
+        self.queue = Some(queue);
+
+        self.transport.driver_ok();
+
+        println!(
+            "virtio-blk with {} sectors of {} bytes, block size {}{}",
+            self.capacity(),
+            SECTOR_SIZE,
+            self.optimal_block_size(),
+            if self.read_only() { ", read-only" } else { "" }
+        );
+        Ok(())
+    }
+
+    fn has_feature(&self, features: u64) -> bool {
+        self.features & features == features
+    }
+
+    /// Return the size of the disk in sectors.
+    pub fn capacity(&self) -> u64 {
+        self.transport
+            .read_device_config::<u64>(CONFIG_CAPACITY)
+            .unwrap_or(0)
+    }
+
+    /// Return the block size the disk reports, the smallest efficient request size. Requests
+    /// are still made of `SECTOR_SIZE` sectors.
+    pub fn optimal_block_size(&self) -> u32 {
+        if !self.has_feature(VIRTIO_BLK_F_BLK_SIZE) {
+            return SECTOR_SIZE as u32;
+        }
+        self.transport
+            .read_device_config::<u32>(CONFIG_BLK_SIZE)
+            .unwrap_or(SECTOR_SIZE as u32)
+    }
+
+    pub fn read_only(&self) -> bool {
+        self.has_feature(VIRTIO_BLK_F_RO)
+    }
+
+    /// Submit the requests in `submit`, then move completed requests to `collect`.
+    ///
+    /// `block` and `num_blocks` of every request count `SECTOR_SIZE` sectors, and `data`
+    /// points to a buffer large enough for them. Requests that do not fit in the queue are
+    /// left in `submit`, writes to a read-only disk complete right away with
+    /// `VIRTIO_BLK_S_IOERR`. Returns the number of submitted requests.
+    pub fn submit_and_poll_breq(
+        &mut self,
+        submit: &mut VecDeque<BlockReq>,
+        collect: &mut VecDeque<BlockResp>,
+    ) -> usize {
+        let read_only = self.read_only();
+        let queue = match self.queue.as_mut() {
+            Some(queue) => queue,
+            None => return 0,
+        };
+        let mut sub_count = 0;
+
+        while let Some(breq) = submit.pop_front() {
+            if read_only && breq.op == BlockOp::Write {
+                collect.push_back(BlockResp::new(breq, VIRTIO_BLK_S_IOERR as i32));
+                self.stats.failed += 1;
+                continue;
+            }
+
+            // Three descriptors: header, data and status
+            if queue.num_free() < 3 {
+                submit.push_front(breq);
+                break;
+            }
+
+            // The chain will start at the next free descriptor, fill in the header and status
+            // slots of that descriptor before the device can see the chain
+            let slot = match queue.next_head() {
+                Some(slot) => slot as usize,
+                None => {
+                    submit.push_front(breq);
+                    break;
+                }
+            };
+            self.headers.0[slot] = VirtioBlkReqHdr {
+                type_: if breq.op == BlockOp::Write {
+                    VIRTIO_BLK_T_OUT
+                } else {
+                    VIRTIO_BLK_T_IN
+                },
+                reserved: 0,
+                sector: breq.block,
+            };
+            self.status.0[slot] = 0xff;
+
+            let header = (
+                &self.headers.0[slot] as *const _ as u64,
+                REQ_HDR_SIZE as u32,
+            );
+            let status = (&self.status.0[slot] as *const u8 as u64, 1);
+            // The device reaches our memory through the IOMMU table of the container, which
+            // maps it at the same addresses
+            let data = (
+                breq.data as u64,
+                breq.num_blocks as u32 * SECTOR_SIZE as u32,
+            );
+
+            let head = if breq.op == BlockOp::Write {
+                queue.add(&[header, data], &[status])
+            } else {
+                queue.add(&[header], &[data, status])
+            }
+            .expect("enough free descriptors");
+            self.requests[head as usize] = Some(breq);
+            sub_count += 1;
+        }
+
+        queue.notify();
+        self.stats.submitted += sub_count as u64;
+
+        self.poll_breq(collect);
+        sub_count
+    }
+
+    /// Move completed requests to `collect`, returning how many there were. `res` of each
+    /// response is the virtio-blk status of the request, `VIRTIO_BLK_S_OK` (0) on success.
+    pub fn poll_breq(&mut self, collect: &mut VecDeque<BlockResp>) -> usize {
+        let queue = match self.queue.as_mut() {
+            Some(queue) => queue,
+            None => return 0,
+        };
+        let mut reap_count = 0;
+
+        while let Some((head, _)) = queue.pop_used() {
+            let breq = match self.requests[head as usize].take() {
+                Some(breq) => breq,
+                None => continue,
+            };
+            let status = unsafe { ptr::read_volatile(&self.status.0[head as usize]) };
+            if status != VIRTIO_BLK_S_OK {
+                println!(
+                    "virtio-blk {:?} of {} sectors at {} failed with status {}",
+                    breq.op, breq.num_blocks, breq.block, status
+                );
+                self.stats.failed += 1;
+            }
+            collect.push_back(BlockResp::new(breq, status as i32));
+            reap_count += 1;
+        }
+
+        self.stats.completed += reap_count as u64;
+        reap_count
+    }
+}
+
+impl BlockDevice for VirtioBlkDevice {
+    fn submit_and_poll_breq(
+        &mut self,
+        submit: &mut VecDeque<BlockReq>,
+        collect: &mut VecDeque<BlockResp>,
+    ) -> usize {
+        VirtioBlkDevice::submit_and_poll_breq(self, submit, collect)
+    }
+
+    fn poll_breq(&mut self, collect: &mut VecDeque<BlockResp>) -> usize {
+        VirtioBlkDevice::poll_breq(self, collect)
+    }
+
+    fn get_stats(&self) -> BlockStats {
+        BlockStats {
+            submitted: self.stats.submitted,
+            completed: self.stats.completed,
+        }
+    }
+
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn capacity(&self) -> u64 {
+        VirtioBlkDevice::capacity(self)
+    }
+}
+
+impl Drop for VirtioBlkDevice {
+    fn drop(&mut self) {
+        // Stop DMA before the queue and buffers are freed
+        self.transport.reset();
+    }
+}
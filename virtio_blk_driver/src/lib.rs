@@ -0,0 +1,8 @@
+#![no_std]
+
+extern crate alloc;
+
+pub mod device;
+pub use log::info as println;
+
+pub use interface::block::{BlockOp, BlockReq, BlockResp};
@@ -0,0 +1,468 @@
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::fmt;
+use core::mem;
+
+use interface::net::{NetDevice, NetStats};
+use libdma::Dma;
+use libvirtio::{
+    Error, Result, VirtQueue, VirtioPciCaps, VirtioTransport, QUEUE_SIZE, VIRTIO_F_ACCESS_PLATFORM,
+    VIRTIO_F_VERSION_1,
+};
+use pcid::utils::PciBarAddr;
+
+use crate::println;
+
+const VIRTIO_NET_F_MAC: u64 = 1 << 5;
+const VIRTIO_NET_F_STATUS: u64 = 1 << 16;
+const VIRTIO_NET_F_CTRL_VQ: u64 = 1 << 17;
+const VIRTIO_NET_F_MQ: u64 = 1 << 22;
+
+const SUPPORTED_FEATURES: u64 = VIRTIO_F_VERSION_1
+    | VIRTIO_F_ACCESS_PLATFORM
+    | VIRTIO_NET_F_MAC
+    | VIRTIO_NET_F_STATUS
+    | VIRTIO_NET_F_CTRL_VQ
+    | VIRTIO_NET_F_MQ;
+
+// Layout of struct virtio_net_config
+const CONFIG_MAC: usize = 0;
+const CONFIG_STATUS: usize = 6;
+const CONFIG_MAX_VIRTQUEUE_PAIRS: usize = 8;
+
+const VIRTIO_NET_S_LINK_UP: u16 = 1;
+
+const VIRTIO_NET_CTRL_MQ: u8 = 4;
+const VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET: u8 = 0;
+const VIRTIO_NET_OK: u8 = 0;
+
+/// Receive buffers must fit a full Ethernet frame, as mergeable receive buffers are not
+/// negotiated.
+const MIN_RX_BUFFER_SIZE: usize = 1514;
+
+/// Header in front of every packet, struct virtio_net_hdr with VIRTIO_F_VERSION_1.
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct VirtioNetHdr {
+    flags: u8,
+    gso_type: u8,
+    hdr_len: u16,
+    gso_size: u16,
+    csum_start: u16,
+    csum_offset: u16,
+    num_buffers: u16,
+}
+
+const NET_HDR_SIZE: usize = mem::size_of::<VirtioNetHdr>();
+
+/// A control queue command setting the number of queue pairs, followed by the ack byte the
+/// device writes.
+#[derive(Debug, Default, Clone, Copy)]
+#[repr(C)]
+struct CtrlMqCommand {
+    class: u8,
+    command: u8,
+    virtqueue_pairs: u16,
+    ack: u8,
+}
+
+pub struct NetworkStats {
+    pub tx_count: u64,
+    pub rx_count: u64,
+}
+
+impl fmt::Display for NetworkStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "=> Tx stats: Count: {}\n=> Rx stats: Count: {}",
+            self.tx_count, self.rx_count
+        )
+    }
+}
+
+/// The DMA address of a packet buffer. The device reaches our memory through the IOMMU
+/// table of the container, which maps it at the same addresses.
+fn dma_addr(buf: &[u8]) -> u64 {
+    buf.as_ptr() as u64
+}
+
+struct QueuePair {
+    rx: VirtQueue,
+    tx: VirtQueue,
+    /// Buffers owned by the device, indexed by the head of their chain.
+    rx_buffers: Vec<Option<Vec<u8>>>,
+    tx_buffers: Vec<Option<Vec<u8>>>,
+}
+
+impl QueuePair {
+    fn new(transport: &VirtioTransport, pair: u16) -> Result<QueuePair> {
+        let mut rx = VirtQueue::new(2 * pair, transport.queue_size(2 * pair)?)?;
+        let mut tx = VirtQueue::new(2 * pair + 1, transport.queue_size(2 * pair + 1)?)?;
+        transport.setup_queue(&mut rx)?;
+        transport.setup_queue(&mut tx)?;
+
+        Ok(QueuePair {
+            rx,
+            tx,
+            rx_buffers: (0..QUEUE_SIZE).map(|_| None).collect(),
+            tx_buffers: (0..QUEUE_SIZE).map(|_| None).collect(),
+        })
+    }
+}
+
+pub struct VirtioNetDevice {
+    transport: VirtioTransport,
+    features: u64,
+    queue_pairs: Vec<QueuePair>,
+    /// Control queue, only present with multiqueue support.
+    ctrl: Option<VirtQueue>,
+    /// Header sent with every packet. No offloads are used, so it is all zeros.
+    tx_header: Dma<VirtioNetHdr>,
+    /// Where the device writes the header of every received packet. We do not look at it,
+    /// so all receive chains share one.
+    rx_header: Dma<VirtioNetHdr>,
+    tx_count: u64,
+    rx_count: u64,
+}
+
+impl VirtioNetDevice {
+    /// Create a driver for the device whose registers are described by `caps`.
+    ///
+    /// # Safety
+    /// `bars` must hold the uncached virtual mappings of the BARs in `caps.bars()`, and the
+    /// device must be bound to the IOMMU table of the caller.
+    pub unsafe fn new(
+        bars: &[Option<PciBarAddr>],
+        caps: &VirtioPciCaps,
+    ) -> Result<VirtioNetDevice> {
+        let dma_err = |_| Error::Config("Out of memory");
+        Ok(VirtioNetDevice {
+            transport: VirtioTransport::new(bars, caps)?,
+            features: 0,
+            queue_pairs: Vec::new(),
+            ctrl: None,
+            tx_header: Dma::new(VirtioNetHdr::default()).map_err(dma_err)?,
+            rx_header: Dma::new(VirtioNetHdr::default()).map_err(dma_err)?,
+            tx_count: 0,
+            rx_count: 0,
+        })
+    }
+
+    /// Reset and initialize the device with up to `num_queue_pairs` pairs of receive and
+    /// transmit queues.
+    pub fn init(&mut self, num_queue_pairs: u16) -> Result<()> {
+        self.features = self.transport.negotiate(SUPPORTED_FEATURES)?;
+        // The reset made the device forget the old queues
+        self.queue_pairs.clear();
+        self.ctrl = None;
+        println!("Negotiated virtio-net features {:#x}", self.features);
+
+        let has_mq = self.has_feature(VIRTIO_NET_F_CTRL_VQ | VIRTIO_NET_F_MQ);
+        let max_pairs = if has_mq {
+            self.transport
+                .read_device_config::<u16>(CONFIG_MAX_VIRTQUEUE_PAIRS)?
+                .max(1)
+        } else {
+            1
+        };
+        let num_queue_pairs = num_queue_pairs.clamp(1, max_pairs);
+
+        for pair in 0..num_queue_pairs {
+            let queue_pair = QueuePair::new(&self.transport, pair)?;
+            self.queue_pairs.push(queue_pair);
+        }
+
+        // The control queue comes after the last possible queue pair
+        if has_mq {
+            let index = 2 * max_pairs;
+            let mut ctrl = VirtQueue::new(index, self.transport.queue_size(index)?)?;
+            self.transport.setup_queue(&mut ctrl)?;
+            self.ctrl = Some(ctrl);
+        }
+
+        self.transport.driver_ok();
+
+        if num_queue_pairs > 1 {
+            self.set_queue_pairs(num_queue_pairs)?;
+        }
+
+        let mac = self.get_mac_addr();
+        println!(
+            "virtio-net {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x} with {} queue pairs, link {}",
+            mac[0],
+            mac[1],
+            mac[2],
+            mac[3],
+            mac[4],
+            mac[5],
+            num_queue_pairs,
+            if self.link_up() { "up" } else { "down" }
+        );
+        Ok(())
+    }
+
+    fn has_feature(&self, features: u64) -> bool {
+        self.features & features == features
+    }
+
+    /// Ask the device to spread traffic over `pairs` queue pairs.
+    fn set_queue_pairs(&mut self, pairs: u16) -> Result<()> {
+        let ctrl = self
+            .ctrl
+            .as_mut()
+            .ok_or(Error::Config("No control queue"))?;
+        let command = Dma::new(CtrlMqCommand {
+            class: VIRTIO_NET_CTRL_MQ,
+            command: VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET,
+            virtqueue_pairs: pairs,
+            ack: 0xff,
+        })
+        .map_err(|_| Error::Config("Out of memory"))?;
+        let base = command.physical() as u64;
+
+        ctrl.add(&[(base, 2), (base + 2, 2)], &[(base + 4, 1)])
+            .ok_or(Error::Config("Control queue full"))?;
+        ctrl.notify();
+        while ctrl.pop_used().is_none() {
+            self.transport.check_status()?;
+            core::hint::spin_loop();
+        }
+
+        let ack = unsafe { core::ptr::read_volatile(&command.ack) };
+        if ack != VIRTIO_NET_OK {
+            return Err(Error::Config("Device refused the number of queue pairs"));
+        }
+        Ok(())
+    }
+
+    pub fn get_mac_addr(&self) -> [u8; 6] {
+        if !self.has_feature(VIRTIO_NET_F_MAC) {
+            return [0; 6];
+        }
+        let mut mac = [0; 6];
+        for (i, byte) in mac.iter_mut().enumerate() {
+            *byte = self
+                .transport
+                .read_device_config::<u8>(CONFIG_MAC + i)
+                .unwrap_or(0);
+        }
+        mac
+    }
+
+    /// Return whether the link is up. Devices that do not report it are always up.
+    pub fn link_up(&self) -> bool {
+        if !self.has_feature(VIRTIO_NET_F_STATUS) {
+            return true;
+        }
+        self.transport
+            .read_device_config::<u16>(CONFIG_STATUS)
+            .map_or(false, |status| status & VIRTIO_NET_S_LINK_UP != 0)
+    }
+
+    pub fn num_queue_pairs(&self) -> usize {
+        self.queue_pairs.len()
+    }
+
+    pub fn get_stats(&self) -> NetworkStats {
+        NetworkStats {
+            tx_count: self.tx_count,
+            rx_count: self.rx_count,
+        }
+    }
+
+    /// Transmit `packets` on queue pair 0. Sent packets stay in the queue until the device
+    /// is done with them.
+    pub fn submit(&mut self, packets: &mut VecDeque<Vec<u8>>) -> usize {
+        self.submit_queue(0, packets)
+    }
+
+    /// Transmit `packets` on queue pair `qid`. Nothing is sent before `init` or if the
+    /// device has no such queue pair.
+    pub fn submit_queue(&mut self, qid: usize, packets: &mut VecDeque<Vec<u8>>) -> usize {
+        let header = (self.tx_header.physical() as u64, NET_HDR_SIZE as u32);
+        let queue = match self.queue_pairs.get_mut(qid) {
+            Some(queue) => queue,
+            None => return 0,
+        };
+        let mut sent = 0;
+
+        while let Some(packet) = packets.pop_front() {
+            let buf = (dma_addr(&packet), packet.len() as u32);
+            match queue.tx.add(&[header, buf], &[]) {
+                Some(head) => {
+                    queue.tx_buffers[head as usize] = Some(packet);
+                    sent += 1;
+                }
+                None => {
+                    // tx queue of device is full, push packet back onto the
+                    // queue of to-be-sent packets
+                    packets.push_front(packet);
+                    break;
+                }
+            }
+        }
+
+        queue.tx.notify();
+        self.tx_count += sent as u64;
+        sent
+    }
+
+    /// Give empty `buffers` to queue pair `qid` to receive into. Buffers too small for a
+    /// full frame stay in `buffers`.
+    fn post_rx_buffers(&mut self, qid: usize, buffers: &mut VecDeque<Vec<u8>>) -> usize {
+        let header = (self.rx_header.physical() as u64, NET_HDR_SIZE as u32);
+        let queue = match self.queue_pairs.get_mut(qid) {
+            Some(queue) => queue,
+            None => return 0,
+        };
+        let mut posted = 0;
+
+        for _ in 0..buffers.len() {
+            let mut buffer = buffers.pop_front().unwrap();
+            if buffer.capacity() < MIN_RX_BUFFER_SIZE {
+                buffers.push_back(buffer);
+                continue;
+            }
+            let capacity = buffer.capacity();
+            unsafe { buffer.set_len(capacity) };
+            let buf = (dma_addr(&buffer), capacity as u32);
+            match queue.rx.add(&[], &[header, buf]) {
+                Some(head) => {
+                    queue.rx_buffers[head as usize] = Some(buffer);
+                    posted += 1;
+                }
+                None => {
+                    unsafe { buffer.set_len(0) };
+                    buffers.push_front(buffer);
+                    break;
+                }
+            }
+        }
+
+        queue.rx.notify();
+        posted
+    }
+
+    /// Move transmitted packets, or received packets if `tx` is false, of queue pair 0 to
+    /// `reap_queue`.
+    pub fn poll(&mut self, reap_queue: &mut VecDeque<Vec<u8>>, tx: bool) -> usize {
+        self.poll_queue(0, reap_queue, tx)
+    }
+
+    /// Move transmitted or received packets of queue pair `qid` to `reap_queue`, if the
+    /// device has that queue pair.
+    pub fn poll_queue(
+        &mut self,
+        qid: usize,
+        reap_queue: &mut VecDeque<Vec<u8>>,
+        tx: bool,
+    ) -> usize {
+        let queue = match self.queue_pairs.get_mut(qid) {
+            Some(queue) => queue,
+            None => return 0,
+        };
+        let mut reaped = 0;
+
+        if tx {
+            while let Some((head, _)) = queue.tx.pop_used() {
+                if let Some(packet) = queue.tx_buffers[head as usize].take() {
+                    reap_queue.push_back(packet);
+                    reaped += 1;
+                }
+            }
+        } else {
+            while let Some((head, len)) = queue.rx.pop_used() {
+                if let Some(mut packet) = queue.rx_buffers[head as usize].take() {
+                    let length = (len as usize).saturating_sub(NET_HDR_SIZE);
+                    unsafe { packet.set_len(length.min(packet.capacity())) };
+                    reap_queue.push_back(packet);
+                    reaped += 1;
+                }
+            }
+            self.rx_count += reaped as u64;
+        }
+        reaped
+    }
+
+    /// Submit `packets` for transmission, or as receive buffers if `tx` is false, then poll
+    /// completions into `reap_queue`, like `IxgbeDevice::submit_and_poll`.
+    pub fn submit_and_poll(
+        &mut self,
+        packets: &mut VecDeque<Vec<u8>>,
+        reap_queue: &mut VecDeque<Vec<u8>>,
+        tx: bool,
+        debug: bool,
+    ) -> usize {
+        self.submit_and_poll_queue(0, packets, reap_queue, tx, debug)
+    }
+
+    /// `submit_and_poll` on queue pair `qid`.
+    pub fn submit_and_poll_queue(
+        &mut self,
+        qid: usize,
+        packets: &mut VecDeque<Vec<u8>>,
+        reap_queue: &mut VecDeque<Vec<u8>>,
+        tx: bool,
+        debug: bool,
+    ) -> usize {
+        let submitted = if tx {
+            self.submit_queue(qid, packets)
+        } else {
+            self.post_rx_buffers(qid, packets)
+        };
+        let reaped = self.poll_queue(qid, reap_queue, tx);
+        if debug {
+            println!(
+                "queue {} {}: submitted {} reaped {}",
+                qid,
+                if tx { "tx" } else { "rx" },
+                submitted,
+                reaped
+            );
+        }
+        if tx {
+            submitted
+        } else {
+            reaped
+        }
+    }
+}
+
+impl NetDevice for VirtioNetDevice {
+    fn submit_and_poll(
+        &mut self,
+        packets: &mut VecDeque<Vec<u8>>,
+        collect: &mut VecDeque<Vec<u8>>,
+        tx: bool,
+    ) -> usize {
+        VirtioNetDevice::submit_and_poll(self, packets, collect, tx, false)
+    }
+
+    fn poll(&mut self, collect: &mut VecDeque<Vec<u8>>, tx: bool) -> usize {
+        VirtioNetDevice::poll(self, collect, tx)
+    }
+
+    fn get_stats(&self) -> NetStats {
+        NetStats {
+            tx_count: self.tx_count,
+            rx_count: self.rx_count,
+            ..NetStats::default()
+        }
+    }
+
+    fn mac_addr(&self) -> [u8; 6] {
+        self.get_mac_addr()
+    }
+
+    fn link_up(&self) -> bool {
+        VirtioNetDevice::link_up(self)
+    }
+}
+
+impl Drop for VirtioNetDevice {
+    fn drop(&mut self) {
+        // Stop DMA before the queues and buffers are freed
+        self.transport.reset();
+    }
+}